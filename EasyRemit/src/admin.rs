@@ -0,0 +1,566 @@
+use rusqlite::{params, Connection, OptionalExtension, Row};
+use std::fmt;
+use crate::audit;
+use crate::auth;
+use crate::backup::{self, BackupError, BackupInfo, Restored};
+use crate::compliance;
+use crate::config;
+use crate::fees::HOUSE_ACCOUNT_ID;
+use crate::ledger::{self, TransferError};
+use crate::limits;
+use crate::webhooks::{self, WebhookError};
+
+// Roles an operator can give a user. Operators sign in to easyremit-admin
+// with their own account, which needs the operator role.
+pub const CUSTOMER: &str = "customer";
+pub const OPERATOR: &str = "operator";
+pub const ROLES: [&str; 3] = [CUSTOMER, compliance::REVIEWER, OPERATOR];
+
+// Who is running easyremit-admin, once their password has been checked
+#[derive(Debug, Clone)]
+pub struct Operator {
+    pub user_id: String,
+    // The audit log actor for everything they do
+    pub actor: String,
+    // No account has the operator role yet, so this one may only make
+    // itself an operator
+    pub bootstrap: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct UserSummary {
+    pub id: String,
+    pub name: String,
+    pub email: String,
+    pub balance: i64,
+    pub frozen: bool,
+    pub role: String,
+    pub created_at: Option<i64>,
+}
+
+impl UserSummary {
+    const COLUMNS: &'static str =
+        "id, COALESCE(name, ''), COALESCE(email, ''), COALESCE(balance, 0), frozen, role, created_at";
+
+    fn from_row(row: &Row) -> rusqlite::Result<UserSummary> {
+        Ok(UserSummary {
+            id: row.get(0)?,
+            name: row.get(1)?,
+            email: row.get(2)?,
+            balance: row.get(3)?,
+            frozen: row.get(4)?,
+            role: row.get(5)?,
+            created_at: row.get(6)?,
+        })
+    }
+}
+
+// Everything stored about a user, with the encrypted fields decrypted when the
+// operator supplied the key. None where a field is missing, no key was given
+// or it does not decrypt with the key.
+#[derive(Debug, Clone)]
+pub struct Profile {
+    pub user: UserSummary,
+    pub passport: Option<String>,
+    pub birth_date: Option<String>,
+    pub failed_logins: i64,
+    pub locked_until: Option<i64>,
+}
+
+#[derive(Debug)]
+pub enum AdminError {
+    UnknownUser,
+    InvalidCredentials,
+    BootstrapOnly,
+    BadKey,
+    ReasonRequired,
+    UnknownRole(String),
+    UnknownKycLevel(i64),
+    Transfer(TransferError),
+    Webhook(WebhookError),
+    Backup(BackupError),
+    Auth(auth::AuthError),
+    Database(rusqlite::Error),
+}
+
+impl fmt::Display for AdminError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AdminError::UnknownUser => write!(f, "No user with that ID"),
+            AdminError::InvalidCredentials => write!(f, "Wrong user ID or password"),
+            AdminError::BootstrapOnly => write!(
+                f,
+                "No account is an operator yet; the first command must be `set-role <your user ID> {} <reason>`",
+                OPERATOR
+            ),
+            AdminError::BadKey => write!(f, "The encryption key must be exactly 16 bytes"),
+            AdminError::ReasonRequired => write!(f, "A reason is required"),
+            AdminError::UnknownRole(role) => write!(f, "Unknown role {}, expected one of: {}", role, ROLES.join(", ")),
+            AdminError::UnknownKycLevel(level) => write!(f, "KYC level {} has no transfer limits", level),
+            AdminError::Transfer(e) => write!(f, "{}", e),
+            AdminError::Webhook(e) => write!(f, "{}", e),
+            AdminError::Backup(e) => write!(f, "{}", e),
+            AdminError::Auth(e) => write!(f, "{}", e),
+            AdminError::Database(e) => write!(f, "Database error: {}", e),
+        }
+    }
+}
+
+impl From<rusqlite::Error> for AdminError {
+    fn from(e: rusqlite::Error) -> Self {
+        AdminError::Database(e)
+    }
+}
+
+impl From<TransferError> for AdminError {
+    fn from(e: TransferError) -> Self {
+        AdminError::Transfer(e)
+    }
+}
+
+impl From<WebhookError> for AdminError {
+    fn from(e: WebhookError) -> Self {
+        AdminError::Webhook(e)
+    }
+}
+
+impl From<auth::AuthError> for AdminError {
+    fn from(e: auth::AuthError) -> Self {
+        match e {
+            auth::AuthError::InvalidCredentials => AdminError::InvalidCredentials,
+            auth::AuthError::Database(e) => AdminError::Database(e),
+            e => AdminError::Auth(e),
+        }
+    }
+}
+
+impl From<BackupError> for AdminError {
+    fn from(e: BackupError) -> Self {
+        AdminError::Backup(e)
+    }
+}
+
+// Checks the operator's password and role through the same lockout as the
+// Login page. A wrong password, an unknown user and an account without the
+// operator role all get InvalidCredentials, so the tool never confirms a
+// customer's password.
+pub fn sign_in(conn: &Connection, user_id: &str, password: &str) -> Result<Operator, AdminError> {
+    let role: Option<String> = conn
+        .query_row(
+            "SELECT role FROM user_information WHERE id = ?1 AND id != ?2",
+            params![user_id, HOUSE_ACCOUNT_ID],
+            |row| row.get(0),
+        )
+        .optional()?;
+    let operators: i64 = conn.query_row(
+        "SELECT COUNT(*) FROM user_information WHERE role = ?1",
+        params![OPERATOR],
+        |row| row.get(0),
+    )?;
+    let bootstrap = operators == 0;
+    let permitted = role.is_some_and(|role| role == OPERATOR || bootstrap);
+    auth::tool_login(conn, "easyremit-admin", user_id, password, permitted)?;
+    Ok(Operator {
+        user_id: user_id.to_string(),
+        actor: format!("admin:{}", user_id),
+        bootstrap,
+    })
+}
+
+// Every operation below is recorded in the audit log with the operator as
+// actor, reads included, since they expose customer data.
+
+pub fn list_users(conn: &Connection, operator: &str, limit: usize) -> Result<Vec<UserSummary>, AdminError> {
+    let mut stmt = conn.prepare(&format!(
+        "SELECT {} FROM user_information WHERE id != ?1 ORDER BY created_at DESC LIMIT ?2",
+        UserSummary::COLUMNS
+    ))?;
+    let users = stmt
+        .query_map(params![HOUSE_ACCOUNT_ID, limit as i64], UserSummary::from_row)?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    audit::record(conn, operator, audit::ADMIN_LIST_USERS, "users", &format!("limit={}", limit))?;
+    Ok(users)
+}
+
+// Matches `query` anywhere in the ID, name or email, ignoring case.
+pub fn search_users(conn: &Connection, operator: &str, query: &str) -> Result<Vec<UserSummary>, AdminError> {
+    let mut stmt = conn.prepare(&format!(
+        "SELECT {} FROM user_information
+         WHERE id != ?1 AND (id LIKE ?2 OR name LIKE ?2 OR email LIKE ?2)
+         ORDER BY name",
+        UserSummary::COLUMNS
+    ))?;
+    let pattern = format!("%{}%", query);
+    let users = stmt
+        .query_map(params![HOUSE_ACCOUNT_ID, pattern], UserSummary::from_row)?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    audit::record(conn, operator, audit::ADMIN_SEARCH_USERS, "users", &format!("query={}", query))?;
+    Ok(users)
+}
+
+// The passport and birth date are only decrypted with a `key` the operator
+// supplies, not with the one the app is configured with.
+pub fn profile(conn: &Connection, operator: &str, user_id: &str, key: Option<&str>) -> Result<Profile, AdminError> {
+    if key.is_some_and(|key| key.len() != 16) {
+        return Err(AdminError::BadKey);
+    }
+    let user = user(conn, user_id)?;
+    let (passport, birth_date, failed_logins, locked_until): (Option<String>, Option<String>, i64, Option<i64>) =
+        conn.query_row(
+            "SELECT encrypted_passport, encrypted_birthdate, failed_logins, locked_until
+             FROM user_information WHERE id = ?1",
+            params![user_id],
+            |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?)),
+        )?;
+    let decrypt = |data: Option<String>| key.zip(data).and_then(|(key, data)| auth::decrypt_with(key, &data));
+    let profile = Profile {
+        user,
+        passport: decrypt(passport),
+        birth_date: decrypt(birth_date),
+        failed_logins,
+        locked_until,
+    };
+    audit::record(conn, operator, audit::ADMIN_VIEW_PROFILE, user_id, &format!("decrypted={}", key.is_some() as i64))?;
+    Ok(profile)
+}
+
+// Frozen accounts can still log in and receive money but cannot send any.
+pub fn set_frozen(conn: &Connection, operator: &str, user_id: &str, frozen: bool, reason: &str) -> Result<(), AdminError> {
+    let reason = required(reason)?;
+    user(conn, user_id)?;
+    let tx = conn.unchecked_transaction()?;
+    tx.execute(
+        "UPDATE user_information SET frozen = ?2 WHERE id = ?1",
+        params![user_id, frozen],
+    )?;
+    let action = if frozen { audit::ADMIN_FREEZE } else { audit::ADMIN_UNFREEZE };
+    audit::record(&tx, operator, action, user_id, &format!("reason={}", reason))?;
+    tx.commit()?;
+    Ok(())
+}
+
+// Posts `delta` to the user's balance as a ledger entry and returns the new
+// balance.
+pub fn adjust_balance(conn: &Connection, operator: &str, user_id: &str, delta: i64, reason: &str) -> Result<i64, AdminError> {
+    let reason = required(reason)?;
+    user(conn, user_id)?;
+    let tx = conn.unchecked_transaction()?;
+    let balance = ledger::adjust_balance(&tx, user_id, delta, reason)?;
+    audit::record(
+        &tx,
+        operator,
+        audit::ADMIN_ADJUST_BALANCE,
+        user_id,
+        &format!("delta={} balance={} reason={}", delta, balance, reason),
+    )?;
+    tx.commit()?;
+    Ok(balance)
+}
+
+pub fn reset_lockout(conn: &Connection, operator: &str, user_id: &str) -> Result<(), AdminError> {
+    user(conn, user_id)?;
+    let tx = conn.unchecked_transaction()?;
+    tx.execute(
+        "UPDATE user_information SET failed_logins = 0, locked_until = NULL WHERE id = ?1",
+        params![user_id],
+    )?;
+    audit::record(&tx, operator, audit::ADMIN_RESET_LOCKOUT, user_id, "")?;
+    tx.commit()?;
+    Ok(())
+}
+
+pub fn set_role(conn: &Connection, operator: &str, user_id: &str, role: &str, reason: &str) -> Result<(), AdminError> {
+    let reason = required(reason)?;
+    if !ROLES.contains(&role) {
+        return Err(AdminError::UnknownRole(role.to_string()));
+    }
+    user(conn, user_id)?;
+    let tx = conn.unchecked_transaction()?;
+    tx.execute(
+        "UPDATE user_information SET role = ?2 WHERE id = ?1",
+        params![user_id, role],
+    )?;
+    audit::record(&tx, operator, audit::ADMIN_SET_ROLE, user_id, &format!("role={} reason={}", role, reason))?;
+    tx.commit()?;
+    Ok(())
+}
+
+// Raises or lowers the user's KYC level. The user is notified if their
+// transfer limits change. Returns the limits that now apply.
+pub fn set_kyc_level(conn: &Connection, operator: &str, user_id: &str, level: i64, reason: &str) -> Result<limits::TransferLimits, AdminError> {
+    let reason = required(reason)?;
+    let known: bool = conn.query_row(
+        "SELECT EXISTS(SELECT 1 FROM transfer_limits WHERE kyc_level = ?1)",
+        params![level],
+        |row| row.get(0),
+    )?;
+    if !known {
+        return Err(AdminError::UnknownKycLevel(level));
+    }
+    user(conn, user_id)?;
+    let tx = conn.unchecked_transaction()?;
+    let limits = limits::set_kyc_level(&tx, user_id, level)?;
+    audit::record(&tx, operator, audit::ADMIN_SET_KYC_LEVEL, user_id, &format!("level={} reason={}", level, reason))?;
+    tx.commit()?;
+    Ok(limits)
+}
+
+// Returns the new subscription's ID and signing secret. The secret is not
+// written to the audit log.
+pub fn add_webhook(conn: &Connection, operator: &str, url: &str, events: &[&str]) -> Result<(i64, String), AdminError> {
+    let tx = conn.unchecked_transaction()?;
+    let (id, secret) = webhooks::subscribe(&tx, url, events, None)?;
+    audit::record(
+        &tx,
+        operator,
+        audit::ADMIN_ADD_WEBHOOK,
+        &format!("webhook:{}", id),
+        &format!("url={} events={}", url, events.join(",")),
+    )?;
+    tx.commit()?;
+    Ok((id, secret))
+}
+
+pub fn remove_webhook(conn: &Connection, operator: &str, id: i64) -> Result<(), AdminError> {
+    let tx = conn.unchecked_transaction()?;
+    webhooks::unsubscribe(&tx, id)?;
+    audit::record(&tx, operator, audit::ADMIN_REMOVE_WEBHOOK, &format!("webhook:{}", id), "")?;
+    tx.commit()?;
+    Ok(())
+}
+
+// Takes a backup of the configured database now and deletes the ones past
+// `backup` retention. Returns the new backup and the deleted ones.
+pub fn backup(conn: &Connection, operator: &str) -> Result<(BackupInfo, Vec<BackupInfo>), AdminError> {
+    let config = config::get();
+    let backup = backup::create(&config.database.path, &config.backup)?;
+    let removed = backup::prune(&config.backup)?;
+    audit::record(
+        conn,
+        operator,
+        audit::ADMIN_BACKUP,
+        &backup.path.display().to_string(),
+        &format!("size={} removed={}", backup.size, removed.len()),
+    )?;
+    Ok((backup, removed))
+}
+
+// Restores the backup named by `what`, a file or a time (see backup::find).
+// The restore is recorded in the audit log of the restored database.
+pub fn restore_backup(conn: &Connection, operator: &str, what: &str) -> Result<Restored, AdminError> {
+    let config = config::get();
+    let path = backup::find(&config.backup, what)?;
+    let restored = backup::restore(&path, &config.database.path, &config.backup)?;
+    let previous = restored.previous.as_ref().map(|previous| previous.path.display().to_string());
+    audit::record(
+        conn,
+        operator,
+        audit::ADMIN_RESTORE_BACKUP,
+        &path.display().to_string(),
+        &format!("taken_at={} previous={}", restored.backup.created_at, previous.unwrap_or_default()),
+    )?;
+    Ok(restored)
+}
+
+fn user(conn: &Connection, user_id: &str) -> Result<UserSummary, AdminError> {
+    conn.query_row(
+        &format!("SELECT {} FROM user_information WHERE id = ?1", UserSummary::COLUMNS),
+        params![user_id],
+        UserSummary::from_row,
+    )
+    .optional()?
+    .ok_or(AdminError::UnknownUser)
+}
+
+fn required(reason: &str) -> Result<&str, AdminError> {
+    match reason.trim() {
+        "" => Err(AdminError::ReasonRequired),
+        reason => Ok(reason),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::init_schema;
+
+    const OPERATOR_ACTOR: &str = "admin:ops";
+
+    fn database() -> Connection {
+        let conn = Connection::open_in_memory().unwrap();
+        init_schema(&conn).unwrap();
+        account(&conn, "alice", CUSTOMER, 100);
+        conn
+    }
+
+    fn account(conn: &Connection, user_id: &str, role: &str, balance: i64) {
+        conn.execute(
+            "INSERT INTO user_information (id, name, email, hashed_password, encrypted_passport, encrypted_birthdate, balance, role, created_at)
+             VALUES (?1, ?1, ?1 || '@example.com', ?2, ?3, ?4, ?5, ?6, strftime('%s', 'now'))",
+            params![
+                user_id,
+                auth::hash_password("correct horse").unwrap(),
+                auth::encrypt("A1234567").unwrap(),
+                auth::encrypt("1990-02-28").unwrap(),
+                balance,
+                role
+            ],
+        )
+        .unwrap();
+    }
+
+    // (actor, action, target, details) of the newest audit entry
+    fn last_audit(conn: &Connection) -> (String, String, String, String) {
+        conn.query_row(
+            "SELECT actor, action, target, details FROM audit_log ORDER BY id DESC LIMIT 1",
+            [],
+            |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?)),
+        )
+        .unwrap()
+    }
+
+    fn audit_count(conn: &Connection) -> i64 {
+        conn.query_row("SELECT COUNT(*) FROM audit_log", [], |row| row.get(0)).unwrap()
+    }
+
+    #[test]
+    fn freezing_needs_a_reason_and_is_audited() {
+        let conn = database();
+        assert!(matches!(set_frozen(&conn, OPERATOR_ACTOR, "alice", true, "  "), Err(AdminError::ReasonRequired)));
+        assert!(matches!(set_frozen(&conn, OPERATOR_ACTOR, "nobody", true, "fraud"), Err(AdminError::UnknownUser)));
+        assert_eq!(audit_count(&conn), 0);
+
+        set_frozen(&conn, OPERATOR_ACTOR, "alice", true, "chargeback").unwrap();
+        assert!(user(&conn, "alice").unwrap().frozen);
+        assert_eq!(
+            last_audit(&conn),
+            (OPERATOR_ACTOR.into(), audit::ADMIN_FREEZE.into(), "alice".into(), "reason=chargeback".into())
+        );
+
+        set_frozen(&conn, OPERATOR_ACTOR, "alice", false, "resolved").unwrap();
+        assert!(!user(&conn, "alice").unwrap().frozen);
+        assert_eq!(last_audit(&conn).1, audit::ADMIN_UNFREEZE);
+    }
+
+    #[test]
+    fn adjustments_post_to_the_ledger_and_are_audited() {
+        let conn = database();
+        assert_eq!(adjust_balance(&conn, OPERATOR_ACTOR, "alice", 25, "refund").unwrap(), 125);
+        assert_eq!(adjust_balance(&conn, OPERATOR_ACTOR, "alice", -5, "correction").unwrap(), 120);
+        assert_eq!(user(&conn, "alice").unwrap().balance, 120);
+        let entries: Vec<(String, String, i64, String)> = conn
+            .prepare("SELECT sender_id, receiver_id, amount, memo FROM transaction_history WHERE kind = 'adjustment' ORDER BY id")
+            .unwrap()
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?)))
+            .unwrap()
+            .collect::<rusqlite::Result<_>>()
+            .unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!((entries[0].1.as_str(), entries[0].2, entries[0].3.as_str()), ("alice", 25, "refund"));
+        assert_eq!((entries[1].0.as_str(), entries[1].2), ("alice", 5));
+        assert_eq!(
+            last_audit(&conn),
+            (
+                OPERATOR_ACTOR.into(),
+                audit::ADMIN_ADJUST_BALANCE.into(),
+                "alice".into(),
+                "delta=-5 balance=120 reason=correction".into()
+            )
+        );
+
+        // Nothing is posted or audited when the adjustment is refused
+        let audited = audit_count(&conn);
+        assert!(matches!(
+            adjust_balance(&conn, OPERATOR_ACTOR, "alice", -500, "overdraw"),
+            Err(AdminError::Transfer(TransferError::InsufficientFunds { .. }))
+        ));
+        assert!(matches!(adjust_balance(&conn, OPERATOR_ACTOR, "alice", 10, ""), Err(AdminError::ReasonRequired)));
+        assert_eq!(user(&conn, "alice").unwrap().balance, 120);
+        assert_eq!(audit_count(&conn), audited);
+    }
+
+    #[test]
+    fn resetting_a_lockout_clears_failed_logins() {
+        let conn = database();
+        conn.execute(
+            "UPDATE user_information SET failed_logins = 5, locked_until = strftime('%s', 'now') + 900 WHERE id = 'alice'",
+            [],
+        )
+        .unwrap();
+        reset_lockout(&conn, OPERATOR_ACTOR, "alice").unwrap();
+        let profile = profile(&conn, OPERATOR_ACTOR, "alice", None).unwrap();
+        assert_eq!((profile.failed_logins, profile.locked_until), (0, None));
+        let audited: Vec<String> = conn
+            .prepare("SELECT action FROM audit_log ORDER BY id")
+            .unwrap()
+            .query_map([], |row| row.get(0))
+            .unwrap()
+            .collect::<rusqlite::Result<_>>()
+            .unwrap();
+        assert_eq!(audited, [audit::ADMIN_RESET_LOCKOUT, audit::ADMIN_VIEW_PROFILE]);
+    }
+
+    #[test]
+    fn profile_fields_decrypt_only_with_the_supplied_key() {
+        let conn = database();
+        let key = config::get().encryption.key.clone();
+
+        let decrypted = profile(&conn, OPERATOR_ACTOR, "alice", Some(&key)).unwrap();
+        assert_eq!(decrypted.passport.as_deref(), Some("A1234567"));
+        assert_eq!(decrypted.birth_date.as_deref(), Some("1990-02-28"));
+        assert_eq!(last_audit(&conn).3, "decrypted=1");
+
+        let without_key = profile(&conn, OPERATOR_ACTOR, "alice", None).unwrap();
+        assert_eq!((without_key.passport, without_key.birth_date), (None, None));
+        assert_eq!(last_audit(&conn).3, "decrypted=0");
+
+        let wrong_key = profile(&conn, OPERATOR_ACTOR, "alice", Some("0123456789abcdef")).unwrap();
+        assert_eq!((wrong_key.passport, wrong_key.birth_date), (None, None));
+
+        assert!(matches!(profile(&conn, OPERATOR_ACTOR, "alice", Some("short")), Err(AdminError::BadKey)));
+    }
+
+    #[test]
+    fn only_operators_sign_in_once_one_exists() {
+        let conn = database();
+
+        // Before there is an operator, any account can sign in to become one
+        let first = sign_in(&conn, "alice", "correct horse").unwrap();
+        assert!(first.bootstrap);
+        assert_eq!(first.actor, "admin:alice");
+        set_role(&conn, &first.actor, "alice", OPERATOR, "first operator").unwrap();
+        assert!(!sign_in(&conn, "alice", "correct horse").unwrap().bootstrap);
+
+        // a customer's right password is refused like a wrong one
+        account(&conn, "bob", CUSTOMER, 0);
+        assert!(matches!(sign_in(&conn, "bob", "correct horse"), Err(AdminError::InvalidCredentials)));
+        assert!(matches!(sign_in(&conn, "bob", "wrong"), Err(AdminError::InvalidCredentials)));
+        assert!(matches!(sign_in(&conn, "nobody", "correct horse"), Err(AdminError::InvalidCredentials)));
+        assert_eq!(
+            last_audit(&conn),
+            ("nobody".into(), audit::LOGIN_FAILED.into(), "nobody".into(), "tool=easyremit-admin".into())
+        );
+    }
+
+    #[test]
+    fn wrong_passwords_lock_the_account() {
+        let conn = database();
+        for _ in 0..auth::MAX_FAILED_LOGINS {
+            assert!(matches!(sign_in(&conn, "alice", "wrong"), Err(AdminError::InvalidCredentials)));
+        }
+        let profile = profile(&conn, OPERATOR_ACTOR, "alice", None).unwrap();
+        assert_eq!(profile.failed_logins, auth::MAX_FAILED_LOGINS);
+        assert!(profile.locked_until.is_some());
+        assert!(matches!(
+            sign_in(&conn, "alice", "correct horse"),
+            Err(AdminError::Auth(auth::AuthError::LockedOut { .. }))
+        ));
+        assert_eq!(
+            last_audit(&conn),
+            ("alice".into(), audit::LOGIN_FAILED.into(), "alice".into(), "tool=easyremit-admin locked".into())
+        );
+
+        reset_lockout(&conn, OPERATOR_ACTOR, "alice").unwrap();
+        assert!(sign_in(&conn, "alice", "correct horse").is_ok());
+    }
+}
@@ -0,0 +1,431 @@
+use serde_json::{json, Value};
+use crate::auth::AuthError;
+use crate::idempotency;
+use crate::ledger::{Receipt, TransferError};
+use crate::metrics;
+use crate::model::{AccountId, Email, Transaction};
+use crate::sessions;
+use crate::storage::{Idempotent, Storage, StorageError};
+
+// Served as-is at GET /openapi.json
+pub const OPENAPI: &str = include_str!("../openapi.json");
+
+// The parts of an HTTP request the API looks at. The server binary fills
+// this in, which keeps the handlers free of any HTTP library.
+#[derive(Debug, Clone, Default)]
+pub struct Request<'a> {
+    pub method: &'a str,
+    pub path: &'a str,
+    pub query: &'a str,
+    // from `Authorization: Bearer <token>`
+    pub token: Option<&'a str>,
+    // from `Idempotency-Key`
+    pub idempotency_key: Option<&'a str>,
+    pub body: &'a str,
+}
+
+#[derive(Debug, Clone)]
+pub struct Response {
+    pub status: u16,
+    pub body: String,
+    pub content_type: &'static str,
+    // true when the body was stored for an earlier request with the same
+    // idempotency key
+    pub replayed: bool,
+}
+
+impl Response {
+    fn json(status: u16, body: Value) -> Response {
+        Response { status, body: body.to_string(), content_type: "application/json", replayed: false }
+    }
+}
+
+// An error response: {"error": {"code": ..., "message": ...}}
+struct ApiError {
+    status: u16,
+    code: &'static str,
+    message: String,
+}
+
+impl ApiError {
+    fn new(status: u16, code: &'static str, message: impl Into<String>) -> ApiError {
+        ApiError { status, code, message: message.into() }
+    }
+
+    fn into_response(self) -> Response {
+        Response::json(self.status, json!({ "error": { "code": self.code, "message": self.message } }))
+    }
+}
+
+impl From<StorageError> for ApiError {
+    fn from(e: StorageError) -> Self {
+        match e {
+            StorageError::Auth(AuthError::InvalidCredentials) => ApiError::new(401, "invalid_credentials", e.to_string()),
+            StorageError::Auth(AuthError::LockedOut { .. }) => ApiError::new(429, "locked_out", e.to_string()),
+            StorageError::Transfer(e) => e.into(),
+            e => ApiError::new(500, "internal", e.to_string()),
+        }
+    }
+}
+
+impl From<TransferError> for ApiError {
+    fn from(e: TransferError) -> Self {
+        let (status, code) = match &e {
+            TransferError::InvalidAmount => (422, "invalid_amount"),
+            TransferError::AccountFrozen => (403, "account_frozen"),
+            TransferError::SameAccount => (422, "same_account"),
+            TransferError::UnknownRecipient => (404, "unknown_recipient"),
+            TransferError::NotHeld => (409, "not_held"),
+            TransferError::InsufficientFunds { .. } => (422, "insufficient_funds"),
+            TransferError::Limit(_) => (422, "limit_exceeded"),
+            TransferError::Database(_) => (500, "internal"),
+        };
+        ApiError::new(status, code, e.to_string())
+    }
+}
+
+pub fn handle(storage: &dyn Storage, request: &Request) -> Response {
+    route(storage, request).unwrap_or_else(ApiError::into_response)
+}
+
+fn route(storage: &dyn Storage, request: &Request) -> Result<Response, ApiError> {
+    let segments: Vec<&str> = request.path.trim_matches('/').split('/').collect();
+    match (request.method, segments.as_slice()) {
+        ("GET", ["openapi.json"]) => Ok(Response {
+            status: 200,
+            body: OPENAPI.to_string(),
+            content_type: "application/json",
+            replayed: false,
+        }),
+        // For Prometheus; localhost only, like the rest of the API
+        ("GET", ["metrics"]) => Ok(Response {
+            status: 200,
+            body: metrics::render(),
+            content_type: "text/plain; version=0.0.4",
+            replayed: false,
+        }),
+        ("POST", ["v1", "sessions"]) => create_session(storage, request),
+        ("DELETE", ["v1", "sessions"]) => {
+            authenticate(storage, request)?;
+            storage.end_session(request.token.unwrap_or_default())?;
+            Ok(Response::json(200, json!({ "logged_out": true })))
+        }
+        ("GET", ["v1", "balance"]) => {
+            let user_id = authenticate(storage, request)?;
+            let balance = storage.balance(&user_id)?;
+            Ok(Response::json(200, json!({ "user_id": user_id, "balance": balance })))
+        }
+        ("GET", ["v1", "transactions"]) => {
+            let user_id = authenticate(storage, request)?;
+            let limit = match query_param(request.query, "limit") {
+                Some(limit) => Some(
+                    limit
+                        .parse::<usize>()
+                        .map_err(|_| ApiError::new(400, "bad_request", "limit must be a positive number"))?,
+                ),
+                None => None,
+            };
+            let history = storage.history(&user_id)?;
+            let skip = limit.map_or(0, |limit| history.len().saturating_sub(limit));
+            let entries: Vec<Value> = history[skip..].iter().map(history_json).collect();
+            Ok(Response::json(200, json!({ "transactions": entries })))
+        }
+        ("GET", ["v1", "recipients", recipient_id]) => {
+            authenticate(storage, request)?;
+            let recipient = match AccountId::parse(recipient_id) {
+                Ok(id) if id != AccountId::house() => storage.profile(&id)?,
+                _ => None,
+            };
+            match recipient {
+                Some(user) => Ok(Response::json(200, json!({ "id": user.id, "name": user.name.unwrap_or_default() }))),
+                None => Err(TransferError::UnknownRecipient.into()),
+            }
+        }
+        ("POST", ["v1", "quotes"]) => {
+            let user_id = authenticate(storage, request)?;
+            let (recipient_id, amount) = transfer_body(request.body)?;
+            let quote = storage.check_transfer(&user_id, &recipient_id, amount)?;
+            Ok(Response::json(
+                200,
+                json!({
+                    "recipient_id": recipient_id,
+                    "amount": quote.amount,
+                    "fee": quote.fee,
+                    "total": quote.total(),
+                }),
+            ))
+        }
+        ("POST", ["v1", "transfers"]) => create_transfer(storage, request),
+        (_, ["openapi.json"]) | (_, ["metrics"]) | (_, ["v1", "sessions"]) | (_, ["v1", "balance"]) | (_, ["v1", "transactions"])
+        | (_, ["v1", "recipients", _]) | (_, ["v1", "quotes"]) | (_, ["v1", "transfers"]) => {
+            Err(ApiError::new(405, "method_not_allowed", format!("{} is not supported here", request.method)))
+        }
+        _ => Err(ApiError::new(404, "not_found", format!("No such endpoint: {}", request.path))),
+    }
+}
+
+fn create_session(storage: &dyn Storage, request: &Request) -> Result<Response, ApiError> {
+    let body = parse_body(request.body)?;
+    let user_id = string_field(&body, "user_id")?;
+    let email = string_field(&body, "email")?;
+    let password = string_field(&body, "password")?;
+    // IDs and emails that could never match get the same answer as a wrong
+    // password
+    let (Ok(user_id), Ok(email)) = (AccountId::parse(&user_id), Email::parse(&email)) else {
+        return Err(StorageError::Auth(AuthError::InvalidCredentials).into());
+    };
+    storage.login(&user_id, &email, &password)?;
+    let token = storage.create_session(&user_id)?;
+    Ok(Response::json(
+        201,
+        json!({ "token": token, "user_id": user_id, "expires_in": sessions::SESSION_TTL_SECS }),
+    ))
+}
+
+// Posting a transfer requires an Idempotency-Key. A retry with the same key
+// and body gets the original response back instead of sending the money
+// twice. Storage::transfer_once looks the key up and saves it with the
+// transfer, so two requests with one key cannot both get past the lookup.
+fn create_transfer(storage: &dyn Storage, request: &Request) -> Result<Response, ApiError> {
+    let user_id = authenticate(storage, request)?;
+    let key = request
+        .idempotency_key
+        .filter(|key| !key.trim().is_empty())
+        .ok_or_else(|| ApiError::new(400, "idempotency_key_required", "Send an Idempotency-Key header with every transfer"))?;
+    let (recipient_id, amount) = transfer_body(request.body)?;
+    let request_hash = idempotency::request_hash(&[recipient_id.as_str(), &amount.to_string()]);
+
+    // Screening reasons are never returned, only the outcome.
+    let respond = |receipt: &Receipt| {
+        let body = json!({
+            "transaction_id": receipt.transaction_id,
+            "status": receipt.status.as_str(),
+            "recipient_id": recipient_id,
+            "recipient_name": receipt.receiver.name,
+            "amount": receipt.quote.amount,
+            "fee": receipt.quote.fee,
+            "total": receipt.quote.total(),
+        });
+        (201, body.to_string())
+    };
+    let (stored, replayed) = match storage.transfer_once(&user_id, &recipient_id, amount, key, &request_hash, &respond)? {
+        Idempotent::Posted(stored) => (stored, false),
+        Idempotent::Replayed(stored) if stored.request_hash != request_hash => {
+            return Err(ApiError::new(
+                409,
+                "idempotency_key_reused",
+                "This Idempotency-Key was already used for a different transfer",
+            ));
+        }
+        Idempotent::Replayed(stored) => (stored, true),
+    };
+    Ok(Response { status: stored.status, body: stored.body, content_type: "application/json", replayed })
+}
+
+fn authenticate(storage: &dyn Storage, request: &Request) -> Result<AccountId, ApiError> {
+    let unauthorized = || ApiError::new(401, "unauthorized", "Send a valid token as `Authorization: Bearer <token>`");
+    let token = request.token.ok_or_else(unauthorized)?;
+    storage.session_user(token)?.ok_or_else(unauthorized)
+}
+
+fn parse_body(body: &str) -> Result<Value, ApiError> {
+    serde_json::from_str(body).map_err(|e| ApiError::new(400, "bad_request", format!("Body is not valid JSON: {}", e)))
+}
+
+fn string_field(body: &Value, field: &str) -> Result<String, ApiError> {
+    body.get(field)
+        .and_then(Value::as_str)
+        .map(str::to_string)
+        .ok_or_else(|| ApiError::new(400, "bad_request", format!("`{}` must be a string", field)))
+}
+
+// {"recipient_id": "...", "amount": 100}. A recipient ID that is not an
+// account ID is refused as the ledger would refuse an unknown one, after the
+// amount.
+fn transfer_body(body: &str) -> Result<(AccountId, i64), ApiError> {
+    let body = parse_body(body)?;
+    let recipient_id = string_field(&body, "recipient_id")?;
+    let amount = body
+        .get("amount")
+        .and_then(Value::as_i64)
+        .ok_or_else(|| ApiError::new(400, "bad_request", "`amount` must be a whole number"))?;
+    if amount <= 0 {
+        return Err(TransferError::InvalidAmount.into());
+    }
+    let recipient_id = AccountId::parse(&recipient_id).map_err(|_| TransferError::UnknownRecipient)?;
+    Ok((recipient_id, amount))
+}
+
+fn query_param<'a>(query: &'a str, name: &str) -> Option<&'a str> {
+    query
+        .split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .find(|(key, _)| *key == name)
+        .map(|(_, value)| value)
+}
+
+fn history_json(entry: &Transaction) -> Value {
+    json!({
+        "transaction_id": entry.id,
+        "created_at": entry.created_at,
+        "kind": entry.kind.as_str(),
+        "direction": if entry.outgoing { "out" } else { "in" },
+        "counterparty_id": entry.counterparty.id,
+        "counterparty_name": entry.counterparty.name,
+        "amount": entry.signed_amount(),
+        "status": entry.status.as_str(),
+        "memo": entry.memo,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rusqlite::{params, Connection};
+    use crate::db::init_schema;
+    use crate::pool::Pool;
+    use crate::storage::SqliteStorage;
+
+    const ALICE: &str = "900000000001";
+    const BOB: &str = "900000000002";
+
+    fn database() -> Pool {
+        let conn = Connection::open_in_memory().unwrap();
+        init_schema(&conn).unwrap();
+        conn.execute("UPDATE screening_rules SET enabled = 0 WHERE rule = 'sanctions'", []).unwrap();
+        for (user_id, name, balance) in [(ALICE, "Alice", 1000), (BOB, "Bob", 0)] {
+            conn.execute(
+                "INSERT INTO user_information (id, name, balance, created_at)
+                 VALUES (?1, ?2, ?3, CAST(strftime('%s', 'now') AS INTEGER) - 60 * 86400)",
+                params![user_id, name, balance],
+            )
+            .unwrap();
+        }
+        Pool::single(conn)
+    }
+
+    fn session(storage: &dyn Storage) -> String {
+        storage.create_session(&AccountId::parse(ALICE).unwrap()).unwrap()
+    }
+
+    fn transfer<'a>(token: &'a str, key: &'a str, body: &'a str) -> Request<'a> {
+        Request {
+            method: "POST",
+            path: "/v1/transfers",
+            token: Some(token),
+            idempotency_key: Some(key),
+            body,
+            ..Request::default()
+        }
+    }
+
+    fn to_bob(amount: i64) -> String {
+        json!({ "recipient_id": BOB, "amount": amount }).to_string()
+    }
+
+    fn error_code(response: &Response) -> String {
+        let body: Value = serde_json::from_str(&response.body).unwrap();
+        body["error"]["code"].as_str().unwrap().to_string()
+    }
+
+    fn transfers(pool: &Pool) -> i64 {
+        pool.read(|conn| {
+            conn.query_row(
+                "SELECT COUNT(*) FROM transaction_history WHERE sender_id = ?1 AND receiver_id = ?2",
+                params![ALICE, BOB],
+                |row| row.get(0),
+            )
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn requests_without_a_valid_session_are_refused() {
+        let pool = database();
+        let storage = SqliteStorage::new(&pool);
+        let balance = |token| handle(&storage, &Request { method: "GET", path: "/v1/balance", token, ..Request::default() });
+
+        for response in [balance(None), balance(Some("not-a-session"))] {
+            assert_eq!(response.status, 401);
+            assert_eq!(error_code(&response), "unauthorized");
+        }
+        let response = handle(&storage, &transfer("not-a-session", "k1", &to_bob(50)));
+        assert_eq!(response.status, 401);
+        assert_eq!(transfers(&pool), 0);
+
+        let token = session(&storage);
+        let response = balance(Some(&token));
+        assert_eq!(response.status, 200);
+        assert_eq!(serde_json::from_str::<Value>(&response.body).unwrap()["balance"], 1000);
+    }
+
+    #[test]
+    fn a_retry_with_the_same_key_is_replayed() {
+        let pool = database();
+        let storage = SqliteStorage::new(&pool);
+        let token = session(&storage);
+        let body = to_bob(50);
+
+        let first = handle(&storage, &transfer(&token, "k1", &body));
+        assert_eq!(first.status, 201, "{}", first.body);
+        assert!(!first.replayed);
+
+        let retry = handle(&storage, &transfer(&token, "k1", &body));
+        assert_eq!((retry.status, retry.body.as_str(), retry.replayed), (201, first.body.as_str(), true));
+        assert_eq!(transfers(&pool), 1);
+        assert_eq!(storage.balance(&AccountId::parse(ALICE).unwrap()).unwrap(), 1000 - 50 - 1);
+
+        // A new key is a new transfer
+        assert!(!handle(&storage, &transfer(&token, "k2", &body)).replayed);
+        assert_eq!(transfers(&pool), 2);
+    }
+
+    #[test]
+    fn reusing_a_key_for_another_transfer_is_a_conflict() {
+        let pool = database();
+        let storage = SqliteStorage::new(&pool);
+        let token = session(&storage);
+        assert_eq!(handle(&storage, &transfer(&token, "k1", &to_bob(50))).status, 201);
+
+        let response = handle(&storage, &transfer(&token, "k1", &to_bob(60)));
+        assert_eq!(response.status, 409);
+        assert_eq!(error_code(&response), "idempotency_key_reused");
+        assert_eq!(transfers(&pool), 1);
+
+        let response = handle(&storage, &transfer(&token, " ", &to_bob(60)));
+        assert_eq!((response.status, error_code(&response).as_str()), (400, "idempotency_key_required"));
+    }
+
+    #[test]
+    fn a_failed_transfer_does_not_take_the_key() {
+        let pool = database();
+        let storage = SqliteStorage::new(&pool);
+        let token = session(&storage);
+        let response = handle(&storage, &transfer(&token, "k1", &to_bob(5000)));
+        assert_eq!(response.status, 422);
+        let response = handle(&storage, &transfer(&token, "k1", &to_bob(50)));
+        assert_eq!((response.status, response.replayed), (201, false));
+    }
+
+    #[test]
+    fn recipients_must_be_account_ids() {
+        let pool = database();
+        let storage = SqliteStorage::new(&pool);
+        let token = session(&storage);
+        let recipient = |id: &str| {
+            let path = format!("/v1/recipients/{}", id);
+            let response = handle(&storage, &Request { method: "GET", path: &path, token: Some(&token), ..Request::default() });
+            (response.status, response.body)
+        };
+
+        assert_eq!(recipient(BOB).0, 200);
+        assert!(recipient(BOB).1.contains("Bob"));
+        for id in ["bob", "000000000000", "HOUSE_REVENUE"] {
+            assert_eq!(recipient(id).0, 404, "{}", id);
+        }
+        let response = handle(&storage, &transfer(&token, "k1", r#"{"recipient_id": "bob", "amount": 50}"#));
+        assert_eq!((response.status, error_code(&response).as_str()), (404, "unknown_recipient"));
+        let response = handle(&storage, &transfer(&token, "k1", r#"{"recipient_id": "bob", "amount": 0}"#));
+        assert_eq!((response.status, error_code(&response).as_str()), (422, "invalid_amount"));
+    }
+}
@@ -0,0 +1,300 @@
+use rusqlite::{params, Connection, OptionalExtension};
+use sha2::{Digest, Sha256};
+use std::fmt;
+
+// prev_hash of the very first entry
+const GENESIS_HASH: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+pub const LOGIN: &str = "login";
+pub const LOGIN_FAILED: &str = "login_failed";
+pub const SIGNUP: &str = "signup";
+pub const SANCTIONS_HIT: &str = "sanctions_hit";
+pub const REVIEW: &str = "review";
+pub const ADMIN_LIST_USERS: &str = "admin_list_users";
+pub const ADMIN_SEARCH_USERS: &str = "admin_search_users";
+pub const ADMIN_VIEW_PROFILE: &str = "admin_view_profile";
+pub const ADMIN_FREEZE: &str = "admin_freeze";
+pub const ADMIN_UNFREEZE: &str = "admin_unfreeze";
+pub const ADMIN_ADJUST_BALANCE: &str = "admin_adjust_balance";
+pub const ADMIN_RESET_LOCKOUT: &str = "admin_reset_lockout";
+pub const ADMIN_SET_ROLE: &str = "admin_set_role";
+pub const ADMIN_SET_KYC_LEVEL: &str = "admin_set_kyc_level";
+pub const ADMIN_ADD_WEBHOOK: &str = "admin_add_webhook";
+pub const ADMIN_REMOVE_WEBHOOK: &str = "admin_remove_webhook";
+pub const ADMIN_BACKUP: &str = "admin_backup";
+pub const ADMIN_RESTORE_BACKUP: &str = "admin_restore_backup";
+pub const TRANSFER: &str = "transfer";
+
+#[derive(Debug, Clone)]
+pub struct AuditEntry {
+    pub id: i64,
+    pub actor: String,
+    pub action: String,
+    pub target: String,
+    pub created_at: i64,
+    pub details: String,
+    pub prev_hash: String,
+    pub hash: String,
+}
+
+impl AuditEntry {
+    // The entry that follows `head` (id and hash of the newest entry, None
+    // for an empty log), with its hash filled in
+    pub(crate) fn after(
+        head: Option<(i64, String)>,
+        actor: &str,
+        action: &str,
+        target: &str,
+        details: &str,
+        created_at: i64,
+    ) -> AuditEntry {
+        let (id, prev_hash) = match head {
+            Some((id, hash)) => (id + 1, hash),
+            None => (1, GENESIS_HASH.to_string()),
+        };
+        let mut entry = AuditEntry {
+            id,
+            actor: actor.to_string(),
+            action: action.to_string(),
+            target: target.to_string(),
+            created_at,
+            details: details.to_string(),
+            prev_hash,
+            hash: String::new(),
+        };
+        entry.hash = entry.compute_hash();
+        entry
+    }
+
+    // SHA-256 over the previous hash and every field of the entry, each
+    // length-prefixed so no two different entries serialise the same way.
+    fn compute_hash(&self) -> String {
+        let mut hasher = Sha256::new();
+        hasher.update(self.prev_hash.as_bytes());
+        hasher.update(self.id.to_be_bytes());
+        for field in [&self.actor, &self.action, &self.target, &self.details] {
+            hasher.update((field.len() as u64).to_be_bytes());
+            hasher.update(field.as_bytes());
+        }
+        hasher.update(self.created_at.to_be_bytes());
+        hex::encode(hasher.finalize())
+    }
+}
+
+// Appends an entry chained to the current head of the log. Call it with the
+// connection or transaction that makes the change being recorded, so the
+// entry commits or rolls back together with it.
+pub fn record(conn: &Connection, actor: &str, action: &str, target: &str, details: &str) -> rusqlite::Result<()> {
+    let head: Option<(i64, String)> = conn
+        .query_row("SELECT id, hash FROM audit_log ORDER BY id DESC LIMIT 1", [], |row| {
+            Ok((row.get(0)?, row.get(1)?))
+        })
+        .optional()?;
+    let created_at: i64 = conn.query_row("SELECT CAST(strftime('%s', 'now') AS INTEGER)", [], |row| row.get(0))?;
+    let entry = AuditEntry::after(head, actor, action, target, details, created_at);
+
+    conn.execute(
+        "INSERT INTO audit_log (id, actor, action, target, created_at, details, prev_hash, hash)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+        params![
+            entry.id,
+            entry.actor,
+            entry.action,
+            entry.target,
+            entry.created_at,
+            entry.details,
+            entry.prev_hash,
+            entry.hash
+        ],
+    )?;
+    Ok(())
+}
+
+#[derive(Debug)]
+pub enum AuditError {
+    // an entry is missing before `id`
+    Gap { id: i64, expected: i64 },
+    // `id` does not point at the hash of the entry before it
+    BrokenLink { id: i64 },
+    // `id` was changed after it was written
+    Modified { id: i64 },
+    // the newest entry is not the head recorded earlier
+    HeadMismatch { expected: String, actual: String },
+    Database(rusqlite::Error),
+}
+
+impl fmt::Display for AuditError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AuditError::Gap { id, expected } => {
+                write!(f, "entry {} follows entry {}: entries were deleted", id, expected - 1)
+            }
+            AuditError::BrokenLink { id } => write!(f, "entry {} does not chain to the entry before it", id),
+            AuditError::Modified { id } => write!(f, "entry {} was modified after it was written", id),
+            AuditError::HeadMismatch { expected, actual } => {
+                write!(f, "log ends at {} but {} was expected: entries were removed from the end", actual, expected)
+            }
+            AuditError::Database(e) => write!(f, "Database error: {}", e),
+        }
+    }
+}
+
+impl From<rusqlite::Error> for AuditError {
+    fn from(e: rusqlite::Error) -> Self {
+        AuditError::Database(e)
+    }
+}
+
+#[derive(Debug)]
+pub struct Verified {
+    pub entries: i64,
+    pub head: String,
+}
+
+// Walks the whole chain from the first entry. Edits and deletions anywhere
+// but the end break it; to catch entries cut off the end, compare against a
+// head hash noted from an earlier run with `expected_head`.
+pub fn verify(conn: &Connection, expected_head: Option<&str>) -> Result<Verified, AuditError> {
+    let mut stmt = conn.prepare(
+        "SELECT id, actor, action, target, created_at, details, prev_hash, hash FROM audit_log ORDER BY id",
+    )?;
+    let entries = stmt.query_map([], |row| {
+        Ok(AuditEntry {
+            id: row.get(0)?,
+            actor: row.get(1)?,
+            action: row.get(2)?,
+            target: row.get(3)?,
+            created_at: row.get(4)?,
+            details: row.get(5)?,
+            prev_hash: row.get(6)?,
+            hash: row.get(7)?,
+        })
+    })?;
+
+    let mut expected_id = 1;
+    let mut prev_hash = GENESIS_HASH.to_string();
+    let mut head_seen = expected_head.is_none();
+    for entry in entries {
+        let entry = entry?;
+        if entry.id != expected_id {
+            return Err(AuditError::Gap { id: entry.id, expected: expected_id });
+        }
+        if entry.prev_hash != prev_hash {
+            return Err(AuditError::BrokenLink { id: entry.id });
+        }
+        if entry.compute_hash() != entry.hash {
+            return Err(AuditError::Modified { id: entry.id });
+        }
+        head_seen |= expected_head == Some(entry.hash.as_str());
+        prev_hash = entry.hash;
+        expected_id += 1;
+    }
+
+    if !head_seen {
+        return Err(AuditError::HeadMismatch {
+            expected: expected_head.unwrap_or_default().to_string(),
+            actual: prev_hash,
+        });
+    }
+    Ok(Verified { entries: expected_id - 1, head: prev_hash })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::init_schema;
+
+    // A log of five entries, with the append-only triggers dropped the way
+    // someone editing the file directly would.
+    fn tampered_log() -> Connection {
+        let conn = Connection::open_in_memory().unwrap();
+        init_schema(&conn).unwrap();
+        for i in 1..=5 {
+            record(&conn, "admin:test", ADMIN_FREEZE, &format!("user{}", i), "reason=test").unwrap();
+        }
+        conn.execute_batch("DROP TRIGGER audit_log_no_update; DROP TRIGGER audit_log_no_delete;")
+            .unwrap();
+        conn
+    }
+
+    fn hash_of(conn: &Connection, id: i64) -> String {
+        conn.query_row("SELECT hash FROM audit_log WHERE id = ?1", params![id], |row| row.get(0))
+            .unwrap()
+    }
+
+    #[test]
+    fn triggers_reject_update_and_delete() {
+        let conn = Connection::open_in_memory().unwrap();
+        init_schema(&conn).unwrap();
+        record(&conn, "admin:test", ADMIN_FREEZE, "user1", "").unwrap();
+        for sql in ["UPDATE audit_log SET actor = 'someone else'", "DELETE FROM audit_log"] {
+            let error = conn.execute(sql, []).unwrap_err();
+            assert!(error.to_string().contains("audit_log is append-only"), "{}", sql);
+        }
+        assert_eq!(verify(&conn, None).unwrap().entries, 1);
+    }
+
+    #[test]
+    fn intact_log_verifies() {
+        let conn = tampered_log();
+        let verified = verify(&conn, None).unwrap();
+        assert_eq!(verified.entries, 5);
+        assert_eq!(verified.head, hash_of(&conn, 5));
+        // a head noted earlier is still in the chain
+        assert_eq!(verify(&conn, Some(&hash_of(&conn, 3))).unwrap().entries, 5);
+
+        conn.execute("DELETE FROM audit_log", []).unwrap();
+        let empty = verify(&conn, None).unwrap();
+        assert_eq!((empty.entries, empty.head.as_str()), (0, GENESIS_HASH));
+    }
+
+    #[test]
+    fn edited_entries_are_detected() {
+        let conn = tampered_log();
+        conn.execute("UPDATE audit_log SET details = 'reason=none' WHERE id = 2", []).unwrap();
+        assert!(matches!(verify(&conn, None), Err(AuditError::Modified { id: 2 })));
+
+        // rehashing the edited entry breaks the link from the next one instead
+        let conn = tampered_log();
+        let created_at: i64 = conn
+            .query_row("SELECT created_at FROM audit_log WHERE id = 2", [], |row| row.get(0))
+            .unwrap();
+        let head = Some((1, hash_of(&conn, 1)));
+        let entry = AuditEntry::after(head, "admin:test", ADMIN_FREEZE, "user9", "reason=test", created_at);
+        conn.execute(
+            "UPDATE audit_log SET target = ?1, hash = ?2 WHERE id = 2",
+            params![entry.target, entry.hash],
+        )
+        .unwrap();
+        assert!(matches!(verify(&conn, None), Err(AuditError::BrokenLink { id: 3 })));
+    }
+
+    #[test]
+    fn deleted_entries_are_detected() {
+        let conn = tampered_log();
+        conn.execute("DELETE FROM audit_log WHERE id = 3", []).unwrap();
+        assert!(matches!(verify(&conn, None), Err(AuditError::Gap { id: 4, expected: 3 })));
+
+        let conn = tampered_log();
+        conn.execute("DELETE FROM audit_log WHERE id = 1", []).unwrap();
+        assert!(matches!(verify(&conn, None), Err(AuditError::Gap { id: 2, expected: 1 })));
+    }
+
+    #[test]
+    fn truncated_tail_needs_the_expected_head() {
+        let conn = tampered_log();
+        let head = verify(&conn, None).unwrap().head;
+        conn.execute("DELETE FROM audit_log WHERE id >= 4", []).unwrap();
+
+        // what is left is a valid chain on its own
+        let verified = verify(&conn, None).unwrap();
+        assert_eq!(verified.entries, 3);
+        match verify(&conn, Some(&head)) {
+            Err(AuditError::HeadMismatch { expected, actual }) => {
+                assert_eq!(expected, head);
+                assert_eq!(actual, verified.head);
+            }
+            other => panic!("expected a head mismatch, got {:?}", other),
+        }
+    }
+}
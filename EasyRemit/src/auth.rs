@@ -0,0 +1,328 @@
+use aes::Aes128;
+use argon2::{Argon2, PasswordHasher, PasswordVerifier, password_hash::{PasswordHash, SaltString}};
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine as _};
+use block_modes::{BlockMode, Cbc};
+use block_modes::block_padding::Pkcs7;
+use rand::thread_rng;
+use rusqlite::{params, Connection, OptionalExtension};
+use std::fmt;
+use std::time::Instant;
+use tracing::{error, info, info_span, warn};
+use crate::audit;
+use crate::config;
+use crate::logging;
+use crate::metrics::{LoginFailure, METRICS};
+use crate::model::{AccountId, BirthDate, Email, PassportNumber};
+use crate::notifications::{self, NotificationKind};
+use crate::sanctions;
+
+type Aes128Cbc = Cbc<Aes128, Pkcs7>;
+
+#[derive(Debug)]
+pub enum AuthError {
+    InvalidCredentials,
+    LockedOut { minutes: i64 },
+    Hashing(String),
+    Encryption(String),
+    Database(rusqlite::Error),
+}
+
+impl fmt::Display for AuthError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AuthError::InvalidCredentials => write!(f, "Email, user ID or password is incorrect"),
+            AuthError::LockedOut { minutes } => {
+                write!(f, "Too many failed logins. Try again in {} minute(s)", minutes)
+            }
+            AuthError::Hashing(e) => write!(f, "Could not hash password: {}", e),
+            AuthError::Encryption(e) => write!(f, "Could not encrypt profile: {}", e),
+            AuthError::Database(e) => write!(f, "Database error: {}", e),
+        }
+    }
+}
+
+impl From<rusqlite::Error> for AuthError {
+    fn from(e: rusqlite::Error) -> Self {
+        AuthError::Database(e)
+    }
+}
+
+// Consecutive failed logins that lock an account, and for how long
+pub const MAX_FAILED_LOGINS: i64 = 5;
+pub const LOCKOUT_SECS: i64 = 15 * 60;
+
+// Counterparty of the welcome bonus in transaction history. It has no
+// account row.
+pub const BONUS_ACCOUNT: &str = "New User's Privilege";
+
+// What the user is told after each login
+pub(crate) const NEW_LOGIN_MESSAGE: &str = "You logged in on this device. If this wasn't you, change your password.";
+
+// Checks the credentials typed on the Login page and records the login in the
+// user's inbox, so a login they did not make stands out. Too many failures in
+// a row lock the account for a while.
+pub fn login(conn: &Connection, user_id: &str, email: &str, password: &str) -> Result<(), AuthError> {
+    let span = info_span!("login", correlation_id = %logging::correlation_id(), user_id);
+    let _entered = span.enter();
+    let result = check_login(conn, user_id, email, password);
+    log_login(&result);
+    result
+}
+
+// What became of a login, in the login's span, and counted in the metrics
+fn log_login(result: &Result<(), AuthError>) {
+    match result {
+        Ok(()) => {
+            info!("logged in");
+            METRICS.logged_in();
+        }
+        Err(AuthError::InvalidCredentials) => {
+            warn!("login failed");
+            METRICS.login_failed(LoginFailure::InvalidCredentials);
+        }
+        Err(AuthError::LockedOut { minutes }) => {
+            warn!(minutes, "login refused, account locked");
+            METRICS.login_failed(LoginFailure::LockedOut);
+        }
+        Err(e) => {
+            error!(error = %e, "login failed");
+            METRICS.login_failed(LoginFailure::Error);
+        }
+    }
+}
+
+// Signs a user in to a command-line tool that asks for a user ID and
+// password, such as the review queue. Failures count towards the same lockout
+// as the Login page. `permitted` says whether the account may use the tool;
+// one that may not is refused exactly as a wrong password is, so the tool
+// never confirms a password to someone without the role.
+pub fn tool_login(conn: &Connection, tool: &str, user_id: &str, password: &str, permitted: bool) -> Result<(), AuthError> {
+    let span = info_span!("login", correlation_id = %logging::correlation_id(), user_id, tool);
+    let _entered = span.enter();
+    let result = check_password(conn, user_id, None, password, permitted, &format!("tool={}", tool), |_| Ok(()));
+    log_login(&result);
+    result
+}
+
+fn check_login(conn: &Connection, user_id: &str, email: &str, password: &str) -> Result<(), AuthError> {
+    check_password(conn, user_id, Some(email), password, true, &format!("email={}", email), |tx| {
+        notifications::notify(tx, user_id, NotificationKind::NewLogin, NEW_LOGIN_MESSAGE)
+    })
+}
+
+// The lockout path every login goes through. Each attempt is audited with
+// `detail`; a success resets the failure count and runs `on_success` in the
+// same transaction.
+fn check_password(
+    conn: &Connection,
+    user_id: &str,
+    email: Option<&str>,
+    password: &str,
+    permitted: bool,
+    detail: &str,
+    on_success: impl FnOnce(&Connection) -> rusqlite::Result<()>,
+) -> Result<(), AuthError> {
+    let account: Option<(Option<String>, String, Option<i64>)> = conn
+        .query_row(
+            "SELECT email, hashed_password, locked_until - CAST(strftime('%s', 'now') AS INTEGER)
+             FROM user_information WHERE id = ?1",
+            params![user_id],
+            |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+        )
+        .optional()?;
+    let locked_for = account.as_ref().and_then(|(_, _, remaining)| *remaining).filter(|secs| *secs > 0);
+    if let Some(remaining) = locked_for {
+        audit::record(conn, user_id, audit::LOGIN_FAILED, user_id, &format!("{} locked", detail))?;
+        return Err(AuthError::LockedOut { minutes: (remaining + 59) / 60 });
+    }
+    // The password is checked even when the account is not permitted, so the
+    // refusal takes as long as a wrong password does
+    let verified = account.is_some_and(|(stored_email, hash, _)| {
+        email.is_none_or(|email| stored_email.as_deref() == Some(email)) && verify_password(password, &hash)
+    });
+    if !verified || !permitted {
+        let tx = conn.unchecked_transaction()?;
+        tx.execute(
+            "UPDATE user_information SET failed_logins = failed_logins + 1,
+                 locked_until = CASE WHEN failed_logins + 1 >= ?2
+                     THEN CAST(strftime('%s', 'now') AS INTEGER) + ?3 ELSE locked_until END
+             WHERE id = ?1",
+            params![user_id, MAX_FAILED_LOGINS, LOCKOUT_SECS],
+        )?;
+        let detail = if verified { format!("{} not permitted", detail) } else { detail.to_string() };
+        audit::record(&tx, user_id, audit::LOGIN_FAILED, user_id, &detail)?;
+        tx.commit()?;
+        return Err(AuthError::InvalidCredentials);
+    }
+
+    let tx = conn.unchecked_transaction()?;
+    tx.execute(
+        "UPDATE user_information SET failed_logins = 0, locked_until = NULL WHERE id = ?1",
+        params![user_id],
+    )?;
+    audit::record(&tx, user_id, audit::LOGIN, user_id, if email.is_some() { "" } else { detail })?;
+    on_success(&tx)?;
+    tx.commit()?;
+    Ok(())
+}
+
+// Details entered on the Signup page. The password has been checked
+// against `validation.password` by the page.
+pub struct NewAccount<'a> {
+    pub email: &'a Email,
+    pub name: &'a str,
+    pub passport: &'a PassportNumber,
+    pub birth_date: &'a BirthDate,
+    pub password: &'a str,
+}
+
+// Creates the account with its welcome bonus and returns the new user ID.
+// Names that match the sanctions list still get an account, but it is opened
+// frozen and the matches wait for manual review.
+pub fn register(conn: &Connection, account: &NewAccount) -> Result<AccountId, AuthError> {
+    let user_id = AccountId::random(account.birth_date);
+    let hashed_password = hash_password(account.password)?;
+    let encrypted_passport = encrypt(account.passport.as_str())?;
+    let encrypted_birth_date = encrypt(account.birth_date.as_str())?;
+
+    let matches = sanctions::screen(conn, account.name, Some(account.birth_date.as_str()))?;
+    let frozen = !matches.is_empty();
+
+    let tx = conn.unchecked_transaction()?;
+    tx.execute(
+        "INSERT INTO user_information (id, email, name, encrypted_passport, encrypted_birthdate, hashed_password, balance, created_at, frozen)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, strftime('%s', 'now'), ?8)",
+        params![user_id, account.email, account.name, encrypted_passport, encrypted_birth_date, hashed_password, config::get().accounts.signup_bonus, frozen],
+    )?;
+    tx.execute(
+        "INSERT INTO transaction_history (sender_id, amount, receiver_id, created_at) VALUES (?1, ?2, ?3, strftime('%s', 'now'))",
+        params![BONUS_ACCOUNT, config::get().accounts.signup_bonus, user_id],
+    )?;
+    audit::record(&tx, user_id.as_str(), audit::SIGNUP, user_id.as_str(), &format!("email={}", account.email))?;
+    if frozen {
+        sanctions::record(&tx, user_id.as_str(), sanctions::SIGNUP, None, &matches)?;
+        audit::record(
+            &tx,
+            user_id.as_str(),
+            audit::SANCTIONS_HIT,
+            user_id.as_str(),
+            &format!("context={} matches={} frozen=1", sanctions::SIGNUP, matches.len()),
+        )?;
+    }
+    tx.commit()?;
+    Ok(user_id)
+}
+
+// The user's birth date as entered at signup. None when it cannot be
+// decrypted, e.g. for rows written before encryption was in place.
+pub fn birth_date_of(conn: &Connection, user_id: &str) -> rusqlite::Result<Option<String>> {
+    let encrypted: Option<String> = conn
+        .query_row(
+            "SELECT encrypted_birthdate FROM user_information WHERE id = ?1",
+            params![user_id],
+            |row| row.get(0),
+        )
+        .optional()?
+        .flatten();
+    Ok(encrypted.and_then(|data| decrypt(&data)))
+}
+
+// With the key and IV from the `encryption` section of the config
+fn cipher() -> Result<Aes128Cbc, block_modes::InvalidKeyIvLength> {
+    cipher_with(&config::get().encryption.key)
+}
+
+// With `key` and the configured IV
+fn cipher_with(key: &str) -> Result<Aes128Cbc, block_modes::InvalidKeyIvLength> {
+    Aes128Cbc::new_from_slices(key.as_bytes(), config::get().encryption.iv.as_bytes())
+}
+
+pub(crate) fn encrypt(data: &str) -> Result<String, AuthError> {
+    let cipher = cipher().map_err(|e| AuthError::Encryption(e.to_string()))?;
+    Ok(BASE64.encode(cipher.encrypt_vec(data.as_bytes())))
+}
+
+// None if the stored value is not valid base64 or does not decrypt
+pub fn decrypt(data: &str) -> Option<String> {
+    decrypt_with(&config::get().encryption.key, data)
+}
+
+// As decrypt, with a key other than the configured one, such as the one an
+// operator gives easyremit-admin
+pub fn decrypt_with(key: &str, data: &str) -> Option<String> {
+    let cipher = cipher_with(key).ok()?;
+    let bytes = cipher.decrypt_vec(&BASE64.decode(data).ok()?).ok()?;
+    String::from_utf8(bytes).ok()
+}
+
+// An Argon2 hash of `password` with a fresh salt, in PHC string format
+pub(crate) fn hash_password(password: &str) -> Result<String, AuthError> {
+    let salt = SaltString::generate(&mut thread_rng());
+    Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .map(|hash| hash.to_string())
+        .map_err(|e| AuthError::Hashing(e.to_string()))
+}
+
+// Timed for the metrics: Argon2 is meant to be slow, but not too slow
+pub(crate) fn verify_password(password: &str, hash: &str) -> bool {
+    let started = Instant::now();
+    let verified = match PasswordHash::new(hash) {
+        Ok(parsed_hash) => Argon2::default().verify_password(password.as_bytes(), &parsed_hash).is_ok(),
+        Err(_) => false,
+    };
+    METRICS.password_verified(started.elapsed());
+    verified
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::init_schema;
+
+    fn database() -> Connection {
+        let conn = Connection::open_in_memory().unwrap();
+        init_schema(&conn).unwrap();
+        for (id, role) in [("rev", "reviewer"), ("bob", "customer")] {
+            conn.execute(
+                "INSERT INTO user_information (id, email, hashed_password, balance, role) VALUES (?1, ?1 || '@x', ?2, 0, ?3)",
+                params![id, hash_password("correct horse").unwrap(), role],
+            )
+            .unwrap();
+        }
+        conn
+    }
+
+    fn failed_logins(conn: &Connection, user_id: &str) -> i64 {
+        conn.query_row("SELECT failed_logins FROM user_information WHERE id = ?1", params![user_id], |row| row.get(0))
+            .unwrap()
+    }
+
+    #[test]
+    fn tool_logins_share_the_lockout() {
+        let conn = database();
+        tool_login(&conn, "review", "rev", "correct horse", true).unwrap();
+
+        // a right password without the role is refused like a wrong one
+        let attempts = [("bob", "correct horse", false), ("bob", "wrong", false), ("nobody", "correct horse", true)];
+        for (user_id, password, permitted) in attempts {
+            let result = tool_login(&conn, "review", user_id, password, permitted);
+            assert!(matches!(result, Err(AuthError::InvalidCredentials)), "{}", user_id);
+        }
+        assert_eq!(failed_logins(&conn, "bob"), 2);
+
+        for _ in 0..MAX_FAILED_LOGINS {
+            assert!(tool_login(&conn, "review", "rev", "wrong", true).is_err());
+        }
+        assert!(matches!(tool_login(&conn, "review", "rev", "correct horse", true), Err(AuthError::LockedOut { .. })));
+        assert!(matches!(login(&conn, "rev", "rev@x", "correct horse"), Err(AuthError::LockedOut { .. })));
+        let refusals: i64 = conn
+            .query_row(
+                "SELECT COUNT(*) FROM audit_log WHERE action = ?1 AND actor = 'rev' AND details LIKE 'tool=review%'",
+                params![audit::LOGIN_FAILED],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(refusals, MAX_FAILED_LOGINS + 1);
+    }
+}
@@ -0,0 +1,589 @@
+use aes::Aes128;
+use argon2::Argon2;
+use block_modes::block_padding::Pkcs7;
+use block_modes::{BlockMode, Cbc};
+use flate2::read::DeflateDecoder;
+use flate2::write::DeflateEncoder;
+use flate2::Compression;
+use hmac::{Hmac, Mac};
+use rand::{thread_rng, RngCore};
+use rusqlite::backup::Backup;
+use rusqlite::{Connection, OpenFlags, OptionalExtension};
+use sha2::Sha256;
+use std::cmp::Reverse;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::{error, info, warn};
+use crate::audit;
+use crate::config::BackupConfig;
+use crate::db;
+
+// Backups of the SQLite database. A snapshot is taken with SQLite's online
+// backup API, so the app and the API keep working while it runs, then
+// compressed, encrypted and signed with keys derived from `backup.key` by
+// Argon2, which makes guessing the key from a stolen backup slow. A backup
+// file is laid out as:
+//   "ERBACKUP", format version (1 byte), taken at (unix seconds, 8 bytes LE),
+//   Argon2 salt (16 bytes), AES IV (16 bytes),
+//   AES-128-CBC of the deflated database file,
+//   HMAC-SHA256 of everything before it (32 bytes)
+// There is no checksum of the database itself: the signature already covers
+// the file, and a hash of the plaintext would let anyone check a guess at it.
+
+type Aes128Cbc = Cbc<Aes128, Pkcs7>;
+
+const MAGIC: &[u8; 8] = b"ERBACKUP";
+const VERSION: u8 = 2;
+const EXTENSION: &str = "backup";
+// magic, version and time, which `list` reads without the key
+const PREFIX_LEN: usize = 17;
+const SALT_LEN: usize = 16;
+const HEADER_LEN: usize = PREFIX_LEN + SALT_LEN + 16;
+const MAC_LEN: usize = 32;
+
+// Pages copied per step of a snapshot, and the pause after each step that
+// lets writers in
+const PAGES_PER_STEP: i32 = 256;
+const STEP_PAUSE: Duration = Duration::from_millis(10);
+
+#[derive(Debug)]
+pub enum BackupError {
+    NoKey,
+    NotFound(String),
+    NotABackup(PathBuf),
+    // the signature does not match
+    Tampered(PathBuf),
+    // decrypted fine but is not a database this build can use
+    Invalid(PathBuf, String),
+    Io(PathBuf, io::Error),
+    Database(rusqlite::Error),
+}
+
+impl fmt::Display for BackupError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BackupError::NoKey => write!(f, "backup.key is not set; backups cannot be encrypted without it"),
+            BackupError::NotFound(what) => write!(f, "No backup {}", what),
+            BackupError::NotABackup(path) => write!(f, "{} is not an EasyRemit backup", path.display()),
+            BackupError::Tampered(path) => write!(
+                f,
+                "{} was changed after it was written, or was made with a different backup.key",
+                path.display()
+            ),
+            BackupError::Invalid(path, why) => write!(f, "{} cannot be restored: {}", path.display(), why),
+            BackupError::Io(path, e) => write!(f, "{}: {}", path.display(), e),
+            BackupError::Database(e) => write!(f, "Database error: {}", e),
+        }
+    }
+}
+
+impl From<rusqlite::Error> for BackupError {
+    fn from(e: rusqlite::Error) -> Self {
+        BackupError::Database(e)
+    }
+}
+
+fn io_error(path: &Path) -> impl FnOnce(io::Error) -> BackupError + '_ {
+    move |e| BackupError::Io(path.to_path_buf(), e)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BackupInfo {
+    pub path: PathBuf,
+    // unix seconds
+    pub created_at: i64,
+    pub size: u64,
+}
+
+#[derive(Debug, Clone)]
+pub struct Restored {
+    pub backup: BackupInfo,
+    // the database as it was before the restore, if there was one
+    pub previous: Option<BackupInfo>,
+}
+
+// Takes a backup of the database at `db_path` into `config.directory` now
+pub fn create(db_path: &Path, config: &BackupConfig) -> Result<BackupInfo, BackupError> {
+    if config.key.is_empty() {
+        return Err(BackupError::NoKey);
+    }
+    let directory = &config.directory;
+    fs::create_dir_all(directory).map_err(io_error(directory))?;
+    let created_at = unix_now();
+    let partial = directory.join(format!(".{}.partial", created_at));
+    let copied = snapshot(db_path, &partial, created_at);
+    let contents = copied.and_then(|name| Ok((name, fs::read(&partial).map_err(io_error(&partial))?)));
+    remove_database(&partial);
+    let (stamp, contents) = contents?;
+    let file = seal(&contents, created_at, &config.key)?;
+
+    let stem = db_path.file_stem().and_then(|stem| stem.to_str()).unwrap_or("bank");
+    let mut path = directory.join(format!("{}-{}.{}", stem, stamp, EXTENSION));
+    for n in 2.. {
+        if !path.exists() {
+            break;
+        }
+        path = directory.join(format!("{}-{}-{}.{}", stem, stamp, n, EXTENSION));
+    }
+    // written under another name first, so a half-written backup is never
+    // listed
+    fs::write(&partial, &file).map_err(io_error(&partial))?;
+    fs::rename(&partial, &path).map_err(io_error(&path))?;
+    Ok(BackupInfo { path, created_at, size: file.len() as u64 })
+}
+
+// Copies the database at `db_path` to a new file `copy` a few pages at a
+// time, and returns `created_at` as UTC time for the backup's file name
+fn snapshot(db_path: &Path, copy: &Path, created_at: i64) -> Result<String, BackupError> {
+    if !db_path.exists() {
+        return Err(BackupError::Io(db_path.to_path_buf(), io::ErrorKind::NotFound.into()));
+    }
+    let source = Connection::open_with_flags(db_path, OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+    source.busy_timeout(Duration::from_secs(5))?;
+    let mut target = Connection::open(copy)?;
+    Backup::new(&source, &mut target)?.run_to_completion(PAGES_PER_STEP, STEP_PAUSE, None)?;
+    Ok(target.query_row("SELECT strftime('%Y%m%d-%H%M%S', ?1, 'unixepoch')", [created_at], |row| row.get(0))?)
+}
+
+// Backups in `directory`, newest first. Other files are left out.
+pub fn list(directory: &Path) -> Result<Vec<BackupInfo>, BackupError> {
+    let entries = match fs::read_dir(directory) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(BackupError::Io(directory.to_path_buf(), e)),
+    };
+    let mut backups = Vec::new();
+    for entry in entries {
+        let path = entry.map_err(io_error(directory))?.path();
+        if path.extension().and_then(|extension| extension.to_str()) != Some(EXTENSION) {
+            continue;
+        }
+        let mut prefix = [0; PREFIX_LEN];
+        let mut file = File::open(&path).map_err(io_error(&path))?;
+        let size = file.metadata().map_err(io_error(&path))?.len();
+        match file.read_exact(&mut prefix).ok().and_then(|()| taken_at(&prefix)) {
+            Some(created_at) => backups.push(BackupInfo { path, created_at, size }),
+            None => warn!(path = %path.display(), "not a backup, skipped"),
+        }
+    }
+    backups.sort_by_key(|backup| Reverse((backup.created_at, backup.path.clone())));
+    Ok(backups)
+}
+
+// The backup named by `what`: a file, or a UTC time such as "2026-10-19
+// 08:30", which picks the newest backup taken at or before it
+pub fn find(config: &BackupConfig, what: &str) -> Result<PathBuf, BackupError> {
+    let path = Path::new(what);
+    if path.is_file() {
+        return Ok(path.to_path_buf());
+    }
+    let time: Option<i64> = Connection::open_in_memory()?.query_row(
+        "SELECT CAST(strftime('%s', ?1) AS INTEGER)",
+        [what],
+        |row| row.get(0),
+    )?;
+    let Some(time) = time else {
+        return Err(BackupError::NotFound(format!("file {}", what)));
+    };
+    list(&config.directory)?
+        .into_iter()
+        .find(|backup| backup.created_at <= time)
+        .map(|backup| backup.path)
+        .ok_or_else(|| BackupError::NotFound(format!("taken at or before {}", what)))
+}
+
+// Deletes the backups the retention settings no longer keep and returns them
+pub fn prune(config: &BackupConfig) -> Result<Vec<BackupInfo>, BackupError> {
+    let backups = list(&config.directory)?;
+    let times: Vec<i64> = backups.iter().map(|backup| backup.created_at).collect();
+    let mut removed = Vec::new();
+    for (backup, keep) in backups.into_iter().zip(retained(&times, config)) {
+        if !keep {
+            fs::remove_file(&backup.path).map_err(io_error(&backup.path))?;
+            removed.push(backup);
+        }
+    }
+    Ok(removed)
+}
+
+// Which of the backups taken at `times` (newest first) to keep: the newest
+// `keep_last`, and the newest of each of the most recent `keep_daily` days
+// and `keep_weekly` weeks that have one. Days are UTC, weeks start on Monday.
+fn retained(times: &[i64], config: &BackupConfig) -> Vec<bool> {
+    let mut keep: Vec<bool> = (0..times.len()).map(|i| i < config.keep_last).collect();
+    // 1970-01-01 was a Thursday
+    for (length, offset, count) in [(86_400, 0, config.keep_daily), (7 * 86_400, 3 * 86_400, config.keep_weekly)] {
+        let mut periods = Vec::new();
+        for (i, time) in times.iter().enumerate() {
+            let period = (time + offset).div_euclid(length);
+            if periods.len() < count && !periods.contains(&period) {
+                periods.push(period);
+                keep[i] = true;
+            }
+        }
+    }
+    keep
+}
+
+// Checks a backup without touching the live database: its signature, then
+// SQLite's integrity check, the schema version and the audit
+// log chain of the database in it
+pub fn verify(path: &Path, config: &BackupConfig) -> Result<BackupInfo, BackupError> {
+    let staging = staging_path(path);
+    let verified = stage(path, config, &staging);
+    remove_database(&staging);
+    verified
+}
+
+// Replaces the database at `db_path` with the backup at `path` once it has
+// passed `verify`. The database as it was is backed up first, so a restore
+// can be undone. The copy is made with the online backup API, so connections
+// that are already open see the restored data, but transfers made while it
+// runs are lost: stop the app and easyremit-api first.
+pub fn restore(path: &Path, db_path: &Path, config: &BackupConfig) -> Result<Restored, BackupError> {
+    let staging = staging_path(db_path);
+    let restored = stage(path, config, &staging).and_then(|backup| {
+        let previous = if db_path.exists() { Some(create(db_path, config)?) } else { None };
+        let source = Connection::open_with_flags(&staging, OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+        let mut live = Connection::open(db_path)?;
+        live.busy_timeout(Duration::from_secs(5))?;
+        // all at once, so nothing is written between the steps
+        Backup::new(&source, &mut live)?.run_to_completion(i32::MAX, Duration::ZERO, None)?;
+        Ok(Restored { backup, previous })
+    });
+    remove_database(&staging);
+    restored
+}
+
+// Decrypts the backup at `path` to `staging` and checks the database there
+fn stage(path: &Path, config: &BackupConfig, staging: &Path) -> Result<BackupInfo, BackupError> {
+    let file = fs::read(path).map_err(io_error(path))?;
+    let (created_at, contents) = open(path, &file, &config.key)?;
+    remove_database(staging);
+    fs::write(staging, &contents).map_err(io_error(staging))?;
+    let invalid = |why: String| BackupError::Invalid(path.to_path_buf(), why);
+
+    let conn = Connection::open(staging)?;
+    let integrity: String = conn
+        .query_row("PRAGMA integrity_check", [], |row| row.get(0))
+        .map_err(|e| invalid(e.to_string()))?;
+    if integrity != "ok" {
+        return Err(invalid(format!("integrity check failed: {}", integrity)));
+    }
+    let version: usize = conn.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+    if version > db::SCHEMA_VERSION {
+        return Err(invalid(format!("it was made by a newer EasyRemit (schema version {})", version)));
+    }
+    let has_audit_log: Option<String> = conn
+        .query_row("SELECT name FROM sqlite_master WHERE type = 'table' AND name = 'audit_log'", [], |row| row.get(0))
+        .optional()?;
+    if has_audit_log.is_some() {
+        audit::verify(&conn, None).map_err(|e| invalid(format!("audit log: {}", e)))?;
+    }
+    Ok(BackupInfo { path: path.to_path_buf(), created_at, size: file.len() as u64 })
+}
+
+fn staging_path(path: &Path) -> PathBuf {
+    let mut staging = path.as_os_str().to_owned();
+    staging.push(".restore");
+    PathBuf::from(staging)
+}
+
+// A database file and whatever WAL files SQLite left next to it
+fn remove_database(path: &Path) {
+    for suffix in ["", "-wal", "-shm", "-journal"] {
+        let mut file = path.as_os_str().to_owned();
+        file.push(suffix);
+        let _ = fs::remove_file(file);
+    }
+}
+
+// Takes a backup every `backup.interval_mins` and prunes the old ones, for as
+// long as the process runs. Does nothing without an interval or a key.
+pub fn spawn_schedule(db_path: PathBuf, config: &'static BackupConfig) {
+    if config.interval_mins == 0 {
+        return;
+    }
+    if config.key.is_empty() {
+        warn!("backup.key is not set, scheduled backups are off");
+        return;
+    }
+    let interval = Duration::from_secs(config.interval_mins * 60);
+    std::thread::spawn(move || loop {
+        std::thread::sleep(interval);
+        match create(&db_path, config).and_then(|backup| Ok((backup, prune(config)?))) {
+            Ok((backup, removed)) => info!(
+                path = %backup.path.display(),
+                size = backup.size,
+                removed = removed.len(),
+                "backup taken"
+            ),
+            Err(e) => error!(error = %e, "scheduled backup failed"),
+        }
+    });
+}
+
+// Keys for encrypting and for signing, derived together from `backup.key`
+// and the backup's own salt
+fn keys(key: &str, salt: &[u8]) -> Result<([u8; 16], [u8; 32]), BackupError> {
+    if key.is_empty() {
+        return Err(BackupError::NoKey);
+    }
+    let mut derived = [0; 48];
+    Argon2::default()
+        .hash_password_into(key.as_bytes(), salt, &mut derived)
+        .expect("Argon2 accepts a 16-byte salt and 48-byte output");
+    let mut cipher_key = [0; 16];
+    cipher_key.copy_from_slice(&derived[..16]);
+    let mut mac_key = [0; 32];
+    mac_key.copy_from_slice(&derived[16..]);
+    Ok((cipher_key, mac_key))
+}
+
+fn signature(mac_key: &[u8], signed: &[u8]) -> Hmac<Sha256> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(mac_key).expect("HMAC accepts keys of any length");
+    mac.update(signed);
+    mac
+}
+
+// The backup file for the database file `contents`
+fn seal(contents: &[u8], created_at: i64, key: &str) -> Result<Vec<u8>, BackupError> {
+    let mut salt = [0; SALT_LEN];
+    thread_rng().fill_bytes(&mut salt);
+    let (cipher_key, mac_key) = keys(key, &salt)?;
+    let mut encoder = DeflateEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(contents).expect("compressing in memory");
+    let compressed = encoder.finish().expect("compressing in memory");
+    let mut iv = [0; 16];
+    thread_rng().fill_bytes(&mut iv);
+    let cipher = Aes128Cbc::new_from_slices(&cipher_key, &iv).expect("16-byte key and IV");
+
+    let mut file = Vec::with_capacity(HEADER_LEN + compressed.len() + 16 + MAC_LEN);
+    file.extend_from_slice(MAGIC);
+    file.push(VERSION);
+    file.extend_from_slice(&created_at.to_le_bytes());
+    file.extend_from_slice(&salt);
+    file.extend_from_slice(&iv);
+    file.extend_from_slice(&cipher.encrypt_vec(&compressed));
+    let mac = signature(&mac_key, &file).finalize().into_bytes();
+    file.extend_from_slice(&mac);
+    Ok(file)
+}
+
+// The time and database file in a backup file, once its signature checks
+// out
+fn open(path: &Path, file: &[u8], key: &str) -> Result<(i64, Vec<u8>), BackupError> {
+    if key.is_empty() {
+        return Err(BackupError::NoKey);
+    }
+    let created_at = Some(file)
+        .filter(|file| file.len() >= HEADER_LEN + MAC_LEN)
+        .and_then(|file| taken_at(&file[..PREFIX_LEN]))
+        .ok_or_else(|| BackupError::NotABackup(path.to_path_buf()))?;
+    let (cipher_key, mac_key) = keys(key, &file[PREFIX_LEN..PREFIX_LEN + SALT_LEN])?;
+    let (signed, mac) = file.split_at(file.len() - MAC_LEN);
+    signature(&mac_key, signed)
+        .verify_slice(mac)
+        .map_err(|_| BackupError::Tampered(path.to_path_buf()))?;
+
+    let invalid = |why: String| BackupError::Invalid(path.to_path_buf(), why);
+    let iv = &signed[PREFIX_LEN + SALT_LEN..HEADER_LEN];
+    let cipher = Aes128Cbc::new_from_slices(&cipher_key, iv).expect("16-byte key and IV");
+    let compressed = cipher.decrypt_vec(&signed[HEADER_LEN..]).map_err(|e| invalid(e.to_string()))?;
+    let mut contents = Vec::new();
+    DeflateDecoder::new(compressed.as_slice())
+        .read_to_end(&mut contents)
+        .map_err(|e| invalid(e.to_string()))?;
+    Ok((created_at, contents))
+}
+
+// The time in the first PREFIX_LEN bytes of a backup file
+fn taken_at(prefix: &[u8]) -> Option<i64> {
+    if &prefix[..MAGIC.len()] != MAGIC || prefix[MAGIC.len()] != VERSION {
+        return None;
+    }
+    Some(i64::from_le_bytes(prefix[MAGIC.len() + 1..PREFIX_LEN].try_into().ok()?))
+}
+
+fn unix_now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs() as i64)
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rusqlite::params;
+    use sha2::Digest;
+
+    // A directory of its own under the system temp directory, removed on drop
+    struct Scratch(PathBuf);
+
+    impl Scratch {
+        fn new() -> Scratch {
+            let path = std::env::temp_dir().join(format!("easyremit-backup-{:016x}", thread_rng().next_u64()));
+            fs::create_dir_all(&path).unwrap();
+            Scratch(path)
+        }
+    }
+
+    impl Drop for Scratch {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn config(scratch: &Scratch) -> BackupConfig {
+        BackupConfig {
+            directory: scratch.0.join("backups"),
+            key: "a backup key for tests".to_string(),
+            ..BackupConfig::default()
+        }
+    }
+
+    fn balance(db_path: &Path) -> i64 {
+        let conn = Connection::open(db_path).unwrap();
+        conn.query_row("SELECT balance FROM user_information WHERE id = '960008484537'", [], |row| row.get(0))
+            .unwrap()
+    }
+
+    #[test]
+    fn restores_the_database_as_it_was() {
+        let scratch = Scratch::new();
+        let config = config(&scratch);
+        let db_path = scratch.0.join("bank.db");
+        let conn = db::open(&db_path).unwrap();
+        conn.execute("INSERT INTO user_information (id, balance) VALUES ('960008484537', 100)", []).unwrap();
+        audit::record(&conn, "test", audit::SIGNUP, "960008484537", "").unwrap();
+
+        let backup = create(&db_path, &config).unwrap();
+        conn.execute("UPDATE user_information SET balance = 5 WHERE id = '960008484537'", []).unwrap();
+        assert_eq!(list(&config.directory).unwrap(), vec![backup.clone()]);
+        assert_eq!(verify(&backup.path, &config).unwrap().created_at, backup.created_at);
+
+        let restored = restore(&backup.path, &db_path, &config).unwrap();
+        assert_eq!(balance(&db_path), 100);
+        // the open connection sees it too
+        let seen: i64 = conn
+            .query_row("SELECT balance FROM user_information WHERE id = '960008484537'", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(seen, 100);
+        // and the database from before the restore was kept
+        let previous = restored.previous.unwrap();
+        restore(&previous.path, &db_path, &config).unwrap();
+        assert_eq!(balance(&db_path), 5);
+        assert!(!staging_path(&db_path).exists());
+    }
+
+    #[test]
+    fn damaged_or_foreign_backups_are_refused() {
+        let scratch = Scratch::new();
+        let config = config(&scratch);
+        let db_path = scratch.0.join("bank.db");
+        db::open(&db_path).unwrap();
+        let backup = create(&db_path, &config).unwrap();
+
+        let wrong_key = BackupConfig { key: "some other backup key".to_string(), ..config.clone() };
+        assert!(matches!(verify(&backup.path, &wrong_key), Err(BackupError::Tampered(_))));
+        let mut file = fs::read(&backup.path).unwrap();
+        let middle = file.len() / 2;
+        file[middle] ^= 1;
+        fs::write(&backup.path, &file).unwrap();
+        assert!(matches!(verify(&backup.path, &config), Err(BackupError::Tampered(_))));
+        assert!(matches!(restore(&backup.path, &db_path, &config), Err(BackupError::Tampered(_))));
+
+        // signed with the right key but holding something that is not a database
+        let garbage = seal(b"not a database", 0, &config.key).unwrap();
+        let path = scratch.0.join("garbage.backup");
+        fs::write(&path, garbage).unwrap();
+        assert!(matches!(verify(&path, &config), Err(BackupError::Invalid(..))));
+        fs::write(&path, b"hello").unwrap();
+        assert!(matches!(verify(&path, &config), Err(BackupError::NotABackup(_))));
+
+        let no_key = BackupConfig { key: String::new(), ..config };
+        assert!(matches!(create(&db_path, &no_key), Err(BackupError::NoKey)));
+    }
+
+    #[test]
+    fn every_backup_has_its_own_salt() {
+        let key = "a backup key for tests";
+        let contents = b"the same database twice";
+        let (first, second) = (seal(contents, 0, key).unwrap(), seal(contents, 0, key).unwrap());
+        let salt = |file: &[u8]| file[PREFIX_LEN..PREFIX_LEN + SALT_LEN].to_vec();
+        assert_ne!(salt(&first), salt(&second));
+        for file in [&first, &second] {
+            assert_eq!(open(Path::new("test.backup"), file, key).unwrap(), (0, contents.to_vec()));
+            // nothing in the file can be checked against a guess without the key
+            let digest = sha2::Sha256::digest(contents);
+            assert!(!file.windows(digest.len()).any(|window| window == digest.as_slice()));
+        }
+    }
+
+    #[test]
+    fn retention_keeps_recent_days_and_weeks() {
+        let config = BackupConfig { keep_last: 2, keep_daily: 3, keep_weekly: 2, ..BackupConfig::default() };
+        let day = 86_400;
+        // Monday 2026-10-19 noon, going back
+        let monday = 1_792_411_200;
+        let times = [
+            monday + 3600,
+            monday,
+            monday - 3600,
+            monday - day,
+            monday - day - 3600,
+            monday - 2 * day,
+            monday - 3 * day,
+            monday - 14 * day,
+        ];
+        let kept: Vec<i64> = retained(&times, &config)
+            .into_iter()
+            .zip(times)
+            .filter_map(|(keep, time)| keep.then_some(time))
+            .collect();
+        // the newest two; the newest of Monday, Sunday and Saturday; the
+        // newest of this week and last week
+        assert_eq!(kept, vec![monday + 3600, monday, monday - day, monday - 2 * day]);
+        assert_eq!(retained(&[], &config), Vec::<bool>::new());
+
+        let scratch = Scratch::new();
+        let config = BackupConfig { keep_last: 1, keep_daily: 0, keep_weekly: 0, ..self::config(&scratch) };
+        fs::create_dir_all(&config.directory).unwrap();
+        for time in [monday, monday - day] {
+            let path = config.directory.join(format!("bank-{}.backup", time));
+            fs::write(&path, seal(b"", time, &config.key).unwrap()).unwrap();
+        }
+        fs::write(config.directory.join("notes.txt"), "kept").unwrap();
+        let removed = prune(&config).unwrap();
+        assert_eq!(removed.iter().map(|backup| backup.created_at).collect::<Vec<_>>(), vec![monday - day]);
+        assert_eq!(list(&config.directory).unwrap().len(), 1);
+        assert!(config.directory.join("notes.txt").exists());
+        assert_eq!(find(&config, "2026-10-19 12:00").unwrap(), config.directory.join(format!("bank-{}.backup", monday)));
+        assert!(matches!(find(&config, "2026-10-18 12:00"), Err(BackupError::NotFound(_))));
+    }
+
+    #[test]
+    fn snapshots_a_database_being_written() {
+        let scratch = Scratch::new();
+        let config = config(&scratch);
+        let db_path = scratch.0.join("bank.db");
+        let conn = db::open(&db_path).unwrap();
+        conn.execute("INSERT INTO user_information (id, balance) VALUES ('960008484537', 1)", []).unwrap();
+        let writer = {
+            let db_path = db_path.clone();
+            std::thread::spawn(move || {
+                let conn = db::open(&db_path).unwrap();
+                for balance in 2..200 {
+                    conn.execute("UPDATE user_information SET balance = ?1", params![balance]).unwrap();
+                }
+            })
+        };
+        let backup = create(&db_path, &config).unwrap();
+        writer.join().unwrap();
+        verify(&backup.path, &config).unwrap();
+    }
+}
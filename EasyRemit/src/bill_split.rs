@@ -0,0 +1,419 @@
+use rusqlite::{params, Connection};
+use std::fmt;
+use crate::ledger::TransferStatus;
+use crate::model::{Account, Transaction, TransactionKind};
+use crate::payment_requests::{self, RequestError};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SplitMethod {
+    Equal,
+    // each participant's value is a whole percentage of the total
+    Percentage,
+    // each participant's value is the exact amount they owe
+    Custom,
+}
+
+impl SplitMethod {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SplitMethod::Equal => "equal",
+            SplitMethod::Percentage => "percentage",
+            SplitMethod::Custom => "custom",
+        }
+    }
+}
+
+// One entry of the participant list: an account id and, for percentage and
+// custom splits, the value given for it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Participant {
+    pub id: String,
+    pub value: Option<i64>,
+}
+
+#[derive(Debug)]
+pub enum SplitError {
+    InvalidTotal,
+    NoParticipants,
+    DuplicateParticipant(String),
+    MissingValue(String),
+    UnexpectedValue(String),
+    NegativeShare(String),
+    PercentagesDontAddUp(i64),
+    AmountsDontAddUp { expected: i64, actual: i64 },
+    Request(String, RequestError),
+    Database(rusqlite::Error),
+}
+
+impl fmt::Display for SplitError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SplitError::InvalidTotal => write!(f, "Enter a total greater than 0"),
+            SplitError::NoParticipants => write!(f, "Add at least one participant"),
+            SplitError::DuplicateParticipant(id) => write!(f, "{} is listed more than once", id),
+            SplitError::MissingValue(id) => write!(f, "Give a value for {} (id:value)", id),
+            SplitError::UnexpectedValue(id) => write!(f, "An equal split takes no value for {}", id),
+            SplitError::NegativeShare(id) => write!(f, "The value for {} can't be negative", id),
+            SplitError::PercentagesDontAddUp(sum) => write!(f, "Percentages add up to {}%, not 100%", sum),
+            SplitError::AmountsDontAddUp { expected, actual } => {
+                write!(f, "Amounts add up to ${}, not ${}", actual, expected)
+            }
+            SplitError::Request(id, e) => write!(f, "{}: {}", id, e),
+            SplitError::Database(e) => write!(f, "Database error: {}", e),
+        }
+    }
+}
+
+impl From<rusqlite::Error> for SplitError {
+    fn from(e: rusqlite::Error) -> Self {
+        SplitError::Database(e)
+    }
+}
+
+// Parses "id, id:value, ..." as typed on the Split page.
+pub fn parse_participants(input: &str) -> Result<Vec<Participant>, SplitError> {
+    let mut participants: Vec<Participant> = Vec::new();
+    for entry in input.split(',').map(str::trim).filter(|entry| !entry.is_empty()) {
+        let (id, value) = match entry.split_once(':') {
+            Some((id, value)) => {
+                let value = value.trim().parse().map_err(|_| SplitError::MissingValue(id.trim().to_string()))?;
+                (id.trim().to_string(), Some(value))
+            }
+            None => (entry.to_string(), None),
+        };
+        if participants.iter().any(|p| p.id == id) {
+            return Err(SplitError::DuplicateParticipant(id));
+        }
+        participants.push(Participant { id, value });
+    }
+    if participants.is_empty() {
+        return Err(SplitError::NoParticipants);
+    }
+    Ok(participants)
+}
+
+// Works out what each participant owes. Whatever cannot be divided evenly is
+// handed out one unit at a time from the top of the list, so the shares
+// always add up to the total exactly.
+pub fn shares(total: i64, method: SplitMethod, participants: &[Participant]) -> Result<Vec<i64>, SplitError> {
+    if total <= 0 {
+        return Err(SplitError::InvalidTotal);
+    }
+    if participants.is_empty() {
+        return Err(SplitError::NoParticipants);
+    }
+
+    match method {
+        SplitMethod::Equal => {
+            if let Some(p) = participants.iter().find(|p| p.value.is_some()) {
+                return Err(SplitError::UnexpectedValue(p.id.clone()));
+            }
+            let weights = vec![1; participants.len()];
+            Ok(proportional(total, &weights))
+        }
+        SplitMethod::Percentage => {
+            let percentages = values(participants)?;
+            let sum = checked_sum(&percentages);
+            if sum != 100 {
+                return Err(SplitError::PercentagesDontAddUp(sum));
+            }
+            Ok(proportional(total, &percentages))
+        }
+        SplitMethod::Custom => {
+            let amounts = values(participants)?;
+            let sum = checked_sum(&amounts);
+            if sum != total {
+                return Err(SplitError::AmountsDontAddUp { expected: total, actual: sum });
+            }
+            Ok(amounts)
+        }
+    }
+}
+
+// Reported before any sum, which a negative value could make look right
+fn values(participants: &[Participant]) -> Result<Vec<i64>, SplitError> {
+    participants
+        .iter()
+        .map(|p| match p.value {
+            None => Err(SplitError::MissingValue(p.id.clone())),
+            Some(value) if value < 0 => Err(SplitError::NegativeShare(p.id.clone())),
+            Some(value) => Ok(value),
+        })
+        .collect()
+}
+
+// Values are typed by the user, so a sum that does not fit is reported as
+// i64::MAX rather than allowed to wrap.
+fn checked_sum(values: &[i64]) -> i64 {
+    values
+        .iter()
+        .try_fold(0i64, |sum, &value| sum.checked_add(value))
+        .unwrap_or(i64::MAX)
+}
+
+// Weights are never negative and sum to more than 0. The products are taken
+// in i128; each share is at most the total, so it fits back in an i64.
+fn proportional(total: i64, weights: &[i64]) -> Vec<i64> {
+    let weight_sum: i128 = weights.iter().map(|&w| i128::from(w)).sum();
+    let mut shares: Vec<i64> = weights
+        .iter()
+        .map(|&w| (i128::from(total) * i128::from(w) / weight_sum) as i64)
+        .collect();
+    let mut remainder = total - shares.iter().sum::<i64>();
+    for (share, weight) in shares.iter_mut().zip(weights) {
+        if remainder == 0 {
+            break;
+        }
+        if *weight > 0 {
+            *share += 1;
+            remainder -= 1;
+        }
+    }
+    shares
+}
+
+// Records the split and sends a payment request for every share. The
+// creator may list themselves; their share is recorded but not requested.
+pub fn create_split(
+    conn: &Connection,
+    creator_id: &str,
+    total: i64,
+    memo: &str,
+    method: SplitMethod,
+    participants: &[Participant],
+) -> Result<i64, SplitError> {
+    let amounts = shares(total, method, participants)?;
+
+    let tx = conn.unchecked_transaction()?;
+    tx.execute(
+        "INSERT INTO bill_splits (creator_id, total, memo, method, created_at) VALUES (?1, ?2, ?3, ?4, strftime('%s', 'now'))",
+        params![creator_id, total, memo.trim(), method.as_str()],
+    )?;
+    let split_id = tx.last_insert_rowid();
+
+    let request_memo = request_memo(split_id, memo);
+    for (participant, amount) in participants.iter().zip(amounts) {
+        let request_id = if participant.id == creator_id || amount == 0 {
+            None
+        } else {
+            let id = payment_requests::create(&tx, creator_id, &participant.id, amount, &request_memo)
+                .map_err(|e| SplitError::Request(participant.id.clone(), e))?;
+            Some(id)
+        };
+        tx.execute(
+            "INSERT INTO bill_split_shares (split_id, participant_id, amount, request_id) VALUES (?1, ?2, ?3, ?4)",
+            params![split_id, participant.id, amount, request_id],
+        )?;
+    }
+    tx.commit()?;
+    Ok(split_id)
+}
+
+// The memo on each request a split sends
+pub(crate) fn request_memo(split_id: i64, memo: &str) -> String {
+    if memo.trim().is_empty() {
+        format!("Split bill #{}", split_id)
+    } else {
+        format!("{} (split #{})", memo.trim(), split_id)
+    }
+}
+
+// Stamps the split that `request_id` belongs to as settled once every
+// requested share has been paid. Called when a payment request is approved.
+pub fn settle_if_complete(conn: &Connection, request_id: i64) -> rusqlite::Result<()> {
+    conn.execute(
+        "UPDATE bill_splits SET settled_at = strftime('%s', 'now')
+         WHERE settled_at IS NULL
+           AND id = (SELECT split_id FROM bill_split_shares WHERE request_id = ?1)
+           AND NOT EXISTS (
+               SELECT 1 FROM bill_split_shares s
+               JOIN payment_requests pr ON pr.id = s.request_id
+               WHERE s.split_id = bill_splits.id AND pr.status != 'paid'
+           )",
+        params![request_id],
+    )?;
+    Ok(())
+}
+
+#[derive(Debug, Clone)]
+pub struct ShareStatus {
+    pub participant_name: String,
+    pub amount: i64,
+    // "paid", "pending", "declined", "expired", or "yours" for the creator's share
+    pub status: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct SplitSummary {
+    pub id: i64,
+    pub total: i64,
+    pub memo: String,
+    pub settled: bool,
+    pub shares: Vec<ShareStatus>,
+}
+
+impl SplitSummary {
+    pub fn collected(&self) -> i64 {
+        self.shares
+            .iter()
+            .filter(|share| share.status == "paid")
+            .map(|share| share.amount)
+            .sum()
+    }
+}
+
+// The creator's splits, newest first.
+pub fn splits_for(conn: &Connection, creator_id: &str) -> rusqlite::Result<Vec<SplitSummary>> {
+    payment_requests::expire_stale(conn)?;
+    let mut stmt = conn.prepare(
+        "SELECT id, total, memo, settled_at IS NOT NULL FROM bill_splits
+         WHERE creator_id = ?1 ORDER BY created_at DESC, id DESC",
+    )?;
+    let mut splits = stmt
+        .query_map(params![creator_id], |row| {
+            Ok(SplitSummary {
+                id: row.get(0)?,
+                total: row.get(1)?,
+                memo: row.get(2)?,
+                settled: row.get(3)?,
+                shares: Vec::new(),
+            })
+        })?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+
+    let mut shares_stmt = conn.prepare(
+        "SELECT COALESCE(u.name, s.participant_id), s.amount, COALESCE(pr.status, 'yours')
+         FROM bill_split_shares s
+         LEFT JOIN user_information u ON u.id = s.participant_id
+         LEFT JOIN payment_requests pr ON pr.id = s.request_id
+         WHERE s.split_id = ?1
+         ORDER BY s.rowid",
+    )?;
+    for split in splits.iter_mut() {
+        split.shares = shares_stmt
+            .query_map(params![split.id], |row| {
+                Ok(ShareStatus {
+                    participant_name: row.get(0)?,
+                    amount: row.get(1)?,
+                    status: row.get(2)?,
+                })
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+    }
+    Ok(splits)
+}
+
+// Accounts the user has sent money to before, most recent first, offered as
+// quick picks on the Split page. `history` is oldest first, as
+// Storage::history returns it.
+pub fn recent_payees(history: &[Transaction], limit: usize) -> Vec<Account> {
+    let mut payees: Vec<Account> = Vec::new();
+    for entry in history.iter().rev() {
+        if payees.len() == limit {
+            break;
+        }
+        let paid = entry.outgoing && entry.kind == TransactionKind::Transfer && entry.status == TransferStatus::Completed;
+        if paid && !payees.iter().any(|payee| payee.id == entry.counterparty.id) {
+            payees.push(entry.counterparty.clone());
+        }
+    }
+    payees
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn participants(input: &str) -> Vec<Participant> {
+        parse_participants(input).unwrap()
+    }
+
+    #[test]
+    fn parses_ids_and_values() {
+        assert_eq!(
+            participants(" alice , bob:30,, carol : 70 "),
+            vec![
+                Participant { id: "alice".to_string(), value: None },
+                Participant { id: "bob".to_string(), value: Some(30) },
+                Participant { id: "carol".to_string(), value: Some(70) },
+            ]
+        );
+        let cases = [
+            ("", "Add at least one participant"),
+            (" , ,", "Add at least one participant"),
+            ("alice, alice:5", "alice is listed more than once"),
+            ("alice:", "Give a value for alice (id:value)"),
+            ("alice:ten", "Give a value for alice (id:value)"),
+            ("alice:99999999999999999999", "Give a value for alice (id:value)"),
+        ];
+        for (input, message) in cases {
+            assert_eq!(parse_participants(input).unwrap_err().to_string(), message, "{:?}", input);
+        }
+    }
+
+    #[test]
+    fn remainder_goes_to_the_top_of_the_list() {
+        let three = participants("a, b, c");
+        assert_eq!(shares(100, SplitMethod::Equal, &three).unwrap(), vec![34, 33, 33]);
+        assert_eq!(shares(101, SplitMethod::Equal, &three).unwrap(), vec![34, 34, 33]);
+        assert_eq!(shares(2, SplitMethod::Equal, &three).unwrap(), vec![1, 1, 0]);
+        assert_eq!(
+            shares(10, SplitMethod::Percentage, &participants("a:33, b:33, c:34")).unwrap(),
+            vec![4, 3, 3]
+        );
+        assert_eq!(
+            shares(7, SplitMethod::Custom, &participants("a:5, b:2")).unwrap(),
+            vec![5, 2]
+        );
+    }
+
+    #[test]
+    fn zero_weights_get_nothing() {
+        assert_eq!(
+            shares(101, SplitMethod::Percentage, &participants("a:0, b:50, c:50")).unwrap(),
+            vec![0, 51, 50]
+        );
+        assert_eq!(
+            shares(9, SplitMethod::Custom, &participants("a:0, b:9")).unwrap(),
+            vec![0, 9]
+        );
+        assert_eq!(proportional(5, &[0, 1, 0, 1]), vec![0, 3, 0, 2]);
+    }
+
+    #[test]
+    fn large_values_do_not_overflow() {
+        assert_eq!(
+            shares(i64::MAX, SplitMethod::Percentage, &participants("a:50, b:50")).unwrap(),
+            vec![i64::MAX / 2 + 1, i64::MAX / 2]
+        );
+        assert_eq!(proportional(1_000, &[i64::MAX, i64::MAX]), vec![500, 500]);
+        let huge = participants(&format!("a:{}, b:{}", i64::MAX, i64::MAX));
+        assert!(matches!(
+            shares(10, SplitMethod::Custom, &huge),
+            Err(SplitError::AmountsDontAddUp { expected: 10, actual: i64::MAX })
+        ));
+        assert!(matches!(
+            shares(10, SplitMethod::Percentage, &huge),
+            Err(SplitError::PercentagesDontAddUp(i64::MAX))
+        ));
+    }
+
+    #[test]
+    fn rejects_bad_splits() {
+        let cases = [
+            (0, SplitMethod::Equal, "a, b", "Enter a total greater than 0"),
+            (10, SplitMethod::Equal, "a, b:5", "An equal split takes no value for b"),
+            (10, SplitMethod::Percentage, "a:50, b", "Give a value for b (id:value)"),
+            (10, SplitMethod::Percentage, "a:60, b:50", "Percentages add up to 110%, not 100%"),
+            (10, SplitMethod::Percentage, "a:150, b:-50", "The value for b can't be negative"),
+            (10, SplitMethod::Custom, "a:5, b:4", "Amounts add up to $9, not $10"),
+            (10, SplitMethod::Custom, "a:15, b:-5", "The value for b can't be negative"),
+            (10, SplitMethod::Custom, "a:-5, b:5", "The value for a can't be negative"),
+        ];
+        for (total, method, input, message) in cases {
+            let error = shares(total, method, &participants(input)).unwrap_err();
+            assert_eq!(error.to_string(), message, "{}", input);
+        }
+        assert!(matches!(shares(10, SplitMethod::Equal, &[]), Err(SplitError::NoParticipants)));
+    }
+}
@@ -0,0 +1,258 @@
+// Operator tool for inspecting and fixing accounts in bank.db without opening
+// the database by hand. Operators sign in with their own EasyRemit account,
+// which needs the operator role, and every command is written to the audit log
+// under that account.
+use easyremit::admin::{self, AdminError, Operator, UserSummary};
+use easyremit::backup;
+use easyremit::config;
+use easyremit::logging;
+use easyremit::db::DB_POOL;
+use easyremit::webhooks;
+use std::io::BufRead;
+use std::path::Path;
+
+const USAGE: &str = "usage: easyremit-admin [--config <file>] --operator <user-id> [--password-stdin]
+                      [--key-file <file>] <command>
+
+  --operator <user-id>                  your own account, which must have the operator role;
+                                        asks for its password unless --password-stdin is given
+  --key-file <file>                     the 16-byte encryption key, for show to decrypt with
+
+  If no account has the operator role yet, the first command must be
+  set-role <your user-id> operator <reason>.
+
+  list [limit]                          newest users first (default 50)
+  search <text>                         users whose ID, name or email contains text
+  show <user-id>                        full profile, with the passport and birth date
+                                        decrypted if --key-file is given
+  freeze <user-id> <reason>             stop the account from sending money
+  unfreeze <user-id> <reason>
+  adjust <user-id> <amount> <reason>    credit (or debit, if negative) the balance
+  reset-lockout <user-id>               clear failed logins so the user can log in again
+  set-role <user-id> <role> <reason>    customer, reviewer or operator
+  set-kyc <user-id> <level> <reason>    move the user to another transfer limit tier
+  webhook-add <url> <event,...>         subscribe a URL to transfer.completed, transfer.failed
+                                        and/or transfer.reversed (reserved, not sent yet);
+                                        prints the signing secret
+  webhook-list                          subscriptions and the state of the outbox
+  webhook-remove <id>                   stop sending to a subscription
+  webhook-deliver                       send every delivery that is due now
+  backup                                back up the database now and delete backups past
+                                        the retention settings
+  backup-list                           backups in backup.directory, newest first
+  backup-verify <file>                  check a backup can be restored, without restoring it
+  backup-restore <file or time>         replace the database with a backup, or with the newest
+                                        one taken at or before a UTC time such as
+                                        \"2026-10-19 08:30\"; stop the app and the API first";
+
+fn main() {
+    let mut args: Vec<String> = std::env::args().skip(1).collect();
+    config::init_or_exit(config::take_config_arg(&mut args).as_deref());
+    logging::init("easyremit-admin");
+    std::process::exit(match sign_in(&mut args).and_then(|(operator, key)| run(&operator, key.as_deref(), &args)) {
+        Ok(()) => 0,
+        Err(Some(e)) => {
+            eprintln!("{}", e);
+            1
+        }
+        Err(None) => {
+            eprintln!("{}", USAGE);
+            2
+        }
+    });
+}
+
+// Takes the sign-in options out of `args` and checks the operator's password.
+// Returns the operator and the key from --key-file, if given.
+fn sign_in(args: &mut Vec<String>) -> Result<(Operator, Option<String>), Option<AdminError>> {
+    let user_id = take_option(args, "--operator")?.ok_or(None)?;
+    let password_stdin = take_flag(args, "--password-stdin");
+    let key = match take_option(args, "--key-file")? {
+        Some(path) => {
+            let key = std::fs::read_to_string(&path).map_err(|e| {
+                eprintln!("Cannot read {}: {}", path, e);
+                None
+            })?;
+            Some(key.trim_end_matches(['\r', '\n']).to_string())
+        }
+        None => None,
+    };
+    let password = if password_stdin {
+        let mut line = String::new();
+        std::io::stdin().lock().read_line(&mut line).map_err(|_| None)?;
+        line.trim_end_matches(['\r', '\n']).to_string()
+    } else {
+        rpassword::prompt_password("Password: ").map_err(|_| None)?
+    };
+    let operator = admin::sign_in(&DB_POOL.writer(), &user_id, &password)?;
+    // The first operator makes themselves one, and that is all they can do
+    // until they have
+    let makes_self_operator = args.first().map(String::as_str) == Some("set-role")
+        && args.get(1) == Some(&user_id)
+        && args.get(2).map(String::as_str) == Some(admin::OPERATOR);
+    if operator.bootstrap && !makes_self_operator {
+        return Err(Some(AdminError::BootstrapOnly));
+    }
+    Ok((operator, key))
+}
+
+// Removes `name <value>` or `name=<value>` from `args`
+fn take_option(args: &mut Vec<String>, name: &str) -> Result<Option<String>, Option<AdminError>> {
+    let prefix = format!("{}=", name);
+    let Some(index) = args.iter().position(|arg| arg == name || arg.starts_with(&prefix)) else {
+        return Ok(None);
+    };
+    let arg = args.remove(index);
+    match arg.strip_prefix(&prefix) {
+        Some(value) => Ok(Some(value.to_string())),
+        None if index < args.len() => Ok(Some(args.remove(index))),
+        None => Err(None),
+    }
+}
+
+fn take_flag(args: &mut Vec<String>, name: &str) -> bool {
+    let present = args.iter().any(|arg| arg == name);
+    args.retain(|arg| arg != name);
+    present
+}
+
+// Err(None) means the command line was wrong
+fn run(operator: &Operator, key: Option<&str>, args: &[String]) -> Result<(), Option<AdminError>> {
+    let conn = DB_POOL.writer();
+    let operator = operator.actor.as_str();
+    let arg = |i: usize| args.get(i).map(String::as_str).ok_or(None);
+    let rest = |i: usize| args.get(i..).unwrap_or_default().join(" ");
+
+    match arg(0)? {
+        "list" => {
+            let limit = match args.get(1) {
+                Some(limit) => limit.parse().map_err(|_| None)?,
+                None => 50,
+            };
+            print_users(&admin::list_users(&conn, operator, limit)?);
+        }
+        "search" => print_users(&admin::search_users(&conn, operator, &rest(1))?),
+        "show" => {
+            let profile = admin::profile(&conn, operator, arg(1)?, key)?;
+            let user = &profile.user;
+            let encrypted = if key.is_some() { "- (does not decrypt with this key)" } else { "(encrypted; pass --key-file)" };
+            println!("ID:            {}", user.id);
+            println!("Name:          {}", user.name);
+            println!("Email:         {}", user.email);
+            println!("Passport:      {}", profile.passport.as_deref().unwrap_or(encrypted));
+            println!("Birth date:    {}", profile.birth_date.as_deref().unwrap_or(encrypted));
+            println!("Balance:       ${}", user.balance);
+            println!("Role:          {}", user.role);
+            println!("Frozen:        {}", if user.frozen { "yes" } else { "no" });
+            println!("Failed logins: {}", profile.failed_logins);
+            if let Some(locked_until) = profile.locked_until {
+                println!("Locked until:  {} (unix time)", locked_until);
+            }
+        }
+        "freeze" | "unfreeze" => {
+            let frozen = arg(0)? == "freeze";
+            admin::set_frozen(&conn, operator, arg(1)?, frozen, &rest(2))?;
+            println!("{} {}", if frozen { "Froze" } else { "Unfroze" }, arg(1)?);
+        }
+        "adjust" => {
+            let delta: i64 = arg(2)?.parse().map_err(|_| None)?;
+            let balance = admin::adjust_balance(&conn, operator, arg(1)?, delta, &rest(3))?;
+            println!("New balance of {}: ${}", arg(1)?, balance);
+        }
+        "reset-lockout" => {
+            admin::reset_lockout(&conn, operator, arg(1)?)?;
+            println!("Cleared failed logins for {}", arg(1)?);
+        }
+        "set-role" => {
+            admin::set_role(&conn, operator, arg(1)?, arg(2)?, &rest(3))?;
+            println!("{} is now {}", arg(1)?, arg(2)?);
+        }
+        "set-kyc" => {
+            let level: i64 = arg(2)?.parse().map_err(|_| None)?;
+            let limits = admin::set_kyc_level(&conn, operator, arg(1)?, level, &rest(3))?;
+            println!(
+                "{} is now KYC level {}: ${} per transfer, ${} a day, ${} a month",
+                arg(1)?,
+                level,
+                limits.per_transaction,
+                limits.daily,
+                limits.monthly
+            );
+        }
+        "webhook-add" => {
+            let events: Vec<&str> = arg(2)?.split(',').map(str::trim).collect();
+            let (id, secret) = admin::add_webhook(&conn, operator, arg(1)?, &events)?;
+            println!("Added webhook {}", id);
+            println!("Signing secret (shown only once): {}", secret);
+        }
+        "webhook-list" => {
+            for subscription in webhooks::subscriptions(&conn).map_err(|e| Some(e.into()))? {
+                println!(
+                    "{:<4} {:<8} {:<50} {}",
+                    subscription.id,
+                    if subscription.active { "active" } else { "removed" },
+                    subscription.url,
+                    subscription.events.join(",")
+                );
+            }
+            for (status, count) in webhooks::outbox_counts(&conn).map_err(|e| Some(e.into()))? {
+                println!("{} {}", count, status);
+            }
+        }
+        "webhook-remove" => {
+            let id: i64 = arg(1)?.parse().map_err(|_| None)?;
+            admin::remove_webhook(&conn, operator, id)?;
+            println!("Removed webhook {}", id);
+        }
+        "webhook-deliver" => {
+            // deliver_due takes the lock itself around each database step
+            drop(conn);
+            let delivered = webhooks::deliver_due(&DB_POOL).map_err(|e| Some(e.into()))?;
+            println!("Delivered {} event(s)", delivered);
+        }
+        "backup" => {
+            let (backup, removed) = admin::backup(&conn, operator)?;
+            println!("Backed up to {} ({} bytes)", backup.path.display(), backup.size);
+            for backup in removed {
+                println!("Deleted {}", backup.path.display());
+            }
+        }
+        "backup-list" => {
+            let backups = backup::list(&config::get().backup.directory).map_err(|e| Some(e.into()))?;
+            for backup in &backups {
+                println!("{:<60} {:>12} bytes", backup.path.display(), backup.size);
+            }
+            println!("{} backup(s)", backups.len());
+        }
+        "backup-verify" => {
+            let verified = backup::verify(Path::new(arg(1)?), &config::get().backup).map_err(|e| Some(e.into()))?;
+            println!("{} is intact and can be restored", verified.path.display());
+        }
+        "backup-restore" => {
+            arg(1)?;
+            let restored = admin::restore_backup(&conn, operator, &rest(1))?;
+            println!("Restored {}", restored.backup.path.display());
+            if let Some(previous) = restored.previous {
+                println!("The database as it was is in {}", previous.path.display());
+            }
+        }
+        _ => return Err(None),
+    }
+    Ok(())
+}
+
+fn print_users(users: &[UserSummary]) {
+    println!("{:<14} {:<24} {:<30} {:>10}  {:<8} flags", "ID", "NAME", "EMAIL", "BALANCE", "ROLE");
+    for user in users {
+        println!(
+            "{:<14} {:<24} {:<30} {:>10}  {:<8} {}",
+            user.id,
+            user.name,
+            user.email,
+            user.balance,
+            user.role,
+            if user.frozen { "frozen" } else { "" }
+        );
+    }
+    println!("{} user(s)", users.len());
+}
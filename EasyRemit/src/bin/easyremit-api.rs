@@ -0,0 +1,118 @@
+// Local HTTP JSON API over the same accounts, checks and ledger as the app.
+// Routing and the handlers live in easyremit::api; this only moves requests
+// and responses between tiny_http and it. See openapi.json for the endpoints.
+use clap::Parser;
+use easyremit::api;
+use easyremit::backup;
+use easyremit::config;
+use easyremit::db::DB_POOL;
+use easyremit::logging;
+use easyremit::sanctions;
+use easyremit::storage;
+use easyremit::webhooks;
+use std::io::Read;
+use std::path::PathBuf;
+use tiny_http::{Header, Request, Response, Server};
+use tracing::{info, info_span, warn};
+
+// Larger bodies are refused rather than read
+const MAX_BODY_BYTES: u64 = 64 * 1024;
+
+#[derive(Parser)]
+#[command(name = "easyremit-api", about = "Serve the EasyRemit JSON API on this machine")]
+struct Cli {
+    /// Configuration file [default: easyremit.toml, or EASYREMIT_CONFIG]
+    #[arg(long)]
+    config: Option<PathBuf>,
+    /// Address to listen on, instead of `api.addr` from the config. Keep it
+    /// on localhost: there is no TLS.
+    #[arg(long)]
+    addr: Option<String>,
+}
+
+fn main() {
+    let cli = Cli::parse();
+    let config = config::init_or_exit(cli.config.as_deref());
+    logging::init("easyremit-api");
+    storage::init_or_exit();
+    sanctions::init_or_exit(&DB_POOL.writer());
+    let addr = cli.addr.as_deref().unwrap_or(&config.api.addr);
+    let server = match Server::http(addr) {
+        Ok(server) => server,
+        Err(e) => {
+            eprintln!("Cannot listen on {}: {}", addr, e);
+            std::process::exit(1);
+        }
+    };
+    eprintln!("Listening on http://{}", addr);
+    info!(addr, "listening");
+    webhooks::spawn_dispatcher(&DB_POOL);
+    backup::spawn_schedule(config.database.path.clone(), &config.backup);
+    for request in server.incoming_requests() {
+        serve(request);
+    }
+}
+
+// Each request is logged in a span with a correlation ID: the caller's
+// X-Correlation-Id if it sent one, else a new one. It is sent back in the
+// same header, so a client can find its request in the log.
+fn serve(mut request: Request) {
+    let method = request.method().to_string();
+    let url = request.url().to_string();
+    let (path, query) = url.split_once('?').unwrap_or((&url, ""));
+    let correlation_id = header(&request, "X-Correlation-Id")
+        .filter(|id| !id.is_empty() && id.len() <= 64 && id.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'-'))
+        .map_or_else(logging::correlation_id, str::to_string);
+    let span = info_span!("request", correlation_id = %correlation_id, method = %method, path);
+    let _entered = span.enter();
+    let token = header(&request, "Authorization")
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .map(|token| token.trim().to_string());
+    let idempotency_key = header(&request, "Idempotency-Key").map(str::to_string);
+
+    let mut body = String::new();
+    let read = request
+        .as_reader()
+        .take(MAX_BODY_BYTES + 1)
+        .read_to_string(&mut body);
+    let response = if read.is_err() || body.len() as u64 > MAX_BODY_BYTES {
+        api::Response {
+            status: 413,
+            body: r#"{"error":{"code":"body_too_large","message":"Request body is too large or not UTF-8"}}"#.to_string(),
+            content_type: "application/json",
+            replayed: false,
+        }
+    } else {
+        api::handle(
+            storage::storage(),
+            &api::Request {
+                method: &method,
+                path,
+                query,
+                token: token.as_deref(),
+                idempotency_key: idempotency_key.as_deref(),
+                body: &body,
+            },
+        )
+    };
+    info!(status = response.status, "served");
+
+    let mut reply = Response::from_string(response.body)
+        .with_status_code(response.status)
+        .with_header(Header::from_bytes("Content-Type", response.content_type).expect("valid header"));
+    reply.add_header(Header::from_bytes("X-Correlation-Id", correlation_id).expect("valid header"));
+    if response.replayed {
+        reply.add_header(Header::from_bytes("Idempotent-Replayed", "true").expect("valid header"));
+    }
+    if let Err(e) = request.respond(reply) {
+        warn!(error = %e, "could not send the response");
+    }
+}
+
+fn header<'a>(request: &'a Request, name: &'static str) -> Option<&'a str> {
+    request
+        .headers()
+        .iter()
+        .find(|header| header.field.equiv(name))
+        .map(|header| header.value.as_str())
+}
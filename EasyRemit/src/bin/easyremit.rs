@@ -0,0 +1,302 @@
+// Command-line client for customers and scripts: the same accounts, checks
+// and ledger as the app, without the GUI. `login` stores a session token in a
+// file that the other commands read.
+use clap::{Parser, Subcommand};
+use easyremit::config;
+use easyremit::db::DB_POOL;
+use easyremit::logging;
+use easyremit::ledger::TransferStatus;
+use easyremit::model::{AccountId, Email, Transaction, TransactionKind};
+use easyremit::sanctions;
+use easyremit::sessions;
+use easyremit::storage::{self, Storage};
+use serde_json::{json, Value};
+use std::io::{BufRead, Write};
+use std::path::PathBuf;
+
+#[derive(Parser)]
+#[command(name = "easyremit", about = "Send money and check your EasyRemit account from the command line")]
+struct Cli {
+    /// Print machine-readable JSON instead of text
+    #[arg(long, global = true)]
+    json: bool,
+    /// Configuration file [default: easyremit.toml, or EASYREMIT_CONFIG]
+    #[arg(long, global = true)]
+    config: Option<PathBuf>,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Log in and remember the session for later commands
+    Login {
+        #[arg(long)]
+        user_id: AccountId,
+        #[arg(long)]
+        email: Email,
+        /// Read the password from the first line of stdin instead of prompting
+        #[arg(long)]
+        password_stdin: bool,
+    },
+    /// End the current session
+    Logout,
+    /// Show your balance
+    Balance,
+    /// Show your transactions, newest last
+    History {
+        /// Only the most recent N
+        #[arg(long)]
+        limit: Option<usize>,
+    },
+    /// Send money to another account
+    Send {
+        recipient: AccountId,
+        amount: i64,
+        /// Run every check and show the fee without sending anything
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Write your full history as CSV (or JSON with --json)
+    Export {
+        /// File to write instead of stdout
+        #[arg(long)]
+        output: Option<PathBuf>,
+    },
+}
+
+fn main() {
+    let cli = Cli::parse();
+    config::init_or_exit(cli.config.as_deref());
+    logging::init("easyremit");
+    // only sending money screens names
+    if matches!(cli.command, Command::Send { .. }) {
+        sanctions::init_or_exit(&DB_POOL.writer());
+    }
+    if let Err(message) = run(storage::storage(), &cli) {
+        if cli.json {
+            println!("{}", json!({ "error": message }));
+        } else {
+            eprintln!("{}", message);
+        }
+        std::process::exit(1);
+    }
+}
+
+fn run(storage: &dyn Storage, cli: &Cli) -> Result<(), String> {
+    match &cli.command {
+        Command::Login { user_id, email, password_stdin } => {
+            let password = if *password_stdin {
+                let mut line = String::new();
+                std::io::stdin().lock().read_line(&mut line).map_err(|e| e.to_string())?;
+                line.trim_end_matches(['\r', '\n']).to_string()
+            } else {
+                rpassword::prompt_password("Password: ").map_err(|e| e.to_string())?
+            };
+            storage.login(user_id, email, &password).map_err(|e| e.to_string())?;
+            let token = storage.create_session(user_id).map_err(|e| e.to_string())?;
+            save_session(&token)?;
+            output(
+                cli.json,
+                json!({ "user_id": user_id, "expires_in": sessions::SESSION_TTL_SECS }),
+                &format!("Logged in as {}", user_id),
+            );
+        }
+        Command::Logout => {
+            if let Ok(token) = std::fs::read_to_string(session_path()) {
+                storage.end_session(token.trim()).map_err(|e| e.to_string())?;
+            }
+            let _ = std::fs::remove_file(session_path());
+            output(cli.json, json!({ "logged_out": true }), "Logged out");
+        }
+        Command::Balance => {
+            let user_id = session_user(storage)?;
+            let profile = storage
+                .profile(&user_id)
+                .map_err(|e| e.to_string())?
+                .ok_or_else(|| format!("Account {} no longer exists", user_id))?;
+            let (name, balance) = (profile.name.unwrap_or_default(), profile.balance);
+            output(
+                cli.json,
+                json!({ "user_id": user_id, "name": name, "balance": balance }),
+                &format!("{} ({}): ${}", name, user_id, balance),
+            );
+        }
+        Command::History { limit } => {
+            let user_id = session_user(storage)?;
+            let history = storage.history(&user_id).map_err(|e| e.to_string())?;
+            let skip = limit.map_or(0, |limit| history.len().saturating_sub(limit));
+            let history = &history[skip..];
+            if cli.json {
+                println!("{}", Value::Array(history.iter().map(entry_json).collect()));
+            } else if history.is_empty() {
+                println!("No transactions yet");
+            } else {
+                for entry in history {
+                    println!("{}", entry_line(entry));
+                }
+            }
+        }
+        Command::Send { recipient, amount, dry_run } => {
+            let user_id = session_user(storage)?;
+            if *dry_run {
+                let quote = storage.check_transfer(&user_id, recipient, *amount).map_err(|e| e.to_string())?;
+                output(
+                    cli.json,
+                    json!({ "recipient_id": recipient, "amount": quote.amount, "fee": quote.fee, "total": quote.total() }),
+                    &format!("Sending ${} to {} would cost ${} including a ${} fee", quote.amount, recipient, quote.total(), quote.fee),
+                );
+                return Ok(());
+            }
+            let receipt = storage.transfer(&user_id, recipient, *amount).map_err(|e| e.to_string())?;
+            // Same wording as the success page; screening reasons are never shown.
+            let text = match receipt.status {
+                TransferStatus::Completed => format!(
+                    "Sent ${} to {} (fee ${}, transaction {})",
+                    receipt.quote.amount, receipt.receiver.name, receipt.quote.fee, receipt.transaction_id
+                ),
+                TransferStatus::Held => format!(
+                    "Transfer under review (transaction {}). We're checking this transfer. No money has left your account yet.",
+                    receipt.transaction_id
+                ),
+                TransferStatus::Blocked => {
+                    return Err("This transfer cannot be completed. No money has left your account.".to_string())
+                }
+            };
+            output(
+                cli.json,
+                json!({
+                    "transaction_id": receipt.transaction_id,
+                    "status": receipt.status.as_str(),
+                    "recipient_id": recipient,
+                    "recipient_name": receipt.receiver.name,
+                    "amount": receipt.quote.amount,
+                    "fee": receipt.quote.fee,
+                    "total": receipt.quote.total(),
+                }),
+                &text,
+            );
+        }
+        Command::Export { output: path } => {
+            let user_id = session_user(storage)?;
+            let history = storage.history(&user_id).map_err(|e| e.to_string())?;
+            let mut out: Box<dyn Write> = match path {
+                Some(path) => Box::new(std::fs::File::create(path).map_err(|e| e.to_string())?),
+                None => Box::new(std::io::stdout()),
+            };
+            if cli.json {
+                let entries = Value::Array(history.iter().map(entry_json).collect());
+                writeln!(out, "{}", entries).map_err(|e| e.to_string())?;
+            } else {
+                write_csv(out, &history).map_err(|e| e.to_string())?;
+            }
+            if let Some(path) = path {
+                eprintln!("Exported {} transactions to {}", history.len(), path.display());
+            }
+        }
+    }
+    Ok(())
+}
+
+fn output(json: bool, value: Value, text: &str) {
+    if json {
+        println!("{}", value);
+    } else {
+        println!("{}", text);
+    }
+}
+
+// Where `login` keeps the session token: EASYREMIT_SESSION if set, else
+// `cli.session_file` from the config
+fn session_path() -> PathBuf {
+    std::env::var_os("EASYREMIT_SESSION")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| config::get().cli.session_file.clone())
+}
+
+fn save_session(token: &str) -> Result<(), String> {
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    let mut file = options.open(session_path()).map_err(|e| e.to_string())?;
+    file.write_all(token.as_bytes()).map_err(|e| e.to_string())
+}
+
+fn session_user(storage: &dyn Storage) -> Result<AccountId, String> {
+    let not_logged_in = || "Not logged in. Run `easyremit login` first".to_string();
+    let token = std::fs::read_to_string(session_path()).map_err(|_| not_logged_in())?;
+    storage
+        .session_user(token.trim())
+        .map_err(|e| e.to_string())?
+        .ok_or_else(not_logged_in)
+}
+
+fn entry_json(entry: &Transaction) -> Value {
+    json!({
+        "transaction_id": entry.id,
+        "created_at": entry.created_at,
+        "kind": entry.kind.as_str(),
+        "direction": if entry.outgoing { "out" } else { "in" },
+        "counterparty_id": entry.counterparty.id,
+        "counterparty_name": entry.counterparty.name,
+        "amount": entry.signed_amount(),
+        "status": entry.status.as_str(),
+        "memo": entry.memo,
+    })
+}
+
+// "2024-05-01 13:45  -$120  Jane Doe (held)"
+fn entry_line(entry: &Transaction) -> String {
+    let date = entry.created_at.map(format_time).unwrap_or_else(|| "-".repeat(16));
+    let amount = if entry.outgoing { format!("-${}", entry.amount) } else { format!("+${}", entry.amount) };
+    let mut line = format!("{}  {:>9}  {}", date, amount, entry.counterparty.name);
+    if entry.kind != TransactionKind::Transfer {
+        line.push_str(&format!(" [{}]", entry.kind.as_str()));
+    }
+    if entry.status != TransferStatus::Completed {
+        line.push_str(&format!(" ({})", entry.status.as_str()));
+    }
+    if let Some(memo) = &entry.memo {
+        line.push_str(&format!(" - {}", memo));
+    }
+    line
+}
+
+fn write_csv(out: impl Write, history: &[Transaction]) -> csv::Result<()> {
+    let mut writer = csv::Writer::from_writer(out);
+    writer.write_record([
+        "transaction_id", "date", "kind", "direction", "counterparty_id", "counterparty_name", "amount", "status", "memo",
+    ])?;
+    for entry in history {
+        writer.write_record([
+            entry.id.to_string(),
+            entry.created_at.map(format_time).unwrap_or_default(),
+            entry.kind.as_str().to_string(),
+            (if entry.outgoing { "out" } else { "in" }).to_string(),
+            entry.counterparty.id.to_string(),
+            entry.counterparty.name.clone(),
+            entry.signed_amount().to_string(),
+            entry.status.as_str().to_string(),
+            entry.memo.clone().unwrap_or_default(),
+        ])?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+// Unix time as "YYYY-MM-DD HH:MM" in UTC, formatted by SQLite as backup.rs
+// does rather than by hand
+fn format_time(secs: i64) -> String {
+    thread_local! {
+        static SQLITE: rusqlite::Result<rusqlite::Connection> = rusqlite::Connection::open_in_memory();
+    }
+    SQLITE.with(|conn| {
+        let conn = conn.as_ref().ok()?;
+        conn.query_row("SELECT strftime('%Y-%m-%d %H:%M', ?1, 'unixepoch')", [secs], |row| row.get(0)).ok()
+    })
+    .unwrap_or_default()
+}
@@ -0,0 +1,496 @@
+use rusqlite::{params, Connection, OptionalExtension};
+use std::fmt;
+use crate::audit;
+use crate::ledger::{self, TransferError, TransferStatus};
+use crate::payment_requests;
+
+// `user_information.role` of users who may work the review queue
+pub const REVIEWER: &str = "reviewer";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ItemKind {
+    // a transfer held by screening, identified by transaction id
+    Transfer,
+    // an account frozen by a sanctions match at signup, identified by user id
+    Account,
+}
+
+impl ItemKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ItemKind::Transfer => "transfer",
+            ItemKind::Account => "account",
+        }
+    }
+
+    pub fn parse(kind: &str) -> Option<ItemKind> {
+        match kind {
+            "transfer" => Some(ItemKind::Transfer),
+            "account" => Some(ItemKind::Account),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Decision {
+    Approve,
+    Reject,
+    Escalate,
+}
+
+impl Decision {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Decision::Approve => "approve",
+            Decision::Reject => "reject",
+            Decision::Escalate => "escalate",
+        }
+    }
+
+    pub fn parse(decision: &str) -> Option<Decision> {
+        match decision {
+            "approve" => Some(Decision::Approve),
+            "reject" => Some(Decision::Reject),
+            "escalate" => Some(Decision::Escalate),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ReviewItem {
+    pub kind: ItemKind,
+    pub id: String,
+    pub summary: String,
+    pub created_at: i64,
+    // why the item was held, one line per rule or sanctions match
+    pub hits: Vec<String>,
+    pub escalated: bool,
+}
+
+#[derive(Debug)]
+pub enum ReviewError {
+    NotReviewer,
+    CommentRequired,
+    // reviewers cannot decide on their own transfers or account
+    OwnItem,
+    NotPending,
+    Transfer(TransferError),
+    Database(rusqlite::Error),
+}
+
+impl fmt::Display for ReviewError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReviewError::NotReviewer => write!(f, "Only compliance reviewers can do this"),
+            ReviewError::CommentRequired => write!(f, "Add a comment explaining the decision"),
+            ReviewError::OwnItem => write!(f, "You cannot review your own transfers or account"),
+            ReviewError::NotPending => write!(f, "This item is no longer waiting for review"),
+            ReviewError::Transfer(e) => write!(f, "{}", e),
+            ReviewError::Database(e) => write!(f, "Database error: {}", e),
+        }
+    }
+}
+
+impl From<rusqlite::Error> for ReviewError {
+    fn from(e: rusqlite::Error) -> Self {
+        ReviewError::Database(e)
+    }
+}
+
+impl From<TransferError> for ReviewError {
+    fn from(e: TransferError) -> Self {
+        match e {
+            TransferError::NotHeld => ReviewError::NotPending,
+            e => ReviewError::Transfer(e),
+        }
+    }
+}
+
+pub fn is_reviewer(conn: &Connection, user_id: &str) -> rusqlite::Result<bool> {
+    let role: Option<String> = conn
+        .query_row(
+            "SELECT role FROM user_information WHERE id = ?1",
+            params![user_id],
+            |row| row.get(0),
+        )
+        .optional()?;
+    Ok(role.as_deref() == Some(REVIEWER))
+}
+
+// Everything waiting for a decision, oldest first. Escalated items stay in
+// the queue, flagged, until someone approves or rejects them.
+pub fn queue(conn: &Connection) -> rusqlite::Result<Vec<ReviewItem>> {
+    let mut items = Vec::new();
+
+    let mut stmt = conn.prepare(
+        "SELECT th.id, th.amount, th.created_at, th.sender_id, s.name, th.receiver_id, r.name
+         FROM transaction_history th
+         LEFT JOIN user_information s ON s.id = th.sender_id
+         LEFT JOIN user_information r ON r.id = th.receiver_id
+         WHERE th.status = 'held'
+         ORDER BY th.created_at, th.id",
+    )?;
+    let transfers = stmt.query_map([], |row| {
+        let id: i64 = row.get(0)?;
+        let summary = format!(
+            "${} from {} ({}) to {} ({})",
+            row.get::<_, i64>(1)?,
+            row.get::<_, Option<String>>(4)?.unwrap_or_default(),
+            row.get::<_, String>(3)?,
+            row.get::<_, Option<String>>(6)?.unwrap_or_default(),
+            row.get::<_, String>(5)?,
+        );
+        Ok((id, summary, row.get::<_, i64>(2)?))
+    })?;
+    for transfer in transfers {
+        let (id, summary, created_at) = transfer?;
+        let mut hits = rule_hits(conn, id)?;
+        hits.extend(sanctions_hits(conn, "transaction_id = ?1", &id)?);
+        items.push(ReviewItem {
+            kind: ItemKind::Transfer,
+            id: id.to_string(),
+            summary,
+            created_at,
+            hits,
+            escalated: escalated(conn, ItemKind::Transfer, &id.to_string())?,
+        });
+    }
+
+    let mut stmt = conn.prepare(
+        "SELECT u.id, u.name, COALESCE(u.created_at, 0) FROM user_information u
+         WHERE u.frozen = 1 AND EXISTS (
+             SELECT 1 FROM sanctions_hits h
+             WHERE h.user_id = u.id AND h.context = 'signup' AND h.status IN ('open', 'escalated')
+         )
+         ORDER BY u.created_at",
+    )?;
+    let accounts = stmt.query_map([], |row| {
+        Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?, row.get::<_, i64>(2)?))
+    })?;
+    for account in accounts {
+        let (id, name, created_at) = account?;
+        items.push(ReviewItem {
+            kind: ItemKind::Account,
+            summary: format!("New account {} ({}) frozen at signup", name, id),
+            created_at,
+            hits: sanctions_hits(conn, "user_id = ?1 AND context = 'signup'", &id)?,
+            escalated: escalated(conn, ItemKind::Account, &id)?,
+            id,
+        });
+    }
+
+    items.sort_by_key(|item| item.created_at);
+    Ok(items)
+}
+
+// Applies a reviewer's decision and records it in the audit log, all in one
+// transaction. Approving a transfer posts it through the ledger like any
+// other; approving an account unfreezes it.
+pub fn decide(
+    conn: &Connection,
+    reviewer_id: &str,
+    kind: ItemKind,
+    id: &str,
+    decision: Decision,
+    comment: &str,
+) -> Result<(), ReviewError> {
+    if !is_reviewer(conn, reviewer_id)? {
+        return Err(ReviewError::NotReviewer);
+    }
+    let comment = comment.trim();
+    if comment.is_empty() {
+        return Err(ReviewError::CommentRequired);
+    }
+
+    let tx = conn.unchecked_transaction()?;
+    match kind {
+        ItemKind::Transfer => decide_transfer(&tx, reviewer_id, id, decision)?,
+        ItemKind::Account => decide_account(&tx, reviewer_id, id, decision)?,
+    }
+    tx.execute(
+        "INSERT INTO review_decisions (item_kind, item_id, reviewer_id, decision, comment, created_at)
+         VALUES (?1, ?2, ?3, ?4, ?5, strftime('%s', 'now'))",
+        params![kind.as_str(), id, reviewer_id, decision.as_str(), comment],
+    )?;
+    audit::record(
+        &tx,
+        reviewer_id,
+        audit::REVIEW,
+        &format!("{}:{}", kind.as_str(), id),
+        &format!("decision={} comment={}", decision.as_str(), comment),
+    )?;
+    tx.commit()?;
+    Ok(())
+}
+
+fn decide_transfer(tx: &Connection, reviewer_id: &str, id: &str, decision: Decision) -> Result<(), ReviewError> {
+    let transaction_id: i64 = id.parse().map_err(|_| ReviewError::NotPending)?;
+    let parties: Option<(String, String)> = tx
+        .query_row(
+            "SELECT sender_id, receiver_id FROM transaction_history WHERE id = ?1 AND status = 'held'",
+            params![transaction_id],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )
+        .optional()?;
+    let (sender_id, receiver_id) = parties.ok_or(ReviewError::NotPending)?;
+    if sender_id == reviewer_id || receiver_id == reviewer_id {
+        return Err(ReviewError::OwnItem);
+    }
+
+    let (status, hit_status) = match decision {
+        Decision::Approve => {
+            ledger::release_held(tx, transaction_id)?;
+            (TransferStatus::Completed, "cleared")
+        }
+        Decision::Reject => {
+            ledger::reject_held(tx, transaction_id)?;
+            (TransferStatus::Blocked, "confirmed")
+        }
+        Decision::Escalate => (TransferStatus::Held, "escalated"),
+    };
+    payment_requests::transfer_reviewed(tx, transaction_id, status)?;
+    tx.execute(
+        "UPDATE sanctions_hits SET status = ?2 WHERE transaction_id = ?1",
+        params![transaction_id, hit_status],
+    )?;
+    Ok(())
+}
+
+fn decide_account(tx: &Connection, reviewer_id: &str, user_id: &str, decision: Decision) -> Result<(), ReviewError> {
+    if user_id == reviewer_id {
+        return Err(ReviewError::OwnItem);
+    }
+    let hit_status = match decision {
+        Decision::Approve => "cleared",
+        Decision::Reject => "confirmed",
+        Decision::Escalate => "escalated",
+    };
+    let updated = tx.execute(
+        "UPDATE sanctions_hits SET status = ?2
+         WHERE user_id = ?1 AND context = 'signup' AND status IN ('open', 'escalated')",
+        params![user_id, hit_status],
+    )?;
+    if updated == 0 {
+        return Err(ReviewError::NotPending);
+    }
+    // a rejected account stays frozen
+    if decision == Decision::Approve {
+        tx.execute("UPDATE user_information SET frozen = 0 WHERE id = ?1", params![user_id])?;
+    }
+    Ok(())
+}
+
+fn rule_hits(conn: &Connection, transaction_id: i64) -> rusqlite::Result<Vec<String>> {
+    let mut stmt = conn.prepare("SELECT rule, reason FROM screening_hits WHERE transaction_id = ?1 AND rule != 'sanctions'")?;
+    let hits = stmt.query_map(params![transaction_id], |row| {
+        Ok(format!("{}: {}", row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+    })?;
+    hits.collect()
+}
+
+// "sanctions: matches DOE, John (SDGT, entry 36) 93%, birth date agrees"
+fn sanctions_hits(conn: &Connection, filter: &str, value: &dyn rusqlite::ToSql) -> rusqlite::Result<Vec<String>> {
+    let mut stmt = conn.prepare(&format!(
+        "SELECT listed_name, program, entry_id, score, dob_corroborated FROM sanctions_hits WHERE {} ORDER BY score DESC",
+        filter
+    ))?;
+    let hits = stmt.query_map([value], |row| {
+        let birth_date = match row.get::<_, Option<bool>>(4)? {
+            Some(true) => "birth date agrees",
+            Some(false) => "birth date differs",
+            None => "no birth date to compare",
+        };
+        Ok(format!(
+            "sanctions: matches {} ({}, entry {}) {}%, {}",
+            row.get::<_, String>(0)?,
+            row.get::<_, String>(1)?,
+            row.get::<_, String>(2)?,
+            row.get::<_, i64>(3)?,
+            birth_date
+        ))
+    })?;
+    hits.collect()
+}
+
+fn escalated(conn: &Connection, kind: ItemKind, id: &str) -> rusqlite::Result<bool> {
+    conn.query_row(
+        "SELECT EXISTS(SELECT 1 FROM review_decisions WHERE item_kind = ?1 AND item_id = ?2 AND decision = 'escalate')",
+        params![kind.as_str(), id],
+        |row| row.get(0),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::init_schema;
+    use crate::limits::LimitViolation;
+
+    // 'new' is a day old, so a transfer of $500 or more from it is held
+    fn database() -> Connection {
+        let conn = Connection::open_in_memory().unwrap();
+        init_schema(&conn).unwrap();
+        conn.execute_batch(
+            "INSERT INTO user_information (id, name, balance, role, created_at) VALUES
+                ('new', 'New', 2000, 'customer', CAST(strftime('%s','now') AS INTEGER) - 86400),
+                ('bob', 'Bob', 0, 'customer', CAST(strftime('%s','now') AS INTEGER) - 86400 * 60),
+                ('rev', 'Reviewer', 0, 'reviewer', CAST(strftime('%s','now') AS INTEGER) - 86400 * 60);
+             UPDATE screening_rules SET enabled = 0 WHERE rule = 'sanctions';",
+        )
+        .unwrap();
+        conn
+    }
+
+    fn hold(conn: &Connection, amount: i64) -> String {
+        let receipt = ledger::execute_transfer(conn, "new", "bob", amount).unwrap();
+        assert_eq!(receipt.status, TransferStatus::Held);
+        receipt.transaction_id.to_string()
+    }
+
+    fn status(conn: &Connection, id: &str) -> String {
+        conn.query_row("SELECT status FROM transaction_history WHERE id = ?1", params![id], |row| row.get(0))
+            .unwrap()
+    }
+
+    fn approve(conn: &Connection, id: &str) -> Result<(), ReviewError> {
+        decide(conn, "rev", ItemKind::Transfer, id, Decision::Approve, "checked")
+    }
+
+    #[test]
+    fn approving_releases_the_transfer() {
+        let conn = database();
+        let id = hold(&conn, 600);
+        let queued = queue(&conn).unwrap();
+        assert_eq!(queued.len(), 1);
+        assert_eq!(queued[0].summary, "$600 from New (new) to Bob (bob)");
+        assert!(queued[0].hits[0].starts_with("new_account_large: "));
+
+        approve(&conn, &id).unwrap();
+        assert_eq!(status(&conn, &id), "completed");
+        assert_eq!(ledger::balance_of(&conn, "new").unwrap(), 2000 - 606);
+        assert_eq!(ledger::balance_of(&conn, "bob").unwrap(), 600);
+        assert!(queue(&conn).unwrap().is_empty());
+        assert!(matches!(approve(&conn, &id), Err(ReviewError::NotPending)));
+
+        let (decision, comment): (String, String) = conn
+            .query_row("SELECT decision, comment FROM review_decisions WHERE item_id = ?1", params![id], |row| {
+                Ok((row.get(0)?, row.get(1)?))
+            })
+            .unwrap();
+        assert_eq!((decision.as_str(), comment.as_str()), ("approve", "checked"));
+        let audited: i64 = conn
+            .query_row("SELECT COUNT(*) FROM audit_log WHERE actor = 'rev' AND action = ?1", params![audit::REVIEW], |row| row.get(0))
+            .unwrap();
+        assert_eq!(audited, 1);
+    }
+
+    #[test]
+    fn rejecting_blocks_it_without_moving_money() {
+        let conn = database();
+        let id = hold(&conn, 600);
+        decide(&conn, "rev", ItemKind::Transfer, &id, Decision::Reject, "mule account").unwrap();
+        assert_eq!(status(&conn, &id), "blocked");
+        assert_eq!(ledger::balance_of(&conn, "new").unwrap(), 2000);
+        assert_eq!(ledger::available_balance(&conn, "new", None).unwrap(), 2000);
+        assert_eq!(ledger::balance_of(&conn, "bob").unwrap(), 0);
+        assert!(queue(&conn).unwrap().is_empty());
+    }
+
+    #[test]
+    fn escalating_keeps_it_in_the_queue() {
+        let conn = database();
+        let id = hold(&conn, 600);
+        decide(&conn, "rev", ItemKind::Transfer, &id, Decision::Escalate, "needs a second look").unwrap();
+        assert_eq!(status(&conn, &id), "held");
+        let queued = queue(&conn).unwrap();
+        assert_eq!(queued.len(), 1);
+        assert!(queued[0].escalated);
+        approve(&conn, &id).unwrap();
+        assert_eq!(status(&conn, &id), "completed");
+    }
+
+    #[test]
+    fn only_other_reviewers_decide_with_a_comment() {
+        let conn = database();
+        let id = hold(&conn, 600);
+        assert!(matches!(
+            decide(&conn, "bob", ItemKind::Transfer, &id, Decision::Approve, "ok"),
+            Err(ReviewError::NotReviewer)
+        ));
+        assert!(matches!(
+            decide(&conn, "rev", ItemKind::Transfer, &id, Decision::Approve, "  "),
+            Err(ReviewError::CommentRequired)
+        ));
+        conn.execute("UPDATE user_information SET role = 'reviewer' WHERE id = 'bob'", []).unwrap();
+        assert!(matches!(
+            decide(&conn, "bob", ItemKind::Transfer, &id, Decision::Approve, "ok"),
+            Err(ReviewError::OwnItem)
+        ));
+        assert_eq!(status(&conn, &id), "held");
+    }
+
+    #[test]
+    fn approval_runs_the_transfer_checks_again() {
+        // frozen while held
+        let conn = database();
+        let id = hold(&conn, 600);
+        conn.execute("UPDATE user_information SET frozen = 1 WHERE id = 'new'", []).unwrap();
+        assert!(matches!(approve(&conn, &id), Err(ReviewError::Transfer(TransferError::AccountFrozen))));
+        assert_eq!(status(&conn, &id), "held");
+        assert_eq!(ledger::balance_of(&conn, "bob").unwrap(), 0);
+
+        // recipient removed while held
+        let conn = database();
+        let id = hold(&conn, 600);
+        conn.execute("DELETE FROM user_information WHERE id = 'bob'", []).unwrap();
+        assert!(matches!(approve(&conn, &id), Err(ReviewError::Transfer(TransferError::UnknownRecipient))));
+
+        // limits lowered while held: level 1 new accounts may send $1000 at once
+        let conn = database();
+        conn.execute("UPDATE user_information SET kyc_level = 2 WHERE id = 'new'", []).unwrap();
+        let id = hold(&conn, 1500);
+        conn.execute("UPDATE user_information SET kyc_level = 1 WHERE id = 'new'", []).unwrap();
+        assert!(matches!(
+            approve(&conn, &id),
+            Err(ReviewError::Transfer(TransferError::Limit(LimitViolation::PerTransaction { limit: 1000 })))
+        ));
+        assert_eq!(status(&conn, &id), "held");
+    }
+
+    #[test]
+    fn held_transfer_is_not_counted_against_itself() {
+        let conn = database();
+        // $1000 of the $2000 daily limit for new level 1 accounts
+        let id = hold(&conn, 1000);
+        // the other $1000 goes out while it waits
+        conn.execute("UPDATE screening_rules SET enabled = 0", []).unwrap();
+        ledger::execute_transfer(&conn, "new", "bob", 900).unwrap();
+        approve(&conn, &id).unwrap();
+        assert_eq!(ledger::balance_of(&conn, "bob").unwrap(), 1900);
+    }
+
+    #[test]
+    fn approving_an_account_unfreezes_it() {
+        let conn = database();
+        conn.execute_batch(
+            "UPDATE user_information SET frozen = 1 WHERE id = 'bob';
+             INSERT INTO sanctions_hits (user_id, context, entry_id, listed_name, program, score, created_at)
+                 VALUES ('bob', 'signup', '36', 'DOE, John', 'SDGT', 91, 0);",
+        )
+        .unwrap();
+        let queued = queue(&conn).unwrap();
+        assert_eq!(queued[0].kind, ItemKind::Account);
+        assert_eq!(queued[0].hits, ["sanctions: matches DOE, John (SDGT, entry 36) 91%, no birth date to compare"]);
+
+        decide(&conn, "rev", ItemKind::Account, "bob", Decision::Approve, "different person").unwrap();
+        let frozen: bool = conn.query_row("SELECT frozen FROM user_information WHERE id = 'bob'", [], |row| row.get(0)).unwrap();
+        assert!(!frozen);
+        assert!(queue(&conn).unwrap().is_empty());
+        assert!(matches!(
+            decide(&conn, "rev", ItemKind::Account, "bob", Decision::Reject, "again"),
+            Err(ReviewError::NotPending)
+        ));
+    }
+}
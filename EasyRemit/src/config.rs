@@ -0,0 +1,591 @@
+use once_cell::sync::OnceCell;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use toml::{Table, Value};
+use tracing_subscriber::EnvFilter;
+use crate::retry::RetryPolicy;
+
+// Settings shared by the app and the tools, read once at startup from a TOML
+// file (see easyremit.example.toml for every key). Each key can be
+// overridden with an environment variable named after it, e.g.
+// `database.path` with EASYREMIT_DATABASE_PATH. Pointing two instances at
+// different files (or setting EASYREMIT_DATABASE_PATH) keeps them apart.
+
+// Read when neither --config nor EASYREMIT_CONFIG names a file. It is fine
+// for it not to exist; the defaults below are used then.
+pub const DEFAULT_FILE: &str = "easyremit.toml";
+
+const ENV_PREFIX: &str = "EASYREMIT_";
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub database: DatabaseConfig,
+    pub app: AppConfig,
+    pub accounts: AccountsConfig,
+    pub validation: ValidationConfig,
+    pub encryption: EncryptionConfig,
+    pub screening: ScreeningConfig,
+    pub cli: CliConfig,
+    pub api: ApiConfig,
+    pub logging: LoggingConfig,
+    pub metrics: MetricsConfig,
+    pub backup: BackupConfig,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DatabaseConfig {
+    pub path: PathBuf,
+    // read-only connections in the pool; 0 runs everything on the writer
+    pub readers: usize,
+    pub retry_attempts: u32,
+    pub retry_initial_delay_ms: u64,
+    pub retry_max_delay_ms: u64,
+    // 0 for no deadline
+    pub retry_deadline_ms: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AppConfig {
+    pub window_width: u32,
+    pub window_height: u32,
+    pub success_sound: PathBuf,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AccountsConfig {
+    // credited to every new account
+    pub signup_bonus: i64,
+}
+
+// Patterns the Signup page checks each field against
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ValidationConfig {
+    pub passport: String,
+    pub birth_date: String,
+    pub password: String,
+}
+
+// AES-128 key and IV for passport numbers and birth dates, 16 characters
+// each. Changing them makes profiles saved with the old ones unreadable.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct EncryptionConfig {
+    pub key: String,
+    pub iv: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ScreeningConfig {
+    pub sanctions_list: PathBuf,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CliConfig {
+    // where `easyremit login` keeps the session token
+    pub session_file: PathBuf,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ApiConfig {
+    pub addr: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LoggingConfig {
+    // error, warn, info, debug or trace
+    pub level: String,
+    // per-module levels on top of `level`, e.g. "easyremit::retry=debug"
+    pub filter: String,
+    // where each program writes its log files; empty for none
+    pub directory: PathBuf,
+    pub rotation: Rotation,
+    // rotated files kept per program
+    pub max_files: usize,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MetricsConfig {
+    // where the desktop app writes its metrics; empty for nowhere.
+    // easyremit-api serves them at GET /metrics instead.
+    pub dump_file: PathBuf,
+    pub dump_interval_secs: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BackupConfig {
+    // where snapshots of `database.path` are written
+    pub directory: PathBuf,
+    // secret the snapshots are encrypted and signed with, at least 16
+    // characters; nothing is backed up without it
+    pub key: String,
+    // between scheduled backups; 0 for on demand only
+    pub interval_mins: u64,
+    // the newest `keep_last` backups are kept, and the newest of each of the
+    // last `keep_daily` days and `keep_weekly` weeks that have one
+    pub keep_last: usize,
+    pub keep_daily: usize,
+    pub keep_weekly: usize,
+}
+
+// When a new log file is started
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Rotation {
+    Minutely,
+    Hourly,
+    #[default]
+    Daily,
+    Never,
+}
+
+impl Default for DatabaseConfig {
+    fn default() -> Self {
+        let retry = RetryPolicy::default();
+        DatabaseConfig {
+            path: PathBuf::from("bank.db"),
+            readers: 4,
+            retry_attempts: retry.max_attempts,
+            retry_initial_delay_ms: retry.initial_delay.as_millis() as u64,
+            retry_max_delay_ms: retry.max_delay.as_millis() as u64,
+            retry_deadline_ms: retry.deadline.map_or(0, |deadline| deadline.as_millis() as u64),
+        }
+    }
+}
+
+impl Default for AppConfig {
+    fn default() -> Self {
+        AppConfig {
+            window_width: 430,
+            window_height: 732,
+            success_sound: PathBuf::from("successsound.wav"),
+        }
+    }
+}
+
+impl Default for AccountsConfig {
+    fn default() -> Self {
+        AccountsConfig { signup_bonus: 500 }
+    }
+}
+
+impl Default for ValidationConfig {
+    fn default() -> Self {
+        ValidationConfig {
+            passport: r"^[A-Z]{2}\d{6}$".to_string(),
+            birth_date: r"^\d{2}/\d{2}/\d{4}$".to_string(),
+            password: r"^\d{6}$".to_string(),
+        }
+    }
+}
+
+impl Default for EncryptionConfig {
+    fn default() -> Self {
+        EncryptionConfig {
+            key: "mysecretkey12345".to_string(),
+            iv: "uniqueiv12345678".to_string(),
+        }
+    }
+}
+
+impl Default for ScreeningConfig {
+    fn default() -> Self {
+        ScreeningConfig { sanctions_list: PathBuf::from("sanctions.csv") }
+    }
+}
+
+impl Default for CliConfig {
+    fn default() -> Self {
+        CliConfig { session_file: PathBuf::from(".easyremit-session") }
+    }
+}
+
+impl Default for ApiConfig {
+    fn default() -> Self {
+        ApiConfig { addr: "127.0.0.1:8080".to_string() }
+    }
+}
+
+impl Default for LoggingConfig {
+    fn default() -> Self {
+        LoggingConfig {
+            level: "info".to_string(),
+            filter: String::new(),
+            directory: PathBuf::from("logs"),
+            rotation: Rotation::Daily,
+            max_files: 7,
+        }
+    }
+}
+
+impl Default for MetricsConfig {
+    fn default() -> Self {
+        MetricsConfig { dump_file: PathBuf::from("metrics.prom"), dump_interval_secs: 60 }
+    }
+}
+
+impl Default for BackupConfig {
+    fn default() -> Self {
+        BackupConfig {
+            directory: PathBuf::from("backups"),
+            key: String::new(),
+            interval_mins: 60,
+            keep_last: 24,
+            keep_daily: 7,
+            keep_weekly: 4,
+        }
+    }
+}
+
+impl LoggingConfig {
+    // `level` and `filter` as one EnvFilter directive string
+    pub fn directives(&self) -> String {
+        match self.filter.trim() {
+            "" => self.level.clone(),
+            filter => format!("{},{}", self.level, filter),
+        }
+    }
+}
+
+impl DatabaseConfig {
+    pub fn retry_policy(&self) -> RetryPolicy {
+        RetryPolicy {
+            max_attempts: self.retry_attempts,
+            initial_delay: Duration::from_millis(self.retry_initial_delay_ms),
+            max_delay: Duration::from_millis(self.retry_max_delay_ms),
+            jitter: true,
+            deadline: Some(Duration::from_millis(self.retry_deadline_ms)).filter(|deadline| !deadline.is_zero()),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Read(PathBuf, std::io::Error),
+    Parse(String, String),
+    Env(String, String),
+    Invalid(String, String),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Read(path, e) => write!(f, "Cannot read config file {}: {}", path.display(), e),
+            ConfigError::Parse(source, e) => write!(f, "Invalid config in {}: {}", source, e.trim_end()),
+            ConfigError::Env(var, e) => write!(f, "Invalid value in {}: {}", var, e),
+            ConfigError::Invalid(key, e) => write!(f, "Invalid config value for `{}`: {}", key, e),
+        }
+    }
+}
+
+static CONFIG: OnceCell<Config> = OnceCell::new();
+
+// Loads the configuration for this process: `path` if given (from
+// --config), else the file named by EASYREMIT_CONFIG, else easyremit.toml if
+// it exists. Call it before anything touches the database.
+pub fn init(path: Option<&Path>) -> Result<&'static Config, ConfigError> {
+    CONFIG.get_or_try_init(|| {
+        let path = path
+            .map(Path::to_path_buf)
+            .or_else(|| std::env::var_os("EASYREMIT_CONFIG").map(PathBuf::from));
+        load(path.as_deref(), std::env::vars())
+    })
+}
+
+// Like `init`, but prints the problem and exits, for use at the top of main
+pub fn init_or_exit(path: Option<&Path>) -> &'static Config {
+    init(path).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(2);
+    })
+}
+
+// The loaded configuration, loading it from the default places if no binary
+// called `init`
+pub fn get() -> &'static Config {
+    init_or_exit(None)
+}
+
+// Removes `--config <file>` (or `--config=<file>`) from arguments parsed by
+// hand and returns the file
+pub fn take_config_arg(args: &mut Vec<String>) -> Option<PathBuf> {
+    let index = args.iter().position(|arg| arg == "--config" || arg.starts_with("--config="))?;
+    let arg = args.remove(index);
+    match arg.strip_prefix("--config=") {
+        Some(path) => Some(PathBuf::from(path)),
+        None if index < args.len() => Some(PathBuf::from(args.remove(index))),
+        None => {
+            eprintln!("--config needs a file name");
+            std::process::exit(2);
+        }
+    }
+}
+
+// `path` must exist if given; without it the default file is optional
+pub fn load(path: Option<&Path>, env: impl Iterator<Item = (String, String)>) -> Result<Config, ConfigError> {
+    let (source, text) = match path {
+        Some(path) => (
+            path.display().to_string(),
+            std::fs::read_to_string(path).map_err(|e| ConfigError::Read(path.to_path_buf(), e))?,
+        ),
+        None => match std::fs::read_to_string(DEFAULT_FILE) {
+            Ok(text) => (DEFAULT_FILE.to_string(), text),
+            // only environment overrides can be wrong then
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => ("EASYREMIT_* variables".to_string(), String::new()),
+            Err(e) => return Err(ConfigError::Read(PathBuf::from(DEFAULT_FILE), e)),
+        },
+    };
+    let mut table: Table = text.parse().map_err(|e: toml::de::Error| ConfigError::Parse(source.clone(), e.to_string()))?;
+    apply_env(&mut table, env)?;
+    let config: Config = table
+        .try_into()
+        .map_err(|e: toml::de::Error| ConfigError::Parse(source, e.to_string()))?;
+    config.validate()?;
+    Ok(config)
+}
+
+// EASYREMIT_<SECTION>_<KEY> replaces `section.key`. The value is read as the
+// type the key's default has, so a key or password made of digits, or the
+// word true, still sets a string.
+fn apply_env(table: &mut Table, env: impl Iterator<Item = (String, String)>) -> Result<(), ConfigError> {
+    let defaults = Table::try_from(Config::default()).expect("the defaults serialize");
+    let mut overrides: Vec<(String, String)> = env.filter(|(var, _)| var.starts_with(ENV_PREFIX)).collect();
+    overrides.sort();
+    for (section, keys) in &defaults {
+        let Value::Table(keys) = keys else { continue };
+        for (key, default) in keys {
+            let var = format!("{}{}_{}", ENV_PREFIX, section, key).to_uppercase();
+            let Some((_, raw)) = overrides.iter().find(|(name, _)| *name == var) else { continue };
+            let value = match default {
+                Value::String(_) => Ok(Value::String(raw.clone())),
+                Value::Integer(_) => raw.trim().parse().map(Value::Integer).map_err(|_| "expected a whole number"),
+                Value::Float(_) => raw.trim().parse().map(Value::Float).map_err(|_| "expected a number"),
+                Value::Boolean(_) => raw.trim().parse().map(Value::Boolean).map_err(|_| "expected true or false"),
+                _ => Err("this key cannot be set from the environment"),
+            }
+            .map_err(|e| ConfigError::Env(var.clone(), e.to_string()))?;
+            match table.entry(section.clone()).or_insert_with(|| Value::Table(Table::new())) {
+                Value::Table(section) => {
+                    section.insert(key.clone(), value);
+                }
+                _ => return Err(ConfigError::Env(var, format!("`{}` in the config file is not a table", section))),
+            }
+        }
+    }
+    Ok(())
+}
+
+impl Config {
+    fn validate(&self) -> Result<(), ConfigError> {
+        let invalid = |key: &str, message: &str| Err(ConfigError::Invalid(key.to_string(), message.to_string()));
+        if self.database.path.as_os_str().is_empty() {
+            return invalid("database.path", "must not be empty");
+        }
+        if self.database.readers > 64 {
+            return invalid("database.readers", "must be at most 64");
+        }
+        if self.database.retry_max_delay_ms < self.database.retry_initial_delay_ms {
+            return invalid("database.retry_max_delay_ms", "must not be less than retry_initial_delay_ms");
+        }
+        if self.app.window_width == 0 || self.app.window_height == 0 {
+            return invalid("app.window_width", "the window must be at least 1x1");
+        }
+        if self.accounts.signup_bonus < 0 {
+            return invalid("accounts.signup_bonus", "must not be negative");
+        }
+        for (key, pattern) in [
+            ("validation.passport", &self.validation.passport),
+            ("validation.birth_date", &self.validation.birth_date),
+            ("validation.password", &self.validation.password),
+        ] {
+            if let Err(e) = Regex::new(pattern) {
+                return Err(ConfigError::Invalid(key.to_string(), e.to_string()));
+            }
+        }
+        if self.encryption.key.len() != 16 {
+            return invalid("encryption.key", "must be exactly 16 bytes");
+        }
+        if self.encryption.iv.len() != 16 {
+            return invalid("encryption.iv", "must be exactly 16 bytes");
+        }
+        if self.api.addr.parse::<std::net::SocketAddr>().is_err() {
+            return invalid("api.addr", "must be an address and port such as 127.0.0.1:8080");
+        }
+        if !["error", "warn", "info", "debug", "trace", "off"].contains(&self.logging.level.as_str()) {
+            return invalid("logging.level", "must be error, warn, info, debug, trace or off");
+        }
+        if let Err(e) = EnvFilter::try_new(self.logging.directives()) {
+            return Err(ConfigError::Invalid("logging.filter".to_string(), e.to_string()));
+        }
+        if self.logging.max_files == 0 {
+            return invalid("logging.max_files", "must be at least 1");
+        }
+        if self.metrics.dump_interval_secs == 0 {
+            return invalid("metrics.dump_interval_secs", "must be at least 1");
+        }
+        if self.backup.directory.as_os_str().is_empty() {
+            return invalid("backup.directory", "must not be empty");
+        }
+        if !self.backup.key.is_empty() && self.backup.key.len() < 16 {
+            return invalid("backup.key", "must be at least 16 characters");
+        }
+        if self.backup.keep_last == 0 {
+            return invalid("backup.keep_last", "must be at least 1");
+        }
+        Ok(())
+    }
+}
+
+impl ValidationConfig {
+    // The patterns were checked when the config was loaded
+    pub fn passport_regex(&self) -> Regex {
+        Regex::new(&self.passport).expect("validated on load")
+    }
+
+    pub fn birth_date_regex(&self) -> Regex {
+        Regex::new(&self.birth_date).expect("validated on load")
+    }
+
+    pub fn password_regex(&self) -> Regex {
+        Regex::new(&self.password).expect("validated on load")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{thread_rng, RngCore};
+
+    // A config file of its own under the system temp directory, removed on
+    // drop
+    struct ConfigFile(PathBuf);
+
+    impl ConfigFile {
+        fn new(text: &str) -> ConfigFile {
+            let path = std::env::temp_dir().join(format!("easyremit-config-{:016x}.toml", thread_rng().next_u64()));
+            std::fs::write(&path, text).unwrap();
+            ConfigFile(path)
+        }
+
+        fn load(&self, env: &[(&str, &str)]) -> Result<Config, ConfigError> {
+            load(Some(&self.0), env.iter().map(|(var, value)| (var.to_string(), value.to_string())))
+        }
+    }
+
+    impl Drop for ConfigFile {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.0);
+        }
+    }
+
+    fn invalid_key(result: Result<Config, ConfigError>) -> String {
+        match result {
+            Err(ConfigError::Invalid(key, _)) => key,
+            other => panic!("expected an invalid value, got {:?}", other.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn the_file_overrides_defaults_and_the_environment_overrides_the_file() {
+        let file = ConfigFile::new("[database]\npath = \"from-file.db\"\nreaders = 2\n\n[api]\naddr = \"127.0.0.1:9000\"\n");
+        let config = file.load(&[]).unwrap();
+        assert_eq!(config.database.path, PathBuf::from("from-file.db"));
+        assert_eq!(config.database.readers, 2);
+        assert_eq!(config.api.addr, "127.0.0.1:9000");
+        assert_eq!(config.accounts.signup_bonus, AccountsConfig::default().signup_bonus);
+
+        let config = file
+            .load(&[
+                ("EASYREMIT_DATABASE_PATH", "from-env.db"),
+                ("EASYREMIT_DATABASE_READERS", "8"),
+                ("EASYREMIT_ACCOUNTS_SIGNUP_BONUS", "0"),
+                ("OTHER_DATABASE_PATH", "ignored.db"),
+            ])
+            .unwrap();
+        assert_eq!(config.database.path, PathBuf::from("from-env.db"));
+        assert_eq!(config.database.readers, 8);
+        assert_eq!(config.accounts.signup_bonus, 0);
+        assert_eq!(config.api.addr, "127.0.0.1:9000");
+    }
+
+    #[test]
+    fn string_overrides_stay_strings() {
+        let file = ConfigFile::new("");
+        let config = file
+            .load(&[
+                ("EASYREMIT_ENCRYPTION_KEY", "1234567890123456"),
+                ("EASYREMIT_ENCRYPTION_IV", "6543210987654321"),
+                ("EASYREMIT_DATABASE_PATH", "true"),
+                ("EASYREMIT_BACKUP_KEY", "[not, an, array]"),
+            ])
+            .unwrap();
+        assert_eq!(config.encryption.key, "1234567890123456");
+        assert_eq!(config.encryption.iv, "6543210987654321");
+        assert_eq!(config.database.path, PathBuf::from("true"));
+        assert_eq!(config.backup.key, "[not, an, array]");
+
+        match file.load(&[("EASYREMIT_DATABASE_READERS", "four")]) {
+            Err(ConfigError::Env(var, _)) => assert_eq!(var, "EASYREMIT_DATABASE_READERS"),
+            other => panic!("expected an environment error, got {:?}", other.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn unknown_keys_are_refused() {
+        for text in ["[database]\npaht = \"typo.db\"\n", "[databse]\npath = \"typo.db\"\n"] {
+            assert!(matches!(ConfigFile::new(text).load(&[]), Err(ConfigError::Parse(..))));
+        }
+        assert!(matches!(ConfigFile::new("[database\n").load(&[]), Err(ConfigError::Parse(..))));
+    }
+
+    #[test]
+    fn bad_values_name_the_key() {
+        let file = ConfigFile::new("[validation]\npassport = \"[A-Z\"\n");
+        assert_eq!(invalid_key(file.load(&[])), "validation.passport");
+
+        let file = ConfigFile::new("");
+        for (var, value, key) in [
+            ("EASYREMIT_ENCRYPTION_KEY", "too short", "encryption.key"),
+            ("EASYREMIT_ENCRYPTION_IV", "seventeen bytes!!", "encryption.iv"),
+            ("EASYREMIT_BACKUP_KEY", "short", "backup.key"),
+            ("EASYREMIT_API_ADDR", "localhost", "api.addr"),
+            ("EASYREMIT_DATABASE_READERS", "65", "database.readers"),
+        ] {
+            assert_eq!(invalid_key(file.load(&[(var, value)])), key, "{}={}", var, value);
+        }
+    }
+
+    #[test]
+    fn config_argument_is_taken_out_of_the_arguments() {
+        let args = |list: &[&str]| list.iter().map(|arg| arg.to_string()).collect::<Vec<_>>();
+
+        let mut separate = args(&["list", "--config", "a.toml", "10"]);
+        assert_eq!(take_config_arg(&mut separate), Some(PathBuf::from("a.toml")));
+        assert_eq!(separate, args(&["list", "10"]));
+
+        let mut joined = args(&["--config=b.toml", "list"]);
+        assert_eq!(take_config_arg(&mut joined), Some(PathBuf::from("b.toml")));
+        assert_eq!(joined, args(&["list"]));
+
+        let mut none = args(&["list"]);
+        assert_eq!(take_config_arg(&mut none), None);
+        assert_eq!(none, args(&["list"]));
+
+        // A file named with --config must exist
+        let missing = std::env::temp_dir().join("easyremit-config-missing.toml");
+        assert!(matches!(load(Some(&missing), std::iter::empty()), Err(ConfigError::Read(path, _)) if path == missing));
+    }
+}
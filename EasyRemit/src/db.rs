@@ -1,46 +1,306 @@
-// 1. In a new file db.rs (create this file):
 use rusqlite::{Connection, Result};
-use std::sync::{Arc, Mutex};
-use once_cell::sync::Lazy;
+use once_cell::sync::{Lazy, OnceCell};
+use std::path::Path;
 use std::time::Duration;
+use crate::config;
+use crate::pool::{Pool, PoolStats};
+use crate::retry::RetryPolicy;
 
-pub static DB_CONN: Lazy<Arc<Mutex<Connection>>> = Lazy::new(|| {
-    let conn = Connection::open("bank.db").expect("Failed to open database");
-    
-    // Configure connection
-    conn.busy_timeout(Duration::from_secs(5))
-        .expect("Failed to set busy timeout");
-    conn.pragma_update(None, "journal_mode", &"WAL")
-        .expect("Failed to enable WAL mode");
-    conn.pragma_update(None, "synchronous", &"NORMAL")
-        .expect("Failed to set synchronous mode");
-    
-    Arc::new(Mutex::new(conn))
-});
-
-pub fn execute_with_retry<T, F>(operation: F, max_retries: u32) -> Result<T>
-where
-    F: Fn() -> Result<T>,
-{
-    let mut last_error = None;
-    
-    for retry in 0..max_retries {
-        match operation() {
-            Ok(result) => return Ok(result),
-            Err(err) => {
-                if let rusqlite::Error::SqliteFailure(error, _) = &err {
-                    if error.code == rusqlite::ErrorCode::DatabaseBusy {
-                        std::thread::sleep(Duration::from_millis(100 * (retry + 1) as u64));
-                        last_error = Some(err);
-                        continue;
-                    }
-                }
-                return Err(err);
-            }
+static POOL: OnceCell<Pool> = OnceCell::new();
+
+// The database named by `database.path` in the config, opened by `init`
+pub static DB_POOL: Lazy<&'static Pool> = Lazy::new(init_or_exit);
+
+// Opens the configured database the first time it is called
+pub fn init() -> Result<&'static Pool> {
+    POOL.get_or_try_init(|| {
+        let config = &config::get().database;
+        let mut pool = Pool::new(&config.path, open(&config.path)?, config.readers);
+        pool.set_retry_policy(config.retry_policy());
+        Ok(pool)
+    })
+}
+
+// Like `init`, but prints the problem and exits, for use at the top of main.
+// Nothing can work without the database.
+pub fn init_or_exit() -> &'static Pool {
+    init().unwrap_or_else(|e| {
+        eprintln!("Cannot open the database {}: {}", config::get().database.path.display(), e);
+        std::process::exit(1);
+    })
+}
+
+// Lock waits and retries of the pool, if `init` has opened it
+pub fn stats() -> Option<PoolStats> {
+    POOL.get().map(Pool::stats)
+}
+
+// Opens the database for writing, with WAL on so readers never block on it,
+// and brings the schema up to date.
+pub fn open(path: impl AsRef<Path>) -> Result<Connection> {
+    let conn = Connection::open(path)?;
+    conn.busy_timeout(Duration::from_secs(5))?;
+    conn.pragma_update(None, "journal_mode", "WAL")?;
+    conn.pragma_update(None, "synchronous", "NORMAL")?;
+    init_schema(&conn)?;
+    Ok(conn)
+}
+
+// Schema migrations, applied in order. `PRAGMA user_version` records how many
+// have already run, so each entry executes exactly once per database file.
+const MIGRATIONS: &[&str] = &[
+    // 1: original tables
+    "CREATE TABLE IF NOT EXISTS user_information(
+        id TEXT PRIMARY KEY,
+        email TEXT,
+        name TEXT,
+        encrypted_passport BLOB,
+        encrypted_birthdate BLOB,
+        hashed_password TEXT,
+        balance INTEGER
+    );
+    CREATE TABLE IF NOT EXISTS transaction_history(
+        sender_id TEXT,
+        amount INTEGER,
+        receiver_id TEXT
+    );",
+    // 2: account age, KYC level and per-tier transfer limits
+    "ALTER TABLE user_information ADD COLUMN created_at INTEGER;
+    ALTER TABLE user_information ADD COLUMN kyc_level INTEGER NOT NULL DEFAULT 1;
+    UPDATE user_information SET created_at = strftime('%s', 'now') WHERE created_at IS NULL;
+    ALTER TABLE transaction_history ADD COLUMN created_at INTEGER;
+    CREATE TABLE transfer_limits(
+        kyc_level INTEGER NOT NULL,
+        min_account_age_days INTEGER NOT NULL,
+        per_transaction INTEGER NOT NULL,
+        daily INTEGER NOT NULL,
+        monthly INTEGER NOT NULL,
+        PRIMARY KEY (kyc_level, min_account_age_days)
+    );
+    INSERT INTO transfer_limits VALUES
+        (1, 0, 1000, 2000, 10000),
+        (1, 30, 2000, 5000, 25000),
+        (2, 0, 5000, 10000, 50000),
+        (2, 30, 10000, 25000, 100000);",
+    // 3: fee schedule, account currency and the house revenue account
+    "ALTER TABLE user_information ADD COLUMN currency TEXT NOT NULL DEFAULT 'USD';
+    ALTER TABLE transaction_history ADD COLUMN kind TEXT NOT NULL DEFAULT 'transfer';
+    CREATE TABLE fee_schedule(
+        id INTEGER PRIMARY KEY,
+        corridor TEXT NOT NULL DEFAULT '*',
+        min_amount INTEGER NOT NULL DEFAULT 0,
+        max_amount INTEGER,
+        flat_fee INTEGER NOT NULL DEFAULT 0,
+        percent_bp INTEGER NOT NULL DEFAULT 0,
+        min_fee INTEGER NOT NULL DEFAULT 0,
+        max_fee INTEGER
+    );
+    INSERT INTO fee_schedule (corridor, min_amount, max_amount, flat_fee, percent_bp, min_fee, max_fee) VALUES
+        ('*', 0, 99, 1, 0, 0, NULL),
+        ('*', 100, 999, 0, 100, 1, NULL),
+        ('*', 1000, NULL, 2, 50, 0, 50),
+        ('USD-THB', 0, NULL, 3, 100, 0, 75);
+    INSERT INTO user_information (id, email, name, balance, created_at)
+        VALUES ('HOUSE_REVENUE', NULL, 'EasyRemit Fees', 0, strftime('%s', 'now'));",
+    // 4: money requests from one account to another
+    "CREATE TABLE payment_requests(
+        id INTEGER PRIMARY KEY,
+        requester_id TEXT NOT NULL,
+        payer_id TEXT NOT NULL,
+        amount INTEGER NOT NULL,
+        memo TEXT NOT NULL DEFAULT '',
+        status TEXT NOT NULL DEFAULT 'pending',
+        created_at INTEGER NOT NULL,
+        expires_at INTEGER NOT NULL,
+        settled_at INTEGER
+    );
+    CREATE INDEX payment_requests_payer ON payment_requests(payer_id, status);",
+    // 5: bills split into one payment request per participant
+    "CREATE TABLE bill_splits(
+        id INTEGER PRIMARY KEY,
+        creator_id TEXT NOT NULL,
+        total INTEGER NOT NULL,
+        memo TEXT NOT NULL DEFAULT '',
+        method TEXT NOT NULL,
+        created_at INTEGER NOT NULL,
+        settled_at INTEGER
+    );
+    CREATE TABLE bill_split_shares(
+        split_id INTEGER NOT NULL REFERENCES bill_splits(id),
+        participant_id TEXT NOT NULL,
+        amount INTEGER NOT NULL,
+        request_id INTEGER REFERENCES payment_requests(id),
+        PRIMARY KEY (split_id, participant_id)
+    );",
+    // 6: per-user notification inbox
+    "CREATE TABLE notifications(
+        id INTEGER PRIMARY KEY,
+        user_id TEXT NOT NULL,
+        kind TEXT NOT NULL,
+        message TEXT NOT NULL,
+        created_at INTEGER NOT NULL,
+        read_at INTEGER
+    );
+    CREATE INDEX notifications_user ON notifications(user_id, read_at);",
+    // 7: hash-chained audit log; the triggers keep honest code from editing
+    // it, `verify-audit` catches anyone who bypasses them
+    "CREATE TABLE audit_log(
+        id INTEGER PRIMARY KEY,
+        actor TEXT NOT NULL,
+        action TEXT NOT NULL,
+        target TEXT NOT NULL,
+        created_at INTEGER NOT NULL,
+        details TEXT NOT NULL,
+        prev_hash TEXT NOT NULL,
+        hash TEXT NOT NULL
+    );
+    CREATE TRIGGER audit_log_no_update BEFORE UPDATE ON audit_log
+    BEGIN
+        SELECT RAISE(ABORT, 'audit_log is append-only');
+    END;
+    CREATE TRIGGER audit_log_no_delete BEFORE DELETE ON audit_log
+    BEGIN
+        SELECT RAISE(ABORT, 'audit_log is append-only');
+    END;",
+    // 8: transaction ids and status, and fraud/AML screening rules and results.
+    // transaction_history is rebuilt so existing rows keep their rowid as id.
+    "CREATE TABLE transaction_history_new(
+        id INTEGER PRIMARY KEY,
+        sender_id TEXT,
+        amount INTEGER,
+        receiver_id TEXT,
+        created_at INTEGER,
+        kind TEXT NOT NULL DEFAULT 'transfer',
+        status TEXT NOT NULL DEFAULT 'completed'
+    );
+    INSERT INTO transaction_history_new (id, sender_id, amount, receiver_id, created_at, kind)
+        SELECT rowid, sender_id, amount, receiver_id, created_at, kind FROM transaction_history;
+    DROP TABLE transaction_history;
+    ALTER TABLE transaction_history_new RENAME TO transaction_history;
+    CREATE INDEX transaction_history_sender ON transaction_history(sender_id, created_at);
+    CREATE INDEX transaction_history_status ON transaction_history(status);
+    ALTER TABLE payment_requests ADD COLUMN transaction_id INTEGER REFERENCES transaction_history(id);
+    CREATE TABLE screening_rules(
+        rule TEXT PRIMARY KEY,
+        enabled INTEGER NOT NULL DEFAULT 1,
+        action TEXT NOT NULL,
+        threshold INTEGER NOT NULL DEFAULT 0,
+        window_secs INTEGER NOT NULL DEFAULT 0,
+        min_count INTEGER NOT NULL DEFAULT 0,
+        margin_percent INTEGER NOT NULL DEFAULT 0
+    );
+    INSERT INTO screening_rules (rule, action, threshold, window_secs, min_count, margin_percent) VALUES
+        ('new_account_large', 'hold', 500, 7 * 86400, 0, 0),
+        ('rapid_fan_out', 'hold', 0, 3600, 5, 0),
+        ('structuring', 'hold', 1000, 86400, 3, 10),
+        ('round_trip', 'hold', 0, 3 * 86400, 0, 10);
+    CREATE TABLE screening_hits(
+        transaction_id INTEGER NOT NULL REFERENCES transaction_history(id),
+        rule TEXT NOT NULL,
+        action TEXT NOT NULL,
+        reason TEXT NOT NULL
+    );
+    CREATE INDEX screening_hits_transaction ON screening_hits(transaction_id);",
+    // 9: sanctions list screening at signup and on transfer recipients.
+    // Accounts that match at signup are opened frozen until reviewed.
+    "ALTER TABLE user_information ADD COLUMN frozen INTEGER NOT NULL DEFAULT 0;
+    INSERT INTO screening_rules (rule, action, threshold) VALUES ('sanctions', 'hold', 88);
+    CREATE TABLE sanctions_hits(
+        id INTEGER PRIMARY KEY,
+        user_id TEXT NOT NULL,
+        context TEXT NOT NULL,
+        transaction_id INTEGER REFERENCES transaction_history(id),
+        entry_id TEXT NOT NULL,
+        listed_name TEXT NOT NULL,
+        program TEXT NOT NULL,
+        score INTEGER NOT NULL,
+        dob_corroborated INTEGER,
+        created_at INTEGER NOT NULL,
+        status TEXT NOT NULL DEFAULT 'open'
+    );
+    CREATE INDEX sanctions_hits_status ON sanctions_hits(status);",
+    // 10: compliance reviewers and their decisions on held transfers and
+    // frozen accounts
+    "ALTER TABLE user_information ADD COLUMN role TEXT NOT NULL DEFAULT 'customer';
+    CREATE TABLE review_decisions(
+        id INTEGER PRIMARY KEY,
+        item_kind TEXT NOT NULL,
+        item_id TEXT NOT NULL,
+        reviewer_id TEXT NOT NULL,
+        decision TEXT NOT NULL,
+        comment TEXT NOT NULL,
+        created_at INTEGER NOT NULL
+    );
+    CREATE INDEX review_decisions_item ON review_decisions(item_kind, item_id);",
+    // 11: login lockouts, and a memo on ledger rows for operator adjustments
+    "ALTER TABLE user_information ADD COLUMN failed_logins INTEGER NOT NULL DEFAULT 0;
+    ALTER TABLE user_information ADD COLUMN locked_until INTEGER;
+    ALTER TABLE transaction_history ADD COLUMN memo TEXT;",
+    // 12: bearer-token sessions for clients other than the GUI
+    "CREATE TABLE sessions(
+        token_hash TEXT PRIMARY KEY,
+        user_id TEXT NOT NULL,
+        created_at INTEGER NOT NULL,
+        expires_at INTEGER NOT NULL
+    );",
+    // 13: responses already sent for an idempotency key, so the API can
+    // replay them when a client retries
+    "CREATE TABLE idempotency_keys(
+        user_id TEXT NOT NULL,
+        key TEXT NOT NULL,
+        request_hash TEXT NOT NULL,
+        status INTEGER NOT NULL,
+        body TEXT NOT NULL,
+        created_at INTEGER NOT NULL,
+        PRIMARY KEY (user_id, key)
+    );",
+    // 14: webhook subscriptions and the outbox their events are delivered from
+    "CREATE TABLE webhook_subscriptions(
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        url TEXT NOT NULL,
+        events TEXT NOT NULL,
+        secret TEXT NOT NULL,
+        active INTEGER NOT NULL DEFAULT 1,
+        created_at INTEGER NOT NULL
+    );
+    CREATE TABLE webhook_outbox(
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        subscription_id INTEGER NOT NULL REFERENCES webhook_subscriptions(id),
+        event_id TEXT NOT NULL,
+        event_type TEXT NOT NULL,
+        payload TEXT NOT NULL,
+        status TEXT NOT NULL DEFAULT 'pending',
+        attempts INTEGER NOT NULL DEFAULT 0,
+        next_attempt_at INTEGER NOT NULL,
+        last_error TEXT,
+        created_at INTEGER NOT NULL,
+        delivered_at INTEGER
+    );
+    CREATE INDEX webhook_outbox_due ON webhook_outbox(status, next_attempt_at);",
+];
+
+// `PRAGMA user_version` of a database this build has fully migrated
+pub const SCHEMA_VERSION: usize = MIGRATIONS.len();
+
+pub fn init_schema(conn: &Connection) -> Result<()> {
+    let applied: usize = conn.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+
+    for (index, migration) in MIGRATIONS.iter().enumerate().skip(applied) {
+        let batch = format!("BEGIN;\n{}\nPRAGMA user_version = {};\nCOMMIT;", migration, index + 1);
+        if let Err(err) = conn.execute_batch(&batch) {
+            let _ = conn.execute_batch("ROLLBACK;");
+            return Err(err);
         }
     }
-    
-    Err(last_error.unwrap())
+    Ok(())
 }
 
-
+// Runs `operation` under the default RetryPolicy with at most
+// `max_attempts` tries. Retries are counted against the caller.
+#[track_caller]
+pub fn execute_with_retry<T, F>(operation: F, max_attempts: u32) -> Result<T>
+where
+    F: Fn() -> Result<T>,
+{
+    RetryPolicy { max_attempts, ..RetryPolicy::default() }.run(operation)
+}
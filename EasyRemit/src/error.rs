@@ -0,0 +1,184 @@
+use std::fmt;
+use crate::auth::AuthError;
+use crate::bill_split::SplitError;
+use crate::compliance::ReviewError;
+use crate::ledger::TransferError;
+use crate::model::ModelError;
+use crate::payment_requests::RequestError;
+use crate::qr::QrError;
+use crate::retry;
+use crate::storage::StorageError;
+
+// What went wrong, sorted by what the user can do about it. Each module keeps
+// its own error type; they all turn into this one where the app shows them.
+//   Validation - something typed in, or asked for, is not allowed; the
+//                message already says why
+//   Auth       - wrong credentials, a locked account, or nobody logged in
+//   Crypto     - hashing or encryption failed
+//   Busy       - the database stayed busy after retrying
+//   Database   - any other storage failure
+// Display gives the message for the user. Crypto and database details are
+// not shown to them; `detail` has them for the log.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AppError {
+    Validation(String),
+    Auth(String),
+    Crypto(String),
+    Busy(String),
+    Database(String),
+}
+
+impl AppError {
+    pub fn not_logged_in() -> Self {
+        AppError::Auth("You are not logged in".to_string())
+    }
+
+    // Failures on our side rather than the user's
+    pub fn is_internal(&self) -> bool {
+        matches!(self, AppError::Crypto(_) | AppError::Busy(_) | AppError::Database(_))
+    }
+
+    pub fn detail(&self) -> &str {
+        match self {
+            AppError::Validation(detail)
+            | AppError::Auth(detail)
+            | AppError::Crypto(detail)
+            | AppError::Busy(detail)
+            | AppError::Database(detail) => detail,
+        }
+    }
+}
+
+impl fmt::Display for AppError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AppError::Validation(message) | AppError::Auth(message) => f.write_str(message),
+            AppError::Crypto(_) => write!(f, "Your details could not be secured. Please try again"),
+            AppError::Busy(_) => write!(f, "EasyRemit is busy right now. Please try again in a moment"),
+            AppError::Database(_) => write!(f, "Something went wrong on our side. Please try again later"),
+        }
+    }
+}
+
+impl std::error::Error for AppError {}
+
+impl From<rusqlite::Error> for AppError {
+    fn from(e: rusqlite::Error) -> Self {
+        if retry::is_retryable(&e) {
+            AppError::Busy(e.to_string())
+        } else {
+            AppError::Database(e.to_string())
+        }
+    }
+}
+
+impl From<ModelError> for AppError {
+    fn from(e: ModelError) -> Self {
+        AppError::Validation(e.to_string())
+    }
+}
+
+impl From<AuthError> for AppError {
+    fn from(e: AuthError) -> Self {
+        match e {
+            AuthError::InvalidCredentials | AuthError::LockedOut { .. } => AppError::Auth(e.to_string()),
+            AuthError::Hashing(_) | AuthError::Encryption(_) => AppError::Crypto(e.to_string()),
+            AuthError::Database(e) => e.into(),
+        }
+    }
+}
+
+impl From<TransferError> for AppError {
+    fn from(e: TransferError) -> Self {
+        match e {
+            TransferError::Database(e) => e.into(),
+            e => AppError::Validation(e.to_string()),
+        }
+    }
+}
+
+impl From<StorageError> for AppError {
+    fn from(e: StorageError) -> Self {
+        match e {
+            StorageError::Auth(e) => e.into(),
+            StorageError::Transfer(e) => e.into(),
+            StorageError::Request(e) => e.into(),
+            StorageError::Split(e) => e.into(),
+            StorageError::Database(e) => AppError::Database(e),
+        }
+    }
+}
+
+impl From<RequestError> for AppError {
+    fn from(e: RequestError) -> Self {
+        match e {
+            RequestError::Transfer(e) => e.into(),
+            RequestError::Database(e) => e.into(),
+            e => AppError::Validation(e.to_string()),
+        }
+    }
+}
+
+impl From<SplitError> for AppError {
+    fn from(e: SplitError) -> Self {
+        match e {
+            SplitError::Database(e) | SplitError::Request(_, RequestError::Database(e)) => e.into(),
+            e => AppError::Validation(e.to_string()),
+        }
+    }
+}
+
+impl From<ReviewError> for AppError {
+    fn from(e: ReviewError) -> Self {
+        match e {
+            ReviewError::NotReviewer => AppError::Auth(e.to_string()),
+            ReviewError::Transfer(e) => e.into(),
+            ReviewError::Database(e) => e.into(),
+            e => AppError::Validation(e.to_string()),
+        }
+    }
+}
+
+// Payment QR codes come from the user's own files and camera
+impl From<QrError> for AppError {
+    fn from(e: QrError) -> Self {
+        AppError::Validation(e.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rusqlite::ffi;
+
+    fn sqlite_error(code: i32) -> rusqlite::Error {
+        rusqlite::Error::SqliteFailure(ffi::Error::new(code), Some("details".to_string()))
+    }
+
+    #[test]
+    fn internal_details_are_not_shown() {
+        let busy = AppError::from(sqlite_error(ffi::SQLITE_BUSY));
+        assert!(matches!(busy, AppError::Busy(_)));
+        let broken = AppError::from(TransferError::Database(sqlite_error(ffi::SQLITE_CORRUPT)));
+        assert!(matches!(broken, AppError::Database(_)));
+        assert!(broken.is_internal());
+        assert!(broken.detail().contains("details"));
+        assert!(!broken.to_string().contains("details"));
+        let hashing = AppError::from(StorageError::Auth(AuthError::Hashing("salt too short".to_string())));
+        assert_eq!(hashing.to_string(), "Your details could not be secured. Please try again");
+    }
+
+    #[test]
+    fn user_errors_keep_their_message() {
+        let funds = TransferError::InsufficientFunds { balance: 5, required: 11 };
+        let message = funds.to_string();
+        assert_eq!(AppError::from(RequestError::Transfer(funds)), AppError::Validation(message));
+        assert_eq!(
+            AppError::from(AuthError::LockedOut { minutes: 15 }).to_string(),
+            "Too many failed logins. Try again in 15 minute(s)"
+        );
+        assert_eq!(AppError::from(ModelError::AccountId).to_string(), "A user ID is 12 digits");
+        assert_eq!(AppError::from(ReviewError::NotReviewer), AppError::Auth("Only compliance reviewers can do this".to_string()));
+        assert!(!AppError::not_logged_in().is_internal());
+    }
+}
@@ -0,0 +1,181 @@
+use rusqlite::{params, Connection, OptionalExtension, Result};
+use std::fmt;
+
+// Account that collects every fee charged on a transfer.
+pub const HOUSE_ACCOUNT_ID: &str = "HOUSE_REVENUE";
+
+// Fee for one transfer, split into the parts the `fee_schedule` row defines so
+// the Transfer and Success pages can show how it was reached.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FeeQuote {
+    pub amount: i64,
+    pub flat_fee: i64,
+    pub percentage_fee: i64,
+    pub percent_bp: i64,
+    pub fee: i64,
+}
+
+impl FeeQuote {
+    // What the sender is charged in total.
+    pub fn total(&self) -> i64 {
+        self.amount.saturating_add(self.fee)
+    }
+}
+
+impl fmt::Display for FeeQuote {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.fee == 0 {
+            return write!(f, "No fee");
+        }
+        let mut parts = Vec::new();
+        if self.flat_fee > 0 {
+            parts.push(format!("${} flat", self.flat_fee));
+        }
+        if self.percent_bp > 0 {
+            parts.push(format!(
+                "${} ({}.{:02}%)",
+                self.percentage_fee,
+                self.percent_bp / 100,
+                self.percent_bp % 100
+            ));
+        }
+        if parts.is_empty() {
+            return write!(f, "Fee ${} (minimum fee)", self.fee);
+        }
+        // min_fee / max_fee can move the fee away from the sum of its parts
+        let computed = self.flat_fee.saturating_add(self.percentage_fee);
+        let adjustment = if computed < self.fee {
+            ", minimum fee applies"
+        } else if computed > self.fee {
+            ", capped"
+        } else {
+            ""
+        };
+        write!(f, "Fee ${} ({}{})", self.fee, parts.join(" + "), adjustment)
+    }
+}
+
+// One row of `fee_schedule`
+pub(crate) struct FeeRule {
+    pub(crate) flat_fee: i64,
+    pub(crate) percent_bp: i64,
+    pub(crate) min_fee: i64,
+    pub(crate) max_fee: Option<i64>,
+}
+
+impl FeeRule {
+    pub(crate) fn price(&self, amount: i64) -> FeeQuote {
+        // Basis points, rounded half up to a whole unit. In i128 so that
+        // no amount can overflow.
+        let percentage_fee = (i128::from(amount) * i128::from(self.percent_bp) + 5_000) / 10_000;
+        let percentage_fee = i64::try_from(percentage_fee).unwrap_or(i64::MAX);
+        let mut fee = self.flat_fee.saturating_add(percentage_fee).max(self.min_fee);
+        if let Some(max_fee) = self.max_fee {
+            fee = fee.min(max_fee);
+        }
+        FeeQuote {
+            amount,
+            flat_fee: self.flat_fee,
+            percentage_fee,
+            percent_bp: self.percent_bp,
+            fee,
+        }
+    }
+}
+
+pub fn corridor(conn: &Connection, sender_id: &str, receiver_id: &str) -> Result<String> {
+    let currency_of = |id: &str| -> Result<String> {
+        conn.query_row(
+            "SELECT currency FROM user_information WHERE id = ?1",
+            params![id],
+            |row| row.get(0),
+        )
+        .optional()
+        .map(|currency| currency.unwrap_or_else(|| "USD".to_string()))
+    };
+    Ok(format!("{}-{}", currency_of(sender_id)?, currency_of(receiver_id)?))
+}
+
+// Picks the schedule row for the amount band, preferring a row for the exact
+// corridor over the '*' default, and prices the transfer with it.
+pub fn quote(conn: &Connection, sender_id: &str, receiver_id: &str, amount: i64) -> Result<FeeQuote> {
+    let corridor = corridor(conn, sender_id, receiver_id)?;
+    let rule = conn
+        .query_row(
+            "SELECT flat_fee, percent_bp, min_fee, max_fee FROM fee_schedule
+             WHERE (corridor = ?1 OR corridor = '*')
+               AND min_amount <= ?2 AND (max_amount IS NULL OR max_amount >= ?2)
+             ORDER BY corridor = '*' ASC, min_amount DESC LIMIT 1",
+            params![corridor, amount],
+            |row| {
+                Ok(FeeRule {
+                    flat_fee: row.get(0)?,
+                    percent_bp: row.get(1)?,
+                    min_fee: row.get(2)?,
+                    max_fee: row.get(3)?,
+                })
+            },
+        )
+        .optional()?;
+
+    Ok(match rule {
+        Some(rule) => rule.price(amount),
+        None => FeeQuote { amount, flat_fee: 0, percentage_fee: 0, percent_bp: 0, fee: 0 },
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::init_schema;
+
+    fn rule(flat_fee: i64, percent_bp: i64, min_fee: i64, max_fee: Option<i64>) -> FeeRule {
+        FeeRule { flat_fee, percent_bp, min_fee, max_fee }
+    }
+
+    #[test]
+    fn percentage_rounds_half_up_and_clamps() {
+        let percent = rule(0, 150, 0, None);
+        // 1.5% of 100 is 1.5, of 99 is 1.485, of 101 is 1.515
+        assert_eq!(percent.price(100).percentage_fee, 2);
+        assert_eq!(percent.price(99).percentage_fee, 1);
+        assert_eq!(percent.price(101).percentage_fee, 2);
+        assert_eq!(percent.price(33).percentage_fee, 0);
+
+        let clamped = rule(1, 100, 3, Some(10));
+        assert_eq!(clamped.price(50).fee, 3);
+        assert_eq!(clamped.price(500).fee, 6);
+        assert_eq!(clamped.price(5_000).fee, 10);
+        assert_eq!(clamped.price(5_000).to_string(), "Fee $10 ($1 flat + $50 (1.00%), capped)");
+        assert_eq!(clamped.price(50).to_string(), "Fee $3 ($1 flat + $1 (1.00%), minimum fee applies)");
+
+        // no overflow, however large the amount
+        let quote = rule(1, 10_000, 0, None).price(i64::MAX);
+        assert_eq!(quote.fee, i64::MAX);
+        assert_eq!(quote.total(), i64::MAX);
+    }
+
+    #[test]
+    fn corridor_rows_win_over_the_default() {
+        let conn = Connection::open_in_memory().unwrap();
+        init_schema(&conn).unwrap();
+        conn.execute_batch(
+            "INSERT INTO user_information (id, balance, currency) VALUES
+                ('us1', 0, 'USD'), ('us2', 0, 'USD'), ('th', 0, 'THB');",
+        )
+        .unwrap();
+        assert_eq!(corridor(&conn, "us1", "th").unwrap(), "USD-THB");
+        // default bands: $1 flat under 100, 1% (at least $1) to 999, $2 + 0.5% capped at $50 from 1000
+        assert_eq!(quote(&conn, "us1", "us2", 50).unwrap().fee, 1);
+        assert_eq!(quote(&conn, "us1", "us2", 100).unwrap().fee, 1);
+        assert_eq!(quote(&conn, "us1", "us2", 999).unwrap().fee, 10);
+        assert_eq!(quote(&conn, "us1", "us2", 1_000).unwrap().fee, 7);
+        assert_eq!(quote(&conn, "us1", "us2", 100_000).unwrap().fee, 50);
+        // USD-THB has its own row for every amount: $3 + 1%, capped at $75
+        assert_eq!(quote(&conn, "us1", "th", 50).unwrap().fee, 4);
+        assert_eq!(quote(&conn, "us1", "th", 1_000).unwrap().fee, 13);
+        assert_eq!(quote(&conn, "us1", "th", 100_000).unwrap().fee, 75);
+        // the other way round is not that corridor
+        assert_eq!(quote(&conn, "th", "us1", 50).unwrap().fee, 1);
+    }
+}
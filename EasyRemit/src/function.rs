@@ -1,76 +1,98 @@
-use iced::{button, Button, Column, Container, Element, Text};
-use iced::{Length, Row, alignment::Horizontal, Alignment, scrollable, Scrollable,Background, Color};
+use iced::{button, Button, Column, Command, Container, Element, Text};
+use iced::{Length, Row, Space, alignment::Horizontal, Alignment, scrollable, Scrollable,Background, Color};
 use crate::Message;
-use crate::get_user_id;
-use rusqlite::{params, Connection, Result};
-use std::sync::{Arc, Mutex};
-use crate::db::execute_with_retry;
-use aes::{Aes128};
-use block_modes::{BlockMode, Cbc};
-use block_modes::block_padding::Pkcs7;
-use base64::decode;
-
-
-
-type Aes128Cbc = Cbc<Aes128, Pkcs7>;
-
-
-#[derive(Debug)]
-struct AppError(String);
-
-impl std::fmt::Display for AppError {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}", self.0)
-    }
+use crate::payment_requests::PaymentRequest;
+use crate::compliance;
+use crate::error::AppError;
+use crate::ledger::TransferStatus;
+use crate::model::{Transaction, User};
+use crate::qr::{self, PaymentPayload};
+use crate::storage::StorageError;
+use crate::tasks::{loading_text, read_user_db, report, with_user_storage, Loadable};
+
+// Everything the page shows, loaded together when the page opens
+#[derive(Debug, Clone)]
+pub struct Dashboard {
+    user: User,
+    history: Vec<Transaction>,
+    unread: i64,
+    is_reviewer: bool,
+    pending_requests: Vec<PaymentRequest>,
+    // the account's receive QR code
+    account_qr: Option<Vec<Vec<bool>>>,
 }
 
-fn decrypt(encrypted_data: &[u8], key: &[u8; 16], iv: &[u8; 16]) -> Result<Vec<u8>, AppError> {
-    let cipher = Aes128Cbc::new_from_slices(key, iv).map_err(|e| AppError(e.to_string()))?;
-    let mut buffer = encrypted_data.to_vec();
-    let decrypted_data = cipher.decrypt(&mut buffer).map_err(|e| AppError(e.to_string()))?;
-    
-    Ok(decrypted_data.to_vec())
-}
 #[derive(Debug, Clone)]
 pub struct FunctionPage {
     transfer_button: button::State,
+    request_button: button::State,
+    split_button: button::State,
+    inbox_button: button::State,
+    review_button: button::State,
     scrollable_state: scrollable::State,
     logout_button:button::State,
+    // Pay / Decline buttons for each pending payment request
+    pending_request_buttons: Vec<(button::State, button::State)>,
+    dashboard: Loadable<Dashboard>,
 }
 
 impl FunctionPage {
-    pub fn new() -> Self {
-        FunctionPage {
+    pub fn new() -> (Self, Command<Message>) {
+        let page = FunctionPage {
             transfer_button: button::State::new(),
+            request_button: button::State::new(),
+            split_button: button::State::new(),
+            inbox_button: button::State::new(),
+            review_button: button::State::new(),
             scrollable_state: scrollable::State::new(),
             logout_button:button::State::new(),
-        }
-    }
-
-    pub fn view<'a>(&'a mut self, db_conn: &'a Arc<Mutex<Connection>>) -> Element<'a, super::Message> {
-        let conn = db_conn.lock().expect("Failed to acquire lock");
-        let user_id=match get_user_id(){
-            Some(id)=>id,
-            None=>"NULL".to_owned(),
+            pending_request_buttons: Vec::new(),
+            dashboard: Loadable::Loading,
         };
+        (page, FunctionPage::load())
+    }
 
-        let user_name = execute_with_retry(|| {
-            conn.query_row(
-                "SELECT name FROM user_information WHERE id = ?1",
-                params![user_id.clone()],
-                |row| row.get::<_, String>(0),
-            )
-        }, 3).unwrap_or_else(|_| "Null".to_string());
+    pub fn load() -> Command<Message> {
+        Command::perform(load_dashboard(), Message::DashboardLoaded)
+    }
 
-        let balance = execute_with_retry(|| {
-            conn.query_row(
-                "SELECT balance FROM user_information WHERE id = ?1",
-                params![user_id.clone()],
-                |row| row.get::<_, i64>(0),
-            )
-        }, 3).unwrap_or(0);
+    pub fn loaded(&mut self, result: Result<Dashboard, String>) {
+        self.dashboard = Loadable::from_result(result);
+    }
 
+    // Declining happens in the background and the page reloads afterwards.
+    pub fn decline(&mut self, request_id: i64) -> Command<Message> {
+        Command::perform(
+            with_user_storage(move |storage, payer_id| storage.decline_request(request_id, payer_id)),
+            Message::RequestDeclined,
+        )
+    }
 
+    pub fn view(&mut self) -> Element<'_, super::Message> {
+        let dashboard = match &self.dashboard {
+            Loadable::Loaded(dashboard) => dashboard,
+            state => {
+                let status = loading_text(state, "your account");
+                let logout_button = Button::new(&mut self.logout_button, Text::new("Log Out"))
+                    .padding(1)
+                    .style(LogOutButtonStyle)
+                    .on_press(Message::GoToHome);
+                return Container::new(
+                    Column::new()
+                        .spacing(20)
+                        .padding(30)
+                        .align_items(Alignment::Center)
+                        .push(status)
+                        .push(logout_button),
+                )
+                .width(Length::Fill)
+                .height(Length::Fill)
+                .center_x()
+                .center_y()
+                .into();
+            }
+        };
+        let user = &dashboard.user;
 
         let total_balance = Container::new(
             Row::new()
@@ -82,59 +104,23 @@ impl FunctionPage {
                         .spacing(5)
                         .align_items(Alignment::Start)
                         .push(Text::new("TOTAL BALANCE").size(14).color([0.6, 0.6, 0.6]))
-                        .push(Text::new(balance.to_string()).size(25).color([0.1, 0.1, 0.1])),
+                        .push(Text::new(user.balance.to_string()).size(25).color([0.1, 0.1, 0.1])),
                 ),
         )
         .width(Length::Fill)
         .padding(20)
         .style(styles::Card);
-        const KEY: [u8; 16] = *b"mysecretkey12345";
-        const IV: [u8; 16] = *b"uniqueiv12345678";
-        let encrypted_birth_date=execute_with_retry(|| {
-            conn.query_row(
-                "SELECT encrypted_birthdate FROM user_information WHERE id = ?1",
-                params![user_id.clone()],
-                |row| row.get::<_, String>(0),
-            )
-        }, 3).unwrap_or_else(|_| "Null".to_string());
-
-        let encrypted_birthdate_bytes = decode(encrypted_birth_date).map_err(|e| AppError(e.to_string())).unwrap();
-
-        let encrypted_passport=execute_with_retry(|| {
-            conn.query_row(
-                "SELECT encrypted_passport FROM user_information WHERE id = ?1",
-                params![user_id.clone()],
-                |row| row.get::<_, String>(0),
-            )
-        }, 3).unwrap_or_else(|_| "Null".to_string());
-
-
-        let encrypted_passport_bytes = decode(encrypted_passport).map_err(|e| AppError(e.to_string())).unwrap();
-        let birth_date=match decrypt(&encrypted_birthdate_bytes, &KEY, &IV){
-            Ok(i)=>String::from_utf8(i)
-            .unwrap_or_else(|_| "Invalid UTF-8 data".to_string()),
-            Err(_)=>"00/00/0000".to_string(),
-
-        };
-
-        let passport=match decrypt(&encrypted_passport_bytes, &KEY, &IV){
-            Ok(i)=>String::from_utf8(i)
-            .unwrap_or_else(|_| "Invalid UTF-8 data".to_string()),
-            Err(_)=>"AA000000".to_string(),
-
-        };
-        let userid_text=format!("Account Number : {}",user_id.clone());
-        let birth_text=format!("Birth Date : {}", birth_date);
-        let passport_text=format!("Passport : {}", passport);
-
+        let userid_text=format!("Account Number : {}", user.id);
+        let birth_text=format!("Birth Date : {}", shown(user.birth_date.as_ref().map(|date| date.as_str())));
+        let passport_text=format!("Passport : {}", shown(user.passport.as_ref().map(|passport| passport.as_str())));
 
         // Card information section
-        let card_info = Container::new(
-            Column::new()
+        let card_details = Column::new()
+                .width(Length::Fill)
                 .spacing(8)
                 .push(
                     Row::new()
-                        .push(Text::new(user_name.clone()).size(22).color([0.1, 0.1, 0.1]))
+                        .push(Text::new(user.name.clone().unwrap_or_default()).size(22).color([0.1, 0.1, 0.1]))
                         .push(
                             Text::new("VISA")
                                 .size(20)
@@ -146,49 +132,44 @@ impl FunctionPage {
                 .push(Text::new(userid_text).size(17).color([0.5, 0.5, 0.5]))
                 .push(Text::new(birth_text).size(13).color([0.5, 0.5, 0.5]))
                 .push(Text::new(passport_text).size(13).color([0.5, 0.5, 0.5]))
-                .push(Text::new("Expiry  : 10/30").size(13).color([0.5, 0.5, 0.5]))
-        )
+                .push(Text::new("Expiry  : 10/30").size(13).color([0.5, 0.5, 0.5]));
+        let mut card_row = Row::new().spacing(10).align_items(Alignment::Center).push(card_details);
+        if let Some(modules) = &dashboard.account_qr {
+            card_row = card_row.push(qr_code_view(modules, 3));
+        }
+        let card_info = Container::new(card_row)
         .padding(20)
         .width(Length::Fill)
         .style(styles::Card);
 
-        // Create a vector to hold transaction rows
-        let transaction_rows = execute_with_retry(|| {
-            let mut rows = Vec::new();
-            let mut stmt = conn.prepare(
-                "SELECT th.sender_id, th.amount, th.receiver_id,
-                        COALESCE(s.name, 'New User''s Privilege') as sender_name,
-                        COALESCE(r.name, 'New User''s Privilege') as receiver_name
-                FROM transaction_history th
-                LEFT JOIN user_information s ON th.sender_id = s.id
-                LEFT JOIN user_information r ON th.receiver_id = r.id
-                WHERE th.sender_id = ?1 OR th.receiver_id = ?1",
-            )?;
-
-            let transactions = stmt.query_map(params![user_id.clone()], |row| {
-                Ok((
-                    row.get::<_, String>(0)?,  // sender_id
-                    row.get::<_, i64>(1)?,     // amount
-                    row.get::<_, String>(2)?,  // receiver_id
-                    row.get::<_, String>(3)?,  // sender_name
-                    row.get::<_, String>(4)?,  // receiver_name
-                ))
-            })?;
-           
-            for transaction in transactions {
-                let (sender_id, amount, receiver_id, sender_name, receiver_name) =
-                    transaction.expect("Failed to retrieve transaction");
-
-                let (amount_str, related_name) = if sender_id == user_id.clone() {
-                    (format!("-${}", amount), receiver_name)
+        let transaction_rows: Vec<Row<Message>> = dashboard
+            .history
+            .iter()
+            .map(|entry| {
+                let amount_str = if entry.outgoing && entry.status != TransferStatus::Completed {
+                    format!("-${} ({})", entry.amount, entry.status.as_str())
+                } else if entry.outgoing {
+                    format!("-${}", entry.amount)
                 } else {
-                    (format!("+${}", amount), sender_name)
+                    format!("+${}", entry.amount)
                 };
-
-                rows.push(transaction_row(related_name, amount_str));
-            }
-            Ok(rows)
-        }, 3).unwrap_or_default();
+                transaction_row(entry.counterparty.name.clone(), amount_str)
+            })
+            .collect();
+
+        let unread = dashboard.unread;
+        self.pending_request_buttons.resize_with(dashboard.pending_requests.len(), Default::default);
+
+        let mut requests_column = Column::new()
+            .spacing(8)
+            .push(Text::new("Payment Requests").size(20).color([0.1, 0.1, 0.1]));
+        for (request, (pay_state, decline_state)) in dashboard.pending_requests.iter().zip(self.pending_request_buttons.iter_mut()) {
+            requests_column = requests_column.push(pending_request_row(request, pay_state, decline_state));
+        }
+        let requests_container = Container::new(requests_column)
+            .padding(15)
+            .width(Length::Fill)
+            .style(styles::Card);
 
         let mut transactions_column = Column::new().spacing(15);
         for row in transaction_rows {
@@ -215,20 +196,58 @@ impl FunctionPage {
             .padding(12)
             .style(styles::TransferButton);
 
+        let request_button = Button::new(&mut self.request_button, Text::new("Request"))
+            .on_press(super::Message::GoToRequest)
+            .padding(12)
+            .style(styles::TransferButton);
+
+        let split_button = Button::new(&mut self.split_button, Text::new("Split"))
+            .on_press(super::Message::GoToSplit)
+            .padding(12)
+            .style(styles::TransferButton);
+
+        let inbox_label = if unread > 0 {
+            format!("Inbox ({})", unread)
+        } else {
+            "Inbox".to_string()
+        };
+        let inbox_button = Button::new(&mut self.inbox_button, Text::new(inbox_label))
+            .on_press(super::Message::GoToInbox)
+            .padding(12)
+            .style(styles::InboxButton { unread: unread > 0 });
+
+        let action_buttons = Row::new()
+            .spacing(20)
+            .push(transfer_button)
+            .push(request_button)
+            .push(split_button)
+            .push(inbox_button);
+
         let logout_button = Button::new(&mut self.logout_button, Text::new("Log Out"))
             .padding(1)
             .style(LogOutButtonStyle)
             .on_press(Message::GoToHome);
 
-        let content = Column::new()
+        let mut content = Column::new()
             .spacing(13)
             .align_items(Alignment::Center)
             .push(total_balance)
             .push(card_info)
-            .padding(30)
+            .padding(30);
+        if !dashboard.pending_requests.is_empty() {
+            content = content.push(requests_container);
+        }
+        let mut content = content
             .push(transactions_container)
-            .push(transfer_button)
-            .push(logout_button);
+            .push(action_buttons);
+        if dashboard.is_reviewer {
+            let review_button = Button::new(&mut self.review_button, Text::new("Review queue"))
+                .on_press(super::Message::GoToReview)
+                .padding(12)
+                .style(styles::TransferButton);
+            content = content.push(review_button);
+        }
+        let content = content.push(logout_button);
 
         Container::new(content)
             .padding(20)
@@ -239,6 +258,30 @@ impl FunctionPage {
     }
 }
 
+async fn load_dashboard() -> Result<Dashboard, String> {
+    let (user, history, unread, pending_requests) = with_user_storage(|storage, user_id| {
+        Ok::<_, StorageError>((
+            storage.profile(user_id)?,
+            storage.history(user_id)?,
+            storage.unread_notifications(user_id)?,
+            storage.pending_requests(user_id)?,
+        ))
+    })
+    .await?;
+    // nothing is stored under the logged-in ID
+    let user = user.ok_or_else(|| report(AppError::Auth("Your account could not be found".to_string())))?;
+    // reviewers are recorded with the compliance queue, in SQLite
+    let is_reviewer = read_user_db(|conn, user_id| compliance::is_reviewer(conn, user_id.as_str())).await?;
+    let account_qr = qr::modules(&PaymentPayload::for_user(&user)).ok();
+    Ok(Dashboard {
+        user,
+        history,
+        unread,
+        is_reviewer,
+        pending_requests,
+        account_qr,
+    })
+}
 
 // Placeholder icon (a simple circle)
 fn icon<'a>() -> Element<'a, Message> {
@@ -294,13 +337,34 @@ mod styles {
         }
     }
 
-    pub struct ButtonContainer;
+    // Turns orange while there are unread notifications.
+    pub struct InboxButton {
+        pub unread: bool,
+    }
 
-    impl container::StyleSheet for ButtonContainer {
+    impl button::StyleSheet for InboxButton {
+        fn active(&self) -> button::Style {
+            button::Style {
+                background: Some(Background::Color(if self.unread {
+                    Color::from_rgb(0.9, 0.45, 0.1)
+                } else {
+                    Color::from_rgb(0.1, 0.3, 0.6)
+                })),
+                border_radius: 8.0,
+                text_color: Color::WHITE,
+                ..button::Style::default()
+            }
+        }
+    }
+
+    pub struct QrModule {
+        pub dark: bool,
+    }
+
+    impl container::StyleSheet for QrModule {
         fn style(&self) -> container::Style {
             container::Style {
-                background: Some(Background::Color(Color::from_rgb(0.1, 0.3, 0.6))),
-                border_radius: 15.0,
+                background: Some(Background::Color(if self.dark { Color::BLACK } else { Color::WHITE })),
                 ..container::Style::default()
             }
         }
@@ -323,6 +387,85 @@ fn transaction_row<'a>(name: String, amount: String) -> Row<'a, Message> {
         )
 }
 
+// A pending payment request with buttons to pay or decline it
+fn pending_request_row<'a>(
+    request: &PaymentRequest,
+    pay_state: &'a mut button::State,
+    decline_state: &'a mut button::State,
+) -> Row<'a, Message> {
+    let days_left = (request.expires_at - now_secs()).max(0) / 86400;
+    let label = if request.memo.is_empty() {
+        format!("{} asks ${}", request.requester_name, request.amount)
+    } else {
+        format!("{} asks ${} for {}", request.requester_name, request.amount, request.memo)
+    };
+
+    Row::new()
+        .spacing(8)
+        .align_items(Alignment::Center)
+        .push(
+            Column::new()
+                .width(Length::Fill)
+                .push(Text::new(label).size(15).color([0.1, 0.1, 0.1]))
+                .push(
+                    Text::new(format!("{} · expires in {} days", request.requester_id, days_left))
+                        .size(12)
+                        .color([0.5, 0.5, 0.5]),
+                ),
+        )
+        .push(
+            Button::new(pay_state, Text::new("Pay").size(15))
+                .padding(6)
+                .style(styles::TransferButton)
+                .on_press(Message::ApproveRequest(request.id)),
+        )
+        .push(
+            Button::new(decline_state, Text::new("Decline").size(15))
+                .padding(6)
+                .style(LogOutButtonStyle)
+                .on_press(Message::DeclineRequest(request.id)),
+        )
+}
+
+// Draws a QR code from its modules, each row as runs of light and dark
+// blocks, with a quiet zone around it.
+pub fn qr_code_view<'a>(modules: &[Vec<bool>], module_px: u16) -> Element<'a, Message> {
+    let mut column = Column::new();
+    for row in modules {
+        let mut runs: Vec<(bool, u16)> = Vec::new();
+        for &dark in row {
+            match runs.last_mut() {
+                Some((colour, len)) if *colour == dark => *len += 1,
+                _ => runs.push((dark, 1)),
+            }
+        }
+        let mut line = Row::new();
+        for (dark, len) in runs {
+            line = line.push(
+                Container::new(Space::new(Length::Units(len * module_px), Length::Units(module_px)))
+                    .style(styles::QrModule { dark }),
+            );
+        }
+        column = column.push(line);
+    }
+    Container::new(column)
+        .padding(module_px * 2)
+        .style(styles::QrModule { dark: false })
+        .into()
+}
+
+// A detail that could not be read back is left out rather than made up
+fn shown(value: Option<&str>) -> &str {
+    value.unwrap_or("Not available")
+}
+
+fn now_secs() -> i64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or(0)
+}
+
 struct LogOutButtonStyle;
 impl iced::button::StyleSheet for LogOutButtonStyle {
     fn active(&self) -> iced::button::Style {
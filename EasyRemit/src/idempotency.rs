@@ -0,0 +1,51 @@
+use rusqlite::{params, Connection, OptionalExtension};
+use sha2::{Digest, Sha256};
+
+// How long a key is remembered. A retry after this is treated as new.
+pub const KEY_TTL_SECS: i64 = 24 * 60 * 60;
+
+// The response sent the first time a key was used
+#[derive(Debug, Clone)]
+pub struct StoredResponse {
+    pub request_hash: String,
+    pub status: u16,
+    pub body: String,
+}
+
+// Fingerprint of the request a key was used with, so reusing the key for a
+// different request can be refused instead of silently replayed.
+pub fn request_hash(parts: &[&str]) -> String {
+    let mut hasher = Sha256::new();
+    for part in parts {
+        hasher.update((part.len() as u64).to_be_bytes());
+        hasher.update(part.as_bytes());
+    }
+    hex::encode(hasher.finalize())
+}
+
+pub fn lookup(conn: &Connection, user_id: &str, key: &str) -> rusqlite::Result<Option<StoredResponse>> {
+    conn.query_row(
+        "SELECT request_hash, status, body FROM idempotency_keys
+         WHERE user_id = ?1 AND key = ?2 AND created_at > strftime('%s', 'now') - ?3",
+        params![user_id, key, KEY_TTL_SECS],
+        |row| {
+            Ok(StoredResponse {
+                request_hash: row.get(0)?,
+                status: row.get(1)?,
+                body: row.get(2)?,
+            })
+        },
+    )
+    .optional()
+}
+
+// Call with the transaction that made the change, so the key is only taken
+// if the change commits.
+pub fn store(conn: &Connection, user_id: &str, key: &str, response: &StoredResponse) -> rusqlite::Result<()> {
+    conn.execute(
+        "INSERT OR REPLACE INTO idempotency_keys (user_id, key, request_hash, status, body, created_at)
+         VALUES (?1, ?2, ?3, ?4, ?5, strftime('%s', 'now'))",
+        params![user_id, key, response.request_hash, response.status, response.body],
+    )?;
+    Ok(())
+}
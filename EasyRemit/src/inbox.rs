@@ -0,0 +1,191 @@
+use iced::{button, Button, Column, Container, Element, Text};
+use iced::{scrollable, Alignment, Length, Row, Scrollable, Background, Color};
+use crate::Message;
+use iced::Command;
+use crate::notifications::Notification;
+use crate::tasks::{loading_text, with_user_storage, Loadable};
+
+// How many notifications the inbox shows.
+const INBOX_SIZE: usize = 50;
+
+#[derive(Debug, Clone)]
+pub struct InboxPage {
+    read_buttons: Vec<button::State>,
+    mark_all_button: button::State,
+    back_button: button::State,
+    scrollable_state: scrollable::State,
+    inbox: Loadable<Vec<Notification>>,
+}
+
+impl InboxPage {
+    pub fn new() -> (Self, Command<Message>) {
+        let page = InboxPage {
+            read_buttons: Vec::new(),
+            mark_all_button: button::State::new(),
+            back_button: button::State::new(),
+            scrollable_state: scrollable::State::new(),
+            inbox: Loadable::Loading,
+        };
+        (page, InboxPage::load())
+    }
+
+    pub fn load() -> Command<Message> {
+        Command::perform(
+            with_user_storage(|storage, user_id| storage.notifications(user_id, INBOX_SIZE)),
+            Message::InboxLoaded,
+        )
+    }
+
+    pub fn loaded(&mut self, result: Result<Vec<Notification>, String>) {
+        self.inbox = Loadable::from_result(result);
+    }
+
+    // Marking runs in the background and the inbox is loaded again after it
+    pub fn mark_read(&mut self, id: i64) -> Command<Message> {
+        Command::perform(
+            with_user_storage(move |storage, user_id| storage.mark_read(user_id, id)),
+            Message::NotificationsMarked,
+        )
+    }
+
+    pub fn mark_all_read(&mut self) -> Command<Message> {
+        Command::perform(
+            with_user_storage(|storage, user_id| storage.mark_all_read(user_id)),
+            Message::NotificationsMarked,
+        )
+    }
+
+    pub fn view(&mut self) -> Element<'_, super::Message> {
+        let empty = Vec::new();
+        let inbox = self.inbox.loaded().unwrap_or(&empty);
+        let unread = inbox.iter().filter(|n| !n.read).count();
+
+        let title = Text::new("Notifications").size(35);
+        let summary = Text::new(match unread {
+            0 => "You're all caught up".to_string(),
+            1 => "1 unread notification".to_string(),
+            n => format!("{} unread notifications", n),
+        })
+        .size(16)
+        .color([0.5, 0.5, 0.5]);
+
+        self.read_buttons.resize_with(inbox.len(), Default::default);
+        let mut list = Column::new().spacing(12).push(loading_text(&self.inbox, "notifications"));
+        if inbox.is_empty() && self.inbox.loaded().is_some() {
+            list = list.push(Text::new("No notifications yet").size(16).color([0.5, 0.5, 0.5]));
+        }
+        for (notification, state) in inbox.iter().zip(self.read_buttons.iter_mut()) {
+            list = list.push(notification_row(notification, state));
+        }
+        let list = Scrollable::new(&mut self.scrollable_state)
+            .padding(8)
+            .width(Length::Fill)
+            .height(Length::Units(420))
+            .push(list);
+
+        let mut mark_all_button = Button::new(&mut self.mark_all_button, Text::new("Mark all as read").size(16))
+            .padding(8)
+            .style(ReadButtonStyle);
+        if unread > 0 {
+            mark_all_button = mark_all_button.on_press(Message::MarkAllNotificationsRead);
+        }
+
+        let back_button = Button::new(&mut self.back_button, Text::new("Back"))
+            .padding(3)
+            .style(BackButtonStyle)
+            .on_press(Message::GoToFunction);
+
+        let content = Column::new()
+            .spacing(12)
+            .padding(20)
+            .align_items(Alignment::Center)
+            .push(title)
+            .push(summary)
+            .push(Container::new(list).padding(10).style(CardStyle))
+            .push(mark_all_button)
+            .push(back_button);
+
+        Container::new(content)
+            .width(Length::Fill)
+            .height(Length::Fill)
+            .center_x()
+            .center_y()
+            .into()
+    }
+}
+
+fn notification_row<'a>(notification: &Notification, state: &'a mut button::State) -> Row<'a, Message> {
+    let shade = if notification.read { [0.5, 0.5, 0.5] } else { [0.1, 0.1, 0.1] };
+    let mut row = Row::new()
+        .spacing(8)
+        .align_items(Alignment::Center)
+        .push(
+            Column::new()
+                .width(Length::Fill)
+                .spacing(2)
+                .push(Text::new(notification.kind.title()).size(16).color(shade))
+                .push(Text::new(notification.message.clone()).size(14).color(shade))
+                .push(Text::new(age(notification.created_at)).size(12).color([0.6, 0.6, 0.6])),
+        );
+    if !notification.read {
+        row = row.push(
+            Button::new(state, Text::new("Read").size(14))
+                .padding(5)
+                .style(ReadButtonStyle)
+                .on_press(Message::MarkNotificationRead(notification.id)),
+        );
+    }
+    row
+}
+
+// "just now", "5 min ago", "3 h ago", "2 days ago"
+fn age(created_at: i64) -> String {
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or(0);
+    let elapsed = (now - created_at).max(0);
+    match elapsed {
+        0..=59 => "just now".to_string(),
+        60..=3599 => format!("{} min ago", elapsed / 60),
+        3600..=86399 => format!("{} h ago", elapsed / 3600),
+        _ => format!("{} days ago", elapsed / 86400),
+    }
+}
+
+struct CardStyle;
+impl iced::container::StyleSheet for CardStyle {
+    fn style(&self) -> iced::container::Style {
+        iced::container::Style {
+            background: Some(Background::Color(Color::WHITE)),
+            border_radius: 15.0,
+            border_width: 1.0,
+            border_color: Color::from_rgb(0.2, 0.4, 0.6),
+            ..iced::container::Style::default()
+        }
+    }
+}
+
+struct ReadButtonStyle;
+impl iced::button::StyleSheet for ReadButtonStyle {
+    fn active(&self) -> iced::button::Style {
+        iced::button::Style {
+            background: Some(Background::Color(Color::from_rgb(0.1, 0.3, 0.6))),
+            border_radius: 8.0,
+            text_color: Color::WHITE,
+            ..iced::button::Style::default()
+        }
+    }
+}
+
+struct BackButtonStyle;
+impl iced::button::StyleSheet for BackButtonStyle {
+    fn active(&self) -> iced::button::Style {
+        iced::button::Style {
+            background: Some(Background::Color(Color::WHITE)),
+            border_radius: 10.0,
+            text_color: Color::BLACK,
+            ..iced::button::Style::default()
+        }
+    }
+}
@@ -0,0 +1,507 @@
+use rusqlite::types::{FromSql, FromSqlError, FromSqlResult, ValueRef};
+use rusqlite::{params, Connection, OptionalExtension};
+use std::fmt;
+use crate::audit;
+use crate::fees::{self, FeeQuote, HOUSE_ACCOUNT_ID};
+use crate::limits::{self, LimitViolation};
+use crate::logging;
+use crate::metrics::METRICS;
+use crate::model::{Account, AccountId, Transaction, TransactionId};
+use crate::notifications::{self, NotificationKind};
+use crate::screening::{self, Action};
+use crate::webhooks;
+use serde_json::json;
+use tracing::{error, info, info_span};
+
+// Counterparty of operator balance corrections. Like the signup bonus it has
+// no account row; the name is what transaction history shows.
+pub const ADJUSTMENT_ACCOUNT: &str = "Balance adjustment";
+
+#[derive(Debug)]
+pub enum TransferError {
+    InvalidAmount,
+    AccountFrozen,
+    SameAccount,
+    UnknownRecipient,
+    NotHeld,
+    InsufficientFunds { balance: i64, required: i64 },
+    Limit(LimitViolation),
+    Database(rusqlite::Error),
+}
+
+impl fmt::Display for TransferError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TransferError::InvalidAmount => write!(f, "Enter an amount greater than 0"),
+            TransferError::AccountFrozen => write!(f, "Your account is under review. Transfers are unavailable until it is cleared"),
+            TransferError::SameAccount => write!(f, "You cannot send money to yourself"),
+            TransferError::UnknownRecipient => write!(f, "Recipient's ID does not exist"),
+            TransferError::NotHeld => write!(f, "Transfer is not waiting for review"),
+            TransferError::InsufficientFunds { balance, required } => {
+                write!(f, "Insufficient balance, ${} needed including fees but you have ${}", required, balance)
+            }
+            TransferError::Limit(violation) => write!(f, "{}", violation),
+            TransferError::Database(e) => write!(f, "Database error: {}", e),
+        }
+    }
+}
+
+impl From<rusqlite::Error> for TransferError {
+    fn from(e: rusqlite::Error) -> Self {
+        TransferError::Database(e)
+    }
+}
+
+// Where a transfer ended up after screening. Only completed transfers move
+// money; held ones wait for compliance review.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TransferStatus {
+    Completed,
+    Held,
+    Blocked,
+}
+
+impl TransferStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TransferStatus::Completed => "completed",
+            TransferStatus::Held => "held",
+            TransferStatus::Blocked => "blocked",
+        }
+    }
+
+    pub fn parse(status: &str) -> Option<Self> {
+        [TransferStatus::Completed, TransferStatus::Held, TransferStatus::Blocked]
+            .into_iter()
+            .find(|s| s.as_str() == status)
+    }
+}
+
+impl FromSql for TransferStatus {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        let status = value.as_str()?;
+        TransferStatus::parse(status).ok_or_else(|| FromSqlError::Other(format!("unknown transfer status {:?}", status).into()))
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Receipt {
+    pub transaction_id: TransactionId,
+    pub status: TransferStatus,
+    pub sender: Account,
+    pub receiver: Account,
+    pub quote: FeeQuote,
+}
+
+// Runs every check a transfer has to pass without moving any money and returns
+// the fee quote it would be charged at. The Transfer page uses this to explain
+// why SEND is disabled.
+pub fn validate_transfer(conn: &Connection, sender_id: &str, receiver_id: &str, amount: i64) -> Result<FeeQuote, TransferError> {
+    validate(conn, sender_id, receiver_id, amount, None)
+}
+
+// `releasing` is a held transfer being released: the balance and limits it
+// has reserved are left out so it is not counted against itself.
+fn validate(conn: &Connection, sender_id: &str, receiver_id: &str, amount: i64, releasing: Option<i64>) -> Result<FeeQuote, TransferError> {
+    if amount <= 0 {
+        return Err(TransferError::InvalidAmount);
+    }
+    let frozen: bool = conn
+        .query_row(
+            "SELECT frozen FROM user_information WHERE id = ?1",
+            params![sender_id],
+            |row| row.get(0),
+        )
+        .optional()?
+        .unwrap_or(false);
+    if frozen {
+        return Err(TransferError::AccountFrozen);
+    }
+    if sender_id == receiver_id {
+        return Err(TransferError::SameAccount);
+    }
+    // The fee account has a row like any user, but only fees go into it
+    let exists: bool = conn.query_row(
+        "SELECT EXISTS(SELECT 1 FROM user_information WHERE id = ?1)",
+        params![receiver_id],
+        |row| row.get(0),
+    )?;
+    if !exists || receiver_id == HOUSE_ACCOUNT_ID {
+        return Err(TransferError::UnknownRecipient);
+    }
+
+    let quote = fees::quote(conn, sender_id, receiver_id, amount)?;
+    let balance = available_balance(conn, sender_id, releasing)?;
+    if balance < quote.total() {
+        return Err(TransferError::InsufficientFunds { balance, required: quote.total() });
+    }
+
+    limits::allowance_except(conn, sender_id, releasing)?
+        .check(amount)
+        .map_err(TransferError::Limit)?;
+    Ok(quote)
+}
+
+// Validates, screens and posts a transfer atomically, so the limit and
+// balance checks see the same state the balances are updated from.
+pub fn execute_transfer(conn: &Connection, sender_id: &str, receiver_id: &str, amount: i64) -> Result<Receipt, TransferError> {
+    let tx = conn.unchecked_transaction()?;
+    let receipt = post_transfer(&tx, sender_id, receiver_id, amount)?;
+    tx.commit()?;
+    Ok(receipt)
+}
+
+// Same as `execute_transfer` for callers that already hold a transaction and
+// need other writes (e.g. settling a payment request) to commit with it.
+pub fn post_transfer(tx: &Connection, sender_id: &str, receiver_id: &str, amount: i64) -> Result<Receipt, TransferError> {
+    let span = info_span!(
+        "transfer",
+        correlation_id = %logging::correlation_id(),
+        sender = sender_id,
+        recipient = receiver_id,
+        amount
+    );
+    let _entered = span.enter();
+    let result = post(tx, sender_id, receiver_id, amount);
+    match &result {
+        Ok(receipt) => {
+            info!(
+                transaction_id = %receipt.transaction_id,
+                status = receipt.status.as_str(),
+                fee = receipt.quote.fee,
+                "transfer posted"
+            );
+            METRICS.transfer_posted(receipt.status, amount, receipt.quote.fee);
+        }
+        Err(TransferError::Database(e)) => {
+            error!(error = %e, "transfer failed");
+            METRICS.transfer_failed();
+        }
+        Err(e) => {
+            info!(reason = %e, "transfer refused");
+            METRICS.transfer_refused();
+        }
+    }
+    result
+}
+
+fn post(tx: &Connection, sender_id: &str, receiver_id: &str, amount: i64) -> Result<Receipt, TransferError> {
+    let quote = validate_transfer(tx, sender_id, receiver_id, amount)?;
+    let screening = screening::screen(tx, sender_id, receiver_id, amount)?;
+    let status = match screening.decision() {
+        Action::Allow => TransferStatus::Completed,
+        Action::Hold => TransferStatus::Held,
+        Action::Block => TransferStatus::Blocked,
+    };
+
+    tx.execute(
+        "INSERT INTO transaction_history (sender_id, amount, receiver_id, created_at, kind, status) VALUES (?1, ?2, ?3, strftime('%s', 'now'), 'transfer', ?4)",
+        params![sender_id, amount, receiver_id, status.as_str()],
+    )?;
+    let transaction_id = tx.last_insert_rowid();
+    screening::record(tx, transaction_id, &screening)?;
+    audit::record(
+        tx,
+        sender_id,
+        audit::TRANSFER,
+        receiver_id,
+        &format!("id={} amount={} fee={} status={}", transaction_id, amount, quote.fee, status.as_str()),
+    )?;
+
+    let receipt = Receipt {
+        transaction_id: TransactionId::assigned(transaction_id),
+        status,
+        sender: account(tx, sender_id)?,
+        receiver: account(tx, receiver_id)?,
+        quote,
+    };
+    match status {
+        TransferStatus::Completed => settle(tx, sender_id, receiver_id, &receipt)?,
+        TransferStatus::Blocked => {
+            publish(tx, webhooks::TRANSFER_FAILED, transaction_id, sender_id, receiver_id, amount, status)?
+        }
+        TransferStatus::Held => {}
+    }
+    Ok(receipt)
+}
+
+// Completes a transfer held by screening once compliance approves it. Every
+// check a new transfer passes is run again, since the sender may have been
+// frozen, the recipient removed or the fee, balance or limits changed while
+// the transfer waited. Only screening is not repeated.
+pub fn release_held(tx: &Connection, transaction_id: i64) -> Result<Receipt, TransferError> {
+    let (sender_id, receiver_id, amount) = held_transfer(tx, transaction_id)?;
+    let quote = validate(tx, &sender_id, &receiver_id, amount, Some(transaction_id))?;
+
+    let status = TransferStatus::Completed;
+    tx.execute(
+        "UPDATE transaction_history SET status = ?2 WHERE id = ?1",
+        params![transaction_id, status.as_str()],
+    )?;
+    audit::record(
+        tx,
+        &sender_id,
+        audit::TRANSFER,
+        &receiver_id,
+        &format!("id={} amount={} fee={} status={}", transaction_id, amount, quote.fee, status.as_str()),
+    )?;
+    let receipt = Receipt {
+        transaction_id: TransactionId::assigned(transaction_id),
+        status,
+        sender: account(tx, &sender_id)?,
+        receiver: account(tx, &receiver_id)?,
+        quote,
+    };
+    settle(tx, &sender_id, &receiver_id, &receipt)?;
+    Ok(receipt)
+}
+
+// Blocks a held transfer for good. No money has moved, so only the status
+// changes.
+pub fn reject_held(tx: &Connection, transaction_id: i64) -> Result<(), TransferError> {
+    let (sender_id, receiver_id, amount) = held_transfer(tx, transaction_id)?;
+    let status = TransferStatus::Blocked;
+    tx.execute(
+        "UPDATE transaction_history SET status = ?2 WHERE id = ?1",
+        params![transaction_id, status.as_str()],
+    )?;
+    audit::record(
+        tx,
+        &sender_id,
+        audit::TRANSFER,
+        &receiver_id,
+        &format!("id={} amount={} status={}", transaction_id, amount, status.as_str()),
+    )?;
+    publish(tx, webhooks::TRANSFER_FAILED, transaction_id, &sender_id, &receiver_id, amount, status)?;
+    Ok(())
+}
+
+fn held_transfer(conn: &Connection, transaction_id: i64) -> Result<(String, String, i64), TransferError> {
+    conn.query_row(
+        "SELECT sender_id, receiver_id, amount FROM transaction_history WHERE id = ?1 AND status = 'held'",
+        params![transaction_id],
+        |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+    )
+    .optional()?
+    .ok_or(TransferError::NotHeld)
+}
+
+// Moves the money for a transfer row that is cleared to complete: charges the
+// fee to the house account, updates balances and tells the recipient.
+fn settle(tx: &Connection, sender_id: &str, receiver_id: &str, receipt: &Receipt) -> Result<(), TransferError> {
+    let quote = &receipt.quote;
+    if quote.fee > 0 {
+        tx.execute(
+            "INSERT INTO transaction_history (sender_id, amount, receiver_id, created_at, kind) VALUES (?1, ?2, ?3, strftime('%s', 'now'), 'fee')",
+            params![sender_id, quote.fee, HOUSE_ACCOUNT_ID],
+        )?;
+    }
+    tx.execute(
+        "UPDATE user_information SET balance = balance - ?1 WHERE id = ?2",
+        params![quote.total(), sender_id],
+    )?;
+    tx.execute(
+        "UPDATE user_information SET balance = balance + ?1 WHERE id = ?2",
+        params![quote.amount, receiver_id],
+    )?;
+    tx.execute(
+        "UPDATE user_information SET balance = balance + ?1 WHERE id = ?2",
+        params![quote.fee, HOUSE_ACCOUNT_ID],
+    )?;
+    notifications::notify(
+        tx,
+        receiver_id,
+        NotificationKind::MoneyReceived,
+        &format!("{} sent you ${}", receipt.sender.name, quote.amount),
+    )?;
+    publish(
+        tx,
+        webhooks::TRANSFER_COMPLETED,
+        receipt.transaction_id.get(),
+        sender_id,
+        receiver_id,
+        quote.amount,
+        receipt.status,
+    )?;
+    Ok(())
+}
+
+// Queues a webhook event for a transfer in the caller's transaction. Screening
+// reasons stay out of it, as everywhere outside the review queue.
+fn publish(
+    tx: &Connection,
+    event: &str,
+    transaction_id: i64,
+    sender_id: &str,
+    receiver_id: &str,
+    amount: i64,
+    status: TransferStatus,
+) -> rusqlite::Result<()> {
+    webhooks::enqueue(
+        tx,
+        event,
+        json!({
+            "transaction_id": transaction_id,
+            "sender_id": sender_id,
+            "receiver_id": receiver_id,
+            "amount": amount,
+            "status": status.as_str(),
+        }),
+    )
+}
+
+// Everything the user sent, in any status, and everything they received
+// that completed, oldest first. Incoming transfers still held or blocked are
+// not shown to the recipient.
+pub fn history(conn: &Connection, user_id: &str) -> rusqlite::Result<Vec<Transaction>> {
+    let mut stmt = conn.prepare(
+        "SELECT th.id, th.created_at, th.kind, th.sender_id, th.receiver_id,
+                COALESCE(s.name, th.sender_id), COALESCE(r.name, th.receiver_id),
+                th.amount, th.status, th.memo
+         FROM transaction_history th
+         LEFT JOIN user_information s ON th.sender_id = s.id
+         LEFT JOIN user_information r ON th.receiver_id = r.id
+         WHERE th.sender_id = ?1 OR (th.receiver_id = ?1 AND th.status = 'completed')
+         ORDER BY th.id",
+    )?;
+    let entries = stmt.query_map(params![user_id], |row| {
+        let sender_id: String = row.get(3)?;
+        let outgoing = sender_id == user_id;
+        let (counterparty_id, counterparty_name) = if outgoing {
+            (row.get(4)?, row.get(6)?)
+        } else {
+            (sender_id, row.get(5)?)
+        };
+        Ok(Transaction {
+            id: row.get(0)?,
+            created_at: row.get(1)?,
+            kind: row.get(2)?,
+            outgoing,
+            counterparty: Account { id: AccountId::stored(counterparty_id), name: counterparty_name },
+            amount: row.get(7)?,
+            status: row.get(8)?,
+            memo: row.get(9)?,
+        })
+    })?;
+    entries.collect()
+}
+
+// Credits (positive `delta`) or debits a user's balance outside the normal
+// transfer flow, posted as an 'adjustment' row with the reason as its memo.
+// Returns the new balance.
+pub fn adjust_balance(tx: &Connection, user_id: &str, delta: i64, memo: &str) -> Result<i64, TransferError> {
+    if delta == 0 {
+        return Err(TransferError::InvalidAmount);
+    }
+    let balance = balance_of(tx, user_id)?;
+    if balance + delta < 0 {
+        return Err(TransferError::InsufficientFunds { balance, required: -delta });
+    }
+    let (sender_id, receiver_id) = if delta > 0 { (ADJUSTMENT_ACCOUNT, user_id) } else { (user_id, ADJUSTMENT_ACCOUNT) };
+    tx.execute(
+        "INSERT INTO transaction_history (sender_id, amount, receiver_id, created_at, kind, memo)
+         VALUES (?1, ?2, ?3, strftime('%s', 'now'), 'adjustment', ?4)",
+        params![sender_id, delta.abs(), receiver_id, memo],
+    )?;
+    tx.execute(
+        "UPDATE user_information SET balance = balance + ?1 WHERE id = ?2",
+        params![delta, user_id],
+    )?;
+    Ok(balance + delta)
+}
+
+pub fn balance_of(conn: &Connection, user_id: &str) -> rusqlite::Result<i64> {
+    conn.query_row(
+        "SELECT balance FROM user_information WHERE id = ?1",
+        params![user_id],
+        |row| row.get(0),
+    )
+    .optional()
+    .map(|balance| balance.unwrap_or(0))
+}
+
+// The balance less what the user's held transfers would cost if released
+// now, so money waiting for review cannot be spent twice. `except` leaves out
+// the held transfer being released.
+pub fn available_balance(conn: &Connection, user_id: &str, except: Option<i64>) -> Result<i64, TransferError> {
+    let mut stmt = conn.prepare(
+        "SELECT receiver_id, amount FROM transaction_history
+         WHERE sender_id = ?1 AND kind = 'transfer' AND status = 'held' AND id IS NOT ?2",
+    )?;
+    let held = stmt
+        .query_map(params![user_id, except], |row| Ok((row.get::<_, String>(0)?, row.get::<_, i64>(1)?)))?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    let mut available = balance_of(conn, user_id)?;
+    for (receiver_id, amount) in held {
+        available -= fees::quote(conn, user_id, &receiver_id, amount)?.total();
+    }
+    Ok(available)
+}
+
+fn account(conn: &Connection, user_id: &str) -> rusqlite::Result<Account> {
+    let name = conn.query_row(
+        "SELECT name FROM user_information WHERE id = ?1",
+        params![user_id],
+        |row| row.get(0),
+    )?;
+    Ok(Account { id: AccountId::stored(user_id.to_string()), name })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::init_schema;
+
+    #[test]
+    fn fee_account_is_not_a_recipient() {
+        let conn = Connection::open_in_memory().unwrap();
+        init_schema(&conn).unwrap();
+        conn.execute(
+            "INSERT INTO user_information (id, balance, created_at) VALUES ('a', 1000, strftime('%s','now'))",
+            [],
+        )
+        .unwrap();
+        assert!(matches!(
+            validate_transfer(&conn, "a", HOUSE_ACCOUNT_ID, 10),
+            Err(TransferError::UnknownRecipient)
+        ));
+        assert!(matches!(
+            execute_transfer(&conn, "a", HOUSE_ACCOUNT_ID, 10),
+            Err(TransferError::UnknownRecipient)
+        ));
+        assert_eq!(balance_of(&conn, "a").unwrap(), 1000);
+    }
+
+    #[test]
+    fn held_transfers_reserve_what_they_would_cost() {
+        let conn = Connection::open_in_memory().unwrap();
+        init_schema(&conn).unwrap();
+        // 'new' is a day old, so sending 500 or more is held for review
+        conn.execute_batch(
+            "INSERT INTO user_information (id, name, balance, created_at) VALUES
+                ('new', 'New', 1000, CAST(strftime('%s','now') AS INTEGER) - 86400),
+                ('b', 'B', 0, CAST(strftime('%s','now') AS INTEGER) - 86400),
+                ('c', 'C', 0, CAST(strftime('%s','now') AS INTEGER) - 86400);
+             UPDATE screening_rules SET enabled = 0 WHERE rule = 'sanctions';",
+        )
+        .unwrap();
+        let held = execute_transfer(&conn, "new", "b", 600).unwrap();
+        assert_eq!(held.status, TransferStatus::Held);
+        assert_eq!(balance_of(&conn, "new").unwrap(), 1000);
+        // 600 and its $6 fee are set aside
+        assert_eq!(available_balance(&conn, "new", None).unwrap(), 394);
+        assert_eq!(available_balance(&conn, "new", Some(held.transaction_id.get())).unwrap(), 1000);
+
+        assert!(matches!(
+            validate_transfer(&conn, "new", "c", 400),
+            Err(TransferError::InsufficientFunds { balance: 394, required: 404 })
+        ));
+        assert_eq!(execute_transfer(&conn, "new", "c", 390).unwrap().status, TransferStatus::Completed);
+
+        // the held transfer can still be paid for when released
+        let released = release_held(&conn, held.transaction_id.get()).unwrap();
+        assert_eq!(released.status, TransferStatus::Completed);
+        assert_eq!(balance_of(&conn, "new").unwrap(), 0);
+        assert_eq!(balance_of(&conn, "b").unwrap(), 600);
+    }
+}
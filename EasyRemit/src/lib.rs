@@ -0,0 +1,30 @@
+// Everything below the GUI: the database, accounts, the ledger and the checks
+// around it. The app and the command-line tools in src/bin are built on it.
+pub mod admin;
+pub mod api;
+pub mod audit;
+pub mod backup;
+pub mod auth;
+pub mod bill_split;
+pub mod compliance;
+pub mod config;
+pub mod db;
+pub mod error;
+pub mod fees;
+pub mod idempotency;
+pub mod ledger;
+pub mod limits;
+pub mod logging;
+pub mod metrics;
+pub mod model;
+pub mod notifications;
+pub mod payment_requests;
+pub mod pool;
+pub mod qr;
+pub mod qr_scan;
+pub mod retry;
+pub mod sanctions;
+pub mod screening;
+pub mod sessions;
+pub mod storage;
+pub mod webhooks;
@@ -0,0 +1,287 @@
+use rusqlite::{params, Connection, OptionalExtension, Result};
+use std::fmt;
+use crate::notifications::{self, NotificationKind};
+
+// Limits that apply to one account, picked from the `transfer_limits` table by
+// the account's KYC level and age.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TransferLimits {
+    pub per_transaction: i64,
+    pub daily: i64,
+    pub monthly: i64,
+}
+
+// The limits together with what has already been sent in the current
+// calendar day and month.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Allowance {
+    pub limits: TransferLimits,
+    pub sent_today: i64,
+    pub sent_this_month: i64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LimitViolation {
+    PerTransaction { limit: i64 },
+    Daily { limit: i64, remaining: i64 },
+    Monthly { limit: i64, remaining: i64 },
+}
+
+impl fmt::Display for LimitViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LimitViolation::PerTransaction { limit } => {
+                write!(f, "A single transfer can be at most ${}", limit)
+            }
+            LimitViolation::Daily { limit, remaining } => {
+                write!(f, "Daily limit is ${}, you can send ${} more today", limit, remaining)
+            }
+            LimitViolation::Monthly { limit, remaining } => {
+                write!(f, "Monthly limit is ${}, you can send ${} more this month", limit, remaining)
+            }
+        }
+    }
+}
+
+impl Allowance {
+    pub fn remaining_today(&self) -> i64 {
+        (self.limits.daily - self.sent_today).max(0)
+    }
+
+    pub fn remaining_this_month(&self) -> i64 {
+        (self.limits.monthly - self.sent_this_month).max(0)
+    }
+
+    // Largest amount that would pass every limit right now.
+    pub fn max_transfer(&self) -> i64 {
+        self.limits
+            .per_transaction
+            .min(self.remaining_today())
+            .min(self.remaining_this_month())
+    }
+
+    pub fn check(&self, amount: i64) -> Result<(), LimitViolation> {
+        if amount > self.limits.per_transaction {
+            return Err(LimitViolation::PerTransaction { limit: self.limits.per_transaction });
+        }
+        if amount > self.remaining_today() {
+            return Err(LimitViolation::Daily {
+                limit: self.limits.daily,
+                remaining: self.remaining_today(),
+            });
+        }
+        if amount > self.remaining_this_month() {
+            return Err(LimitViolation::Monthly {
+                limit: self.limits.monthly,
+                remaining: self.remaining_this_month(),
+            });
+        }
+        Ok(())
+    }
+}
+
+pub fn limits_for(conn: &Connection, user_id: &str) -> Result<TransferLimits> {
+    let (kyc_level, age_days): (i64, i64) = conn.query_row(
+        "SELECT kyc_level, (strftime('%s', 'now') - COALESCE(created_at, strftime('%s', 'now'))) / 86400
+         FROM user_information WHERE id = ?1",
+        params![user_id],
+        |row| Ok((row.get(0)?, row.get(1)?)),
+    )?;
+
+    // The tier with the highest age threshold the account has reached. Levels
+    // without their own rows fall back to the strictest tier in the table.
+    let tier = conn
+        .query_row(
+            "SELECT per_transaction, daily, monthly FROM transfer_limits
+             WHERE kyc_level = ?1 AND min_account_age_days <= ?2
+             ORDER BY min_account_age_days DESC LIMIT 1",
+            params![kyc_level, age_days],
+            |row| {
+                Ok(TransferLimits {
+                    per_transaction: row.get(0)?,
+                    daily: row.get(1)?,
+                    monthly: row.get(2)?,
+                })
+            },
+        )
+        .optional()?;
+
+    match tier {
+        Some(limits) => Ok(limits),
+        None => conn.query_row(
+            "SELECT per_transaction, daily, monthly FROM transfer_limits
+             ORDER BY kyc_level ASC, min_account_age_days ASC LIMIT 1",
+            [],
+            |row| {
+                Ok(TransferLimits {
+                    per_transaction: row.get(0)?,
+                    daily: row.get(1)?,
+                    monthly: row.get(2)?,
+                })
+            },
+        ),
+    }
+}
+
+// Moves the account to another KYC level and, if that changes its limits,
+// tells the user. The caller checks the level has rows in `transfer_limits`.
+pub fn set_kyc_level(conn: &Connection, user_id: &str, kyc_level: i64) -> Result<TransferLimits> {
+    let before = limits_for(conn, user_id)?;
+    conn.execute(
+        "UPDATE user_information SET kyc_level = ?2 WHERE id = ?1",
+        params![user_id, kyc_level],
+    )?;
+    let after = limits_for(conn, user_id)?;
+    if after != before {
+        let message = format!(
+            "You can now send up to ${} per transfer, ${} a day and ${} a month",
+            after.per_transaction, after.daily, after.monthly
+        );
+        notifications::notify(conn, user_id, NotificationKind::LimitChanged, &message)?;
+    }
+    Ok(after)
+}
+
+pub fn allowance(conn: &Connection, user_id: &str) -> Result<Allowance> {
+    allowance_except(conn, user_id, None)
+}
+
+// The allowance as if the held transfer `except` had not been sent, for
+// checking it again when it is released.
+pub(crate) fn allowance_except(conn: &Connection, user_id: &str, except: Option<i64>) -> Result<Allowance> {
+    let limits = limits_for(conn, user_id)?;
+    let (sent_today, sent_this_month): (i64, i64) = conn.query_row(
+        "SELECT
+            COALESCE(SUM(CASE WHEN created_at >= strftime('%s', 'now', 'start of day') THEN amount END), 0),
+            COALESCE(SUM(amount), 0)
+         FROM transaction_history
+         WHERE sender_id = ?1 AND kind = 'transfer' AND status IN ('completed', 'held')
+           AND created_at >= strftime('%s', 'now', 'start of month') AND id IS NOT ?2",
+        params![user_id, except],
+        |row| Ok((row.get(0)?, row.get(1)?)),
+    )?;
+
+    Ok(Allowance { limits, sent_today, sent_this_month })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::init_schema;
+
+    const DAY: i64 = 86_400;
+
+    fn database() -> Connection {
+        let conn = Connection::open_in_memory().unwrap();
+        init_schema(&conn).unwrap();
+        conn
+    }
+
+    // An account `age_days` old at `kyc_level`
+    fn account(conn: &Connection, user_id: &str, kyc_level: i64, age_days: i64) {
+        conn.execute(
+            "INSERT INTO user_information (id, balance, kyc_level, created_at)
+             VALUES (?1, 0, ?2, CAST(strftime('%s', 'now') AS INTEGER) - ?3)",
+            params![user_id, kyc_level, age_days * DAY + 60],
+        )
+        .unwrap();
+    }
+
+    fn sent(conn: &Connection, user_id: &str, amount: i64, created_at: &str, status: &str) {
+        conn.execute(
+            &format!(
+                "INSERT INTO transaction_history (sender_id, receiver_id, amount, created_at, status)
+                 VALUES (?1, 'someone', ?2, {}, ?3)",
+                created_at
+            ),
+            params![user_id, amount, status],
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn tiers_follow_kyc_level_and_account_age() {
+        let conn = database();
+        for (user_id, kyc_level, age_days, per_transaction, daily, monthly) in [
+            ("a", 1, 0, 1000, 2000, 10000),
+            ("b", 1, 29, 1000, 2000, 10000),
+            ("c", 1, 30, 2000, 5000, 25000),
+            ("d", 2, 29, 5000, 10000, 50000),
+            ("e", 2, 30, 10000, 25000, 100000),
+            // no tier of its own: the strictest one
+            ("f", 7, 400, 1000, 2000, 10000),
+        ] {
+            account(&conn, user_id, kyc_level, age_days);
+            assert_eq!(
+                limits_for(&conn, user_id).unwrap(),
+                TransferLimits { per_transaction, daily, monthly },
+                "{}",
+                user_id
+            );
+        }
+    }
+
+    #[test]
+    fn allowance_counts_today_and_this_month() {
+        let conn = database();
+        account(&conn, "a", 1, 0);
+        sent(&conn, "a", 300, "strftime('%s', 'now')", "completed");
+        sent(&conn, "a", 200, "strftime('%s', 'now')", "held");
+        // not counted: turned down, or sent by someone else
+        sent(&conn, "a", 900, "strftime('%s', 'now')", "blocked");
+        sent(&conn, "b", 900, "strftime('%s', 'now')", "completed");
+        // the last second of yesterday and of last month
+        sent(&conn, "a", 50, "strftime('%s', 'now', 'start of day') - 1", "completed");
+        sent(&conn, "a", 70, "strftime('%s', 'now', 'start of month') - 1", "completed");
+
+        let allowance = allowance(&conn, "a").unwrap();
+        assert_eq!(allowance.sent_today, 500);
+        // yesterday is still this month unless today is the 1st
+        assert!([500, 550].contains(&allowance.sent_this_month), "{:?}", allowance);
+        assert_eq!(allowance.remaining_today(), 1500);
+    }
+
+    #[test]
+    fn checks_each_limit_at_its_boundary() {
+        let allowance = Allowance {
+            limits: TransferLimits { per_transaction: 1000, daily: 2000, monthly: 10000 },
+            sent_today: 1200,
+            sent_this_month: 9500,
+        };
+        assert_eq!(allowance.max_transfer(), 500);
+        assert_eq!(allowance.check(500), Ok(()));
+        assert_eq!(allowance.check(501), Err(LimitViolation::Monthly { limit: 10000, remaining: 500 }));
+
+        let allowance = Allowance { sent_this_month: 1200, ..allowance };
+        assert_eq!(allowance.max_transfer(), 800);
+        assert_eq!(allowance.check(800), Ok(()));
+        assert_eq!(allowance.check(801), Err(LimitViolation::Daily { limit: 2000, remaining: 800 }));
+
+        let allowance = Allowance { sent_today: 0, sent_this_month: 0, ..allowance };
+        assert_eq!(allowance.check(1000), Ok(()));
+        assert_eq!(allowance.check(1001), Err(LimitViolation::PerTransaction { limit: 1000 }));
+
+        // over the limit already, e.g. after the tier was lowered
+        let allowance = Allowance { sent_today: 2500, ..allowance };
+        assert_eq!(allowance.remaining_today(), 0);
+        assert_eq!(allowance.max_transfer(), 0);
+    }
+
+    #[test]
+    fn changing_kyc_level_notifies_only_when_limits_change() {
+        let conn = database();
+        account(&conn, "a", 1, 40);
+        let inbox = |conn: &Connection| notifications::inbox(conn, "a", 10).unwrap();
+
+        // the level it already has changes nothing
+        assert_eq!(set_kyc_level(&conn, "a", 1).unwrap().daily, 5000);
+        assert!(inbox(&conn).is_empty());
+
+        let limits = set_kyc_level(&conn, "a", 2).unwrap();
+        assert_eq!(limits, TransferLimits { per_transaction: 10000, daily: 25000, monthly: 100000 });
+        let notified = inbox(&conn);
+        assert_eq!(notified.len(), 1);
+        assert_eq!(notified[0].kind, NotificationKind::LimitChanged);
+        assert_eq!(notified[0].message, "You can now send up to $10000 per transfer, $25000 a day and $100000 a month");
+    }
+}
@@ -0,0 +1,188 @@
+use once_cell::sync::Lazy;
+use rand::Rng;
+use regex::{Captures, Regex};
+use std::fmt;
+use tracing::field::Field;
+use tracing::Subscriber;
+use tracing_appender::rolling::{self, RollingFileAppender};
+use tracing_subscriber::field::MakeExt;
+use tracing_subscriber::filter::LevelFilter;
+use tracing_subscriber::fmt::format::{self, Writer};
+use tracing_subscriber::fmt::MakeWriter;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::{EnvFilter, Layer};
+use crate::config::{self, Rotation};
+
+// Diagnostics for whoever runs the app, as opposed to what the pages and the
+// tools tell the user. Each program logs to its own rotating files in
+// `logging.directory` (e.g. logs/easyremit-api.2026-10-19.log), at the
+// levels set by `logging.level` and `logging.filter`; warnings and errors
+// also go to stderr.
+//
+// Personal data is masked as lines are written, whoever logged them: account
+// IDs, email addresses and passport numbers wherever they appear, and the
+// value of any field called `name` or ending in `_name`.
+
+// Sets up logging for this process. Called once at the top of main, after
+// the config is loaded.
+pub fn init(program: &str) {
+    let config = &config::get().logging;
+    let file = if config.directory.as_os_str().is_empty() {
+        None
+    } else {
+        let rotation = match config.rotation {
+            Rotation::Minutely => rolling::Rotation::MINUTELY,
+            Rotation::Hourly => rolling::Rotation::HOURLY,
+            Rotation::Daily => rolling::Rotation::DAILY,
+            Rotation::Never => rolling::Rotation::NEVER,
+        };
+        let appender = RollingFileAppender::builder()
+            .rotation(rotation)
+            .filename_prefix(program)
+            .filename_suffix("log")
+            .max_log_files(config.max_files)
+            .build(&config.directory);
+        match appender {
+            Ok(appender) => Some(masked_layer(appender)),
+            Err(e) => {
+                eprintln!("Warning: not logging to {}: {}", config.directory.display(), e);
+                None
+            }
+        }
+    };
+    let subscriber = tracing_subscriber::registry()
+        .with(EnvFilter::new(config.directives()))
+        .with(file)
+        .with(masked_layer(std::io::stderr).with_filter(LevelFilter::WARN));
+    // a second call leaves the first subscriber in place
+    let _ = tracing::subscriber::set_global_default(subscriber);
+}
+
+// Lines as the fmt layer writes them, with every field passed through
+// `mask_field`
+fn masked_layer<S, W>(writer: W) -> impl Layer<S>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    W: for<'w> MakeWriter<'w> + Send + Sync + 'static,
+{
+    tracing_subscriber::fmt::layer()
+        .with_ansi(false)
+        .fmt_fields(format::debug_fn(write_field).delimited(" "))
+        .with_writer(writer)
+}
+
+fn write_field(writer: &mut Writer<'_>, field: &Field, value: &dyn fmt::Debug) -> fmt::Result {
+    let text = mask_field(field.name(), &format!("{:?}", value));
+    match field.name() {
+        "message" => write!(writer, "{}", text),
+        name => write!(writer, "{}={}", name, text),
+    }
+}
+
+// Ties together everything logged about one transfer, login or retried
+// database call
+pub fn correlation_id() -> String {
+    format!("{:016x}", rand::thread_rng().gen::<u64>())
+}
+
+// A field's value as it appears in the log
+pub fn mask_field(name: &str, value: &str) -> String {
+    if name == "name" || name.ends_with("_name") {
+        mask_name(value)
+    } else {
+        redact(value)
+    }
+}
+
+static EMAIL: Lazy<Regex> = Lazy::new(|| Regex::new(r#"([^\s@"'=<>(),;:\[\]{}])[^\s@"'=<>(),;:\[\]{}]*@"#).unwrap());
+static ACCOUNT_ID: Lazy<Regex> = Lazy::new(|| Regex::new(r"\b\d{8}(\d{4})\b").unwrap());
+// `validation.passport` without its anchors, so it finds numbers in text
+static PASSPORT: Lazy<Option<Regex>> = Lazy::new(|| {
+    let pattern = &config::get().validation.passport;
+    let pattern = pattern.strip_prefix('^').unwrap_or(pattern);
+    let pattern = pattern.strip_suffix('$').unwrap_or(pattern);
+    Regex::new(&format!(r"\b(?:{})\b", pattern)).ok()
+});
+
+// Masks the personal data that can be recognised in any text:
+//   alice@example.com -> a***@example.com
+//   960008484537      -> ********4537
+//   AB123456          -> ********
+pub fn redact(text: &str) -> String {
+    let text = EMAIL.replace_all(text, "$1***@");
+    let text = ACCOUNT_ID.replace_all(&text, "********$1");
+    match &*PASSPORT {
+        Some(passport) => passport
+            .replace_all(&text, |found: &Captures| "*".repeat(found[0].chars().count()))
+            .into_owned(),
+        None => text.into_owned(),
+    }
+}
+
+// Keeps the first letter of each word: "Alice Anders" -> "A**** A*****"
+pub fn mask_name(name: &str) -> String {
+    let mut masked = String::with_capacity(name.len());
+    let mut in_word = false;
+    for c in name.chars() {
+        if c.is_alphanumeric() {
+            masked.push(if in_word { '*' } else { c });
+            in_word = true;
+        } else {
+            masked.push(c);
+            in_word = false;
+        }
+    }
+    masked
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io;
+    use std::sync::{Arc, Mutex};
+
+    #[test]
+    fn masks_personal_data() {
+        assert_eq!(redact("user 960008484537 sent 20"), "user ********4537 sent 20");
+        assert_eq!(redact("email=alice@example.com"), "email=a***@example.com");
+        assert_eq!(redact("passport AB123456 checked"), "passport ******** checked");
+        assert_eq!(redact("id=7 amount=1000 HOUSE_REVENUE"), "id=7 amount=1000 HOUSE_REVENUE");
+        assert_eq!(mask_name("\"Alice Anders\""), "\"A**** A*****\"");
+        assert_eq!(mask_field("receiver_name", "Bob"), "B**");
+        assert_eq!(mask_field("recipient", "960004412772"), "********2772");
+    }
+
+    // Collects what the layer writes
+    #[derive(Clone, Default)]
+    struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+    impl io::Write for Buffer {
+        fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(bytes);
+            Ok(bytes.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn spans_and_events_are_masked() {
+        let buffer = Buffer::default();
+        let writer = buffer.clone();
+        let subscriber = tracing_subscriber::registry().with(masked_layer(move || writer.clone()));
+        tracing::subscriber::with_default(subscriber, || {
+            let span = tracing::info_span!("login", correlation_id = "c0ffee", user_id = "960008484537");
+            let _entered = span.enter();
+            tracing::info!(name = "Alice Anders", email = "a.anders@example.com", "welcome back, AB123456");
+        });
+        let output = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+        assert!(output.contains("login{correlation_id=\"c0ffee\" user_id=\"********4537\"}"), "{}", output);
+        assert!(output.contains("welcome back, ******** name=\"A**** A*****\" email=\"a***@example.com\""), "{}", output);
+        for secret in ["960008484537", "Alice", "Anders", "a.anders", "AB123456"] {
+            assert!(!output.contains(secret), "{} in {}", secret, output);
+        }
+    }
+}
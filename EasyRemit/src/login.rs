@@ -1,48 +1,9 @@
-use iced::{button, Button, Column, Container, Element, Text};
+use iced::{button, Button, Column, Command, Container, Element, Text};
 use iced::{text_input, Alignment,Length,TextInput, Background, Color};
 use crate::Message;
-use rusqlite::{params, Connection, Result};
-use std::sync::{Arc, Mutex};
-use std::error::Error;
-use argon2::{Argon2,PasswordVerifier,password_hash::{SaltString, PasswordHash}};
-use std::fmt;
+use crate::model::{AccountId, Email};
 use crate::set_user_id;
-#[derive(Debug)]
-struct Argon2Error(argon2::password_hash::Error);
-
-impl fmt::Display for Argon2Error {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "Argon2 error: {}", self.0)
-    }
-}
-
-impl std::error::Error for Argon2Error {}
-fn check_user_exists(conn: &Arc<Mutex<Connection>>, id: &str, email: &str) -> Result<bool> {
-    // Lock the Mutex to access the Connection
-    let conn = conn.lock().map_err(|_| rusqlite::Error::InvalidQuery)?;
-    
-    let mut stmt = conn.prepare(
-        "SELECT COUNT(*) FROM user_information WHERE id = ?1 AND email = ?2",
-    )?;
-    
-    let count: i32 = stmt.query_row(params![id, email], |row| row.get(0))?;
-    
-    // Return true if the count is greater than 0, meaning the user exists
-    Ok(count > 0)
-}
-
-
-fn verify_password(password: &str, hash: &str) -> Result<bool, Box<dyn Error>> {
-    let argon2 = Argon2::default();
-    let parsed_hash = PasswordHash::new(hash).map_err(Argon2Error)?;
-
-    match argon2.verify_password(password.as_bytes(), &parsed_hash){
-        Ok(_)=> Ok(true),
-        Err(_)=>Ok(false),
-    }
-
-    
-}
+use crate::tasks::with_storage;
 
 #[derive(Debug, Clone)]
 pub struct LoginPage {
@@ -54,6 +15,9 @@ pub struct LoginPage {
     password_input:text_input::State,
     login_button: button::State,
     back_button: button::State,
+    error: Option<String>,
+    // true while the password is being checked
+    submitting: bool,
 }
 
 impl LoginPage {
@@ -67,6 +31,8 @@ impl LoginPage {
                 password_input:text_input::State::new(),
                 login_button: button::State::new(),
                 back_button:button::State::new(),
+                error: None,
+                submitting: false,
         }
     }
 
@@ -87,7 +53,45 @@ impl LoginPage {
         }
     }
 
-    pub fn view(&mut self, d_conn: &Arc<Mutex<Connection>>) -> Element<super::Message> {
+    // Checks the password off the UI thread; the answer comes back as
+    // Message::LoginFinished. A user ID or email that cannot be right is
+    // turned down straight away.
+    pub fn submit(&mut self) -> Command<Message> {
+        if self.submitting {
+            return Command::none();
+        }
+        let (user_id, email) = match (AccountId::parse(&self.userid), Email::parse(&self.email)) {
+            (Ok(user_id), Ok(email)) => (user_id, email),
+            (Err(e), _) | (_, Err(e)) => {
+                self.error = Some(e.to_string());
+                return Command::none();
+            }
+        };
+        self.submitting = true;
+        self.error = None;
+        let password = self.password.clone();
+        Command::perform(
+            with_storage(move |storage| storage.login(&user_id, &email, &password).map(|()| user_id)),
+            Message::LoginFinished,
+        )
+    }
+
+    // Returns true once the user is logged in.
+    pub fn finished(&mut self, result: Result<AccountId, String>) -> bool {
+        self.submitting = false;
+        match result {
+            Ok(user_id) => {
+                set_user_id(user_id);
+                true
+            }
+            Err(e) => {
+                self.error = Some(e);
+                false
+            }
+        }
+    }
+
+    pub fn view(&mut self) -> Element<'_, super::Message> {
        
         let main_text = Text::new("Secure your financial future with us")
             .size(45)
@@ -143,26 +147,21 @@ impl LoginPage {
         .padding(15)
         .size(25);
        
-        let userverified= match check_user_exists(d_conn, &self.userid, &self.email){
-            Ok(b)=> b,
-            Err(_)=>false,
-        };
-        
-        let check_user= userverified && verify_user_password(d_conn, &self.userid, &self.password);
-
-        let log_in_button=if check_user {
-            set_user_id(self.userid.clone());
-            
-             Button::new(&mut self.login_button, Text::new("LOG IN"))
+        let mut log_in_button = Button::new(
+            &mut self.login_button,
+            Text::new(if self.submitting { "LOGGING IN..." } else { "LOG IN" }),
+        )
             .padding(15)
-            .style(LogInButtonStyle) // Custom style for the send button
-            .on_press(Message::GoToFunction)
+            .style(LogInButtonStyle); // Custom style for the send button
+        if !self.submitting {
+            log_in_button = log_in_button.on_press(Message::SubmitLogin);
+        }
 
-        }else{
-            Button::new(&mut self.login_button, Text::new("LOG IN"))
-            .padding(15)
-            .style(LogInButtonStyle) // Custom style for the send button
-            .on_press(Message::GoToLogin)
+        let error_text = match &self.error {
+            Some(error) => Text::new(error.clone())
+                .color(Color::from_rgb(1.0, 0.0, 0.0))
+                .size(16),
+            None => Text::new("").size(4),
         };
         
         let back_button = Button::new(&mut self.back_button, Text::new("Back"))
@@ -183,6 +182,7 @@ impl LoginPage {
             .push(input_userid)
             .push(password_text)
             .push(input_password)
+            .push(error_text)
             .push(log_in_button)
             .push(back_button);
 
@@ -220,26 +220,3 @@ impl iced::button::StyleSheet for BackButtonStyle {
     }
 }
 
-
-fn verify_user_password(d_conn: &Arc<Mutex<Connection>>, userid: &str, input_password: &str) -> bool {
-    // Lock the connection
-    let conn = match d_conn.lock() {
-        Ok(c) => c,
-        Err(_) => return false,
-    };
-
-    // Prepare and execute the SQL statement to get the hashed password
-    if let Ok(hashed_password) = conn.query_row(
-        "SELECT hashed_password FROM user_information WHERE id = ?1",
-        params![userid],
-        |row| row.get::<_, String>(0), // Ensure we get a String from the row
-    ) {
-        // Parse the stored hashed password
-        if let Ok(parsed_hash) = PasswordHash::new(&hashed_password) {
-            // Verify the input password against the stored hash
-            return Argon2::default().verify_password(input_password.as_bytes(), &parsed_hash).is_ok();
-        }
-    }
-
-    false // Return false if any step fails
-}
@@ -1,68 +1,185 @@
 mod home;
+mod inbox;
 mod login;
 mod signup;
 mod transfer;
 mod success;
 mod function;
-use rusqlite::{params, Connection, Result};
+mod request;
+mod review;
+mod split;
+mod tasks;
+use easyremit::{audit, auth, backup, bill_split, compliance, config, db, error, fees, ledger, limits};
+use easyremit::{logging, metrics, model, notifications, payment_requests, qr, sanctions, storage, webhooks};
 use once_cell::sync::Lazy;
-use std::sync::{Arc, Mutex};
-use iced::{Application, Command, Element, Settings};
-mod db;  
-use crate::db::{DB_CONN, execute_with_retry};
+use std::sync::{Mutex, PoisonError};
+use std::time::Duration;
+use iced::{button, container, Alignment, Application, Background, Button, Color, Column, Command, Container};
+use iced::{Element, Length, Row, Settings, Text};
+use crate::db::DB_POOL;
+use crate::model::AccountId;
+use crate::tasks::with_user_storage;
 
 pub fn main() -> Result<(), iced::Error> {
+    // `--config <file>` picks the configuration for the app and both
+    // commands below
+    let mut args: Vec<String> = std::env::args().collect();
+    let config = config::init_or_exit(config::take_config_arg(&mut args).as_deref());
+    logging::init("EasyRemit");
+    // `EasyRemit verify-audit [expected-head-hash]` checks the audit log
+    // instead of opening the app
+    if args.get(1).map(String::as_str) == Some("verify-audit") {
+        std::process::exit(verify_audit(args.get(2).map(String::as_str)));
+    }
+    // `EasyRemit review <reviewer-id> ...` works the compliance review queue
+    if args.get(1).map(String::as_str) == Some("review") {
+        std::process::exit(review_queue(&args[2..]));
+    }
+
+    // Fails here, with the reason, rather than on the first page that needs
+    // the database
+    db::init_or_exit();
+    storage::init_or_exit();
+    sanctions::init_or_exit(&DB_POOL.writer());
 
     let settings = Settings {
         window: iced::window::Settings {
-            size: (430, 732),
+            size: (config.app.window_width, config.app.window_height),
             ..iced::window::Settings::default()
         },
         ..Settings::default()
     };
     
-    EasyRemit::run(settings);
-    Ok(())
+    // Sends queued webhook events, including any left by an earlier run
+    webhooks::spawn_dispatcher(&DB_POOL);
+    backup::spawn_schedule(config.database.path.clone(), &config.backup);
+    // No endpoint to scrape on the desktop, so the metrics go to a file
+    if !config.metrics.dump_file.as_os_str().is_empty() {
+        metrics::spawn_dump(config.metrics.dump_file.clone(), Duration::from_secs(config.metrics.dump_interval_secs));
+    }
+    EasyRemit::run(settings)
 }
 
-pub static TAMOUNT: Lazy<Mutex<Option<i64>>> = Lazy::new(|| Mutex::new(None));
-
-pub fn set_t_amount(amount: i64) {
-    let mut t_amount = TAMOUNT.lock().unwrap();
-    *t_amount = Some(amount);
-}
-pub fn get_t_amount() -> Option<i64> {
-    let t_amount = TAMOUNT.lock().unwrap();
-    t_amount.clone()
+fn verify_audit(expected_head: Option<&str>) -> i32 {
+    let verified = DB_POOL.reader().map_err(audit::AuditError::from).and_then(|conn| audit::verify(&conn, expected_head));
+    match verified {
+        Ok(verified) => {
+            println!("Audit log intact: {} entries", verified.entries);
+            println!("Head: {}", verified.head);
+            0
+        }
+        Err(e) => {
+            eprintln!("Audit log verification failed: {}", e);
+            1
+        }
+    }
 }
 
-pub static USER_ID: Lazy<Mutex<Option<String>>> = Lazy::new(|| Mutex::new(None));
-pub static RECEIVER_ID: Lazy<Mutex<Option<String>>> = Lazy::new(|| Mutex::new(None));
-pub fn set_user_id(id: String) {
-    let mut user_id_lock = USER_ID.lock().unwrap();
-    *user_id_lock = Some(id);
-}
+const REVIEW_USAGE: &str = "usage: EasyRemit review <reviewer-id> [--password-stdin] list
+       EasyRemit review <reviewer-id> [--password-stdin] <approve|reject|escalate> <transfer|account> <id> <comment>";
+
+// The reviewer signs in with their password, as on the Login page, before
+// the queue is shown or changed. A reviewer ID alone is no secret: it is
+// on the account's payment QR code.
+fn review_queue(args: &[String]) -> i32 {
+    let conn = DB_POOL.writer();
+    let mut args = args.to_vec();
+    let password_stdin = match args.iter().position(|arg| arg == "--password-stdin") {
+        Some(index) => {
+            args.remove(index);
+            true
+        }
+        None => false,
+    };
+    let Some(reviewer_id) = args.first() else {
+        eprintln!("{}", REVIEW_USAGE);
+        return 2;
+    };
+    let password = if password_stdin {
+        let mut line = String::new();
+        std::io::stdin().read_line(&mut line).map(|_| line.trim_end_matches(['\r', '\n']).to_string())
+    } else {
+        rpassword::prompt_password("Password: ")
+    };
+    let password = match password {
+        Ok(password) => password,
+        Err(e) => {
+            eprintln!("Cannot read the password: {}", e);
+            return 1;
+        }
+    };
+    let signed_in = compliance::is_reviewer(&conn, reviewer_id)
+        .map_err(auth::AuthError::from)
+        .and_then(|permitted| auth::tool_login(&conn, "review", reviewer_id, &password, permitted));
+    if let Err(e) = signed_in {
+        eprintln!("{}", e);
+        return 1;
+    }
 
-pub fn set_receiver_id(id:String){
-    let mut receiver_id_lock = RECEIVER_ID.lock().unwrap();
-    *receiver_id_lock = Some(id);
+    match args.get(1).map(String::as_str) {
+        Some("list") | None => match compliance::queue(&conn) {
+            Ok(queue) => {
+                for item in &queue {
+                    let flag = if item.escalated { " [escalated]" } else { "" };
+                    println!("{} {}{}  {}", item.kind.as_str(), item.id, flag, item.summary);
+                    for hit in &item.hits {
+                        println!("    {}", hit);
+                    }
+                }
+                println!("{} item(s) waiting for review", queue.len());
+                0
+            }
+            Err(e) => {
+                eprintln!("Database error: {}", e);
+                1
+            }
+        },
+        Some(decision) => {
+            let decision = compliance::Decision::parse(decision);
+            let kind = args.get(2).and_then(|kind| compliance::ItemKind::parse(kind));
+            let (Some(decision), Some(kind), Some(id)) = (decision, kind, args.get(3)) else {
+                eprintln!("{}", REVIEW_USAGE);
+                return 2;
+            };
+            let comment = args[4..].join(" ");
+            match compliance::decide(&conn, reviewer_id, kind, id, decision, &comment) {
+                Ok(()) => {
+                    println!("{} {}: {}", kind.as_str(), id, decision.as_str());
+                    0
+                }
+                Err(e) => {
+                    eprintln!("{}", e);
+                    1
+                }
+            }
+        }
+    }
 }
-    
-    pub fn get_receiver_id() -> Option<String> {
-    let receiver_id_lock = RECEIVER_ID.lock().unwrap();
-    receiver_id_lock.clone()
+
+pub static USER_ID: Lazy<Mutex<Option<AccountId>>> = Lazy::new(|| Mutex::new(None));
+pub fn set_user_id(id: AccountId) {
+    let mut user_id_lock = USER_ID.lock().unwrap_or_else(PoisonError::into_inner);
+    *user_id_lock = Some(id);
 }
 
 
 // Function to retrieve the user ID globally
 pub fn get_user_id() -> Option<String> {
-    let user_id_lock = USER_ID.lock().unwrap();
+    get_account_id().map(String::from)
+}
+
+// The same, for pages that go through storage
+pub fn get_account_id() -> Option<AccountId> {
+    let user_id_lock = USER_ID.lock().unwrap_or_else(PoisonError::into_inner);
     user_id_lock.clone()
 }
 
 
 struct EasyRemit {
     current_page: Page,
+    // a failure with nowhere else to be shown, above whichever page is open
+    error: Option<String>,
+    dismiss_error_button: button::State,
 }
 
 #[derive(Debug, Clone)]
@@ -73,6 +190,10 @@ enum Page {
     Function(function::FunctionPage),
     Transfer(transfer::TransferPage),
     Success(success::SuccessPage),
+    Request(request::RequestPage),
+    Split(split::SplitPage),
+    Inbox(inbox::InboxPage),
+    Review(review::ReviewPage),
 }
 
 impl Application for EasyRemit {
@@ -85,6 +206,8 @@ impl Application for EasyRemit {
         (
             EasyRemit {
                 current_page: Page::Home(home_page),
+                error: None,
+                dismiss_error_button: button::State::new(),
             },
             Command::none(),
         )
@@ -95,67 +218,275 @@ impl Application for EasyRemit {
     }
 
     fn update(&mut self, message: Self::Message) -> Command<Self::Message> {
-        match &mut self.current_page {
+        // Pages start their own background work (checks, QR decoding,
+        // reloading after a change) from here
+        let page_command = match &mut self.current_page {
             Page::Login(page) => {
                 page.update(message.clone());
+                Command::none()
             }
             Page::Signup(page) => {
                 page.update(message.clone());
+                Command::none()
             }
-            Page::Transfer(page) => {
+            Page::Transfer(page) => page.update(message.clone()),
+            Page::Request(page) => {
                 page.update(message.clone());
+                Command::none()
             }
+            Page::Split(page) => page.update(message.clone()),
+            Page::Review(page) => page.update(message.clone()),
         
             // Other pages do not need to handle these messages
-            _ => {}
-        }
-        match message {
+            _ => Command::none(),
+        };
+        let command = match message {
             Message::GoToLogin => {
                 let login_page = login::LoginPage::new();
                 self.current_page = Page::Login(login_page);
+                Command::none()
+            }
+            Message::SubmitLogin => match &mut self.current_page {
+                Page::Login(page) => page.submit(),
+                _ => Command::none(),
+            },
+            Message::LoginFinished(result) => {
+                let done = match &mut self.current_page {
+                    Page::Login(page) => page.finished(result),
+                    _ => false,
+                };
+                if done {
+                    self.open_function()
+                } else {
+                    Command::none()
+                }
             }
             Message::GoToSignup => {
                 let signup_page = signup::SignupPage::new();
                 self.current_page = Page::Signup(signup_page);
+                Command::none()
             }
-            Message::GoToFunction => {
-               
-                let function_page = function::FunctionPage::new();
-                self.current_page = Page::Function(function_page);
+            Message::SubmitSignup => match &mut self.current_page {
+                Page::Signup(page) => page.submit(),
+                _ => Command::none(),
+            },
+            Message::SignupFinished(result) => {
+                let done = match &mut self.current_page {
+                    Page::Signup(page) => page.finished(result),
+                    _ => false,
+                };
+                if done {
+                    self.open_function()
+                } else {
+                    Command::none()
+                }
+            }
+            Message::GoToFunction => self.open_function(),
+            Message::DashboardLoaded(result) => {
+                if let Page::Function(page) = &mut self.current_page {
+                    page.loaded(result);
+                }
+                Command::none()
             }
             Message::GoToTransfer => {
-                let transfer_page = transfer::TransferPage::new();
+                let (transfer_page, command) = transfer::TransferPage::new();
                 self.current_page = Page::Transfer(transfer_page);
+                command
             }
-            Message::GoToSuccess => {
-                
-                
-                let success_page = success::SuccessPage::new();
-                self.current_page = Page::Success(success_page);
+            Message::SubmitTransfer => {
+                let ready = match &self.current_page {
+                    Page::Transfer(page) => page.ready_to_send(),
+                    _ => None,
+                };
+                match ready {
+                    Some((receiver_id, amount)) => {
+                        self.current_page = Page::Success(success::SuccessPage::sending());
+                        Command::perform(
+                            with_user_storage(move |storage, sender_id| storage.transfer(sender_id, &receiver_id, amount)),
+                            Message::TransferFinished,
+                        )
+                    }
+                    None => Command::none(),
+                }
+            }
+            Message::TransferFinished(outcome) => {
+                if let Page::Success(page) = &mut self.current_page {
+                    page.finished(outcome);
+                }
+                Command::none()
+            }
+
+            Message::GoToRequest => {
+                let request_page = request::RequestPage::new();
+                self.current_page = Page::Request(request_page);
+                Command::none()
+            }
+            Message::SubmitRequest => match &mut self.current_page {
+                Page::Request(page) => page.submit(),
+                _ => Command::none(),
+            },
+            Message::ShowRequestQr => match &mut self.current_page {
+                Page::Request(page) => page.show_qr(),
+                _ => Command::none(),
+            },
+            Message::SaveRequestQr => match &mut self.current_page {
+                Page::Request(page) => page.save_qr(),
+                _ => Command::none(),
+            },
+            Message::GoToSplit => {
+                let (split_page, command) = split::SplitPage::new();
+                self.current_page = Page::Split(split_page);
+                command
+            }
+            Message::SubmitSplit => match &mut self.current_page {
+                Page::Split(page) => page.submit(),
+                _ => Command::none(),
+            },
+            Message::ApproveRequest(id) => {
+                self.current_page = Page::Success(success::SuccessPage::sending());
+                Command::perform(
+                    with_user_storage(move |storage, payer_id| storage.pay_request(id, payer_id)),
+                    Message::TransferFinished,
+                )
+            }
+            Message::DeclineRequest(id) => match &mut self.current_page {
+                Page::Function(page) => page.decline(id),
+                _ => Command::none(),
+            },
+            Message::RequestDeclined(result) => {
+                if let Err(e) = result {
+                    self.error = Some(format!("Could not decline the request: {}", e));
+                }
+                match &self.current_page {
+                    Page::Function(_) => function::FunctionPage::load(),
+                    _ => Command::none(),
+                }
+            }
+
+            Message::GoToInbox => {
+                let (inbox_page, command) = inbox::InboxPage::new();
+                self.current_page = Page::Inbox(inbox_page);
+                command
+            }
+            Message::InboxLoaded(result) => {
+                if let Page::Inbox(page) = &mut self.current_page {
+                    page.loaded(result);
+                }
+                Command::none()
+            }
+            Message::MarkNotificationRead(id) => match &mut self.current_page {
+                Page::Inbox(page) => page.mark_read(id),
+                _ => Command::none(),
+            },
+            Message::MarkAllNotificationsRead => match &mut self.current_page {
+                Page::Inbox(page) => page.mark_all_read(),
+                _ => Command::none(),
+            },
+            Message::NotificationsMarked(result) => {
+                if let Err(e) = result {
+                    self.error = Some(format!("Could not mark notifications as read: {}", e));
+                }
+                match &self.current_page {
+                    Page::Inbox(_) => inbox::InboxPage::load(),
+                    _ => Command::none(),
+                }
+            }
+
+            Message::GoToReview => {
+                let (review_page, command) = review::ReviewPage::new();
+                self.current_page = Page::Review(review_page);
+                command
+            }
+            Message::ReviewDecision(kind, id, decision) => match &mut self.current_page {
+                Page::Review(page) => page.decide(kind, id, decision),
+                _ => Command::none(),
+            },
+
+            Message::DismissError => {
+                self.error = None;
+                Command::none()
             }
 
             Message::GoToHome => {
-                
-                
                 let home_page = home::HomePage::new();
                 self.current_page = Page::Home(home_page);
+                Command::none()
             }
-            _=>{}
-        }
-        Command::none()
+            _ => Command::none(),
+        };
+        Command::batch([page_command, command])
     }
-    fn view(&mut self) -> Element<Self::Message> {
-        match &mut self.current_page {
+    fn view(&mut self) -> Element<'_, Self::Message> {
+        let page = match &mut self.current_page {
             Page::Home(page) => page.view(),
-            Page::Login(page) => page.view(&DB_CONN),
-            Page::Signup(page) => page.view(&DB_CONN),
-            Page::Function(page) => page.view(&DB_CONN),
-            Page::Transfer(page) => page.view(&DB_CONN),
-            Page::Success(page) => page.view(&DB_CONN),
+            Page::Login(page) => page.view(),
+            Page::Signup(page) => page.view(),
+            Page::Function(page) => page.view(),
+            Page::Transfer(page) => page.view(),
+            Page::Success(page) => page.view(),
+            Page::Request(page) => page.view(),
+            Page::Split(page) => page.view(),
+            Page::Inbox(page) => page.view(),
+            Page::Review(page) => page.view(),
+        };
+        match &self.error {
+            Some(error) => Column::new()
+                .push(error_banner(error, &mut self.dismiss_error_button))
+                .push(page)
+                .into(),
+            None => page,
+        }
+    }
+}
+
+fn error_banner<'a>(error: &str, dismiss_button: &'a mut button::State) -> Element<'a, Message> {
+    let dismiss_button = Button::new(dismiss_button, Text::new("Dismiss").size(14))
+        .style(DismissButtonStyle)
+        .on_press(Message::DismissError);
+    Container::new(
+        Row::new()
+            .spacing(10)
+            .align_items(Alignment::Center)
+            .push(Text::new(error).size(16).width(Length::Fill))
+            .push(dismiss_button),
+    )
+    .width(Length::Fill)
+    .padding(10)
+    .style(BannerStyle)
+    .into()
+}
+
+struct BannerStyle;
+impl container::StyleSheet for BannerStyle {
+    fn style(&self) -> container::Style {
+        container::Style {
+            text_color: Some(Color::WHITE),
+            background: Some(Background::Color(Color::from_rgb(0.8, 0.2, 0.2))),
+            ..container::Style::default()
         }
     }
 }
 
+struct DismissButtonStyle;
+impl button::StyleSheet for DismissButtonStyle {
+    fn active(&self) -> button::Style {
+        button::Style {
+            background: Some(Background::Color(Color::WHITE)),
+            border_radius: 5.0,
+            text_color: Color::from_rgb(0.8, 0.2, 0.2),
+            ..button::Style::default()
+        }
+    }
+}
+
+impl EasyRemit {
+    fn open_function(&mut self) -> Command<Message> {
+        let (function_page, command) = function::FunctionPage::new();
+        self.current_page = Page::Function(function_page);
+        command
+    }
+}
+
 #[derive(Debug, Clone)]
 enum Message {
     GoToHome,
@@ -163,7 +494,6 @@ enum Message {
     GoToSignup,
     GoToFunction,
     GoToTransfer,
-    GoToSuccess,
     EmailChanged(String),
     PassportChanged(String),
     NameChanged(String),
@@ -172,5 +502,50 @@ enum Message {
     UserIDChanged(String),
     InputChanged(String),
     KeypadPressed(char),
-    
+    GoToRequest,
+    RequestPayerChanged(String),
+    RequestAmountChanged(String),
+    RequestMemoChanged(String),
+    SubmitRequest,
+    ApproveRequest(i64),
+    DeclineRequest(i64),
+    GoToSplit,
+    SplitTotalChanged(String),
+    SplitMemoChanged(String),
+    SplitParticipantsChanged(String),
+    SplitMethodSelected(bill_split::SplitMethod),
+    SplitAddParticipant(String),
+    SubmitSplit,
+    ShowRequestQr,
+    SaveRequestQr,
+    QrPathChanged(String),
+    ImportQr,
+    SubmitLogin,
+    SubmitSignup,
+    GoToInbox,
+    MarkNotificationRead(i64),
+    MarkAllNotificationsRead,
+    GoToReview,
+    DismissError,
+    ReviewCommentChanged(String),
+    ReviewDecision(compliance::ItemKind, String, compliance::Decision),
+
+    // Results of work run in the background with Command::perform
+    LoginFinished(Result<AccountId, String>),
+    SignupFinished(Result<AccountId, String>),
+    DashboardLoaded(Result<function::Dashboard, String>),
+    RequestDeclined(Result<(), String>),
+    TransferChecked(Result<transfer::TransferCheck, String>),
+    QrImported(Result<qr::PaymentPayload, String>),
+    SubmitTransfer,
+    TransferFinished(Result<ledger::Receipt, String>),
+    RequestSent(Result<String, String>),
+    RequestQrReady(Result<Vec<Vec<bool>>, String>),
+    RequestQrSaved(Result<String, String>),
+    SplitLoaded(Result<split::SplitData, String>),
+    SplitCreated(Result<i64, String>),
+    InboxLoaded(Result<Vec<notifications::Notification>, String>),
+    NotificationsMarked(Result<(), String>),
+    ReviewLoaded(Result<Vec<compliance::ReviewItem>, String>),
+    ReviewDecided(Result<String, String>),
 }
\ No newline at end of file
@@ -0,0 +1,274 @@
+use once_cell::sync::Lazy;
+use std::fmt::Write as _;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+use crate::db;
+use crate::ledger::TransferStatus;
+use crate::pool::PoolStats;
+use crate::retry::{self, RetryStats};
+
+// Counters for whoever runs EasyRemit: logins, transfers, password checks and
+// how hard the database is working. They live in this process and start from
+// zero with it. `render` gives them in Prometheus' text format, which
+// easyremit-api serves at GET /metrics and the desktop app writes to
+// `metrics.dump_file` (see `spawn_dump`).
+
+// Every login, transfer and password check in this process is counted here
+pub static METRICS: Lazy<Metrics> = Lazy::new(Metrics::default);
+
+// Why a login did not go through, as the `reason` label
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LoginFailure {
+    InvalidCredentials,
+    LockedOut,
+    Error,
+}
+
+const LOGIN_FAILURES: [LoginFailure; 3] = [LoginFailure::InvalidCredentials, LoginFailure::LockedOut, LoginFailure::Error];
+
+impl LoginFailure {
+    fn label(self) -> &'static str {
+        match self {
+            LoginFailure::InvalidCredentials => "invalid_credentials",
+            LoginFailure::LockedOut => "locked_out",
+            LoginFailure::Error => "error",
+        }
+    }
+}
+
+// `outcome` label of easyremit_transfers_total: the status of a posted
+// transfer, or why none was posted
+const TRANSFER_OUTCOMES: [&str; 5] = ["completed", "held", "blocked", "refused", "failed"];
+// the first three are statuses of posted transfers
+const POSTED: usize = 3;
+const REFUSED: usize = 3;
+const FAILED: usize = 4;
+
+fn posted_index(status: TransferStatus) -> usize {
+    match status {
+        TransferStatus::Completed => 0,
+        TransferStatus::Held => 1,
+        TransferStatus::Blocked => 2,
+    }
+}
+
+// Upper bounds of the password check buckets, in seconds
+const VERIFY_BUCKETS: [f64; 10] = [0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0];
+
+#[derive(Debug, Default)]
+struct Histogram {
+    // observations at most each bound; larger ones only count in `count`
+    buckets: [AtomicU64; VERIFY_BUCKETS.len()],
+    count: AtomicU64,
+    sum_micros: AtomicU64,
+}
+
+impl Histogram {
+    fn observe(&self, elapsed: Duration) {
+        let seconds = elapsed.as_secs_f64();
+        for (bound, bucket) in VERIFY_BUCKETS.iter().zip(&self.buckets) {
+            if seconds <= *bound {
+                bucket.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.sum_micros.fetch_add(elapsed.as_micros() as u64, Ordering::Relaxed);
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Metrics {
+    logins: AtomicU64,
+    failed_logins: [AtomicU64; LOGIN_FAILURES.len()],
+    transfers: [AtomicU64; TRANSFER_OUTCOMES.len()],
+    // whole dollars sent, by status, not counting fees
+    volume: [AtomicU64; POSTED],
+    fees: AtomicU64,
+    password_verify: Histogram,
+}
+
+impl Metrics {
+    pub fn logged_in(&self) {
+        self.logins.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn login_failed(&self, reason: LoginFailure) {
+        let index = LOGIN_FAILURES.iter().position(|failure| *failure == reason).unwrap_or_default();
+        self.failed_logins[index].fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn transfer_posted(&self, status: TransferStatus, amount: i64, fee: i64) {
+        let index = posted_index(status);
+        self.transfers[index].fetch_add(1, Ordering::Relaxed);
+        self.volume[index].fetch_add(amount.max(0) as u64, Ordering::Relaxed);
+        self.fees.fetch_add(fee.max(0) as u64, Ordering::Relaxed);
+    }
+
+    // Turned down by a check (funds, limits, a frozen account, ...)
+    pub fn transfer_refused(&self) {
+        self.transfers[REFUSED].fetch_add(1, Ordering::Relaxed);
+    }
+
+    // Could not be posted because of the database
+    pub fn transfer_failed(&self) {
+        self.transfers[FAILED].fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn password_verified(&self, elapsed: Duration) {
+        self.password_verify.observe(elapsed);
+    }
+
+    // Everything counted here, plus the pool's lock waits and the retries of
+    // each call site, in Prometheus' text exposition format
+    pub fn render(&self, pool: Option<PoolStats>, retries: &[(String, RetryStats)]) -> String {
+        let mut out = String::new();
+        let load = |counter: &AtomicU64| counter.load(Ordering::Relaxed);
+
+        header(&mut out, "easyremit_logins_total", "counter", "Successful logins.");
+        let _ = writeln!(out, "easyremit_logins_total {}", load(&self.logins));
+        header(&mut out, "easyremit_failed_logins_total", "counter", "Logins that were refused, by reason.");
+        for (failure, counter) in LOGIN_FAILURES.iter().zip(&self.failed_logins) {
+            let _ = writeln!(out, "easyremit_failed_logins_total{{reason=\"{}\"}} {}", failure.label(), load(counter));
+        }
+
+        header(&mut out, "easyremit_transfers_total", "counter", "Transfers by outcome: the status of posted ones, or refused or failed.");
+        for (outcome, counter) in TRANSFER_OUTCOMES.iter().zip(&self.transfers) {
+            let _ = writeln!(out, "easyremit_transfers_total{{outcome=\"{}\"}} {}", outcome, load(counter));
+        }
+        header(&mut out, "easyremit_transfer_volume_dollars_total", "counter", "Dollars sent in posted transfers, by status, without fees.");
+        for (status, counter) in TRANSFER_OUTCOMES.iter().zip(&self.volume) {
+            let _ = writeln!(out, "easyremit_transfer_volume_dollars_total{{status=\"{}\"}} {}", status, load(counter));
+        }
+        header(&mut out, "easyremit_transfer_fees_dollars_total", "counter", "Fees charged on posted transfers.");
+        let _ = writeln!(out, "easyremit_transfer_fees_dollars_total {}", load(&self.fees));
+
+        let verify = &self.password_verify;
+        header(&mut out, "easyremit_password_verify_seconds", "histogram", "Time taken to check a password against its Argon2 hash.");
+        for (bound, bucket) in VERIFY_BUCKETS.iter().zip(&verify.buckets) {
+            let _ = writeln!(out, "easyremit_password_verify_seconds_bucket{{le=\"{}\"}} {}", bound, load(bucket));
+        }
+        let _ = writeln!(out, "easyremit_password_verify_seconds_bucket{{le=\"+Inf\"}} {}", load(&verify.count));
+        let _ = writeln!(out, "easyremit_password_verify_seconds_sum {}", load(&verify.sum_micros) as f64 / 1e6);
+        let _ = writeln!(out, "easyremit_password_verify_seconds_count {}", load(&verify.count));
+
+        if let Some(pool) = pool {
+            let locks = [("writer", pool.writer), ("reader", pool.readers)];
+            header(&mut out, "easyremit_db_lock_acquired_total", "counter", "Database connections handed out by the pool.");
+            for (lock, waits) in locks {
+                let _ = writeln!(out, "easyremit_db_lock_acquired_total{{lock=\"{}\"}} {}", lock, waits.acquired);
+            }
+            header(&mut out, "easyremit_db_lock_wait_seconds_total", "counter", "Time spent waiting for a database connection.");
+            for (lock, waits) in locks {
+                let _ = writeln!(out, "easyremit_db_lock_wait_seconds_total{{lock=\"{}\"}} {}", lock, waits.total_wait.as_secs_f64());
+            }
+            header(&mut out, "easyremit_db_lock_wait_seconds_max", "gauge", "Longest wait for a database connection.");
+            for (lock, waits) in locks {
+                let _ = writeln!(out, "easyremit_db_lock_wait_seconds_max{{lock=\"{}\"}} {}", lock, waits.max_wait.as_secs_f64());
+            }
+            header(&mut out, "easyremit_db_readers_open", "gauge", "Read-only connections open.");
+            let _ = writeln!(out, "easyremit_db_readers_open {}", pool.readers_open);
+        }
+
+        header(&mut out, "easyremit_db_calls_total", "counter", "Database operations run under a retry policy, by call site.");
+        for (site, stats) in retries {
+            let _ = writeln!(out, "easyremit_db_calls_total{{site=\"{}\"}} {}", escape(site), stats.calls);
+        }
+        header(&mut out, "easyremit_db_retries_total", "counter", "Database operations run again because the database was busy, by call site.");
+        for (site, stats) in retries {
+            let _ = writeln!(out, "easyremit_db_retries_total{{site=\"{}\"}} {}", escape(site), stats.retries);
+        }
+        header(&mut out, "easyremit_db_retries_exhausted_total", "counter", "Database operations still busy when their retries ran out, by call site.");
+        for (site, stats) in retries {
+            let _ = writeln!(out, "easyremit_db_retries_exhausted_total{{site=\"{}\"}} {}", escape(site), stats.exhausted);
+        }
+        out
+    }
+}
+
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
+
+// Label values are quoted; backslashes (Windows paths) and quotes are escaped
+fn escape(value: &str) -> String {
+    value.replace('\\', r"\\").replace('"', "\\\"")
+}
+
+// The metrics of this process. The pool's waits are included once the
+// database has been opened.
+pub fn render() -> String {
+    METRICS.render(db::stats(), &retry::retry_stats())
+}
+
+// Writes `render` to `path`, through a temporary file so that a reader never
+// sees half of it
+pub fn dump(path: &Path) -> std::io::Result<()> {
+    let mut temporary = path.as_os_str().to_owned();
+    temporary.push(".tmp");
+    std::fs::write(&temporary, render())?;
+    std::fs::rename(&temporary, path)
+}
+
+// Rewrites `path` every `interval` for as long as the process runs, for the
+// desktop app, which has no endpoint to scrape. The file suits
+// node_exporter's textfile collector.
+pub fn spawn_dump(path: PathBuf, interval: Duration) {
+    std::thread::spawn(move || loop {
+        if let Err(e) = dump(&path) {
+            tracing::warn!(path = %path.display(), error = %e, "could not write metrics");
+        }
+        std::thread::sleep(interval);
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pool::WaitStats;
+
+    #[test]
+    fn renders_prometheus_text() {
+        let metrics = Metrics::default();
+        metrics.logged_in();
+        metrics.logged_in();
+        metrics.login_failed(LoginFailure::LockedOut);
+        metrics.transfer_posted(TransferStatus::Completed, 20, 1);
+        metrics.transfer_posted(TransferStatus::Held, 500, 5);
+        metrics.transfer_refused();
+        metrics.password_verified(Duration::from_millis(30));
+        metrics.password_verified(Duration::from_secs(9));
+        let waits = WaitStats { acquired: 4, total_wait: Duration::from_millis(1500), max_wait: Duration::from_secs(1) };
+        let pool = PoolStats { writer: waits, readers: waits, readers_open: 2, max_readers: 4, retries: 0 };
+        let retries = [("src\\ledger.rs:10".to_string(), RetryStats { calls: 7, retries: 3, exhausted: 1 })];
+
+        let text = metrics.render(Some(pool), &retries);
+        for line in [
+            "# TYPE easyremit_logins_total counter",
+            "easyremit_logins_total 2",
+            "easyremit_failed_logins_total{reason=\"locked_out\"} 1",
+            "easyremit_failed_logins_total{reason=\"invalid_credentials\"} 0",
+            "easyremit_transfers_total{outcome=\"completed\"} 1",
+            "easyremit_transfers_total{outcome=\"refused\"} 1",
+            "easyremit_transfers_total{outcome=\"failed\"} 0",
+            "easyremit_transfer_volume_dollars_total{status=\"held\"} 500",
+            "easyremit_transfer_fees_dollars_total 6",
+            "# TYPE easyremit_password_verify_seconds histogram",
+            "easyremit_password_verify_seconds_bucket{le=\"0.025\"} 0",
+            "easyremit_password_verify_seconds_bucket{le=\"0.05\"} 1",
+            "easyremit_password_verify_seconds_bucket{le=\"5\"} 1",
+            "easyremit_password_verify_seconds_bucket{le=\"+Inf\"} 2",
+            "easyremit_password_verify_seconds_sum 9.03",
+            "easyremit_password_verify_seconds_count 2",
+            "easyremit_db_lock_wait_seconds_total{lock=\"writer\"} 1.5",
+            "easyremit_db_readers_open 2",
+            "easyremit_db_retries_total{site=\"src\\\\ledger.rs:10\"} 3",
+            "easyremit_db_retries_exhausted_total{site=\"src\\\\ledger.rs:10\"} 1",
+        ] {
+            assert!(text.lines().any(|found| found == line), "{} missing from\n{}", line, text);
+        }
+        // no volume line for transfers that were never posted
+        assert!(!text.contains("easyremit_transfer_volume_dollars_total{status=\"refused\"}"));
+    }
+}
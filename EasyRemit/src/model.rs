@@ -0,0 +1,418 @@
+use rusqlite::types::{FromSql, FromSqlError, FromSqlResult, ToSql, ToSqlOutput, ValueRef};
+use rand::{thread_rng, Rng};
+use serde::{Deserialize, Serialize};
+use std::borrow::Borrow;
+use std::fmt;
+use std::str::FromStr;
+use crate::config;
+use crate::fees::HOUSE_ACCOUNT_ID;
+use crate::ledger::TransferStatus;
+
+// The values pages and storage pass each other. Each type checks its value
+// when it is made, so anything holding one can rely on it:
+//   AccountId      - a user's 12-digit account number, or the fee account
+//   TransactionId  - a row of transaction history
+//   Email          - one @ with something either side
+//   PassportNumber - matches `validation.passport`
+//   BirthDate      - matches `validation.birth_date` and is a real date
+// They serialise as the plain string or number, and deserialising checks
+// them the same way. User, Account and Transaction are built from them.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ModelError {
+    AccountId,
+    TransactionId,
+    Email,
+    PassportNumber,
+    BirthDate,
+}
+
+impl fmt::Display for ModelError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ModelError::AccountId => write!(f, "A user ID is 12 digits"),
+            ModelError::TransactionId => write!(f, "A transaction ID is a number greater than 0"),
+            ModelError::Email => write!(f, "Enter an email address such as abc@gmail.com"),
+            ModelError::PassportNumber => write!(f, "Enter the passport number as printed, e.g. AB123456"),
+            ModelError::BirthDate => write!(f, "Enter a real date as dd/mm/yyyy, e.g. 06/04/2004"),
+        }
+    }
+}
+
+impl std::error::Error for ModelError {}
+
+// Conversions shared by the string newtypes
+macro_rules! string_type {
+    ($name:ident) => {
+        impl $name {
+            pub fn as_str(&self) -> &str {
+                &self.0
+            }
+        }
+
+        impl fmt::Display for $name {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                f.write_str(&self.0)
+            }
+        }
+
+        impl AsRef<str> for $name {
+            fn as_ref(&self) -> &str {
+                &self.0
+            }
+        }
+
+        impl FromStr for $name {
+            type Err = ModelError;
+
+            fn from_str(value: &str) -> Result<Self, ModelError> {
+                $name::parse(value)
+            }
+        }
+
+        impl TryFrom<String> for $name {
+            type Error = ModelError;
+
+            fn try_from(value: String) -> Result<Self, ModelError> {
+                $name::parse(&value)
+            }
+        }
+
+        impl From<$name> for String {
+            fn from(value: $name) -> String {
+                value.0
+            }
+        }
+
+        impl PartialEq<str> for $name {
+            fn eq(&self, other: &str) -> bool {
+                self.0 == other
+            }
+        }
+
+        impl ToSql for $name {
+            fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+                self.0.to_sql()
+            }
+        }
+
+        // Values read back are checked like any other
+        impl FromSql for $name {
+            fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+                $name::parse(value.as_str()?).map_err(|e| FromSqlError::Other(Box::new(e)))
+            }
+        }
+    };
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct AccountId(String);
+
+impl AccountId {
+    pub fn parse(value: &str) -> Result<Self, ModelError> {
+        let digits = value.len() == 12 && value.bytes().all(|b| b.is_ascii_digit());
+        if digits || value == HOUSE_ACCOUNT_ID {
+            Ok(AccountId(value.to_string()))
+        } else {
+            Err(ModelError::AccountId)
+        }
+    }
+
+    // Where transfer fees are paid to
+    pub fn house() -> Self {
+        AccountId(HOUSE_ACCOUNT_ID.to_string())
+    }
+
+    // An ID read back from the ledger, which also books against accounts of
+    // its own such as auth::BONUS_ACCOUNT
+    pub(crate) fn stored(id: String) -> Self {
+        AccountId(id)
+    }
+
+    // A new user's ID: two digits taken from the birth year followed by ten
+    // random digits
+    pub(crate) fn random(birth_date: &BirthDate) -> Self {
+        let year = birth_date
+            .as_str()
+            .get(7..9)
+            .filter(|year| year.bytes().all(|b| b.is_ascii_digit()))
+            .unwrap_or("00");
+        let random_id: u64 = thread_rng().gen_range(0..10_000_000);
+        AccountId(format!("{}{:010}", year, random_id))
+    }
+}
+
+string_type!(AccountId);
+
+// So maps keyed by account can be searched with a plain &str
+impl Borrow<str> for AccountId {
+    fn borrow(&self) -> &str {
+        &self.0
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(try_from = "i64", into = "i64")]
+pub struct TransactionId(i64);
+
+impl TransactionId {
+    pub fn new(id: i64) -> Result<Self, ModelError> {
+        if id > 0 {
+            Ok(TransactionId(id))
+        } else {
+            Err(ModelError::TransactionId)
+        }
+    }
+
+    // An ID the database has just assigned, which is always positive
+    pub(crate) fn assigned(id: i64) -> Self {
+        TransactionId(id)
+    }
+
+    pub fn get(self) -> i64 {
+        self.0
+    }
+}
+
+impl fmt::Display for TransactionId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl TryFrom<i64> for TransactionId {
+    type Error = ModelError;
+
+    fn try_from(id: i64) -> Result<Self, ModelError> {
+        TransactionId::new(id)
+    }
+}
+
+impl From<TransactionId> for i64 {
+    fn from(id: TransactionId) -> i64 {
+        id.0
+    }
+}
+
+impl ToSql for TransactionId {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        self.0.to_sql()
+    }
+}
+
+impl FromSql for TransactionId {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        TransactionId::new(value.as_i64()?).map_err(|e| FromSqlError::Other(Box::new(e)))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Email(String);
+
+impl Email {
+    pub fn parse(value: &str) -> Result<Self, ModelError> {
+        let valid = match value.split_once('@') {
+            Some((local, domain)) => {
+                !local.is_empty() && !domain.is_empty() && !domain.contains('@') && value.len() <= 254
+            }
+            None => false,
+        };
+        if valid && !value.chars().any(char::is_whitespace) {
+            Ok(Email(value.to_string()))
+        } else {
+            Err(ModelError::Email)
+        }
+    }
+}
+
+string_type!(Email);
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct PassportNumber(String);
+
+impl PassportNumber {
+    pub fn parse(value: &str) -> Result<Self, ModelError> {
+        if config::get().validation.passport_regex().is_match(value) {
+            Ok(PassportNumber(value.to_string()))
+        } else {
+            Err(ModelError::PassportNumber)
+        }
+    }
+}
+
+string_type!(PassportNumber);
+
+// Kept as entered, dd/mm/yyyy with the default pattern
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct BirthDate(String);
+
+impl BirthDate {
+    pub fn parse(value: &str) -> Result<Self, ModelError> {
+        if !config::get().validation.birth_date_regex().is_match(value) {
+            return Err(ModelError::BirthDate);
+        }
+        // A pattern that lets other shapes through is trusted with them
+        if let Some((day, month, year)) = day_month_year(value) {
+            if month == 0 || month > 12 || day == 0 || day > days_in_month(month, year) {
+                return Err(ModelError::BirthDate);
+            }
+        }
+        Ok(BirthDate(value.to_string()))
+    }
+}
+
+string_type!(BirthDate);
+
+fn day_month_year(value: &str) -> Option<(u32, u32, u32)> {
+    let mut parts = value.split('/').map(|part| part.parse::<u32>().ok());
+    match (parts.next()?, parts.next()?, parts.next()?, parts.next()) {
+        (Some(day), Some(month), Some(year), None) => Some((day, month, year)),
+        _ => None,
+    }
+}
+
+fn days_in_month(month: u32, year: u32) -> u32 {
+    match month {
+        2 if year.is_multiple_of(4) && (!year.is_multiple_of(100) || year.is_multiple_of(400)) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+// A user and their account as the dashboard shows them. Passport and birth
+// date are decrypted, and are None when missing, unreadable or no longer
+// valid under the configured patterns.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct User {
+    pub id: AccountId,
+    pub name: Option<String>,
+    pub email: Option<Email>,
+    pub balance: i64,
+    // ISO 4217 code of the account, which picks the fee corridor
+    pub currency: String,
+    pub passport: Option<PassportNumber>,
+    pub birth_date: Option<BirthDate>,
+}
+
+// One side of a transaction, with the name it goes by: the user's name, or
+// the ID itself for the ledger's own accounts
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Account {
+    pub id: AccountId,
+    pub name: String,
+}
+
+// What a row of transaction history is for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TransactionKind {
+    Transfer,
+    // the fee paid to the house account for a transfer
+    Fee,
+    // posted by an operator with ledger::adjust_balance
+    Adjustment,
+}
+
+impl TransactionKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TransactionKind::Transfer => "transfer",
+            TransactionKind::Fee => "fee",
+            TransactionKind::Adjustment => "adjustment",
+        }
+    }
+
+    pub fn parse(kind: &str) -> Option<Self> {
+        [TransactionKind::Transfer, TransactionKind::Fee, TransactionKind::Adjustment]
+            .into_iter()
+            .find(|k| k.as_str() == kind)
+    }
+}
+
+impl FromSql for TransactionKind {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        let kind = value.as_str()?;
+        TransactionKind::parse(kind).ok_or_else(|| FromSqlError::Other(format!("unknown transaction kind {:?}", kind).into()))
+    }
+}
+
+// One row of a user's transaction history as they see it
+#[derive(Debug, Clone)]
+pub struct Transaction {
+    pub id: TransactionId,
+    pub created_at: Option<i64>,
+    pub kind: TransactionKind,
+    // true when the money left this user's account
+    pub outgoing: bool,
+    pub counterparty: Account,
+    pub amount: i64,
+    pub status: TransferStatus,
+    pub memo: Option<String>,
+}
+
+impl Transaction {
+    // Negative for money going out
+    pub fn signed_amount(&self) -> i64 {
+        if self.outgoing { -self.amount } else { self.amount }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn account_ids() {
+        assert!(AccountId::parse("960008484537").is_ok());
+        assert_eq!(AccountId::parse("HOUSE_REVENUE"), Ok(AccountId::house()));
+        for id in ["", "96000848453", "9600084845370", "96000848453x", "NULL"] {
+            assert_eq!(AccountId::parse(id), Err(ModelError::AccountId), "{}", id);
+        }
+        assert!(TransactionId::new(1).is_ok());
+        assert_eq!(TransactionId::new(0), Err(ModelError::TransactionId));
+    }
+
+    #[test]
+    fn emails() {
+        for email in ["abc123@gmail.com", "a@x"] {
+            assert_eq!(Email::parse(email).unwrap().as_str(), email);
+        }
+        for email in ["", "abc", "@gmail.com", "abc@", "a@b@c", "a b@c.com"] {
+            assert_eq!(Email::parse(email), Err(ModelError::Email), "{}", email);
+        }
+    }
+
+    #[test]
+    fn passports_and_birth_dates() {
+        assert!(PassportNumber::parse("AB123456").is_ok());
+        assert_eq!(PassportNumber::parse("ab123456"), Err(ModelError::PassportNumber));
+        for date in ["06/04/2004", "29/02/2000", "31/12/1990"] {
+            assert!(BirthDate::parse(date).is_ok(), "{}", date);
+        }
+        for date in ["6/4/2004", "31/04/2004", "29/02/1900", "00/01/2000", "01/13/2000"] {
+            assert_eq!(BirthDate::parse(date), Err(ModelError::BirthDate), "{}", date);
+        }
+    }
+
+    #[test]
+    fn transaction_kinds() {
+        for kind in [TransactionKind::Transfer, TransactionKind::Fee, TransactionKind::Adjustment] {
+            assert_eq!(TransactionKind::parse(kind.as_str()), Some(kind));
+        }
+        assert_eq!(TransactionKind::parse("refund"), None);
+    }
+
+    #[test]
+    fn serde_checks_values() {
+        let id: AccountId = serde_json::from_str("\"960008484537\"").unwrap();
+        assert_eq!(serde_json::to_string(&id).unwrap(), "\"960008484537\"");
+        assert!(serde_json::from_str::<AccountId>("\"12\"").is_err());
+        assert_eq!(serde_json::to_string(&TransactionId::new(7).unwrap()).unwrap(), "7");
+        assert!(serde_json::from_str::<TransactionId>("-1").is_err());
+        assert!(serde_json::from_str::<Email>("\"nobody\"").is_err());
+    }
+}
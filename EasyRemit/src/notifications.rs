@@ -0,0 +1,174 @@
+use rusqlite::{params, Connection};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NotificationKind {
+    MoneyReceived,
+    RequestReceived,
+    NewLogin,
+    LimitChanged,
+}
+
+impl NotificationKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            NotificationKind::MoneyReceived => "money_received",
+            NotificationKind::RequestReceived => "request_received",
+            NotificationKind::NewLogin => "new_login",
+            NotificationKind::LimitChanged => "limit_changed",
+        }
+    }
+
+    pub(crate) fn parse(kind: &str) -> Option<Self> {
+        [
+            NotificationKind::MoneyReceived,
+            NotificationKind::RequestReceived,
+            NotificationKind::NewLogin,
+            NotificationKind::LimitChanged,
+        ]
+        .into_iter()
+        .find(|k| k.as_str() == kind)
+    }
+
+    pub fn title(&self) -> &'static str {
+        match self {
+            NotificationKind::MoneyReceived => "Money received",
+            NotificationKind::RequestReceived => "Payment request",
+            NotificationKind::NewLogin => "New login",
+            NotificationKind::LimitChanged => "Limits changed",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Notification {
+    pub id: i64,
+    pub kind: NotificationKind,
+    pub message: String,
+    pub created_at: i64,
+    pub read: bool,
+}
+
+// Records an event for `user_id`. Called from the code that makes the change,
+// inside its transaction where there is one, so a rolled back transfer leaves
+// no notification behind.
+pub fn notify(conn: &Connection, user_id: &str, kind: NotificationKind, message: &str) -> rusqlite::Result<()> {
+    conn.execute(
+        "INSERT INTO notifications (user_id, kind, message, created_at) VALUES (?1, ?2, ?3, strftime('%s', 'now'))",
+        params![user_id, kind.as_str(), message],
+    )?;
+    Ok(())
+}
+
+pub fn unread_count(conn: &Connection, user_id: &str) -> rusqlite::Result<i64> {
+    conn.query_row(
+        "SELECT COUNT(*) FROM notifications WHERE user_id = ?1 AND read_at IS NULL",
+        params![user_id],
+        |row| row.get(0),
+    )
+}
+
+// The user's most recent notifications, newest first.
+pub fn inbox(conn: &Connection, user_id: &str, limit: usize) -> rusqlite::Result<Vec<Notification>> {
+    let mut stmt = conn.prepare(
+        "SELECT id, kind, message, created_at, read_at IS NOT NULL FROM notifications
+         WHERE user_id = ?1 ORDER BY created_at DESC, id DESC LIMIT ?2",
+    )?;
+    let rows = stmt.query_map(params![user_id, limit as i64], |row| {
+        Ok((
+            row.get::<_, i64>(0)?,
+            row.get::<_, String>(1)?,
+            row.get::<_, String>(2)?,
+            row.get::<_, i64>(3)?,
+            row.get::<_, bool>(4)?,
+        ))
+    })?;
+
+    let mut notifications = Vec::new();
+    for row in rows {
+        let (id, kind, message, created_at, read) = row?;
+        // skip kinds written by a newer version of the app
+        if let Some(kind) = NotificationKind::parse(&kind) {
+            notifications.push(Notification { id, kind, message, created_at, read });
+        }
+    }
+    Ok(notifications)
+}
+
+pub fn mark_read(conn: &Connection, user_id: &str, id: i64) -> rusqlite::Result<()> {
+    conn.execute(
+        "UPDATE notifications SET read_at = strftime('%s', 'now') WHERE id = ?1 AND user_id = ?2 AND read_at IS NULL",
+        params![id, user_id],
+    )?;
+    Ok(())
+}
+
+pub fn mark_all_read(conn: &Connection, user_id: &str) -> rusqlite::Result<()> {
+    conn.execute(
+        "UPDATE notifications SET read_at = strftime('%s', 'now') WHERE user_id = ?1 AND read_at IS NULL",
+        params![user_id],
+    )?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::init_schema;
+
+    fn database() -> Connection {
+        let conn = Connection::open_in_memory().unwrap();
+        init_schema(&conn).unwrap();
+        conn
+    }
+
+    #[test]
+    fn inbox_is_per_user_and_newest_first() {
+        let conn = database();
+        notify(&conn, "a", NotificationKind::MoneyReceived, "Received $5 from b").unwrap();
+        notify(&conn, "a", NotificationKind::NewLogin, "New login").unwrap();
+        notify(&conn, "b", NotificationKind::RequestReceived, "a requested $5").unwrap();
+
+        let inbox_a = inbox(&conn, "a", 10).unwrap();
+        let kinds: Vec<_> = inbox_a.iter().map(|n| n.kind).collect();
+        assert_eq!(kinds, [NotificationKind::NewLogin, NotificationKind::MoneyReceived]);
+        assert!(inbox_a.iter().all(|n| !n.read));
+        assert_eq!(inbox(&conn, "a", 1).unwrap().len(), 1);
+        assert_eq!(unread_count(&conn, "a").unwrap(), 2);
+        assert_eq!(unread_count(&conn, "b").unwrap(), 1);
+        assert_eq!(unread_count(&conn, "c").unwrap(), 0);
+    }
+
+    #[test]
+    fn marking_read_only_touches_the_users_own() {
+        let conn = database();
+        notify(&conn, "a", NotificationKind::MoneyReceived, "one").unwrap();
+        notify(&conn, "a", NotificationKind::MoneyReceived, "two").unwrap();
+        notify(&conn, "b", NotificationKind::MoneyReceived, "three").unwrap();
+        let b_id = inbox(&conn, "b", 10).unwrap()[0].id;
+        let a_id = inbox(&conn, "a", 10).unwrap()[0].id;
+
+        mark_read(&conn, "a", b_id).unwrap();
+        assert_eq!(unread_count(&conn, "b").unwrap(), 1);
+        mark_read(&conn, "a", a_id).unwrap();
+        assert_eq!(unread_count(&conn, "a").unwrap(), 1);
+        assert!(inbox(&conn, "a", 10).unwrap()[0].read);
+
+        mark_all_read(&conn, "a").unwrap();
+        assert_eq!(unread_count(&conn, "a").unwrap(), 0);
+        assert_eq!(unread_count(&conn, "b").unwrap(), 1);
+    }
+
+    #[test]
+    fn unknown_kinds_are_skipped() {
+        let conn = database();
+        conn.execute(
+            "INSERT INTO notifications (user_id, kind, message, created_at) VALUES ('a', 'scheduled_transfer_failed', 'x', 0)",
+            [],
+        )
+        .unwrap();
+        notify(&conn, "a", NotificationKind::LimitChanged, "New limits").unwrap();
+        let inbox_a = inbox(&conn, "a", 10).unwrap();
+        assert_eq!(inbox_a.len(), 1);
+        assert_eq!(inbox_a[0].kind.title(), "Limits changed");
+    }
+}
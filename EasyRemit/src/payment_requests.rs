@@ -0,0 +1,352 @@
+use rusqlite::{params, Connection, OptionalExtension};
+use std::fmt;
+use crate::bill_split;
+use crate::ledger::{self, Receipt, TransferError, TransferStatus};
+use crate::model::TransactionId;
+use crate::notifications::{self, NotificationKind};
+
+// How long a payer has to act on a request before it expires.
+pub const REQUEST_TTL_SECS: i64 = 7 * 24 * 60 * 60;
+
+#[derive(Debug, Clone)]
+pub struct PaymentRequest {
+    pub id: i64,
+    pub requester_id: String,
+    pub requester_name: String,
+    pub payer_id: String,
+    pub amount: i64,
+    pub memo: String,
+    pub expires_at: i64,
+}
+
+#[derive(Debug)]
+pub enum RequestError {
+    InvalidAmount,
+    SameAccount,
+    UnknownPayer,
+    NotFound,
+    NotPending(String),
+    Expired,
+    Transfer(TransferError),
+    Database(rusqlite::Error),
+}
+
+impl fmt::Display for RequestError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RequestError::InvalidAmount => write!(f, "Enter an amount greater than 0"),
+            RequestError::SameAccount => write!(f, "You cannot request money from yourself"),
+            RequestError::UnknownPayer => write!(f, "Payer's ID does not exist"),
+            RequestError::NotFound => write!(f, "Request not found"),
+            RequestError::NotPending(status) => write!(f, "Request is already {}", status),
+            RequestError::Expired => write!(f, "Request has expired"),
+            RequestError::Transfer(e) => write!(f, "{}", e),
+            RequestError::Database(e) => write!(f, "Database error: {}", e),
+        }
+    }
+}
+
+impl From<rusqlite::Error> for RequestError {
+    fn from(e: rusqlite::Error) -> Self {
+        RequestError::Database(e)
+    }
+}
+
+impl From<TransferError> for RequestError {
+    fn from(e: TransferError) -> Self {
+        RequestError::Transfer(e)
+    }
+}
+
+pub fn create(conn: &Connection, requester_id: &str, payer_id: &str, amount: i64, memo: &str) -> Result<i64, RequestError> {
+    if amount <= 0 {
+        return Err(RequestError::InvalidAmount);
+    }
+    if requester_id == payer_id {
+        return Err(RequestError::SameAccount);
+    }
+    let exists: bool = conn.query_row(
+        "SELECT EXISTS(SELECT 1 FROM user_information WHERE id = ?1)",
+        params![payer_id],
+        |row| row.get(0),
+    )?;
+    if !exists {
+        return Err(RequestError::UnknownPayer);
+    }
+
+    conn.execute(
+        "INSERT INTO payment_requests (requester_id, payer_id, amount, memo, created_at, expires_at)
+         VALUES (?1, ?2, ?3, ?4, strftime('%s', 'now'), strftime('%s', 'now') + ?5)",
+        params![requester_id, payer_id, amount, memo.trim(), REQUEST_TTL_SECS],
+    )?;
+    let id = conn.last_insert_rowid();
+
+    let requester_name: String = conn
+        .query_row(
+            "SELECT name FROM user_information WHERE id = ?1",
+            params![requester_id],
+            |row| row.get(0),
+        )
+        .optional()?
+        .unwrap_or_else(|| requester_id.to_string());
+    notifications::notify(conn, payer_id, NotificationKind::RequestReceived, &request_message(&requester_name, amount, memo))?;
+    Ok(id)
+}
+
+// What the payer is told about a new request
+pub(crate) fn request_message(requester_name: &str, amount: i64, memo: &str) -> String {
+    if memo.trim().is_empty() {
+        format!("{} requested ${}", requester_name, amount)
+    } else {
+        format!("{} requested ${} for {}", requester_name, amount, memo.trim())
+    }
+}
+
+// Marks every pending request past its expiry time as expired.
+pub fn expire_stale(conn: &Connection) -> rusqlite::Result<usize> {
+    conn.execute(
+        "UPDATE payment_requests SET status = 'expired'
+         WHERE status = 'pending' AND expires_at < strftime('%s', 'now')",
+        [],
+    )
+}
+
+// Requests waiting for `payer_id` to approve or decline, oldest first.
+pub fn pending_for(conn: &Connection, payer_id: &str) -> rusqlite::Result<Vec<PaymentRequest>> {
+    expire_stale(conn)?;
+    let mut stmt = conn.prepare(
+        "SELECT pr.id, pr.requester_id, COALESCE(u.name, pr.requester_id), pr.payer_id, pr.amount, pr.memo, pr.expires_at
+         FROM payment_requests pr
+         LEFT JOIN user_information u ON u.id = pr.requester_id
+         WHERE pr.payer_id = ?1 AND pr.status = 'pending'
+         ORDER BY pr.created_at ASC, pr.id ASC",
+    )?;
+    let requests = stmt.query_map(params![payer_id], |row| {
+        Ok(PaymentRequest {
+            id: row.get(0)?,
+            requester_id: row.get(1)?,
+            requester_name: row.get(2)?,
+            payer_id: row.get(3)?,
+            amount: row.get(4)?,
+            memo: row.get(5)?,
+            expires_at: row.get(6)?,
+        })
+    })?;
+    requests.collect()
+}
+
+// Loads a request that `payer_id` may still act on.
+fn pending_request(conn: &Connection, id: i64, payer_id: &str) -> Result<PaymentRequest, RequestError> {
+    let row = conn
+        .query_row(
+            "SELECT pr.requester_id, COALESCE(u.name, pr.requester_id), pr.payer_id, pr.amount, pr.memo, pr.expires_at,
+                    pr.status, pr.expires_at < strftime('%s', 'now')
+             FROM payment_requests pr
+             LEFT JOIN user_information u ON u.id = pr.requester_id
+             WHERE pr.id = ?1",
+            params![id],
+            |row| {
+                Ok((
+                    PaymentRequest {
+                        id,
+                        requester_id: row.get(0)?,
+                        requester_name: row.get(1)?,
+                        payer_id: row.get(2)?,
+                        amount: row.get(3)?,
+                        memo: row.get(4)?,
+                        expires_at: row.get(5)?,
+                    },
+                    row.get::<_, String>(6)?,
+                    row.get::<_, bool>(7)?,
+                ))
+            },
+        )
+        .optional()?;
+
+    let (request, status, expired) = match row {
+        Some(row) => row,
+        None => return Err(RequestError::NotFound),
+    };
+    if request.payer_id != payer_id {
+        return Err(RequestError::NotFound);
+    }
+    if status != "pending" {
+        return Err(RequestError::NotPending(status));
+    }
+    if expired {
+        return Err(RequestError::Expired);
+    }
+    Ok(request)
+}
+
+// Pays a request with a normal transfer from the payer to the requester. The
+// transfer and the status change commit together. A transfer held by
+// screening leaves the request 'held' until compliance reviews it.
+pub fn approve(conn: &Connection, id: i64, payer_id: &str) -> Result<Receipt, RequestError> {
+    let tx = conn.unchecked_transaction()?;
+    let request = pending_request(&tx, id, payer_id)?;
+    let receipt = ledger::post_transfer(&tx, payer_id, &request.requester_id, request.amount)?;
+    let status = match receipt.status {
+        TransferStatus::Completed => "paid",
+        TransferStatus::Held => "held",
+        TransferStatus::Blocked => "blocked",
+    };
+    settle(&tx, id, status, Some(receipt.transaction_id))?;
+    bill_split::settle_if_complete(&tx, id)?;
+    tx.commit()?;
+    Ok(receipt)
+}
+
+// Carries a compliance decision on a held transfer over to the request it
+// pays, if any: 'paid' once released, 'blocked' once rejected.
+pub fn transfer_reviewed(conn: &Connection, transaction_id: i64, status: TransferStatus) -> rusqlite::Result<()> {
+    let request_status = match status {
+        TransferStatus::Completed => "paid",
+        TransferStatus::Held => return Ok(()),
+        TransferStatus::Blocked => "blocked",
+    };
+    let request_id: Option<i64> = conn
+        .query_row(
+            "SELECT id FROM payment_requests WHERE transaction_id = ?1 AND status = 'held'",
+            params![transaction_id],
+            |row| row.get(0),
+        )
+        .optional()?;
+    if let Some(request_id) = request_id {
+        conn.execute(
+            "UPDATE payment_requests SET status = ?2, settled_at = strftime('%s', 'now') WHERE id = ?1",
+            params![request_id, request_status],
+        )?;
+        bill_split::settle_if_complete(conn, request_id)?;
+    }
+    Ok(())
+}
+
+pub fn decline(conn: &Connection, id: i64, payer_id: &str) -> Result<(), RequestError> {
+    let tx = conn.unchecked_transaction()?;
+    pending_request(&tx, id, payer_id)?;
+    settle(&tx, id, "declined", None)?;
+    tx.commit()?;
+    Ok(())
+}
+
+// Moves a request out of 'pending'. The status guard makes a concurrent
+// approve and decline fail on whichever comes second instead of both landing.
+fn settle(conn: &Connection, id: i64, status: &str, transaction_id: Option<TransactionId>) -> Result<(), RequestError> {
+    let updated = conn.execute(
+        "UPDATE payment_requests SET status = ?2, transaction_id = ?3, settled_at = strftime('%s', 'now')
+         WHERE id = ?1 AND status = 'pending'",
+        params![id, status, transaction_id],
+    )?;
+    if updated == 0 {
+        let current: String = conn.query_row(
+            "SELECT status FROM payment_requests WHERE id = ?1",
+            params![id],
+            |row| row.get(0),
+        )?;
+        return Err(RequestError::NotPending(current));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::init_schema;
+
+    fn database() -> Connection {
+        let conn = Connection::open_in_memory().unwrap();
+        init_schema(&conn).unwrap();
+        conn.execute_batch(
+            "INSERT INTO user_information (id, name, balance, created_at) VALUES
+                ('alice', 'Alice', 500, strftime('%s','now')),
+                ('bob', 'Bob', 500, strftime('%s','now'));",
+        )
+        .unwrap();
+        conn
+    }
+
+    fn balance(conn: &Connection, id: &str) -> i64 {
+        ledger::balance_of(conn, id).unwrap()
+    }
+
+    fn status(conn: &Connection, id: i64) -> String {
+        conn.query_row("SELECT status FROM payment_requests WHERE id = ?1", params![id], |row| row.get(0))
+            .unwrap()
+    }
+
+    #[test]
+    fn create_checks_the_request_and_notifies_the_payer() {
+        let conn = database();
+        assert!(matches!(create(&conn, "alice", "bob", 0, ""), Err(RequestError::InvalidAmount)));
+        assert!(matches!(create(&conn, "alice", "alice", 10, ""), Err(RequestError::SameAccount)));
+        assert!(matches!(create(&conn, "alice", "carol", 10, ""), Err(RequestError::UnknownPayer)));
+
+        let id = create(&conn, "alice", "bob", 25, " lunch ").unwrap();
+        let pending = pending_for(&conn, "bob").unwrap();
+        assert_eq!(pending.len(), 1);
+        assert_eq!((pending[0].id, pending[0].amount, pending[0].memo.as_str()), (id, 25, "lunch"));
+        assert_eq!(pending[0].requester_name, "Alice");
+        assert!(pending_for(&conn, "alice").unwrap().is_empty());
+        let message: String = conn
+            .query_row("SELECT message FROM notifications WHERE user_id = 'bob'", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(message, "Alice requested $25 for lunch");
+    }
+
+    #[test]
+    fn approve_pays_the_requester_once() {
+        let conn = database();
+        let id = create(&conn, "alice", "bob", 50, "").unwrap();
+        // only the payer can act on it
+        assert!(matches!(approve(&conn, id, "alice"), Err(RequestError::NotFound)));
+
+        let receipt = approve(&conn, id, "bob").unwrap();
+        assert_eq!(receipt.status, TransferStatus::Completed);
+        assert_eq!(status(&conn, id), "paid");
+        assert_eq!(balance(&conn, "alice"), 550);
+        assert_eq!(balance(&conn, "bob"), 500 - receipt.quote.total());
+
+        assert!(matches!(approve(&conn, id, "bob"), Err(RequestError::NotPending(s)) if s == "paid"));
+        assert!(matches!(decline(&conn, id, "bob"), Err(RequestError::NotPending(s)) if s == "paid"));
+        assert_eq!(balance(&conn, "alice"), 550);
+    }
+
+    #[test]
+    fn decline_moves_no_money_and_cannot_be_approved_after() {
+        let conn = database();
+        let id = create(&conn, "alice", "bob", 50, "").unwrap();
+        decline(&conn, id, "bob").unwrap();
+        assert_eq!(status(&conn, id), "declined");
+        assert!(matches!(approve(&conn, id, "bob"), Err(RequestError::NotPending(s)) if s == "declined"));
+        assert_eq!((balance(&conn, "alice"), balance(&conn, "bob")), (500, 500));
+    }
+
+    #[test]
+    fn status_guard_refuses_a_second_settlement() {
+        let conn = database();
+        let id = create(&conn, "alice", "bob", 50, "").unwrap();
+        // as if a concurrent approve committed between the check and the update
+        conn.execute("UPDATE payment_requests SET status = 'paid' WHERE id = ?1", params![id]).unwrap();
+        assert!(matches!(settle(&conn, id, "declined", None), Err(RequestError::NotPending(s)) if s == "paid"));
+        assert_eq!(status(&conn, id), "paid");
+    }
+
+    #[test]
+    fn stale_requests_expire() {
+        let conn = database();
+        let id = create(&conn, "alice", "bob", 50, "").unwrap();
+        conn.execute(
+            "UPDATE payment_requests SET expires_at = strftime('%s','now') - 1 WHERE id = ?1",
+            params![id],
+        )
+        .unwrap();
+        assert!(matches!(approve(&conn, id, "bob"), Err(RequestError::Expired)));
+        assert!(matches!(decline(&conn, id, "bob"), Err(RequestError::Expired)));
+
+        assert!(pending_for(&conn, "bob").unwrap().is_empty());
+        assert_eq!(status(&conn, id), "expired");
+        assert!(matches!(approve(&conn, id, "bob"), Err(RequestError::NotPending(s)) if s == "expired"));
+        assert_eq!(balance(&conn, "alice"), 500);
+    }
+}
@@ -0,0 +1,353 @@
+use rusqlite::{Connection, Result};
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Condvar, Mutex, MutexGuard, PoisonError};
+use std::time::{Duration, Instant};
+use crate::retry::RetryPolicy;
+
+// Connections to one database file under WAL: a single writer, which SQLite
+// allows only one of at a time anyway, and up to `max_readers` read-only
+// connections that read alongside it and each other.
+pub struct Pool {
+    path: Option<PathBuf>,
+    writer: Mutex<Connection>,
+    readers: Mutex<Readers>,
+    reader_returned: Condvar,
+    max_readers: usize,
+    writer_waits: LockWaits,
+    reader_waits: LockWaits,
+    retry: RetryPolicy,
+    retries: AtomicU64,
+}
+
+struct Readers {
+    idle: Vec<Connection>,
+    // idle and checked out
+    open: usize,
+}
+
+// How many times a lock was taken and how long callers waited for it
+#[derive(Debug, Default)]
+struct LockWaits {
+    acquired: AtomicU64,
+    wait_micros: AtomicU64,
+    max_wait_micros: AtomicU64,
+}
+
+impl LockWaits {
+    fn record(&self, what: &str, waited: Duration) {
+        let micros = waited.as_micros() as u64;
+        self.acquired.fetch_add(1, Ordering::Relaxed);
+        self.wait_micros.fetch_add(micros, Ordering::Relaxed);
+        self.max_wait_micros.fetch_max(micros, Ordering::Relaxed);
+        if waited >= SLOW_WAIT {
+            tracing::warn!(waited_ms = waited.as_millis() as u64, "waited for the database {}", what);
+        }
+    }
+
+    fn snapshot(&self) -> WaitStats {
+        WaitStats {
+            acquired: self.acquired.load(Ordering::Relaxed),
+            total_wait: Duration::from_micros(self.wait_micros.load(Ordering::Relaxed)),
+            max_wait: Duration::from_micros(self.max_wait_micros.load(Ordering::Relaxed)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WaitStats {
+    pub acquired: u64,
+    pub total_wait: Duration,
+    pub max_wait: Duration,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PoolStats {
+    pub writer: WaitStats,
+    pub readers: WaitStats,
+    pub readers_open: usize,
+    pub max_readers: usize,
+    // operations run again by `read`/`write` because the database was busy
+    pub retries: u64,
+}
+
+// Waits at least this long are logged as a warning
+const SLOW_WAIT: Duration = Duration::from_millis(250);
+
+impl Pool {
+    // `writer` must already be configured and migrated (see db::open).
+    // Reader connections to `path` are opened when first needed.
+    pub fn new(path: impl AsRef<Path>, writer: Connection, max_readers: usize) -> Pool {
+        let mut pool = Pool::single(writer);
+        pool.path = Some(path.as_ref().to_path_buf());
+        pool.max_readers = max_readers;
+        pool
+    }
+
+    // A pool that runs everything on one connection, e.g. an in-memory
+    // database in tests
+    pub fn single(conn: Connection) -> Pool {
+        Pool {
+            path: None,
+            writer: Mutex::new(conn),
+            readers: Mutex::new(Readers { idle: Vec::new(), open: 0 }),
+            reader_returned: Condvar::new(),
+            max_readers: 0,
+            writer_waits: LockWaits::default(),
+            reader_waits: LockWaits::default(),
+            retry: RetryPolicy::default(),
+            retries: AtomicU64::new(0),
+        }
+    }
+
+    // How `read` and `write` retry while the database is busy
+    pub fn set_retry_policy(&mut self, policy: RetryPolicy) {
+        self.retry = policy;
+    }
+
+    pub fn retry_policy(&self) -> RetryPolicy {
+        self.retry
+    }
+
+    // The write connection, held until the guard is dropped. Everything that
+    // changes the database goes through here.
+    pub fn writer(&self) -> MutexGuard<'_, Connection> {
+        let started = Instant::now();
+        // A panic while holding the guard rolls back any open transaction
+        // when it unwinds, so the connection is still fine to use.
+        let guard = self.writer.lock().unwrap_or_else(PoisonError::into_inner);
+        self.writer_waits.record("writer", started.elapsed());
+        guard
+    }
+
+    // A read-only connection. Waits for one to be returned if all
+    // `max_readers` are checked out; with no readers this is the writer.
+    pub fn reader(&self) -> Result<Reader<'_>> {
+        let path = match &self.path {
+            Some(path) if self.max_readers > 0 => path,
+            _ => return Ok(Reader::Writer(self.writer())),
+        };
+        let started = Instant::now();
+        let mut readers = self.readers.lock().unwrap_or_else(PoisonError::into_inner);
+        loop {
+            if let Some(conn) = readers.idle.pop() {
+                self.reader_waits.record("reader", started.elapsed());
+                return Ok(Reader::Pooled { pool: self, conn: Some(conn) });
+            }
+            if readers.open < self.max_readers {
+                readers.open += 1;
+                // Opening can take a moment, so the list is unlocked meanwhile
+                drop(readers);
+                return match open_reader(path) {
+                    Ok(conn) => {
+                        self.reader_waits.record("reader", started.elapsed());
+                        Ok(Reader::Pooled { pool: self, conn: Some(conn) })
+                    }
+                    Err(e) => {
+                        self.readers.lock().unwrap_or_else(PoisonError::into_inner).open -= 1;
+                        self.reader_returned.notify_one();
+                        Err(e)
+                    }
+                };
+            }
+            readers = self.reader_returned.wait(readers).unwrap_or_else(PoisonError::into_inner);
+        }
+    }
+
+    // Runs `operation` on a reader, trying again while the database is busy
+    #[track_caller]
+    pub fn read<T>(&self, operation: impl Fn(&Connection) -> Result<T>) -> Result<T> {
+        let conn = self.reader()?;
+        self.with_retry(|| operation(&conn))
+    }
+
+    // Runs `operation` on the writer, trying again while the database is
+    // busy. An operation that opens a transaction is rolled back before it
+    // is tried again.
+    #[track_caller]
+    pub fn write<T>(&self, operation: impl Fn(&Connection) -> Result<T>) -> Result<T> {
+        let conn = self.writer();
+        self.with_retry(|| operation(&conn))
+    }
+
+    #[track_caller]
+    fn with_retry<T>(&self, operation: impl Fn() -> Result<T>) -> Result<T> {
+        let mut attempts = 0u64;
+        let result = self.retry.run(|| {
+            attempts += 1;
+            operation()
+        });
+        let retries = attempts.saturating_sub(1);
+        self.retries.fetch_add(retries, Ordering::Relaxed);
+        result
+    }
+
+    pub fn stats(&self) -> PoolStats {
+        PoolStats {
+            writer: self.writer_waits.snapshot(),
+            readers: self.reader_waits.snapshot(),
+            readers_open: self.readers.lock().unwrap_or_else(PoisonError::into_inner).open,
+            max_readers: self.max_readers,
+            retries: self.retries.load(Ordering::Relaxed),
+        }
+    }
+}
+
+fn open_reader(path: &Path) -> Result<Connection> {
+    let conn = Connection::open(path)?;
+    conn.busy_timeout(Duration::from_secs(5))?;
+    conn.pragma_update(None, "query_only", true)?;
+    Ok(conn)
+}
+
+pub enum Reader<'a> {
+    Pooled { pool: &'a Pool, conn: Option<Connection> },
+    Writer(MutexGuard<'a, Connection>),
+}
+
+impl Deref for Reader<'_> {
+    type Target = Connection;
+
+    fn deref(&self) -> &Connection {
+        match self {
+            Reader::Pooled { conn, .. } => conn.as_ref().expect("reader is only taken on drop"),
+            Reader::Writer(conn) => conn,
+        }
+    }
+}
+
+impl Drop for Reader<'_> {
+    fn drop(&mut self) {
+        if let Reader::Pooled { pool, conn } = self {
+            if let Some(conn) = conn.take() {
+                pool.readers.lock().unwrap_or_else(PoisonError::into_inner).idle.push(conn);
+                pool.reader_returned.notify_one();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{thread_rng, RngCore};
+    use std::sync::mpsc;
+    use std::thread;
+
+    const BLOCKED: Duration = Duration::from_millis(150);
+
+    // A WAL database file of its own under the system temp directory, removed
+    // with its -wal and -shm files on drop
+    struct Scratch(PathBuf);
+
+    impl Scratch {
+        fn new() -> Scratch {
+            Scratch(std::env::temp_dir().join(format!("easyremit-pool-{:016x}.db", thread_rng().next_u64())))
+        }
+
+        fn pool(&self, max_readers: usize) -> Pool {
+            let writer = Connection::open(&self.0).unwrap();
+            writer.pragma_update(None, "journal_mode", "WAL").unwrap();
+            writer.execute("CREATE TABLE counter (n INTEGER)", []).unwrap();
+            writer.execute("INSERT INTO counter VALUES (0)", []).unwrap();
+            Pool::new(&self.0, writer, max_readers)
+        }
+    }
+
+    impl Drop for Scratch {
+        fn drop(&mut self) {
+            for suffix in ["", "-wal", "-shm"] {
+                let _ = std::fs::remove_file(format!("{}{}", self.0.display(), suffix));
+            }
+        }
+    }
+
+    fn count(conn: &Connection) -> i64 {
+        conn.query_row("SELECT n FROM counter", [], |row| row.get(0)).unwrap()
+    }
+
+    #[test]
+    fn readers_are_returned_and_reused() {
+        let scratch = Scratch::new();
+        let pool = scratch.pool(2);
+        assert_eq!(pool.stats().readers_open, 0);
+        for _ in 0..5 {
+            let reader = pool.reader().unwrap();
+            assert!(matches!(reader, Reader::Pooled { .. }));
+            assert_eq!(count(&reader), 0);
+        }
+        let stats = pool.stats();
+        assert_eq!((stats.readers_open, stats.max_readers, stats.readers.acquired), (1, 2, 5));
+
+        // Readers see what the writer committed, and cannot write themselves
+        pool.writer().execute("UPDATE counter SET n = 1", []).unwrap();
+        let reader = pool.reader().unwrap();
+        assert_eq!(count(&reader), 1);
+        assert!(reader.execute("UPDATE counter SET n = 2", []).is_err());
+    }
+
+    #[test]
+    fn readers_wait_once_max_readers_are_out() {
+        let scratch = Scratch::new();
+        let pool = scratch.pool(2);
+        let first = pool.reader().unwrap();
+        let second = pool.reader().unwrap();
+        assert_eq!(pool.stats().readers_open, 2);
+
+        let (done, finished) = mpsc::channel();
+        thread::scope(|scope| {
+            scope.spawn(|| {
+                let third = pool.reader().unwrap();
+                done.send(count(&third)).unwrap();
+            });
+            assert!(finished.recv_timeout(BLOCKED).is_err(), "a third reader was opened");
+            drop(first);
+            assert_eq!(finished.recv_timeout(Duration::from_secs(5)).unwrap(), 0);
+        });
+        drop(second);
+
+        let stats = pool.stats();
+        assert_eq!((stats.readers_open, stats.readers.acquired), (2, 3));
+        assert!(stats.readers.max_wait >= BLOCKED);
+        assert!(stats.readers.total_wait >= stats.readers.max_wait);
+    }
+
+    #[test]
+    fn the_writer_is_held_by_one_caller_at_a_time() {
+        let scratch = Scratch::new();
+        let pool = scratch.pool(2);
+        let writer = pool.writer();
+        assert_eq!(pool.stats().writer.acquired, 1);
+
+        let (done, finished) = mpsc::channel();
+        thread::scope(|scope| {
+            scope.spawn(|| {
+                let conn = pool.writer();
+                conn.execute("UPDATE counter SET n = n + 1", []).unwrap();
+                done.send(()).unwrap();
+            });
+            assert!(finished.recv_timeout(BLOCKED).is_err(), "two callers had the writer");
+            // Readers are not held up by the writer
+            assert_eq!(count(&pool.reader().unwrap()), 0);
+            drop(writer);
+            finished.recv_timeout(Duration::from_secs(5)).unwrap();
+        });
+
+        let stats = pool.stats();
+        assert_eq!(stats.writer.acquired, 2);
+        assert!(stats.writer.max_wait >= BLOCKED);
+        assert_eq!(count(&pool.writer()), 1);
+    }
+
+    #[test]
+    fn without_readers_reads_use_the_writer() {
+        let pool = Pool::single(Connection::open_in_memory().unwrap());
+        let reader = pool.reader().unwrap();
+        assert!(matches!(reader, Reader::Writer(_)));
+        drop(reader);
+        let stats = pool.stats();
+        assert_eq!((stats.readers_open, stats.max_readers, stats.readers.acquired), (0, 0, 0));
+        assert_eq!(stats.writer.acquired, 1);
+    }
+}
@@ -0,0 +1,412 @@
+use image::{DynamicImage, GrayImage, ImageFormat, Luma};
+use qrcode::{Color as ModuleColor, EcLevel, QrCode};
+use std::fmt;
+use std::path::Path;
+use crate::model::User;
+use crate::qr_scan;
+
+// Globally unique identifier of EasyRemit accounts inside the merchant
+// account information template (ID 29) of the payload.
+const EASYREMIT_GUID: &str = "com.easyremit";
+
+// The length of every field is written as two digits.
+const MAX_FIELD_LEN: usize = 99;
+
+// Quiet zone around the symbol, in modules, when rendered to an image.
+const QUIET_ZONE: u32 = 4;
+
+// Longest value EMVCo allows for the merchant name (59) and the purpose of
+// transaction (62/08) fields, counted here in UTF-8 bytes: a QR code stores
+// bytes, and 25 characters of Burmese would not fit the versions qr_scan
+// reads.
+const MAX_TEXT_BYTES: usize = 25;
+
+// What a payment QR code carries: the account to pay and, for a payment
+// request, how much and what for.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PaymentPayload {
+    pub account_id: String,
+    pub name: Option<String>,
+    pub currency: Option<String>,
+    pub amount: Option<i64>,
+    pub memo: Option<String>,
+}
+
+#[derive(Debug)]
+pub enum QrError {
+    Malformed(String),
+    ChecksumMismatch,
+    NotEasyRemit,
+    FieldTooLong(String),
+    Encode(qrcode::types::QrError),
+    Image(image::ImageError),
+    Scan(qr_scan::ScanError),
+}
+
+impl fmt::Display for QrError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            QrError::Malformed(reason) => write!(f, "Not a valid payment QR code: {}", reason),
+            QrError::ChecksumMismatch => write!(f, "QR code checksum does not match"),
+            QrError::NotEasyRemit => write!(f, "QR code is not an EasyRemit account"),
+            QrError::FieldTooLong(id) => write!(f, "Field {} is longer than {} characters", id, MAX_FIELD_LEN),
+            QrError::Encode(e) => write!(f, "Could not create QR code: {}", e),
+            QrError::Image(e) => write!(f, "Could not read image: {}", e),
+            QrError::Scan(e) => write!(f, "No QR code found: {}", e),
+        }
+    }
+}
+
+impl From<image::ImageError> for QrError {
+    fn from(e: image::ImageError) -> Self {
+        QrError::Image(e)
+    }
+}
+
+impl From<qr_scan::ScanError> for QrError {
+    fn from(e: qr_scan::ScanError) -> Self {
+        QrError::Scan(e)
+    }
+}
+
+impl PaymentPayload {
+    pub fn for_account(account_id: &str) -> Self {
+        PaymentPayload {
+            account_id: account_id.to_string(),
+            name: None,
+            currency: None,
+            amount: None,
+            memo: None,
+        }
+    }
+
+    // The account's static code, labelled with the holder's name and currency.
+    pub fn for_user(user: &User) -> Self {
+        PaymentPayload {
+            name: user.name.clone(),
+            currency: Some(user.currency.clone()),
+            ..PaymentPayload::for_account(user.id.as_str())
+        }
+    }
+
+    // Serialises to an EMVCo merchant-presented style TLV string, ending in a
+    // CRC-16/CCITT-FALSE checksum (ID 63).
+    pub fn encode(&self) -> Result<String, QrError> {
+        let mut payload = String::new();
+        push_field(&mut payload, "00", "01")?;
+        // 11 = static code that can be reused, 12 = dynamic code for one payment
+        push_field(&mut payload, "01", if self.amount.is_some() { "12" } else { "11" })?;
+
+        let mut account = String::new();
+        push_field(&mut account, "00", EASYREMIT_GUID)?;
+        push_field(&mut account, "01", &self.account_id)?;
+        push_field(&mut payload, "29", &account)?;
+
+        if let Some(code) = self.currency.as_deref().and_then(numeric_currency) {
+            push_field(&mut payload, "53", code)?;
+        }
+        if let Some(amount) = self.amount {
+            push_field(&mut payload, "54", &amount.to_string())?;
+        }
+        if let Some(name) = &self.name {
+            push_field(&mut payload, "59", &truncate(name))?;
+        }
+        if let Some(memo) = self.memo.as_ref().filter(|memo| !memo.trim().is_empty()) {
+            let mut additional = String::new();
+            push_field(&mut additional, "08", &truncate(memo.trim()))?;
+            push_field(&mut payload, "62", &additional)?;
+        }
+
+        payload.push_str("6304");
+        let checksum = crc16(payload.as_bytes());
+        payload.push_str(&format!("{:04X}", checksum));
+        Ok(payload)
+    }
+
+    pub fn decode(payload: &str) -> Result<Self, QrError> {
+        let payload = payload.trim();
+        let crc_start = payload
+            .len()
+            .checked_sub(4)
+            .filter(|&i| payload.is_char_boundary(i) && payload[..i].ends_with("6304"))
+            .ok_or_else(|| QrError::Malformed("missing checksum".to_string()))?;
+        let expected = u16::from_str_radix(&payload[crc_start..], 16)
+            .map_err(|_| QrError::Malformed("checksum is not hexadecimal".to_string()))?;
+        if crc16(&payload.as_bytes()[..crc_start]) != expected {
+            return Err(QrError::ChecksumMismatch);
+        }
+
+        let fields = parse_fields(&payload[..crc_start - 4])?;
+        let field = |id: &str| fields.iter().find(|(tag, _)| tag == id).map(|(_, value)| value.clone());
+
+        if field("00").as_deref() != Some("01") {
+            return Err(QrError::Malformed("unsupported payload format".to_string()));
+        }
+        let account = parse_fields(&field("29").ok_or(QrError::NotEasyRemit)?)?;
+        let sub_field = |fields: &[(String, String)], id: &str| {
+            fields.iter().find(|(tag, _)| tag == id).map(|(_, value)| value.clone())
+        };
+        if sub_field(&account, "00").as_deref() != Some(EASYREMIT_GUID) {
+            return Err(QrError::NotEasyRemit);
+        }
+        let account_id = sub_field(&account, "01")
+            .filter(|id| !id.is_empty())
+            .ok_or_else(|| QrError::Malformed("missing account number".to_string()))?;
+
+        let amount = match field("54") {
+            Some(amount) => Some(parse_amount(&amount)?),
+            None => None,
+        };
+        let memo = match field("62") {
+            Some(additional) => sub_field(&parse_fields(&additional)?, "08"),
+            None => None,
+        };
+
+        Ok(PaymentPayload {
+            account_id,
+            name: field("59"),
+            currency: field("53").and_then(|code| alpha_currency(&code)).map(str::to_string),
+            amount,
+            memo,
+        })
+    }
+}
+
+fn push_field(out: &mut String, id: &str, value: &str) -> Result<(), QrError> {
+    let len = value.chars().count();
+    if len > MAX_FIELD_LEN {
+        return Err(QrError::FieldTooLong(id.to_string()));
+    }
+    out.push_str(&format!("{}{:02}{}", id, len, value));
+    Ok(())
+}
+
+fn truncate(text: &str) -> String {
+    let mut end = text.len().min(MAX_TEXT_BYTES);
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+    text[..end].to_string()
+}
+
+// Splits "IDLLvalue..." into (ID, value) pairs. Lengths count characters.
+fn parse_fields(data: &str) -> Result<Vec<(String, String)>, QrError> {
+    let chars: Vec<char> = data.chars().collect();
+    let mut fields = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        if i + 4 > chars.len() {
+            return Err(QrError::Malformed("truncated field".to_string()));
+        }
+        let id: String = chars[i..i + 2].iter().collect();
+        let len: usize = chars[i + 2..i + 4]
+            .iter()
+            .collect::<String>()
+            .parse()
+            .map_err(|_| QrError::Malformed(format!("bad length for field {}", id)))?;
+        let end = i + 4 + len;
+        if end > chars.len() {
+            return Err(QrError::Malformed(format!("field {} runs past the end", id)));
+        }
+        fields.push((id, chars[i + 4..end].iter().collect()));
+        i = end;
+    }
+    Ok(fields)
+}
+
+// Accounts hold whole units, so "150" and "150.00" are accepted but "150.5"
+// is not.
+fn parse_amount(amount: &str) -> Result<i64, QrError> {
+    let (whole, fraction) = amount.split_once('.').unwrap_or((amount, ""));
+    if !fraction.chars().all(|c| c == '0') {
+        return Err(QrError::Malformed("amount has a fractional part".to_string()));
+    }
+    whole
+        .parse::<i64>()
+        .ok()
+        .filter(|&amount| amount > 0)
+        .ok_or_else(|| QrError::Malformed("amount is not a positive number".to_string()))
+}
+
+const CURRENCIES: &[(&str, &str)] = &[
+    ("USD", "840"),
+    ("THB", "764"),
+    ("MMK", "104"),
+    ("EUR", "978"),
+    ("GBP", "826"),
+    ("SGD", "702"),
+    ("JPY", "392"),
+];
+
+fn numeric_currency(alpha: &str) -> Option<&'static str> {
+    CURRENCIES.iter().find(|(a, _)| *a == alpha).map(|(_, n)| *n)
+}
+
+fn alpha_currency(numeric: &str) -> Option<&'static str> {
+    CURRENCIES.iter().find(|(_, n)| *n == numeric).map(|(a, _)| *a)
+}
+
+// CRC-16/CCITT-FALSE as required by EMVCo: polynomial 0x1021, initial 0xFFFF.
+fn crc16(data: &[u8]) -> u16 {
+    let mut crc: u16 = 0xFFFF;
+    for &byte in data {
+        crc ^= (byte as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 { (crc << 1) ^ 0x1021 } else { crc << 1 };
+        }
+    }
+    crc
+}
+
+// The symbol as rows of modules, `true` for dark, without a quiet zone.
+pub fn modules(payload: &PaymentPayload) -> Result<Vec<Vec<bool>>, QrError> {
+    let code = QrCode::with_error_correction_level(payload.encode()?.as_bytes(), EcLevel::M)
+        .map_err(QrError::Encode)?;
+    let width = code.width();
+    let colors = code.to_colors();
+    Ok(colors
+        .chunks(width)
+        .map(|row| row.iter().map(|&color| color == ModuleColor::Dark).collect())
+        .collect())
+}
+
+pub fn render(payload: &PaymentPayload, module_px: u32) -> Result<GrayImage, QrError> {
+    let modules = modules(payload)?;
+    let size = (modules.len() as u32 + 2 * QUIET_ZONE) * module_px;
+    Ok(GrayImage::from_fn(size, size, |x, y| {
+        let (column, row) = (x / module_px, y / module_px);
+        let dark = column >= QUIET_ZONE
+            && row >= QUIET_ZONE
+            && modules
+                .get((row - QUIET_ZONE) as usize)
+                .and_then(|r| r.get((column - QUIET_ZONE) as usize))
+                .copied()
+                .unwrap_or(false);
+        Luma([if dark { 0 } else { 255 }])
+    }))
+}
+
+// Writes the code as a PNG, or JPEG if the path ends in .jpg/.jpeg.
+pub fn save(payload: &PaymentPayload, path: &Path) -> Result<(), QrError> {
+    let format = ImageFormat::from_path(path).unwrap_or(ImageFormat::Png);
+    render(payload, 8)?.save_with_format(path, format)?;
+    Ok(())
+}
+
+pub fn scan_image(image: &DynamicImage) -> Result<PaymentPayload, QrError> {
+    let text = qr_scan::decode(&image.to_luma8())?;
+    PaymentPayload::decode(&text)
+}
+
+pub fn scan_file(path: &Path) -> Result<PaymentPayload, QrError> {
+    scan_image(&image::open(path)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn request_payload() -> PaymentPayload {
+        PaymentPayload {
+            account_id: "000003538655".to_string(),
+            name: Some("Thiri Thaw".to_string()),
+            currency: Some("USD".to_string()),
+            amount: Some(150),
+            memo: Some("Dinner at Saturday's".to_string()),
+        }
+    }
+
+    #[test]
+    fn payload_round_trip() {
+        for payload in [request_payload(), PaymentPayload::for_account("000001630664")] {
+            let encoded = payload.encode().unwrap();
+            assert!(encoded.starts_with("000201"));
+            assert_eq!(PaymentPayload::decode(&encoded).unwrap(), payload);
+        }
+    }
+
+    #[test]
+    fn tampered_payload_is_rejected() {
+        let encoded = request_payload().encode().unwrap().replace("000003538655", "000003538656");
+        assert!(matches!(PaymentPayload::decode(&encoded), Err(QrError::ChecksumMismatch)));
+    }
+
+    #[test]
+    fn long_fields_are_refused() {
+        let payload = PaymentPayload::for_account(&"1".repeat(78));
+        assert_eq!(PaymentPayload::decode(&payload.encode().unwrap()).unwrap(), payload);
+        // next to the GUID field that makes the account template 100 characters
+        let payload = PaymentPayload::for_account(&"1".repeat(79));
+        assert!(matches!(payload.encode(), Err(QrError::FieldTooLong(id)) if id == "29"));
+        assert!(matches!(modules(&payload), Err(QrError::FieldTooLong(_))));
+        // the largest payload that encodes is still one qr_scan reads
+        let payload = PaymentPayload {
+            name: Some("ñ".repeat(40)),
+            memo: Some("ñ".repeat(40)),
+            currency: Some("USD".to_string()),
+            amount: Some(i64::MAX),
+            ..PaymentPayload::for_account(&"x".repeat(78))
+        };
+        assert!(modules(&payload).unwrap().len() <= qr_scan::MAX_VERSION * 4 + 17);
+    }
+
+    #[test]
+    fn png_round_trip() {
+        let payload = request_payload();
+        let mut png = Vec::new();
+        DynamicImage::ImageLuma8(render(&payload, 4).unwrap())
+            .write_to(&mut Cursor::new(&mut png), ImageFormat::Png)
+            .unwrap();
+
+        let image = image::load_from_memory(&png).unwrap();
+        assert_eq!(scan_image(&image).unwrap(), payload);
+    }
+
+    #[test]
+    fn longest_text_fits_the_scanner() {
+        let name = "သီရိသော်".repeat(4);
+        let memo = "ညစာ စားဖို့ ".repeat(4);
+        let payload = PaymentPayload {
+            account_id: "000003538655".to_string(),
+            name: Some(name.clone()),
+            currency: Some("MMK".to_string()),
+            amount: Some(i64::MAX),
+            memo: Some(memo.clone()),
+        };
+        let scanned = scan_image(&DynamicImage::ImageLuma8(render(&payload, 4).unwrap())).unwrap();
+
+        let scanned_name = scanned.name.unwrap();
+        let scanned_memo = scanned.memo.unwrap();
+        assert!(scanned_name.len() <= MAX_TEXT_BYTES && name.starts_with(&scanned_name), "{}", scanned_name);
+        assert!(scanned_memo.len() <= MAX_TEXT_BYTES && memo.starts_with(&scanned_memo), "{}", scanned_memo);
+        assert!(scanned_name.len() > MAX_TEXT_BYTES - 4 && scanned_memo.len() > MAX_TEXT_BYTES - 4);
+        assert_eq!((scanned.account_id.as_str(), scanned.amount), ("000003538655", Some(i64::MAX)));
+    }
+
+    #[test]
+    fn jpeg_round_trip() {
+        let payload = PaymentPayload::for_account("000001630664");
+        let mut jpeg = Vec::new();
+        DynamicImage::ImageLuma8(render(&payload, 6).unwrap())
+            .write_to(&mut Cursor::new(&mut jpeg), ImageFormat::Jpeg)
+            .unwrap();
+
+        let image = image::load_from_memory(&jpeg).unwrap();
+        assert_eq!(scan_image(&image).unwrap(), payload);
+    }
+
+    #[test]
+    fn damaged_modules_are_corrected() {
+        let payload = request_payload();
+        let mut image = render(&payload, 4).unwrap();
+        // Blot out a few modules in the bottom-right data area.
+        let size = image.width();
+        for y in size - 40..size - 28 {
+            for x in size - 40..size - 28 {
+                image.put_pixel(x, y, Luma([0]));
+            }
+        }
+        assert_eq!(scan_image(&DynamicImage::ImageLuma8(image)).unwrap(), payload);
+    }
+}
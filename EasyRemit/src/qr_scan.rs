@@ -0,0 +1,860 @@
+// A small QR code reader for the images the Transfer page imports: codes
+// saved by EasyRemit itself, screenshots and reasonably straight photos or
+// scans. It locates the three finder patterns, samples the module grid,
+// corrects errors with Reed-Solomon and decodes numeric, alphanumeric and
+// byte segments. Versions 1 to 10 are supported, which covers every payment
+// payload EasyRemit produces.
+use image::GrayImage;
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ScanError {
+    NoFinderPatterns,
+    UnsupportedVersion(usize),
+    FormatInformation,
+    TooManyErrors,
+    BadData(&'static str),
+}
+
+impl fmt::Display for ScanError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ScanError::NoFinderPatterns => write!(f, "could not locate the code in the image"),
+            ScanError::UnsupportedVersion(v) => write!(f, "QR version {} is not supported", v),
+            ScanError::FormatInformation => write!(f, "format information is unreadable"),
+            ScanError::TooManyErrors => write!(f, "too damaged to read"),
+            ScanError::BadData(reason) => write!(f, "{}", reason),
+        }
+    }
+}
+
+pub(crate) const MAX_VERSION: usize = 10;
+
+// Error correction codewords per block, indexed by [version - 1][level] with
+// levels in the order L, M, Q, H (ISO/IEC 18004 table 9).
+const EC_CODEWORDS_PER_BLOCK: [[usize; 4]; MAX_VERSION] = [
+    [7, 10, 13, 17],
+    [10, 16, 22, 28],
+    [15, 26, 18, 22],
+    [20, 18, 26, 16],
+    [26, 24, 18, 22],
+    [18, 16, 24, 28],
+    [20, 18, 18, 26],
+    [24, 22, 22, 26],
+    [30, 22, 20, 24],
+    [18, 26, 24, 28],
+];
+
+// (data codewords, block count) of the first group, then of the second group.
+const DATA_BLOCKS: [[(usize, usize, usize, usize); 4]; MAX_VERSION] = [
+    [(19, 1, 0, 0), (16, 1, 0, 0), (13, 1, 0, 0), (9, 1, 0, 0)],
+    [(34, 1, 0, 0), (28, 1, 0, 0), (22, 1, 0, 0), (16, 1, 0, 0)],
+    [(55, 1, 0, 0), (44, 1, 0, 0), (17, 2, 0, 0), (13, 2, 0, 0)],
+    [(80, 1, 0, 0), (32, 2, 0, 0), (24, 2, 0, 0), (9, 4, 0, 0)],
+    [(108, 1, 0, 0), (43, 2, 0, 0), (15, 2, 16, 2), (11, 2, 12, 2)],
+    [(68, 2, 0, 0), (27, 4, 0, 0), (19, 4, 0, 0), (15, 4, 0, 0)],
+    [(78, 2, 0, 0), (31, 4, 0, 0), (14, 2, 15, 4), (13, 4, 14, 1)],
+    [(97, 2, 0, 0), (38, 2, 39, 2), (18, 4, 19, 2), (14, 4, 15, 2)],
+    [(116, 2, 0, 0), (36, 3, 37, 2), (16, 4, 17, 4), (12, 4, 13, 4)],
+    [(68, 2, 69, 2), (43, 4, 44, 1), (19, 6, 20, 2), (15, 6, 16, 2)],
+];
+
+const ALIGNMENT_POSITIONS: [&[usize]; MAX_VERSION] = [
+    &[],
+    &[6, 18],
+    &[6, 22],
+    &[6, 26],
+    &[6, 30],
+    &[6, 34],
+    &[6, 22, 38],
+    &[6, 24, 42],
+    &[6, 26, 46],
+    &[6, 28, 50],
+];
+
+pub fn decode(image: &GrayImage) -> Result<String, ScanError> {
+    let threshold = threshold(image);
+    let dark = |x: i64, y: i64| -> bool {
+        x >= 0
+            && y >= 0
+            && (x as u32) < image.width()
+            && (y as u32) < image.height()
+            && image.get_pixel(x as u32, y as u32).0[0] < threshold
+    };
+
+    let (top_left, top_right, bottom_left, module_size) = locate(image, &dark)?;
+    let grid = sample(&dark, top_left, top_right, bottom_left, module_size)?;
+    read_symbol(&grid)
+}
+
+// Midway between the darkest and lightest pixels.
+fn threshold(image: &GrayImage) -> u8 {
+    let (min, max) = image
+        .pixels()
+        .fold((255u8, 0u8), |(min, max), p| (min.min(p.0[0]), max.max(p.0[0])));
+    ((min as u16 + max as u16) / 2) as u8
+}
+
+//------------------------------------------------------------------------------
+// Locating the symbol
+
+#[derive(Debug, Clone, Copy)]
+struct Point {
+    x: f64,
+    y: f64,
+}
+
+fn distance(a: Point, b: Point) -> f64 {
+    ((a.x - b.x).powi(2) + (a.y - b.y).powi(2)).sqrt()
+}
+
+// True if the five run lengths look like the 1:1:3:1:1 profile of a finder
+// pattern.
+fn is_finder_profile(runs: &[usize; 5]) -> bool {
+    let total: usize = runs.iter().sum();
+    if total < 7 || runs.contains(&0) {
+        return false;
+    }
+    let module = total as f64 / 7.0;
+    let tolerance = module / 2.0;
+    (runs[0] as f64 - module).abs() < tolerance
+        && (runs[1] as f64 - module).abs() < tolerance
+        && (runs[2] as f64 - 3.0 * module).abs() < 3.0 * tolerance
+        && (runs[3] as f64 - module).abs() < tolerance
+        && (runs[4] as f64 - module).abs() < tolerance
+}
+
+// Walks outwards from a point inside the centre stone along one axis and
+// returns the centre of the pattern on that axis and its width.
+fn cross_check(dark: &dyn Fn(i64, i64) -> bool, x: i64, y: i64, vertical: bool) -> Option<(f64, usize)> {
+    let at = |offset: i64| if vertical { dark(x, y + offset) } else { dark(x + offset, y) };
+    if !at(0) {
+        return None;
+    }
+    let limit = 4096;
+    let mut runs = [0usize; 5];
+
+    let mut offset = 0;
+    while offset > -limit && at(offset) {
+        runs[2] += 1;
+        offset -= 1;
+    }
+    while offset > -limit && !at(offset) {
+        runs[1] += 1;
+        offset -= 1;
+    }
+    while offset > -limit && at(offset) {
+        runs[0] += 1;
+        offset -= 1;
+    }
+    let start = offset + 1;
+
+    offset = 1;
+    while offset < limit && at(offset) {
+        runs[2] += 1;
+        offset += 1;
+    }
+    while offset < limit && !at(offset) {
+        runs[3] += 1;
+        offset += 1;
+    }
+    while offset < limit && at(offset) {
+        runs[4] += 1;
+        offset += 1;
+    }
+
+    if !is_finder_profile(&runs) {
+        return None;
+    }
+    let base = if vertical { y } else { x };
+    let centre = (base + start) as f64 + runs[0] as f64 + runs[1] as f64 + runs[2] as f64 / 2.0;
+    Some((centre, runs.iter().sum()))
+}
+
+struct Candidate {
+    centre: Point,
+    module_size: f64,
+    hits: usize,
+}
+
+fn locate(image: &GrayImage, dark: &dyn Fn(i64, i64) -> bool) -> Result<(Point, Point, Point, f64), ScanError> {
+    let mut candidates: Vec<Candidate> = Vec::new();
+
+    for y in 0..image.height() as i64 {
+        // run lengths along the row, starting with a light run
+        let mut runs: Vec<(bool, usize, i64)> = Vec::new();
+        for x in 0..image.width() as i64 {
+            let d = dark(x, y);
+            match runs.last_mut() {
+                Some((colour, len, _)) if *colour == d => *len += 1,
+                _ => runs.push((d, 1, x)),
+            }
+        }
+
+        for window in runs.windows(5) {
+            if !window[0].0 {
+                continue;
+            }
+            let profile = [window[0].1, window[1].1, window[2].1, window[3].1, window[4].1];
+            if !is_finder_profile(&profile) {
+                continue;
+            }
+            let cx = window[2].2 + window[2].1 as i64 / 2;
+            let (cy, height) = match cross_check(dark, cx, y, true) {
+                Some(found) => found,
+                None => continue,
+            };
+            let (cx, width) = match cross_check(dark, cx, cy.round() as i64, false) {
+                Some(found) => found,
+                None => continue,
+            };
+            let centre = Point { x: cx, y: cy };
+            let module_size = (width + height) as f64 / 14.0;
+
+            match candidates
+                .iter_mut()
+                .find(|c| distance(c.centre, centre) < c.module_size * 2.0)
+            {
+                Some(existing) => {
+                    let n = existing.hits as f64;
+                    existing.centre.x = (existing.centre.x * n + centre.x) / (n + 1.0);
+                    existing.centre.y = (existing.centre.y * n + centre.y) / (n + 1.0);
+                    existing.module_size = (existing.module_size * n + module_size) / (n + 1.0);
+                    existing.hits += 1;
+                }
+                None => candidates.push(Candidate { centre, module_size, hits: 1 }),
+            }
+        }
+    }
+
+    if candidates.len() < 3 {
+        return Err(ScanError::NoFinderPatterns);
+    }
+    candidates.sort_by_key(|c| std::cmp::Reverse(c.hits));
+    candidates.truncate(3);
+
+    // The top-left pattern is the one opposite the longest side.
+    let (a, b, c) = (candidates[0].centre, candidates[1].centre, candidates[2].centre);
+    let (ab, bc, ac) = (distance(a, b), distance(b, c), distance(a, c));
+    let (corner, p, q) = if bc >= ab && bc >= ac {
+        (a, b, c)
+    } else if ac >= ab && ac >= bc {
+        (b, a, c)
+    } else {
+        (c, a, b)
+    };
+    // With y pointing down, top-right × bottom-left is positive.
+    let cross = (p.x - corner.x) * (q.y - corner.y) - (p.y - corner.y) * (q.x - corner.x);
+    let (top_right, bottom_left) = if cross > 0.0 { (p, q) } else { (q, p) };
+
+    let module_size = candidates.iter().map(|c| c.module_size).sum::<f64>() / 3.0;
+    Ok((corner, top_right, bottom_left, module_size))
+}
+
+// Samples every module centre by mapping grid coordinates onto the frame
+// spanned by the three finder centres.
+fn sample(
+    dark: &dyn Fn(i64, i64) -> bool,
+    top_left: Point,
+    top_right: Point,
+    bottom_left: Point,
+    module_size: f64,
+) -> Result<Vec<Vec<bool>>, ScanError> {
+    let span = (distance(top_left, top_right) + distance(top_left, bottom_left)) / 2.0 / module_size;
+    let version = ((span + 7.0 - 17.0) / 4.0).round() as i64;
+    if version < 1 || version as usize > MAX_VERSION {
+        return Err(ScanError::UnsupportedVersion(version.max(0) as usize));
+    }
+    let dimension = 17 + 4 * version as usize;
+    let steps = (dimension - 7) as f64;
+
+    let grid = (0..dimension)
+        .map(|row| {
+            (0..dimension)
+                .map(|column| {
+                    let u = (column as f64 - 3.0) / steps;
+                    let v = (row as f64 - 3.0) / steps;
+                    let x = top_left.x + u * (top_right.x - top_left.x) + v * (bottom_left.x - top_left.x);
+                    let y = top_left.y + u * (top_right.y - top_left.y) + v * (bottom_left.y - top_left.y);
+                    // majority of the centre pixel and its four neighbours
+                    let (x, y) = (x.floor() as i64, y.floor() as i64);
+                    let votes = [(0, 0), (-1, 0), (1, 0), (0, -1), (0, 1)]
+                        .iter()
+                        .filter(|(dx, dy)| dark(x + dx, y + dy))
+                        .count();
+                    votes >= 3
+                })
+                .collect()
+        })
+        .collect();
+    Ok(grid)
+}
+
+//------------------------------------------------------------------------------
+// Reading the module grid
+
+fn read_symbol(grid: &[Vec<bool>]) -> Result<String, ScanError> {
+    let dimension = grid.len();
+    let version = (dimension - 17) / 4;
+    let at = |x: usize, y: usize| grid[y][x];
+
+    let (level, mask) = read_format(&at, dimension)?;
+    let function = function_patterns(version, dimension);
+
+    // Codewords are laid out in two-column strips from the bottom-right,
+    // snaking up and down, skipping the vertical timing pattern.
+    let mut codewords = Vec::new();
+    let (mut byte, mut bits) = (0u8, 0);
+    let mut upward = true;
+    let mut right = dimension - 1;
+    while right > 0 {
+        if right == 6 {
+            right -= 1;
+        }
+        for count in 0..dimension {
+            let y = if upward { dimension - 1 - count } else { count };
+            for x in [right, right - 1] {
+                if function[y][x] {
+                    continue;
+                }
+                let bit = at(x, y) ^ mask_bit(mask, y, x);
+                byte = (byte << 1) | bit as u8;
+                bits += 1;
+                if bits == 8 {
+                    codewords.push(byte);
+                    byte = 0;
+                    bits = 0;
+                }
+            }
+        }
+        upward = !upward;
+        right = right.saturating_sub(2);
+    }
+
+    let data = deinterleave_and_correct(&codewords, version, level)?;
+    decode_segments(&data, version)
+}
+
+// Error correction level index (L, M, Q, H) and mask pattern.
+fn read_format(at: &dyn Fn(usize, usize) -> bool, dimension: usize) -> Result<(usize, u8), ScanError> {
+    let mut first = 0u32;
+    let push = |bits: &mut u32, x: usize, y: usize| *bits = (*bits << 1) | at(x, y) as u32;
+    for x in 0..6 {
+        push(&mut first, x, 8);
+    }
+    push(&mut first, 7, 8);
+    push(&mut first, 8, 8);
+    push(&mut first, 8, 7);
+    for y in (0..6).rev() {
+        push(&mut first, 8, y);
+    }
+
+    let mut second = 0u32;
+    for y in (dimension - 7..dimension).rev() {
+        push(&mut second, 8, y);
+    }
+    for x in dimension - 8..dimension {
+        push(&mut second, x, 8);
+    }
+
+    let mut best: Option<(u32, u32)> = None;
+    for data in 0..32u32 {
+        let code = format_code(data);
+        let errors = (code ^ first).count_ones().min((code ^ second).count_ones());
+        if best.is_none_or(|(_, fewest)| errors < fewest) {
+            best = Some((data, errors));
+        }
+    }
+    match best {
+        Some((data, errors)) if errors <= 3 => {
+            // format bits 01, 00, 11, 10 stand for L, M, Q, H
+            let level = match data >> 3 {
+                1 => 0,
+                0 => 1,
+                3 => 2,
+                _ => 3,
+            };
+            Ok((level, (data & 7) as u8))
+        }
+        _ => Err(ScanError::FormatInformation),
+    }
+}
+
+// BCH(15,5) format code for five data bits, with the standard mask applied.
+fn format_code(data: u32) -> u32 {
+    let mut remainder = data << 10;
+    for bit in (10..15).rev() {
+        if remainder & (1 << bit) != 0 {
+            remainder ^= 0x537 << (bit - 10);
+        }
+    }
+    ((data << 10) | remainder) ^ 0x5412
+}
+
+fn mask_bit(mask: u8, row: usize, column: usize) -> bool {
+    let (i, j) = (row, column);
+    match mask {
+        0 => (i + j) % 2 == 0,
+        1 => i % 2 == 0,
+        2 => j % 3 == 0,
+        3 => (i + j) % 3 == 0,
+        4 => (i / 2 + j / 3) % 2 == 0,
+        5 => (i * j) % 2 + (i * j) % 3 == 0,
+        6 => ((i * j) % 2 + (i * j) % 3) % 2 == 0,
+        _ => ((i + j) % 2 + (i * j) % 3) % 2 == 0,
+    }
+}
+
+// Modules that belong to finder, timing, alignment, format and version
+// patterns rather than data.
+fn function_patterns(version: usize, dimension: usize) -> Vec<Vec<bool>> {
+    let mut function = vec![vec![false; dimension]; dimension];
+    let mut mark = |left: usize, top: usize, width: usize, height: usize| {
+        for row in function.iter_mut().skip(top).take(height) {
+            for cell in row.iter_mut().skip(left).take(width) {
+                *cell = true;
+            }
+        }
+    };
+
+    mark(0, 0, 9, 9);
+    mark(dimension - 8, 0, 8, 9);
+    mark(0, dimension - 8, 9, 8);
+
+    let positions = ALIGNMENT_POSITIONS[version - 1];
+    let last = positions.len().saturating_sub(1);
+    for (i, &x) in positions.iter().enumerate() {
+        for (j, &y) in positions.iter().enumerate() {
+            let overlaps_finder = (i == 0 && (j == 0 || j == last)) || (i == last && j == 0);
+            if !overlaps_finder {
+                mark(x - 2, y - 2, 5, 5);
+            }
+        }
+    }
+
+    mark(6, 9, 1, dimension - 17);
+    mark(9, 6, dimension - 17, 1);
+
+    if version >= 7 {
+        mark(dimension - 11, 0, 3, 6);
+        mark(0, dimension - 11, 6, 3);
+    }
+    function
+}
+
+fn deinterleave_and_correct(codewords: &[u8], version: usize, level: usize) -> Result<Vec<u8>, ScanError> {
+    let ec_len = EC_CODEWORDS_PER_BLOCK[version - 1][level];
+    let (size1, count1, size2, count2) = DATA_BLOCKS[version - 1][level];
+    let sizes: Vec<usize> = std::iter::repeat_n(size1, count1)
+        .chain(std::iter::repeat_n(size2, count2))
+        .collect();
+    let total: usize = sizes.iter().map(|size| size + ec_len).sum();
+    if codewords.len() < total {
+        return Err(ScanError::BadData("symbol has fewer codewords than expected"));
+    }
+
+    let mut blocks: Vec<Vec<u8>> = sizes.iter().map(|&size| Vec::with_capacity(size + ec_len)).collect();
+    let mut next = codewords.iter();
+    let longest = sizes.iter().copied().max().unwrap_or(0);
+    for i in 0..longest {
+        for (block, &size) in blocks.iter_mut().zip(&sizes) {
+            if i < size {
+                block.push(*next.next().unwrap_or(&0));
+            }
+        }
+    }
+    for _ in 0..ec_len {
+        for block in blocks.iter_mut() {
+            block.push(*next.next().unwrap_or(&0));
+        }
+    }
+
+    let mut data = Vec::new();
+    for (mut block, size) in blocks.into_iter().zip(sizes) {
+        reed_solomon::correct(&mut block, ec_len)?;
+        data.extend_from_slice(&block[..size]);
+    }
+    Ok(data)
+}
+
+//------------------------------------------------------------------------------
+// Segments
+
+struct BitReader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl BitReader<'_> {
+    fn remaining(&self) -> usize {
+        self.data.len() * 8 - self.position
+    }
+
+    fn read(&mut self, count: usize) -> Result<u32, ScanError> {
+        if count > self.remaining() {
+            return Err(ScanError::BadData("data ends in the middle of a segment"));
+        }
+        let mut value = 0u32;
+        for _ in 0..count {
+            let bit = (self.data[self.position / 8] >> (7 - self.position % 8)) & 1;
+            value = (value << 1) | bit as u32;
+            self.position += 1;
+        }
+        Ok(value)
+    }
+}
+
+const ALPHANUMERIC: &[u8; 45] = b"0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZ $%*+-./:";
+
+fn decode_segments(data: &[u8], version: usize) -> Result<String, ScanError> {
+    let mut reader = BitReader { data, position: 0 };
+    let mut bytes: Vec<u8> = Vec::new();
+    // character count indicator widths for versions 1-9 and 10-26
+    let wide = version >= 10;
+
+    while reader.remaining() >= 4 {
+        match reader.read(4)? {
+            0b0000 => break,
+            0b0001 => {
+                let mut count = reader.read(if wide { 12 } else { 10 })? as usize;
+                while count >= 3 {
+                    bytes.extend(format!("{:03}", reader.read(10)?).bytes());
+                    count -= 3;
+                }
+                if count == 2 {
+                    bytes.extend(format!("{:02}", reader.read(7)?).bytes());
+                } else if count == 1 {
+                    bytes.extend(format!("{}", reader.read(4)?).bytes());
+                }
+            }
+            0b0010 => {
+                let mut count = reader.read(if wide { 11 } else { 9 })? as usize;
+                while count >= 2 {
+                    let pair = reader.read(11)? as usize;
+                    if pair >= 45 * 45 {
+                        return Err(ScanError::BadData("invalid alphanumeric data"));
+                    }
+                    bytes.push(ALPHANUMERIC[pair / 45]);
+                    bytes.push(ALPHANUMERIC[pair % 45]);
+                    count -= 2;
+                }
+                if count == 1 {
+                    let single = reader.read(6)? as usize;
+                    bytes.push(*ALPHANUMERIC.get(single).ok_or(ScanError::BadData("invalid alphanumeric data"))?);
+                }
+            }
+            0b0100 => {
+                let count = reader.read(if wide { 16 } else { 8 })?;
+                for _ in 0..count {
+                    bytes.push(reader.read(8)? as u8);
+                }
+            }
+            // ECI designator; the text is treated as UTF-8 either way
+            0b0111 => {
+                reader.read(8)?;
+            }
+            _ => return Err(ScanError::BadData("unsupported segment mode")),
+        }
+    }
+
+    Ok(match String::from_utf8(bytes) {
+        Ok(text) => text,
+        // fall back to ISO-8859-1, the default QR byte encoding
+        Err(e) => e.into_bytes().into_iter().map(char::from).collect(),
+    })
+}
+
+//------------------------------------------------------------------------------
+// Reed-Solomon over GF(256) with the QR polynomial x^8 + x^4 + x^3 + x^2 + 1
+
+mod reed_solomon {
+    use super::ScanError;
+
+    struct Field {
+        exp: [u8; 512],
+        log: [u8; 256],
+    }
+
+    fn field() -> Field {
+        let mut exp = [0u8; 512];
+        let mut log = [0u8; 256];
+        let mut x: u16 = 1;
+        for (i, entry) in exp.iter_mut().take(255).enumerate() {
+            *entry = x as u8;
+            log[x as usize] = i as u8;
+            x <<= 1;
+            if x & 0x100 != 0 {
+                x ^= 0x11D;
+            }
+        }
+        for i in 255..512 {
+            exp[i] = exp[i - 255];
+        }
+        Field { exp, log }
+    }
+
+    impl Field {
+        fn mul(&self, a: u8, b: u8) -> u8 {
+            if a == 0 || b == 0 {
+                0
+            } else {
+                self.exp[self.log[a as usize] as usize + self.log[b as usize] as usize]
+            }
+        }
+
+        fn div(&self, a: u8, b: u8) -> u8 {
+            if a == 0 {
+                0
+            } else {
+                self.exp[(self.log[a as usize] as usize + 255 - self.log[b as usize] as usize) % 255]
+            }
+        }
+
+        fn pow(&self, power: usize) -> u8 {
+            self.exp[power % 255]
+        }
+
+        // Evaluates a polynomial stored lowest degree first.
+        fn eval(&self, poly: &[u8], x: u8) -> u8 {
+            poly.iter().rev().fold(0, |acc, &c| self.mul(acc, x) ^ c)
+        }
+    }
+
+    // Corrects `block` (data followed by `ec_len` check codewords) in place.
+    pub fn correct(block: &mut [u8], ec_len: usize) -> Result<(), ScanError> {
+        let gf = field();
+        let n = block.len();
+
+        // block[k] is the coefficient of x^(n-1-k)
+        let syndromes: Vec<u8> = (0..ec_len)
+            .map(|i| {
+                block
+                    .iter()
+                    .fold(0u8, |acc, &c| gf.mul(acc, gf.pow(i)) ^ c)
+            })
+            .collect();
+        if syndromes.iter().all(|&s| s == 0) {
+            return Ok(());
+        }
+
+        // Berlekamp-Massey for the error locator polynomial.
+        let mut locator = vec![1u8];
+        let mut previous = vec![1u8];
+        let mut errors = 0;
+        let mut shift = 1;
+        let mut last_discrepancy = 1u8;
+        for step in 0..ec_len {
+            let mut discrepancy = syndromes[step];
+            for i in 1..=errors.min(locator.len() - 1) {
+                discrepancy ^= gf.mul(locator[i], syndromes[step - i]);
+            }
+            if discrepancy == 0 {
+                shift += 1;
+                continue;
+            }
+            let scale = gf.div(discrepancy, last_discrepancy);
+            let mut updated = locator.clone();
+            if updated.len() < previous.len() + shift {
+                updated.resize(previous.len() + shift, 0);
+            }
+            for (i, &c) in previous.iter().enumerate() {
+                updated[i + shift] ^= gf.mul(scale, c);
+            }
+            if 2 * errors <= step {
+                previous = locator;
+                errors = step + 1 - errors;
+                last_discrepancy = discrepancy;
+                shift = 1;
+            } else {
+                shift += 1;
+            }
+            locator = updated;
+        }
+        while locator.len() > 1 && *locator.last().unwrap() == 0 {
+            locator.pop();
+        }
+        if errors * 2 > ec_len || locator.len() - 1 != errors {
+            return Err(ScanError::TooManyErrors);
+        }
+
+        // Error evaluator: syndromes × locator mod x^ec_len.
+        let mut evaluator = vec![0u8; ec_len];
+        for (i, &s) in syndromes.iter().enumerate() {
+            for (j, &l) in locator.iter().enumerate() {
+                if i + j < ec_len {
+                    evaluator[i + j] ^= gf.mul(s, l);
+                }
+            }
+        }
+        // Formal derivative keeps the odd powers.
+        let derivative: Vec<u8> = locator
+            .iter()
+            .enumerate()
+            .skip(1)
+            .map(|(i, &c)| if i % 2 == 1 { c } else { 0 })
+            .collect();
+
+        // Chien search over every position, then Forney for the magnitude.
+        let mut found = 0;
+        for power in 0..n {
+            let x_inv = gf.pow(255 - power % 255);
+            if gf.eval(&locator, x_inv) != 0 {
+                continue;
+            }
+            let x = gf.pow(power);
+            let denominator = gf.eval(&derivative, x_inv);
+            if denominator == 0 {
+                return Err(ScanError::TooManyErrors);
+            }
+            let magnitude = gf.mul(x, gf.div(gf.eval(&evaluator, x_inv), denominator));
+            block[n - 1 - power] ^= magnitude;
+            found += 1;
+        }
+        if found != errors {
+            return Err(ScanError::TooManyErrors);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Luma;
+    use qrcode::types::Version;
+    use qrcode::{Color, EcLevel, QrCode};
+
+    const LEVELS: [EcLevel; 4] = [EcLevel::L, EcLevel::M, EcLevel::Q, EcLevel::H];
+
+    fn symbol(text: &str, version: usize, level: EcLevel) -> Vec<Vec<bool>> {
+        let code = QrCode::with_version(text, Version::Normal(version as i16), level).unwrap();
+        code.to_colors()
+            .chunks(code.width())
+            .map(|row| row.iter().map(|&color| color == Color::Dark).collect())
+            .collect()
+    }
+
+    // Four pixels a module with a four module quiet zone.
+    fn image(grid: &[Vec<bool>]) -> GrayImage {
+        let size = (grid.len() as u32 + 8) * 4;
+        GrayImage::from_fn(size, size, |x, y| {
+            let (column, row) = ((x / 4) as usize, (y / 4) as usize);
+            let dark = column >= 4 && row >= 4 && grid.get(row - 4).and_then(|r| r.get(column - 4)) == Some(&true);
+            Luma([if dark { 0 } else { 255 }])
+        })
+    }
+
+    fn codeword(data: &[u8], ec_len: usize) -> Vec<u8> {
+        let mut block = data.to_vec();
+        block.extend(qrcode::ec::create_error_correction_code(data, ec_len));
+        block
+    }
+
+    #[test]
+    fn reed_solomon_corrects_up_to_half_the_check_codewords() {
+        let data: Vec<u8> = (0..26u8).map(|i| i.wrapping_mul(37) ^ 0x5A).collect();
+        let original = codeword(&data, 10);
+
+        // zero syndromes: a clean block is left alone
+        let mut block = original.clone();
+        reed_solomon::correct(&mut block, 10).unwrap();
+        assert_eq!(block, original);
+
+        // errors in data and check codewords alike, up to ec_len / 2
+        for positions in [&[0][..], &[3, 35], &[1, 9, 17, 26, 35], &[26, 27, 28, 29, 30]] {
+            let mut block = original.clone();
+            for &i in positions {
+                block[i] ^= 0xA5;
+            }
+            reed_solomon::correct(&mut block, 10).unwrap();
+            assert_eq!(block, original, "errors at {:?}", positions);
+        }
+
+        let mut block = original.clone();
+        for i in [0, 5, 10, 15, 20, 25] {
+            block[i] ^= 0xFF;
+        }
+        assert_eq!(reed_solomon::correct(&mut block, 10), Err(ScanError::TooManyErrors));
+    }
+
+    // A version 1 grid holding only the two copies of the format information.
+    fn format_grid(first: u32, second: u32) -> Vec<Vec<bool>> {
+        let mut grid = vec![vec![false; 21]; 21];
+        let mut put = |bits: u32, cells: Vec<(usize, usize)>| {
+            for (i, (x, y)) in cells.into_iter().enumerate() {
+                grid[y][x] = bits & (1 << (14 - i)) != 0;
+            }
+        };
+        let mut cells: Vec<(usize, usize)> = (0..6).map(|x| (x, 8)).collect();
+        cells.extend([(7, 8), (8, 8), (8, 7)]);
+        cells.extend((0..6).rev().map(|y| (8, y)));
+        put(first, cells);
+        let mut cells: Vec<(usize, usize)> = (14..21).rev().map(|y| (8, y)).collect();
+        cells.extend((13..21).map(|x| (x, 8)));
+        put(second, cells);
+        grid
+    }
+
+    #[test]
+    fn format_information_is_bch_coded() {
+        // ISO/IEC 18004 table C.1: L, M, Q, H with mask 0, and M with mask 5
+        assert_eq!(format_code(0b01_000), 0x77C4);
+        assert_eq!(format_code(0b00_000), 0x5412);
+        assert_eq!(format_code(0b11_000), 0x355F);
+        assert_eq!(format_code(0b10_000), 0x1689);
+        assert_eq!(format_code(0b00_101), 0x40CE);
+
+        for data in 0..32u32 {
+            let code = format_code(data);
+            let (level, mask) = (data >> 3, data & 7);
+            let expected = Ok(([1, 0, 3, 2][level as usize], mask as u8));
+            // three flipped bits are still read correctly
+            let damaged = code ^ 0b100_0001_0000_0100;
+            let both = format_grid(damaged, damaged);
+            assert_eq!(read_format(&|x, y| both[y][x], 21), expected, "format {:05b}", data);
+            // five in the first copy are too many, but the second copy is read instead
+            let second_only = format_grid(code ^ 0b011_1110_0000_0000, code ^ 1);
+            assert_eq!(read_format(&|x, y| second_only[y][x], 21), expected, "format {:05b}", data);
+        }
+
+        let noise = |x: usize, y: usize| (x * 7 + y * 3) % 5 < 2;
+        assert_eq!(read_format(&noise, 21), Err(ScanError::FormatInformation));
+    }
+
+    #[test]
+    fn reads_every_version_and_level() {
+        // short enough for version 2 at level H in byte mode
+        let text = "EasyRemit 42";
+        for version in 2..=MAX_VERSION {
+            for (index, &level) in LEVELS.iter().enumerate() {
+                let grid = symbol(text, version, level);
+                assert_eq!(grid.len(), 17 + 4 * version);
+                assert_eq!(read_symbol(&grid).unwrap(), text, "version {} level {}", version, index);
+            }
+        }
+    }
+
+    #[test]
+    fn decodes_images_with_alignment_and_version_patterns() {
+        let text = "Dinner at Saturday's, $150";
+        for version in [3, 5, 7, 10] {
+            let grid = symbol(text, version, EcLevel::M);
+            assert_eq!(decode(&image(&grid)).unwrap(), text, "version {}", version);
+        }
+    }
+
+    #[test]
+    fn reports_what_it_cannot_read() {
+        let blank = GrayImage::from_pixel(120, 120, Luma([255]));
+        assert_eq!(decode(&blank), Err(ScanError::NoFinderPatterns));
+
+        // too many damaged codewords for level L to recover
+        let mut grid = symbol("EASYREMIT", 2, EcLevel::L);
+        for row in grid.iter_mut().skip(9).take(12) {
+            for cell in row.iter_mut().skip(9).take(12) {
+                *cell = !*cell;
+            }
+        }
+        assert_eq!(read_symbol(&grid), Err(ScanError::TooManyErrors));
+    }
+}
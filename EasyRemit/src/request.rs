@@ -0,0 +1,273 @@
+use iced::{button, Button, Column, Container, Element, Text};
+use iced::{text_input, Alignment, Length, Row, TextInput, Background, Color};
+use crate::Message;
+use iced::Command;
+use crate::payment_requests::{RequestError, REQUEST_TTL_SECS};
+use crate::qr::{self, PaymentPayload};
+use crate::function::qr_code_view;
+use crate::error::AppError;
+use crate::model::AccountId;
+use crate::storage::{Storage, StorageError};
+use crate::tasks::{parse_amount, status_text, with_user_storage, Loadable};
+use std::path::PathBuf;
+
+#[derive(Debug, Clone)]
+pub struct RequestPage {
+    payer: String,
+    payer_input: text_input::State,
+    amount: String,
+    amount_input: text_input::State,
+    memo: String,
+    memo_input: text_input::State,
+    request_button: button::State,
+    show_qr_button: button::State,
+    save_qr_button: button::State,
+    back_button: button::State,
+    status: Option<Loadable<String>>,
+    // modules of the request QR currently on screen
+    qr: Option<Vec<Vec<bool>>>,
+}
+
+impl RequestPage {
+    pub fn new() -> Self {
+        RequestPage {
+            payer: String::new(),
+            payer_input: text_input::State::new(),
+            amount: String::new(),
+            amount_input: text_input::State::new(),
+            memo: String::new(),
+            memo_input: text_input::State::new(),
+            request_button: button::State::new(),
+            show_qr_button: button::State::new(),
+            save_qr_button: button::State::new(),
+            back_button: button::State::new(),
+            status: None,
+            qr: None,
+        }
+    }
+
+    pub fn update(&mut self, message: Message) {
+        match message {
+            Message::RequestPayerChanged(value) => {
+                self.payer = value;
+            }
+            Message::RequestAmountChanged(value) if value.chars().all(|c| c.is_ascii_digit()) => {
+                self.amount = value;
+                self.qr = None;
+            }
+            Message::RequestMemoChanged(value) => {
+                self.memo = value;
+                self.qr = None;
+            }
+            Message::RequestSent(result) => {
+                if result.is_ok() {
+                    self.payer.clear();
+                    self.amount.clear();
+                    self.memo.clear();
+                }
+                self.status = Some(Loadable::from_result(result));
+            }
+            Message::RequestQrReady(result) => match result {
+                Ok(modules) => {
+                    self.qr = Some(modules);
+                    self.status = None;
+                }
+                Err(e) => self.status = Some(Loadable::Failed(e)),
+            },
+            Message::RequestQrSaved(result) => {
+                self.status = Some(Loadable::from_result(result));
+            }
+            _ => {}
+        }
+    }
+
+    pub fn submit(&mut self) -> Command<Message> {
+        let amount = match parse_amount(&self.amount) {
+            Ok(amount) => amount,
+            Err(e) => {
+                self.status = Some(Loadable::Failed(e));
+                return Command::none();
+            }
+        };
+        let payer = self.payer.trim().to_string();
+        let memo = self.memo.clone();
+
+        self.status = Some(Loadable::Loading);
+        Command::perform(
+            with_user_storage(move |storage, user_id| {
+                // the amount is checked first, as in payment_requests::create
+                let payer_id = AccountId::parse(&payer)
+                    .map_err(|_| if amount <= 0 { RequestError::InvalidAmount } else { RequestError::UnknownPayer })?;
+                storage.request_payment(user_id, &payer_id, amount, &memo)?;
+                Ok::<_, StorageError>(format!(
+                    "Requested ${} from {}. It expires in {} days.",
+                    amount,
+                    payer,
+                    REQUEST_TTL_SECS / 86400
+                ))
+            }),
+            Message::RequestSent,
+        )
+    }
+
+    pub fn show_qr(&mut self) -> Command<Message> {
+        let amount = match parse_amount(&self.amount) {
+            Ok(amount) => amount,
+            Err(e) => {
+                self.status = Some(Loadable::Failed(e));
+                return Command::none();
+            }
+        };
+        let memo = self.memo.clone();
+        self.status = Some(Loadable::Loading);
+        Command::perform(
+            with_user_storage(move |storage, user_id| {
+                let payload = payload(storage, user_id, amount, memo)?;
+                Ok::<_, AppError>(qr::modules(&payload)?)
+            }),
+            Message::RequestQrReady,
+        )
+    }
+
+    pub fn save_qr(&mut self) -> Command<Message> {
+        let amount = match parse_amount(&self.amount) {
+            Ok(amount) => amount,
+            Err(e) => {
+                self.status = Some(Loadable::Failed(e));
+                return Command::none();
+            }
+        };
+        let memo = self.memo.clone();
+        self.status = Some(Loadable::Loading);
+        Command::perform(
+            with_user_storage(move |storage, user_id| {
+                let payload = payload(storage, user_id, amount, memo)?;
+                let path = PathBuf::from(format!("easyremit-request-{}-{}.png", payload.account_id, amount));
+                qr::save(&payload, &path)?;
+                Ok::<_, AppError>(format!("QR code saved to {}", path.display()))
+            }),
+            Message::RequestQrSaved,
+        )
+    }
+
+    pub fn view(&mut self) -> Element<'_, super::Message> {
+        let title = Text::new("Request Money").size(35);
+
+        let payer_input = TextInput::new(
+            &mut self.payer_input,
+            "Payer's ID: ",
+            &self.payer,
+            Message::RequestPayerChanged,
+        )
+        .padding(10)
+        .size(20);
+
+        let amount_input = TextInput::new(
+            &mut self.amount_input,
+            "Amount: ",
+            &self.amount,
+            Message::RequestAmountChanged,
+        )
+        .padding(10)
+        .size(20);
+
+        let memo_input = TextInput::new(
+            &mut self.memo_input,
+            "What is it for? ",
+            &self.memo,
+            Message::RequestMemoChanged,
+        )
+        .padding(10)
+        .size(20);
+
+        let status_text = status_text(&self.status, 16);
+
+        let request_button = Button::new(&mut self.request_button, Text::new("REQUEST"))
+            .padding(15)
+            .style(RequestButtonStyle)
+            .on_press(Message::SubmitRequest);
+
+        let qr_buttons = Row::new()
+            .spacing(10)
+            .push(
+                Button::new(&mut self.show_qr_button, Text::new("Show QR").size(16))
+                    .padding(8)
+                    .style(BackButtonStyle)
+                    .on_press(Message::ShowRequestQr),
+            )
+            .push(
+                Button::new(&mut self.save_qr_button, Text::new("Save QR").size(16))
+                    .padding(8)
+                    .style(BackButtonStyle)
+                    .on_press(Message::SaveRequestQr),
+            );
+
+        let back_button = Button::new(&mut self.back_button, Text::new("Back"))
+            .padding(3)
+            .style(BackButtonStyle)
+            .on_press(Message::GoToFunction);
+
+        let mut content = Column::new()
+            .spacing(15)
+            .padding(20)
+            .align_items(Alignment::Center)
+            .push(title)
+            .push(payer_input)
+            .push(amount_input)
+            .push(memo_input)
+            .push(status_text)
+            .push(request_button)
+            .push(qr_buttons);
+        if let Some(modules) = &self.qr {
+            content = content.push(qr_code_view(modules, 4));
+        }
+        let content = content.push(back_button);
+
+        Container::new(content)
+            .width(Length::Fill)
+            .height(Length::Fill)
+            .center_x()
+            .center_y()
+            .into()
+    }
+}
+
+// A code anyone can scan to pay this request, whoever they are.
+fn payload(storage: &dyn Storage, user_id: &AccountId, amount: i64, memo: String) -> Result<PaymentPayload, AppError> {
+    if amount <= 0 {
+        return Err(AppError::Validation("Enter an amount to put on the QR code".to_string()));
+    }
+    let user = storage
+        .profile(user_id)?
+        .ok_or_else(|| AppError::Auth("Your account could not be found".to_string()))?;
+    let payload = PaymentPayload::for_user(&user);
+    Ok(PaymentPayload {
+        amount: Some(amount),
+        memo: Some(memo),
+        ..payload
+    })
+}
+
+struct RequestButtonStyle;
+impl iced::button::StyleSheet for RequestButtonStyle {
+    fn active(&self) -> iced::button::Style {
+        iced::button::Style {
+            background: Some(Background::Color(Color::from_rgb(0.1, 0.3, 0.6))),
+            border_radius: 10.0,
+            text_color: Color::WHITE,
+            ..iced::button::Style::default()
+        }
+    }
+}
+
+struct BackButtonStyle;
+impl iced::button::StyleSheet for BackButtonStyle {
+    fn active(&self) -> iced::button::Style {
+        iced::button::Style {
+            background: Some(Background::Color(Color::WHITE)),
+            border_radius: 10.0,
+            text_color: Color::BLACK,
+            ..iced::button::Style::default()
+        }
+    }
+}
@@ -0,0 +1,384 @@
+use once_cell::sync::Lazy;
+use rand::Rng;
+use rusqlite::{ffi, ErrorCode, Result};
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::panic::Location;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::task::{Context, Poll, Waker};
+use std::time::{Duration, Instant};
+use tracing::{debug, info, info_span, warn, Span};
+use crate::logging;
+
+// How an operation is retried when SQLite reports that another connection
+// holds the lock it needs
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+    // including the first try; 0 is treated as 1
+    pub max_attempts: u32,
+    // before the first retry, doubled for each one after it
+    pub initial_delay: Duration,
+    pub max_delay: Duration,
+    // randomise each delay between half and all of it, so callers that
+    // collided do not retry in step
+    pub jitter: bool,
+    // give up rather than start a wait that would end after this long
+    pub deadline: Option<Duration>,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_attempts: 3,
+            initial_delay: Duration::from_millis(100),
+            max_delay: Duration::from_secs(2),
+            jitter: true,
+            deadline: Some(Duration::from_secs(10)),
+        }
+    }
+}
+
+// Busy and locked errors clear up once the other connection is done. This
+// includes SQLITE_BUSY_SNAPSHOT, where a WAL read transaction cannot be
+// upgraded to a write because the database changed since it started; trying
+// again starts from a fresh snapshot.
+pub fn is_retryable(err: &rusqlite::Error) -> bool {
+    match err {
+        rusqlite::Error::SqliteFailure(error, _) => {
+            matches!(error.code, ErrorCode::DatabaseBusy | ErrorCode::DatabaseLocked)
+                || error.extended_code == ffi::SQLITE_BUSY_SNAPSHOT
+        }
+        _ => false,
+    }
+}
+
+impl RetryPolicy {
+    // Runs `operation` until it succeeds, fails with an error that is not
+    // worth retrying, or the policy runs out. Retries are counted against the
+    // caller's source location (see `retry_stats`).
+    #[track_caller]
+    pub fn run<T>(&self, mut operation: impl FnMut() -> Result<T>) -> Result<T> {
+        let mut log = RetryLog::new(Location::caller());
+        let started = Instant::now();
+        let mut retries = 0;
+        loop {
+            match operation() {
+                Err(err) if is_retryable(&err) => match self.next_delay(retries, started.elapsed()) {
+                    Some(delay) => {
+                        retries += 1;
+                        log.retrying(retries, delay, &err);
+                        std::thread::sleep(delay);
+                    }
+                    None => {
+                        log.finished(retries, true);
+                        return Err(err);
+                    }
+                },
+                result => {
+                    log.finished(retries, false);
+                    return result;
+                }
+            }
+        }
+    }
+
+    // `run` for async callers: waiting between attempts does not hold up the
+    // thread polling the future.
+    #[track_caller]
+    pub fn run_async<T, F, Fut>(&self, mut operation: F) -> impl Future<Output = Result<T>>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T>>,
+    {
+        let mut log = RetryLog::new(Location::caller());
+        let policy = *self;
+        async move {
+            let started = Instant::now();
+            let mut retries = 0;
+            loop {
+                match operation().await {
+                    Err(err) if is_retryable(&err) => match policy.next_delay(retries, started.elapsed()) {
+                        Some(delay) => {
+                            retries += 1;
+                            log.retrying(retries, delay, &err);
+                            Delay::new(delay).await;
+                        }
+                        None => {
+                            log.finished(retries, true);
+                            return Err(err);
+                        }
+                    },
+                    result => {
+                        log.finished(retries, false);
+                        return result;
+                    }
+                }
+            }
+        }
+    }
+
+    // The wait before retry number `retries + 1`, or None to give up
+    fn next_delay(&self, retries: u32, elapsed: Duration) -> Option<Duration> {
+        if retries + 1 >= self.max_attempts.max(1) {
+            return None;
+        }
+        let backoff = self.backoff(retries);
+        let delay = if self.jitter && !backoff.is_zero() {
+            rand::thread_rng().gen_range(backoff / 2..=backoff)
+        } else {
+            backoff
+        };
+        match self.deadline {
+            Some(deadline) if elapsed + delay > deadline => None,
+            _ => Some(delay),
+        }
+    }
+
+    // Before jitter: initial_delay doubled `retries` times, at most max_delay
+    fn backoff(&self, retries: u32) -> Duration {
+        let factor = 1u32.checked_shl(retries).unwrap_or(u32::MAX);
+        self.initial_delay.saturating_mul(factor).min(self.max_delay)
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RetryStats {
+    pub calls: u64,
+    // attempts after the first
+    pub retries: u64,
+    // calls that were still busy when the policy ran out
+    pub exhausted: u64,
+}
+
+// Keyed by "file:line" of the code that asked for the retry
+static STATS: Lazy<Mutex<BTreeMap<String, RetryStats>>> = Lazy::new(|| Mutex::new(BTreeMap::new()));
+
+fn record(site: &Location, retries: u32, exhausted: bool) {
+    let mut stats = STATS.lock().unwrap_or_else(PoisonError::into_inner);
+    let entry = stats.entry(format!("{}:{}", site.file(), site.line())).or_default();
+    entry.calls += 1;
+    entry.retries += u64::from(retries);
+    entry.exhausted += u64::from(exhausted);
+}
+
+// Counts a call's retries and logs them. A call that needs retrying gets a
+// `db_retry` span, inside whatever transfer or login it is part of; calls
+// that succeed first time log nothing.
+struct RetryLog {
+    site: &'static Location<'static>,
+    span: Option<Span>,
+}
+
+impl RetryLog {
+    fn new(site: &'static Location<'static>) -> Self {
+        RetryLog { site, span: None }
+    }
+
+    fn retrying(&mut self, retry: u32, delay: Duration, err: &rusqlite::Error) {
+        let site = self.site;
+        let span = self.span.get_or_insert_with(|| {
+            info_span!("db_retry", correlation_id = %logging::correlation_id(), site = %site)
+        });
+        span.in_scope(|| debug!(retry, delay_ms = delay.as_millis() as u64, error = %err, "database busy, retrying"));
+    }
+
+    fn finished(&self, retries: u32, exhausted: bool) {
+        record(self.site, retries, exhausted);
+        if let Some(span) = &self.span {
+            span.in_scope(|| {
+                if exhausted {
+                    warn!(retries, "database still busy, giving up");
+                } else {
+                    info!(retries, "succeeded after retrying");
+                }
+            });
+        }
+    }
+}
+
+// Every call site that has run an operation under a RetryPolicy so far
+pub fn retry_stats() -> Vec<(String, RetryStats)> {
+    let stats = STATS.lock().unwrap_or_else(PoisonError::into_inner);
+    stats.iter().map(|(site, stats)| (site.clone(), *stats)).collect()
+}
+
+// A future that is ready once `duration` has passed. A short-lived thread
+// does the waiting, so this works on any executor.
+struct Delay {
+    state: Arc<Mutex<(bool, Option<Waker>)>>,
+}
+
+impl Delay {
+    fn new(duration: Duration) -> Delay {
+        let state = Arc::new(Mutex::new((false, None::<Waker>)));
+        let timer = Arc::clone(&state);
+        std::thread::spawn(move || {
+            std::thread::sleep(duration);
+            let mut state = timer.lock().unwrap_or_else(PoisonError::into_inner);
+            state.0 = true;
+            if let Some(waker) = state.1.take() {
+                waker.wake();
+            }
+        });
+        Delay { state }
+    }
+}
+
+impl Future for Delay {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        if state.0 {
+            Poll::Ready(())
+        } else {
+            state.1 = Some(cx.waker().clone());
+            Poll::Pending
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+
+    fn failure(code: i32) -> rusqlite::Error {
+        rusqlite::Error::SqliteFailure(ffi::Error::new(code), None)
+    }
+
+    fn fast(max_attempts: u32) -> RetryPolicy {
+        RetryPolicy {
+            max_attempts,
+            initial_delay: Duration::ZERO,
+            ..RetryPolicy::default()
+        }
+    }
+
+    // Fails with `error` for the first `failures` calls, then returns how
+    // many calls it took
+    fn flaky(failures: u32, error: i32, calls: &Cell<u32>) -> impl FnMut() -> Result<u32> + '_ {
+        move || {
+            calls.set(calls.get() + 1);
+            if calls.get() <= failures {
+                Err(failure(error))
+            } else {
+                Ok(calls.get())
+            }
+        }
+    }
+
+    fn stats_for(line: u32) -> RetryStats {
+        let site = format!("{}:{}", file!(), line);
+        retry_stats()
+            .into_iter()
+            .find(|(key, _)| *key == site)
+            .map(|(_, stats)| stats)
+            .unwrap_or_default()
+    }
+
+    #[test]
+    fn retries_busy_until_it_succeeds() {
+        let calls = Cell::new(0);
+        assert_eq!(fast(5).run(flaky(3, ffi::SQLITE_BUSY, &calls)).unwrap(), 4);
+    }
+
+    #[test]
+    fn retries_locked_and_busy_snapshot() {
+        let calls = Cell::new(0);
+        assert_eq!(fast(5).run(flaky(1, ffi::SQLITE_LOCKED, &calls)).unwrap(), 2);
+        let calls = Cell::new(0);
+        assert_eq!(fast(5).run(flaky(2, ffi::SQLITE_BUSY_SNAPSHOT, &calls)).unwrap(), 3);
+    }
+
+    #[test]
+    fn other_errors_are_returned_at_once() {
+        let calls = Cell::new(0);
+        let result = fast(5).run(flaky(1, ffi::SQLITE_CONSTRAINT, &calls));
+        assert!(!is_retryable(&result.unwrap_err()));
+        assert_eq!(calls.get(), 1);
+    }
+
+    #[test]
+    fn gives_up_after_max_attempts() {
+        let calls = Cell::new(0);
+        let result = fast(3).run(flaky(10, ffi::SQLITE_BUSY, &calls));
+        assert!(is_retryable(&result.unwrap_err()));
+        assert_eq!(calls.get(), 3);
+    }
+
+    #[test]
+    fn zero_attempts_still_tries_once() {
+        let calls = Cell::new(0);
+        assert!(fast(0).run(flaky(10, ffi::SQLITE_BUSY, &calls)).is_err());
+        assert_eq!(calls.get(), 1);
+        let calls = Cell::new(0);
+        assert_eq!(fast(0).run(flaky(0, ffi::SQLITE_BUSY, &calls)).unwrap(), 1);
+    }
+
+    #[test]
+    fn stops_at_the_deadline() {
+        let policy = RetryPolicy {
+            max_attempts: 100,
+            initial_delay: Duration::from_millis(50),
+            jitter: false,
+            deadline: Some(Duration::from_millis(200)),
+            ..RetryPolicy::default()
+        };
+        let calls = Cell::new(0);
+        let started = Instant::now();
+        assert!(policy.run(flaky(100, ffi::SQLITE_BUSY, &calls)).is_err());
+        // waits of 50 and 100 ms fit, with room for a slow test machine, and
+        // the next 200 ms one would not
+        assert_eq!(calls.get(), 3);
+        assert!(started.elapsed() < Duration::from_millis(350));
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_max_delay_with_jitter_below_it() {
+        let policy = RetryPolicy {
+            max_attempts: 100,
+            initial_delay: Duration::from_millis(100),
+            max_delay: Duration::from_millis(1000),
+            jitter: true,
+            deadline: None,
+        };
+        assert_eq!(policy.backoff(0), Duration::from_millis(100));
+        assert_eq!(policy.backoff(2), Duration::from_millis(400));
+        assert_eq!(policy.backoff(4), Duration::from_millis(1000));
+        assert_eq!(policy.backoff(40), Duration::from_millis(1000));
+        for retries in 0..6 {
+            let delay = policy.next_delay(retries, Duration::ZERO).unwrap();
+            assert!(delay >= policy.backoff(retries) / 2 && delay <= policy.backoff(retries));
+        }
+    }
+
+    #[test]
+    fn counts_retries_per_call_site() {
+        let calls = Cell::new(0);
+        let line = line!() + 1;
+        fast(5).run(flaky(2, ffi::SQLITE_BUSY, &calls)).unwrap();
+        let calls = Cell::new(0);
+        let exhausted_line = line!() + 1;
+        fast(2).run(flaky(5, ffi::SQLITE_BUSY, &calls)).unwrap_err();
+
+        assert_eq!(stats_for(line), RetryStats { calls: 1, retries: 2, exhausted: 0 });
+        assert_eq!(stats_for(exhausted_line), RetryStats { calls: 1, retries: 1, exhausted: 1 });
+    }
+
+    #[test]
+    fn async_variant_waits_without_blocking() {
+        let policy = RetryPolicy {
+            initial_delay: Duration::from_millis(5),
+            ..fast(5)
+        };
+        let calls = Cell::new(0);
+        let result = futures::executor::block_on(policy.run_async(|| {
+            calls.set(calls.get() + 1);
+            let outcome = if calls.get() <= 2 { Err(failure(ffi::SQLITE_BUSY)) } else { Ok(calls.get()) };
+            async move { outcome }
+        }));
+        assert_eq!(result.unwrap(), 3);
+    }
+}
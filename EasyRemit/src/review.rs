@@ -0,0 +1,207 @@
+use iced::{button, Button, Column, Container, Element, Text};
+use iced::{scrollable, text_input, Alignment, Length, Row, Scrollable, TextInput, Background, Color};
+use crate::Message;
+use iced::Command;
+use crate::compliance::{self, Decision, ReviewItem};
+use crate::tasks::{loading_text, read_db, status_text, with_user_db, Loadable};
+
+#[derive(Debug, Clone, Default)]
+struct ItemButtons {
+    approve: button::State,
+    reject: button::State,
+    escalate: button::State,
+}
+
+#[derive(Debug, Clone)]
+pub struct ReviewPage {
+    comment: String,
+    comment_input: text_input::State,
+    item_buttons: Vec<ItemButtons>,
+    back_button: button::State,
+    scrollable_state: scrollable::State,
+    status: Option<Loadable<String>>,
+    queue: Loadable<Vec<ReviewItem>>,
+}
+
+impl ReviewPage {
+    pub fn new() -> (Self, Command<Message>) {
+        let page = ReviewPage {
+            comment: String::new(),
+            comment_input: text_input::State::new(),
+            item_buttons: Vec::new(),
+            back_button: button::State::new(),
+            scrollable_state: scrollable::State::new(),
+            status: None,
+            queue: Loadable::Loading,
+        };
+        (page, ReviewPage::load())
+    }
+
+    fn load() -> Command<Message> {
+        Command::perform(
+            read_db(compliance::queue),
+            Message::ReviewLoaded,
+        )
+    }
+
+    pub fn update(&mut self, message: Message) -> Command<Message> {
+        match message {
+            Message::ReviewCommentChanged(value) => {
+                self.comment = value;
+            }
+            Message::ReviewLoaded(result) => {
+                self.queue = Loadable::from_result(result);
+            }
+            Message::ReviewDecided(result) => {
+                if result.is_ok() {
+                    self.comment.clear();
+                }
+                self.status = Some(Loadable::from_result(result));
+                // The decided item leaves the queue
+                return ReviewPage::load();
+            }
+            _ => {}
+        }
+        Command::none()
+    }
+
+    pub fn decide(&mut self, kind: compliance::ItemKind, id: String, decision: Decision) -> Command<Message> {
+        let comment = self.comment.clone();
+        self.status = Some(Loadable::Loading);
+        Command::perform(
+            with_user_db(move |conn, reviewer_id| {
+                compliance::decide(conn, reviewer_id.as_str(), kind, &id, decision, &comment)?;
+                Ok::<_, compliance::ReviewError>(format!("{} {}: {}", kind.as_str(), id, decision.as_str()))
+            }),
+            Message::ReviewDecided,
+        )
+    }
+
+    pub fn view(&mut self) -> Element<'_, super::Message> {
+        let empty = Vec::new();
+        let queue = self.queue.loaded().unwrap_or(&empty);
+
+        let title = Text::new("Review queue").size(35);
+        let summary = match &self.queue {
+            Loadable::Loaded(_) => Text::new(match queue.len() {
+                0 => "Nothing waiting for review".to_string(),
+                1 => "1 item waiting for review".to_string(),
+                n => format!("{} items waiting for review", n),
+            })
+            .size(16)
+            .color([0.5, 0.5, 0.5]),
+            _ => loading_text(&self.queue, "the review queue"),
+        };
+
+        let comment_input = TextInput::new(
+            &mut self.comment_input,
+            "Comment (required for every decision)",
+            &self.comment,
+            Message::ReviewCommentChanged,
+        )
+        .padding(10)
+        .size(16);
+
+        let status_text = status_text(&self.status, 14);
+
+        self.item_buttons.resize_with(queue.len(), Default::default);
+        let mut list = Column::new().spacing(16);
+        for (item, buttons) in queue.iter().zip(self.item_buttons.iter_mut()) {
+            list = list.push(item_view(item, buttons));
+        }
+        let list = Scrollable::new(&mut self.scrollable_state)
+            .padding(8)
+            .width(Length::Fill)
+            .height(Length::Units(380))
+            .push(list);
+
+        let back_button = Button::new(&mut self.back_button, Text::new("Back"))
+            .padding(3)
+            .style(BackButtonStyle)
+            .on_press(Message::GoToFunction);
+
+        let content = Column::new()
+            .spacing(12)
+            .padding(20)
+            .align_items(Alignment::Center)
+            .push(title)
+            .push(summary)
+            .push(comment_input)
+            .push(status_text)
+            .push(Container::new(list).padding(10).style(CardStyle))
+            .push(back_button);
+
+        Container::new(content)
+            .width(Length::Fill)
+            .height(Length::Fill)
+            .center_x()
+            .center_y()
+            .into()
+    }
+}
+
+fn item_view<'a>(item: &ReviewItem, buttons: &'a mut ItemButtons) -> Column<'a, Message> {
+    let heading = if item.escalated {
+        format!("{} {} (escalated)", item.kind.as_str(), item.id)
+    } else {
+        format!("{} {}", item.kind.as_str(), item.id)
+    };
+    let mut column = Column::new()
+        .spacing(4)
+        .push(Text::new(heading).size(16).color([0.1, 0.1, 0.1]))
+        .push(Text::new(item.summary.clone()).size(14).color([0.3, 0.3, 0.3]));
+    for hit in &item.hits {
+        column = column.push(Text::new(hit.clone()).size(13).color([0.7, 0.3, 0.0]));
+    }
+
+    let decision_button = |state, label, decision| {
+        Button::new(state, Text::new(label).size(14))
+            .padding(5)
+            .style(DecisionButtonStyle)
+            .on_press(Message::ReviewDecision(item.kind, item.id.clone(), decision))
+    };
+    column.push(
+        Row::new()
+            .spacing(8)
+            .push(decision_button(&mut buttons.approve, "Approve", Decision::Approve))
+            .push(decision_button(&mut buttons.reject, "Reject", Decision::Reject))
+            .push(decision_button(&mut buttons.escalate, "Escalate", Decision::Escalate)),
+    )
+}
+
+struct CardStyle;
+impl iced::container::StyleSheet for CardStyle {
+    fn style(&self) -> iced::container::Style {
+        iced::container::Style {
+            background: Some(Background::Color(Color::WHITE)),
+            border_radius: 15.0,
+            border_width: 1.0,
+            border_color: Color::from_rgb(0.2, 0.4, 0.6),
+            ..iced::container::Style::default()
+        }
+    }
+}
+
+struct DecisionButtonStyle;
+impl iced::button::StyleSheet for DecisionButtonStyle {
+    fn active(&self) -> iced::button::Style {
+        iced::button::Style {
+            background: Some(Background::Color(Color::from_rgb(0.1, 0.3, 0.6))),
+            border_radius: 8.0,
+            text_color: Color::WHITE,
+            ..iced::button::Style::default()
+        }
+    }
+}
+
+struct BackButtonStyle;
+impl iced::button::StyleSheet for BackButtonStyle {
+    fn active(&self) -> iced::button::Style {
+        iced::button::Style {
+            background: Some(Background::Color(Color::WHITE)),
+            border_radius: 10.0,
+            text_color: Color::BLACK,
+            ..iced::button::Style::default()
+        }
+    }
+}
@@ -0,0 +1,421 @@
+use once_cell::sync::Lazy;
+use regex::Regex;
+use rusqlite::{params, Connection, OptionalExtension};
+use std::fmt;
+use std::io::Read;
+use std::path::Path;
+use crate::config;
+
+// Name of the `screening_rules` row that switches sanctions screening on and
+// holds its settings: `threshold` is the match score, in percent, at which a
+// name is reported and `action` is what happens to a transfer to that person.
+pub const RULE: &str = "sanctions";
+
+// Where a hit was raised
+pub const SIGNUP: &str = "signup";
+pub const RECIPIENT: &str = "recipient";
+
+// How much an agreeing birth date raises the name score. A contradicting
+// one never lowers it: the birth date is self-reported at signup.
+const DOB_MATCH_BONUS: f64 = 0.05;
+
+// The sanctions list export named by `screening.sanctions_list` in the
+// config, e.g. OFAC's sdn.csv, read at first use. Binaries that screen call
+// `init_or_exit` at startup so a missing list stops them rather than letting
+// every name screen clean.
+static LIST: Lazy<Result<SanctionsList, ListError>> =
+    Lazy::new(|| SanctionsList::load(&config::get().screening.sanctions_list));
+
+#[derive(Debug)]
+pub enum ListError {
+    Io(std::io::Error),
+    Csv(csv::Error),
+}
+
+impl fmt::Display for ListError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ListError::Io(e) => write!(f, "{}", e),
+            ListError::Csv(e) => write!(f, "malformed list: {}", e),
+        }
+    }
+}
+
+// A date as precise as the list gives it: "12 Mar 1965", "Mar 1965", "1965"
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BirthDate {
+    pub year: u16,
+    pub month: Option<u8>,
+    pub day: Option<u8>,
+}
+
+impl BirthDate {
+    // Dates typed on the Signup page, dd/mm/yyyy
+    pub fn parse_signup(date: &str) -> Option<BirthDate> {
+        let mut parts = date.trim().split('/');
+        let day = parts.next()?.parse().ok()?;
+        let month = parts.next()?.parse().ok()?;
+        let year = parts.next()?.parse().ok()?;
+        Some(BirthDate { year, month: Some(month), day: Some(day) })
+    }
+
+    // True when nothing either date states contradicts the other
+    fn agrees_with(&self, other: &BirthDate) -> bool {
+        fn same<T: PartialEq>(a: Option<T>, b: Option<T>) -> bool {
+            match (a, b) {
+                (Some(a), Some(b)) => a == b,
+                _ => true,
+            }
+        }
+        self.year == other.year && same(self.month, other.month) && same(self.day, other.day)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ListEntry {
+    pub id: String,
+    pub name: String,
+    pub program: String,
+    pub aliases: Vec<String>,
+    pub birth_dates: Vec<BirthDate>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct SanctionsList {
+    entries: Vec<ListEntry>,
+}
+
+impl SanctionsList {
+    pub fn load(path: impl AsRef<Path>) -> Result<SanctionsList, ListError> {
+        let file = std::fs::File::open(path).map_err(ListError::Io)?;
+        SanctionsList::from_reader(file)
+    }
+
+    // OFAC sdn.csv layout, no header row: ent_num, SDN_Name, SDN_Type,
+    // Program, Title, Call_Sign, Vess_type, Tonnage, GRT, Vess_flag,
+    // Vess_owner, Remarks. Birth dates and aliases live in Remarks.
+    pub fn from_reader(reader: impl Read) -> Result<SanctionsList, ListError> {
+        let dob = Regex::new(r"DOB (?:circa )?(?:(\d{1,2}) )?(?:([A-Z][a-z]{2}) )?(\d{4})").unwrap();
+        let aka = Regex::new(r"a\.k\.a\. '([^']+)'").unwrap();
+
+        let mut csv = csv::ReaderBuilder::new()
+            .has_headers(false)
+            .flexible(true)
+            .from_reader(reader);
+        let mut entries = Vec::new();
+        for record in csv.records() {
+            let record = record.map_err(ListError::Csv)?;
+            let field = |i: usize| record.get(i).map(str::trim).filter(|v| *v != "-0-").unwrap_or("");
+            // vessels and aircraft cannot open accounts
+            if matches!(field(2), "vessel" | "aircraft") || field(1).is_empty() {
+                continue;
+            }
+            let remarks = field(11);
+            entries.push(ListEntry {
+                id: field(0).to_string(),
+                name: field(1).to_string(),
+                program: field(3).to_string(),
+                aliases: aka.captures_iter(remarks).map(|c| c[1].to_string()).collect(),
+                birth_dates: dob
+                    .captures_iter(remarks)
+                    .filter_map(|c| {
+                        Some(BirthDate {
+                            year: c[3].parse().ok()?,
+                            month: c.get(2).and_then(|m| month_number(m.as_str())),
+                            day: c.get(1).and_then(|d| d.as_str().parse().ok()),
+                        })
+                    })
+                    .collect(),
+            });
+        }
+        Ok(SanctionsList { entries })
+    }
+
+    // Every entry whose name or alias scores at least `threshold` (0.0-1.0)
+    // against `name`, best first. A birth date that agrees with the list
+    // raises the score; one that differs is recorded on the match and sorts
+    // it after equal scores, but the match is still reported.
+    pub fn search(&self, name: &str, birth_date: Option<BirthDate>, threshold: f64) -> Vec<Match> {
+        let query = tokens(name);
+        if query.is_empty() {
+            return Vec::new();
+        }
+        let mut matches: Vec<Match> = self
+            .entries
+            .iter()
+            .filter_map(|entry| {
+                let name_score = std::iter::once(&entry.name)
+                    .chain(&entry.aliases)
+                    .map(|listed| name_similarity(&query, &tokens(listed)))
+                    .fold(0.0, f64::max);
+                let dob_corroborated = birth_date
+                    .filter(|_| !entry.birth_dates.is_empty())
+                    .map(|dob| entry.birth_dates.iter().any(|listed| listed.agrees_with(&dob)));
+                let score = match dob_corroborated {
+                    Some(true) => (name_score + DOB_MATCH_BONUS).min(1.0),
+                    _ => name_score,
+                };
+                (score >= threshold).then(|| Match {
+                    entry_id: entry.id.clone(),
+                    listed_name: entry.name.clone(),
+                    program: entry.program.clone(),
+                    score,
+                    dob_corroborated,
+                })
+            })
+            .collect();
+        // Some(true) > None > Some(false)
+        let dob_rank = |m: &Match| m.dob_corroborated.map_or(1, |agrees| if agrees { 2 } else { 0 });
+        matches.sort_by(|a, b| b.score.total_cmp(&a.score).then(dob_rank(b).cmp(&dob_rank(a))));
+        matches
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Match {
+    pub entry_id: String,
+    pub listed_name: String,
+    pub program: String,
+    pub score: f64,
+    // None when either side has no birth date
+    pub dob_corroborated: Option<bool>,
+}
+
+// Screens a name against the configured sanctions list with the
+// threshold set on the `sanctions` screening rule. Returns nothing when the
+// rule is disabled.
+pub fn screen(conn: &Connection, name: &str, birth_date: Option<&str>) -> rusqlite::Result<Vec<Match>> {
+    Ok(match (threshold(conn)?, &*LIST) {
+        (Some(percent), Ok(list)) => {
+            list.search(name, birth_date.and_then(BirthDate::parse_signup), percent as f64 / 100.0)
+        }
+        (Some(_), Err(e)) => {
+            tracing::error!(error = %e, "sanctions list not loaded, name not screened");
+            Vec::new()
+        }
+        (None, _) => Vec::new(),
+    })
+}
+
+// Loads the list now. If the `sanctions` rule is enabled and the list cannot
+// be read, prints why and exits, for use at the top of main.
+pub fn init_or_exit(conn: &Connection) {
+    let enabled = threshold(conn)
+        .unwrap_or_else(|e| {
+            eprintln!("Cannot read the screening rules: {}", e);
+            std::process::exit(1);
+        })
+        .is_some();
+    if let (true, Err(e)) = (enabled, &*LIST) {
+        eprintln!(
+            "Cannot load the sanctions list {}: {}. Sanctions screening is enabled, so the list is required.",
+            config::get().screening.sanctions_list.display(),
+            e
+        );
+        std::process::exit(1);
+    }
+}
+
+// Score in percent from the `sanctions` rule, None while it is disabled
+fn threshold(conn: &Connection) -> rusqlite::Result<Option<i64>> {
+    conn.query_row(
+        "SELECT threshold FROM screening_rules WHERE rule = ?1 AND enabled = 1",
+        params![RULE],
+        |row| row.get(0),
+    )
+    .optional()
+}
+
+// Queues matches for manual review. `transaction_id` is set for hits raised
+// on a transfer recipient.
+pub fn record(
+    conn: &Connection,
+    user_id: &str,
+    context: &str,
+    transaction_id: Option<i64>,
+    matches: &[Match],
+) -> rusqlite::Result<()> {
+    for m in matches {
+        conn.execute(
+            "INSERT INTO sanctions_hits (user_id, context, transaction_id, entry_id, listed_name, program, score, dob_corroborated, created_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, strftime('%s', 'now'))",
+            params![
+                user_id,
+                context,
+                transaction_id,
+                m.entry_id,
+                m.listed_name,
+                m.program,
+                (m.score * 100.0).round() as i64,
+                m.dob_corroborated
+            ],
+        )?;
+    }
+    Ok(())
+}
+
+// Transliterated to ASCII, upper-cased and split on anything that is not a
+// letter or digit, with common spelling variants folded together.
+fn tokens(name: &str) -> Vec<String> {
+    deunicode::deunicode(name)
+        .to_uppercase()
+        .split(|c: char| !c.is_ascii_alphanumeric())
+        .filter(|token| !token.is_empty())
+        .map(fold_spelling)
+        .collect()
+}
+
+// Mohammed/Muhammad, Yusuf/Youssef, Khalid/Halid: romanisations of the same
+// name differ mostly in these places.
+fn fold_spelling(token: &str) -> String {
+    let token = token.replace("PH", "F").replace("KH", "H").replace("OU", "U").replace('Y', "I");
+    let mut folded = String::with_capacity(token.len());
+    for c in token.chars() {
+        if !folded.ends_with(c) {
+            folded.push(c);
+        }
+    }
+    folded
+}
+
+// Each token of the shorter name is paired with its closest token in the
+// longer one, so word order and extra middle names don't matter. A
+// single-word name only partly matching a longer one is discounted.
+fn name_similarity(a: &[String], b: &[String]) -> f64 {
+    let (short, long) = if a.len() <= b.len() { (a, b) } else { (b, a) };
+    if short.is_empty() {
+        return 0.0;
+    }
+    let total: f64 = short
+        .iter()
+        .map(|token| long.iter().map(|other| strsim::jaro_winkler(token, other)).fold(0.0, f64::max))
+        .sum();
+    let score = total / short.len() as f64;
+    if short.len() == 1 && long.len() > 1 {
+        score * 0.85
+    } else {
+        score
+    }
+}
+
+fn month_number(month: &str) -> Option<u8> {
+    const MONTHS: [&str; 12] = ["Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"];
+    MONTHS.iter().position(|m| *m == month).map(|i| i as u8 + 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Rows in the layout of OFAC's sdn.csv, without the vessel columns
+    // filled in.
+    const SDN: &str = r#"6366,"AL-ZAWAHIRI, Ayman",individual,"SDGT",-0- ,-0- ,-0- ,-0- ,-0- ,-0- ,-0- ,"DOB 19 Jun 1951; POB Giza, Egypt; a.k.a. 'AL-ZAWAHRI, Dr. Ayman'; a.k.a. 'ABU MUHAMMAD'."
+7757,"KHALIL, Yusuf Mohammed",individual,"SDGT",-0- ,-0- ,-0- ,-0- ,-0- ,-0- ,-0- ,"DOB Mar 1965; alt. DOB 1967."
+9001,"MÜLLER, Jürgen",individual,"UKRAINE-EO13660",-0- ,-0- ,-0- ,-0- ,-0- ,-0- ,-0- ,"DOB circa 1970."
+9002,"SOUTHERN STAR",vessel,"IRAN",-0- ,-0- ,-0- ,-0- ,-0- ,-0- ,-0- ,-0-
+9003,-0- ,individual,"SDGT",-0- ,-0- ,-0- ,-0- ,-0- ,-0- ,-0- ,-0-
+9004,"AL-HAJ, Ahmad",individual,"SDGT"
+"#;
+
+    fn list() -> SanctionsList {
+        SanctionsList::from_reader(SDN.as_bytes()).unwrap()
+    }
+
+    fn date(year: u16, month: Option<u8>, day: Option<u8>) -> BirthDate {
+        BirthDate { year, month, day }
+    }
+
+    fn best(list: &SanctionsList, name: &str, birth_date: Option<BirthDate>) -> Option<String> {
+        list.search(name, birth_date, 0.88).first().map(|m| m.entry_id.clone())
+    }
+
+    #[test]
+    fn reads_the_ofac_layout() {
+        let list = list();
+        let ids: Vec<&str> = list.entries.iter().map(|e| e.id.as_str()).collect();
+        // the vessel and the row without a name are skipped
+        assert_eq!(ids, ["6366", "7757", "9001", "9004"]);
+
+        let zawahiri = &list.entries[0];
+        assert_eq!((zawahiri.name.as_str(), zawahiri.program.as_str()), ("AL-ZAWAHIRI, Ayman", "SDGT"));
+        assert_eq!(zawahiri.aliases, ["AL-ZAWAHRI, Dr. Ayman", "ABU MUHAMMAD"]);
+        assert_eq!(zawahiri.birth_dates, [date(1951, Some(6), Some(19))]);
+        assert_eq!(list.entries[1].birth_dates, [date(1965, Some(3), None), date(1967, None, None)]);
+        assert_eq!(list.entries[2].birth_dates, [date(1970, None, None)]);
+        assert!(list.entries[3].aliases.is_empty() && list.entries[3].birth_dates.is_empty());
+
+        assert!(matches!(SanctionsList::load("no-such-sanctions.csv"), Err(ListError::Io(_))));
+    }
+
+    #[test]
+    fn names_are_transliterated_and_folded() {
+        for (name, expected) in [
+            ("Ayman al-Ẓawāhirī", &["AIMAN", "AL", "ZAWAHIRI"][..]),
+            ("MÜLLER, Jürgen", &["MULER", "JURGEN"]),
+            ("Muhammad", &["MUHAMAD"]),
+            ("Youssef", &["IUSEF"]),
+            ("Khalid", &["HALID"]),
+            ("Halid", &["HALID"]),
+            ("Philip", &["FILIP"]),
+            (" -- ", &[]),
+        ] {
+            assert_eq!(tokens(name), expected, "{}", name);
+        }
+    }
+
+    #[test]
+    fn birth_dates_agree_as_far_as_both_are_known() {
+        assert_eq!(BirthDate::parse_signup("19/06/1951"), Some(date(1951, Some(6), Some(19))));
+        assert_eq!(BirthDate::parse_signup("1951-06-19"), None);
+        let full = date(1965, Some(3), Some(2));
+        assert!(full.agrees_with(&date(1965, Some(3), None)));
+        assert!(full.agrees_with(&date(1965, None, None)));
+        assert!(!full.agrees_with(&date(1965, Some(4), None)));
+        assert!(!full.agrees_with(&date(1966, None, None)));
+    }
+
+    #[test]
+    fn matches_names_spellings_and_aliases() {
+        let list = list();
+        let cases = [
+            ("Ayman al-Zawahiri", Some("6366")),
+            ("Ayman al-Ẓawāhirī", Some("6366")),
+            ("Aiman Zawahri", Some("6366")),
+            // alias, in another spelling
+            ("Abu Mohammed", Some("6366")),
+            ("Youssef Mohamed Halil", Some("7757")),
+            ("Khalil Yusuf", Some("7757")),
+            ("Jurgen Muller", Some("9001")),
+            ("Juergen Mueller", Some("9001")),
+            ("Ahmed Haj", Some("9004")),
+            // a shared first name, a common name alone, or nobody on the list
+            ("Ayman Smith", None),
+            ("Muhammad", None),
+            ("John Smith", None),
+            ("", None),
+        ];
+        for (name, expected) in cases {
+            assert_eq!(best(&list, name, None).as_deref(), expected, "{}", name);
+        }
+    }
+
+    #[test]
+    fn birth_date_only_raises_the_score() {
+        let list = list();
+        let score = |name: &str, dob: Option<BirthDate>| list.search(name, dob, 0.0)[0].score;
+        let plain = score("Abu Mohammed", None);
+        assert!((score("Abu Mohammed", Some(date(1951, Some(6), Some(19)))) - (plain + DOB_MATCH_BONUS)).abs() < 1e-9);
+        assert_eq!(score("Abu Mohammed", Some(date(1980, None, None))), plain);
+        // never above a perfect match
+        assert_eq!(score("Ayman al-Zawahiri", Some(date(1951, None, None))), 1.0);
+
+        // a different birth date does not clear an exact name, but is recorded
+        let matched = &list.search("Ayman al-Zawahiri", Some(date(1980, Some(1), Some(1))), 0.88)[0];
+        assert_eq!((matched.entry_id.as_str(), matched.score, matched.dob_corroborated), ("6366", 1.0, Some(false)));
+        assert_eq!(best(&list, "Yusuf Khalil", Some(date(1966, None, None))).as_deref(), Some("7757"));
+        assert_eq!(best(&list, "Yusuf Khalil", Some(date(1967, Some(8), Some(1)))).as_deref(), Some("7757"));
+        // entries without a birth date are scored on the name alone
+        let matched = &list.search("Ahmad Al Hajj", Some(date(1980, None, None)), 0.88)[0];
+        assert_eq!((matched.score, matched.dob_corroborated), (1.0, None));
+    }
+}
@@ -0,0 +1,339 @@
+use rusqlite::{params, Connection};
+use std::fmt;
+use crate::auth;
+use crate::sanctions::{self, Match};
+
+// What a rule wants done with a transfer. Ordered so the strictest action
+// among all hits decides the outcome.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Action {
+    Allow,
+    Hold,
+    Block,
+}
+
+impl Action {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Action::Allow => "allow",
+            Action::Hold => "hold",
+            Action::Block => "block",
+        }
+    }
+
+    fn parse(action: &str) -> Action {
+        match action {
+            "hold" => Action::Hold,
+            "block" => Action::Block,
+            _ => Action::Allow,
+        }
+    }
+}
+
+impl fmt::Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+// One row of `screening_rules`. Which of the numeric settings a rule uses is
+// described next to each check below.
+#[derive(Debug, Clone)]
+struct Rule {
+    name: String,
+    action: Action,
+    threshold: i64,
+    window_secs: i64,
+    min_count: i64,
+    margin_percent: i64,
+}
+
+#[derive(Debug, Clone)]
+pub struct RuleHit {
+    pub rule: String,
+    pub action: Action,
+    pub reason: String,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Screening {
+    pub hits: Vec<RuleHit>,
+    // sanctions list entries the recipient matched, queued for review by
+    // `record`
+    pub recipient_id: String,
+    pub sanctions_matches: Vec<Match>,
+}
+
+impl Screening {
+    pub fn decision(&self) -> Action {
+        self.hits.iter().map(|hit| hit.action).max().unwrap_or(Action::Allow)
+    }
+}
+
+// Runs every enabled rule against a transfer that has passed validation but
+// has not been posted yet.
+pub fn screen(conn: &Connection, sender_id: &str, receiver_id: &str, amount: i64) -> rusqlite::Result<Screening> {
+    let mut stmt = conn.prepare(
+        "SELECT rule, action, threshold, window_secs, min_count, margin_percent
+         FROM screening_rules WHERE enabled = 1 ORDER BY rule",
+    )?;
+    let rules = stmt
+        .query_map([], |row| {
+            Ok(Rule {
+                name: row.get(0)?,
+                action: Action::parse(&row.get::<_, String>(1)?),
+                threshold: row.get(2)?,
+                window_secs: row.get(3)?,
+                min_count: row.get(4)?,
+                margin_percent: row.get(5)?,
+            })
+        })?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+
+    let mut screening = Screening { recipient_id: receiver_id.to_string(), ..Screening::default() };
+    for rule in rules {
+        let reason = match rule.name.as_str() {
+            sanctions::RULE => {
+                screening.sanctions_matches = sanctioned_recipient(conn, receiver_id)?;
+                screening.sanctions_matches.first().map(|m| {
+                    format!("Recipient matches sanctions list entry {} ({}%)", m.entry_id, (m.score * 100.0).round())
+                })
+            }
+            "new_account_large" => new_account_large(conn, &rule, sender_id, amount)?,
+            "rapid_fan_out" => rapid_fan_out(conn, &rule, sender_id, receiver_id)?,
+            "structuring" => structuring(conn, &rule, sender_id, amount)?,
+            "round_trip" => round_trip(conn, &rule, sender_id, receiver_id, amount)?,
+            // rows for rules this version does not know are ignored
+            _ => None,
+        };
+        if let Some(reason) = reason {
+            screening.hits.push(RuleHit { rule: rule.name, action: rule.action, reason });
+        }
+    }
+    Ok(screening)
+}
+
+// Stores the hits against the transaction they were raised for.
+pub fn record(conn: &Connection, transaction_id: i64, screening: &Screening) -> rusqlite::Result<()> {
+    for hit in &screening.hits {
+        conn.execute(
+            "INSERT INTO screening_hits (transaction_id, rule, action, reason) VALUES (?1, ?2, ?3, ?4)",
+            params![transaction_id, hit.rule, hit.action.as_str(), hit.reason],
+        )?;
+    }
+    sanctions::record(
+        conn,
+        &screening.recipient_id,
+        sanctions::RECIPIENT,
+        Some(transaction_id),
+        &screening.sanctions_matches,
+    )
+}
+
+// The recipient's name and birth date checked against the sanctions list
+fn sanctioned_recipient(conn: &Connection, receiver_id: &str) -> rusqlite::Result<Vec<Match>> {
+    let name: String = conn.query_row(
+        "SELECT name FROM user_information WHERE id = ?1",
+        params![receiver_id],
+        |row| row.get(0),
+    )?;
+    let birth_date = auth::birth_date_of(conn, receiver_id)?;
+    sanctions::screen(conn, &name, birth_date.as_deref())
+}
+
+// threshold: smallest amount that counts as large
+// window_secs: accounts younger than this are new
+fn new_account_large(conn: &Connection, rule: &Rule, sender_id: &str, amount: i64) -> rusqlite::Result<Option<String>> {
+    let age_secs: i64 = conn.query_row(
+        "SELECT strftime('%s', 'now') - COALESCE(created_at, strftime('%s', 'now')) FROM user_information WHERE id = ?1",
+        params![sender_id],
+        |row| row.get(0),
+    )?;
+    Ok((age_secs < rule.window_secs && amount >= rule.threshold).then(|| {
+        format!(
+            "${} sent from an account opened {} days ago",
+            amount,
+            age_secs / 86400
+        )
+    }))
+}
+
+// min_count: distinct recipients, including this one, within window_secs
+fn rapid_fan_out(conn: &Connection, rule: &Rule, sender_id: &str, receiver_id: &str) -> rusqlite::Result<Option<String>> {
+    let others: i64 = conn.query_row(
+        "SELECT COUNT(DISTINCT receiver_id) FROM transaction_history
+         WHERE sender_id = ?1 AND receiver_id != ?2 AND kind = 'transfer' AND status != 'blocked'
+           AND created_at >= strftime('%s', 'now') - ?3",
+        params![sender_id, receiver_id, rule.window_secs],
+        |row| row.get(0),
+    )?;
+    let recipients = others + 1;
+    Ok((recipients >= rule.min_count).then(|| {
+        format!(
+            "{} different recipients within {} minutes",
+            recipients,
+            rule.window_secs / 60
+        )
+    }))
+}
+
+// threshold: the amount people try to stay under
+// margin_percent: how far below it counts as "just below"
+// min_count: such transfers, including this one, within window_secs
+fn structuring(conn: &Connection, rule: &Rule, sender_id: &str, amount: i64) -> rusqlite::Result<Option<String>> {
+    let floor = rule.threshold * (100 - rule.margin_percent) / 100;
+    if amount < floor || amount >= rule.threshold {
+        return Ok(None);
+    }
+    let earlier: i64 = conn.query_row(
+        "SELECT COUNT(*) FROM transaction_history
+         WHERE sender_id = ?1 AND kind = 'transfer' AND status != 'blocked'
+           AND amount >= ?2 AND amount < ?3
+           AND created_at >= strftime('%s', 'now') - ?4",
+        params![sender_id, floor, rule.threshold, rule.window_secs],
+        |row| row.get(0),
+    )?;
+    let count = earlier + 1;
+    Ok((count >= rule.min_count).then(|| {
+        format!(
+            "{} transfers between ${} and ${} within {} hours",
+            count,
+            floor,
+            rule.threshold - 1,
+            rule.window_secs / 3600
+        )
+    }))
+}
+
+// A transfer back to someone who sent a similar amount (within
+// margin_percent) within window_secs.
+fn round_trip(conn: &Connection, rule: &Rule, sender_id: &str, receiver_id: &str, amount: i64) -> rusqlite::Result<Option<String>> {
+    let margin = amount * rule.margin_percent / 100;
+    let returned: Option<i64> = conn.query_row(
+        "SELECT MAX(amount) FROM transaction_history
+         WHERE sender_id = ?1 AND receiver_id = ?2 AND kind = 'transfer' AND status != 'blocked'
+           AND amount BETWEEN ?3 AND ?4
+           AND created_at >= strftime('%s', 'now') - ?5",
+        params![receiver_id, sender_id, amount - margin, amount + margin, rule.window_secs],
+        |row| row.get(0),
+    )?;
+    Ok(returned.map(|received| {
+        format!(
+            "Returns ${} to an account that sent ${} within {} hours",
+            amount,
+            received,
+            rule.window_secs / 3600
+        )
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::init_schema;
+
+    const DAY: i64 = 86_400;
+
+    // Sanctions screening needs the list file; sanctions.rs tests it.
+    fn database() -> Connection {
+        let conn = Connection::open_in_memory().unwrap();
+        init_schema(&conn).unwrap();
+        conn.execute("UPDATE screening_rules SET enabled = 0 WHERE rule = ?1", params![sanctions::RULE])
+            .unwrap();
+        for (id, age_days) in [("old", 30), ("new", 1), ("a", 30), ("b", 30), ("c", 30), ("d", 30), ("e", 30)] {
+            conn.execute(
+                "INSERT INTO user_information (id, name, balance, created_at)
+                 VALUES (?1, ?1, 100000, CAST(strftime('%s', 'now') AS INTEGER) - ?2)",
+                params![id, age_days * DAY],
+            )
+            .unwrap();
+        }
+        conn
+    }
+
+    fn sent(conn: &Connection, sender_id: &str, receiver_id: &str, amount: i64, secs_ago: i64, status: &str) {
+        conn.execute(
+            "INSERT INTO transaction_history (sender_id, receiver_id, amount, created_at, status)
+             VALUES (?1, ?2, ?3, CAST(strftime('%s', 'now') AS INTEGER) - ?4, ?5)",
+            params![sender_id, receiver_id, amount, secs_ago, status],
+        )
+        .unwrap();
+    }
+
+    fn rules_hit(conn: &Connection, sender_id: &str, receiver_id: &str, amount: i64) -> Vec<String> {
+        let screening = screen(conn, sender_id, receiver_id, amount).unwrap();
+        screening.hits.into_iter().map(|hit| hit.rule).collect()
+    }
+
+    #[test]
+    fn new_accounts_sending_large_amounts() {
+        let conn = database();
+        assert_eq!(rules_hit(&conn, "new", "a", 500), ["new_account_large"]);
+        assert!(rules_hit(&conn, "new", "a", 499).is_empty());
+        assert!(rules_hit(&conn, "old", "a", 5000).is_empty());
+    }
+
+    #[test]
+    fn rapid_fan_out_counts_distinct_recent_recipients() {
+        let conn = database();
+        for receiver_id in ["a", "b", "c"] {
+            sent(&conn, "old", receiver_id, 10, 60, "completed");
+        }
+        sent(&conn, "old", "a", 10, 60, "completed");
+        // too long ago, and blocked, do not count
+        sent(&conn, "old", "d", 10, 2 * 3600, "completed");
+        sent(&conn, "old", "d", 10, 60, "blocked");
+        assert!(rules_hit(&conn, "old", "e", 10).is_empty());
+
+        sent(&conn, "old", "d", 10, 60, "held");
+        assert_eq!(rules_hit(&conn, "old", "e", 10), ["rapid_fan_out"]);
+        // the recipient itself is only counted once
+        assert!(rules_hit(&conn, "old", "d", 10).is_empty());
+    }
+
+    #[test]
+    fn structuring_just_below_the_threshold() {
+        let conn = database();
+        sent(&conn, "old", "a", 950, 3600, "completed");
+        assert!(rules_hit(&conn, "old", "b", 999).is_empty());
+        sent(&conn, "old", "b", 900, 3600, "completed");
+        // outside the band or the window
+        sent(&conn, "old", "c", 899, 3600, "completed");
+        sent(&conn, "old", "c", 990, 2 * DAY, "completed");
+
+        assert_eq!(rules_hit(&conn, "old", "c", 999), ["structuring"]);
+        assert!(rules_hit(&conn, "old", "c", 899).is_empty());
+        assert!(rules_hit(&conn, "old", "c", 1000).is_empty());
+    }
+
+    #[test]
+    fn round_trips_back_to_a_recent_sender() {
+        let conn = database();
+        sent(&conn, "a", "old", 100, DAY, "completed");
+        sent(&conn, "b", "old", 100, 4 * DAY, "completed");
+
+        let screening = screen(&conn, "old", "a", 95).unwrap();
+        assert_eq!(screening.hits[0].rule, "round_trip");
+        assert_eq!(screening.hits[0].reason, "Returns $95 to an account that sent $100 within 72 hours");
+        assert!(rules_hit(&conn, "old", "a", 80).is_empty());
+        assert!(rules_hit(&conn, "old", "b", 100).is_empty());
+    }
+
+    #[test]
+    fn strictest_enabled_rule_decides() {
+        let conn = database();
+        sent(&conn, "a", "new", 500, DAY, "completed");
+        assert_eq!(screen(&conn, "new", "a", 500).unwrap().decision(), Action::Hold);
+
+        conn.execute("UPDATE screening_rules SET action = 'block' WHERE rule = 'round_trip'", [])
+            .unwrap();
+        let screening = screen(&conn, "new", "a", 500).unwrap();
+        assert_eq!(screening.hits.len(), 2);
+        assert_eq!(screening.decision(), Action::Block);
+
+        conn.execute("UPDATE screening_rules SET enabled = 0", []).unwrap();
+        conn.execute("INSERT INTO screening_rules (rule, action) VALUES ('from_a_newer_version', 'block')", [])
+            .unwrap();
+        assert_eq!(screen(&conn, "new", "a", 500).unwrap().decision(), Action::Allow);
+    }
+}
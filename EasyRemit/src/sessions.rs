@@ -0,0 +1,45 @@
+use rand::{thread_rng, RngCore};
+use rusqlite::{params, Connection, OptionalExtension};
+use sha2::{Digest, Sha256};
+
+// How long a login from the command line or the API stays valid
+pub const SESSION_TTL_SECS: i64 = 24 * 60 * 60;
+
+// Starts a session for a user who has just passed `auth::login` and returns
+// its bearer token. Only a hash of the token is stored, so the database alone
+// cannot be used to act as anyone.
+pub fn create(conn: &Connection, user_id: &str) -> rusqlite::Result<String> {
+    let token = new_token();
+    conn.execute(
+        "INSERT INTO sessions (token_hash, user_id, created_at, expires_at)
+         VALUES (?1, ?2, strftime('%s', 'now'), strftime('%s', 'now') + ?3)",
+        params![hash(&token), user_id, SESSION_TTL_SECS],
+    )?;
+    Ok(token)
+}
+
+// The user a token belongs to, or None if it is unknown or has expired.
+pub fn user_for(conn: &Connection, token: &str) -> rusqlite::Result<Option<String>> {
+    conn.query_row(
+        "SELECT user_id FROM sessions WHERE token_hash = ?1 AND expires_at > strftime('%s', 'now')",
+        params![hash(token)],
+        |row| row.get(0),
+    )
+    .optional()
+}
+
+pub fn revoke(conn: &Connection, token: &str) -> rusqlite::Result<()> {
+    conn.execute("DELETE FROM sessions WHERE token_hash = ?1", params![hash(token)])?;
+    Ok(())
+}
+
+// 32 random bytes, hex-encoded
+pub(crate) fn new_token() -> String {
+    let mut bytes = [0u8; 32];
+    thread_rng().fill_bytes(&mut bytes);
+    hex::encode(bytes)
+}
+
+pub(crate) fn hash(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
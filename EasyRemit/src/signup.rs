@@ -1,82 +1,11 @@
-use iced::{button, Button, Column, Container, Element, Text};
+use iced::{button, Button, Column, Command, Container, Element, Text};
 use iced::{text_input, Alignment,Length,TextInput, Background, Color};
 use crate::Message;
-use regex::Regex;
-use rusqlite::{params, Connection};
-use argon2::{Argon2, PasswordHasher, password_hash::{SaltString, PasswordHash}};
-use aes::{Aes128, BlockEncrypt};
-use block_modes::{BlockMode, Cbc};
-use block_modes::block_padding::Pkcs7;
-use rand::{Rng, thread_rng};
-use std::error::Error;
-use std::fmt;
-use std::io::{self, Write};
-use base64::encode;
-use std::sync::{Arc, Mutex};
+use crate::config;
+use crate::auth::NewAccount;
+use crate::model::{AccountId, BirthDate, Email, PassportNumber};
 use crate::set_user_id;
-
-static mut ERROR:bool=true;
-type Aes128Cbc = Cbc<Aes128, Pkcs7>;
-
-// Custom Error Type for Argon2 Errors
-#[derive(Debug)]
-struct Argon2Error(argon2::password_hash::Error);
-
-impl fmt::Display for Argon2Error {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "Argon2 error: {}", self.0)
-    }
-}
-
-impl std::error::Error for Argon2Error {}
-struct EncryptedData {
-    encrypted_string: String, // Field to store the encrypted string
-}
-fn hash_password(password: &str) -> Result<String, Box<dyn Error>> {
-    let salt: SaltString = SaltString::generate(&mut rand::thread_rng());
-    let argon2 = Argon2::default();
-    
-    let hash = argon2.hash_password(password.as_bytes(), &salt).map_err(Argon2Error)?;
-    Ok(hash.to_string())
-}
-
-fn encrypt(data: &[u8], key: &[u8; 16], iv: &[u8; 16]) -> Result<EncryptedData, AppError> {
-    let cipher = Aes128Cbc::new_from_slices(key, iv)
-        .map_err(|e| AppError(e.to_string()))?;
-
-    let mut buffer = data.to_vec();
-    let pos = buffer.len();
-    let padding_length = 16 - (pos % 16);
-    buffer.resize(pos + padding_length, 0); // Resize buffer for padding
-
-    let encrypted_data = cipher.encrypt(&mut buffer, pos)
-        .map_err(|e| AppError(e.to_string()))?;
-
-    // Use Base64 encoding to convert the encrypted data into a String
-    let encrypted_string = encode(encrypted_data);
-
-    // Return the struct that holds the encrypted string
-    Ok(EncryptedData { encrypted_string })
-}
-
-
-// Custom App Error for general application errors
-#[derive(Debug)]
-struct AppError(String);
-
-impl std::fmt::Display for AppError {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}", self.0)
-    }
-}
-
-impl std::error::Error for AppError {}
-
-fn create_user_id(birth_date: &str) -> String {
-    let year = &birth_date[7..9]; 
-    let random_id: u64 = thread_rng().gen_range(0..10_000_000); 
-    format!("{}{:010}", year, random_id) 
-}
+use crate::tasks::with_storage;
 
 
 #[derive(Debug, Clone)]
@@ -94,6 +23,9 @@ pub struct SignupPage {
     signup_button: button::State,
     back_button:button::State,
     valid_fields: [bool; 5],
+    error: Option<String>,
+    // true while the account is being created
+    submitting: bool,
 }
 
 
@@ -114,30 +46,30 @@ impl SignupPage {
                 signup_button: button::State::new(),
                 back_button:button::State::new(),
                 valid_fields: [false; 5],
+                error: None,
+                submitting: false,
         }
     }
 
     pub fn update(&mut self, message: Message) {
         match message {
             Message::EmailChanged(value)=>{
-                self.email = value.clone();
-                self.valid_fields[0] = value.contains("@");
+                self.valid_fields[0] = Email::parse(&value).is_ok();
+                self.email = value;
             }
             Message::PassportChanged(value)=>{
-                let passport_regex = Regex::new(r"^[A-Z]{2}\d{6}$").unwrap();
-                self.passport = value.clone();
-                self.valid_fields[1] = passport_regex.is_match(&value); 
+                self.valid_fields[1] = PassportNumber::parse(&value).is_ok();
+                self.passport = value;
             }
             Message::NameChanged(value)=>{
                 self.name = value.clone();
                 self.valid_fields[2] = !value.is_empty();}
             Message::BirthDateChanged(value)=>{
-                let birthdate_regex = Regex::new(r"^\d{2}/\d{2}/\d{4}$").unwrap();
-                self.birth_date = value.clone();
-                self.valid_fields[3] = birthdate_regex.is_match(&value);
+                self.valid_fields[3] = BirthDate::parse(&value).is_ok();
+                self.birth_date = value;
             }
             Message::PasswordChanged(value)=>{
-                let password_regex = Regex::new(r"^\d{6}$").unwrap();
+                let password_regex = config::get().validation.password_regex();
                     self.password = value.clone();
                     self.valid_fields[4] = password_regex.is_match(&value);
             }
@@ -146,8 +78,51 @@ impl SignupPage {
         }
     }
 
-    pub fn view(&mut self, db_conn: &Arc<Mutex<Connection>> ) -> Element<super::Message> {
-        let conn = db_conn.lock().expect("Failed to acquire lock");
+    // Creates the account once every field is valid. Hashing the password
+    // and screening the name run off the UI thread; the answer comes back as
+    // Message::SignupFinished.
+    pub fn submit(&mut self) -> Command<Message> {
+        if self.submitting || !self.valid_fields.iter().all(|&valid| valid) {
+            return Command::none();
+        }
+        let parsed = (Email::parse(&self.email), PassportNumber::parse(&self.passport), BirthDate::parse(&self.birth_date));
+        let (Ok(email), Ok(passport), Ok(birth_date)) = parsed else {
+            return Command::none();
+        };
+        self.submitting = true;
+        self.error = None;
+        let (name, password) = (self.name.clone(), self.password.clone());
+        Command::perform(
+            with_storage(move |storage| {
+                let account = NewAccount {
+                    email: &email,
+                    name: &name,
+                    passport: &passport,
+                    birth_date: &birth_date,
+                    password: &password,
+                };
+                storage.register(&account)
+            }),
+            Message::SignupFinished,
+        )
+    }
+
+    // Returns true once the new user is logged in.
+    pub fn finished(&mut self, result: Result<AccountId, String>) -> bool {
+        self.submitting = false;
+        match result {
+            Ok(user_id) => {
+                set_user_id(user_id);
+                true
+            }
+            Err(e) => {
+                self.error = Some(e);
+                false
+            }
+        }
+    }
+
+    pub fn view(&mut self) -> Element<'_, super::Message> {
         let all_valid = self.valid_fields.iter().all(|&valid| valid);
         // Main heading
         let main_text = Text::new("Secure your financial future with us")
@@ -163,12 +138,11 @@ impl SignupPage {
 
         
 
-        unsafe {
-            if all_valid{
-                ERROR=false;
-            }
-            
-            let error_text = if !ERROR{
+        let error_text = if let Some(error) = &self.error {
+            Text::new(error.clone())
+                .color(Color::from_rgb(1.0, 0.0, 0.0))
+                .size(16)
+        } else if all_valid {
             Text::new("All entries are valid. You may proceed.")
             .color(Color::from_rgb(0.0, 1.0, 0.0))
             .size(16)
@@ -256,48 +230,16 @@ impl SignupPage {
         .size(20);
 
         
-        let sign_up_button=if all_valid 
-        {   
-            let user_id = create_user_id(&self.birth_date);
-            let hashed_password = match hash_password(&self.password){
-                Ok(s)=>s,
-                Err(e)=>"error occured in hashing".to_string(),
-            };
-             
-    // Encryption key and IV
-            const KEY: [u8; 16] = *b"mysecretkey12345";
-            const IV: [u8; 16] = *b"uniqueiv12345678";
-
-    //Encrypt passport number and birth date
-            let encrypted_passport:String= match encrypt(self.passport.as_bytes(), &KEY, &IV){
-                Ok(encrypted)=>{encrypted.encrypted_string}
-                Err(e)=>{"a".to_string()}
-            };
-            let encrypted_birth_date:String= match encrypt(self.birth_date.as_bytes(), &KEY, &IV){
-                Ok(encrypted)=>{encrypted.encrypted_string}
-                Err(e)=>{"a".to_string()}
-            };
-            
-            conn.execute("INSERT INTO user_information (id,email,name,encrypted_passport,encrypted_birthdate,hashed_password,balance) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)", params![user_id.clone(), self.email.clone(), self.name.clone(),encrypted_passport.clone(),encrypted_birth_date.clone(),hashed_password.clone(),500],
-            ).unwrap();
-            conn.execute("INSERT INTO transaction_history (sender_id, amount, receiver_id) VALUES (?1, ?2, ?3)", params!["New User's Privilege", 500, user_id.clone()]).unwrap();
-            
-            set_user_id(user_id.clone());
-            // When all fields are valid, the button activates and sends the Message::GoToFunction
-             Button::new(&mut self.signup_button, Text::new("Sign Up"))
-                .on_press(Message::GoToFunction)
-                .padding(12)
-                .style(SignUpButtonStyle)
-
-           
-        } else {
-            
-            // When fields are not valid, the button is still rendered but does nothing
-            Button::new(&mut self.signup_button, Text::new("Sign Up"))
-                .on_press(Message::GoToSignup)
-                .padding(12)
-                .style(SignUpButtonStyle)
-        };
+        // The button only does something once every field is valid
+        let mut sign_up_button = Button::new(
+            &mut self.signup_button,
+            Text::new(if self.submitting { "Signing up..." } else { "Sign Up" }),
+        )
+            .padding(12)
+            .style(SignUpButtonStyle);
+        if all_valid && !self.submitting {
+            sign_up_button = sign_up_button.on_press(Message::SubmitSignup);
+        }
 
         let back_button = Button::new(&mut self.back_button, Text::new("Back"))
         .padding(3)
@@ -329,8 +271,7 @@ impl SignupPage {
             .height(Length::Fill)
             .center_x()
             .center_y()
-            .into()}
-        
+            .into()
     }
 }
 
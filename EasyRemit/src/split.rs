@@ -0,0 +1,300 @@
+use iced::{button, Button, Column, Container, Element, Text};
+use iced::{text_input, scrollable, Alignment, Length, Row, Scrollable, TextInput, Background, Color};
+use crate::Message;
+use iced::Command;
+use crate::bill_split::{self, SplitMethod, SplitSummary};
+use crate::model::Account;
+use crate::storage::StorageError;
+use crate::tasks::{loading_text, parse_amount, status_text, with_user_storage, Loadable};
+
+// Recent payees to pick from and the user's own splits
+#[derive(Debug, Clone)]
+pub struct SplitData {
+    payees: Vec<Account>,
+    splits: Vec<SplitSummary>,
+}
+
+#[derive(Debug, Clone)]
+pub struct SplitPage {
+    total: String,
+    total_input: text_input::State,
+    memo: String,
+    memo_input: text_input::State,
+    participants: String,
+    participants_input: text_input::State,
+    method: SplitMethod,
+    method_buttons: [button::State; 3],
+    payee_buttons: Vec<button::State>,
+    create_button: button::State,
+    back_button: button::State,
+    scrollable_state: scrollable::State,
+    status: Option<Loadable<String>>,
+    data: Loadable<SplitData>,
+}
+
+impl SplitPage {
+    pub fn new() -> (Self, Command<Message>) {
+        let page = SplitPage {
+            total: String::new(),
+            total_input: text_input::State::new(),
+            memo: String::new(),
+            memo_input: text_input::State::new(),
+            participants: String::new(),
+            participants_input: text_input::State::new(),
+            method: SplitMethod::Equal,
+            method_buttons: Default::default(),
+            payee_buttons: Vec::new(),
+            create_button: button::State::new(),
+            back_button: button::State::new(),
+            scrollable_state: scrollable::State::new(),
+            status: None,
+            data: Loadable::Loading,
+        };
+        (page, SplitPage::load())
+    }
+
+    fn load() -> Command<Message> {
+        Command::perform(
+            with_user_storage(|storage, user_id| {
+                Ok::<_, StorageError>(SplitData {
+                    payees: bill_split::recent_payees(&storage.history(user_id)?, 3),
+                    splits: storage.bill_splits(user_id)?,
+                })
+            }),
+            Message::SplitLoaded,
+        )
+    }
+
+    pub fn update(&mut self, message: Message) -> Command<Message> {
+        match message {
+            Message::SplitTotalChanged(value) if value.chars().all(|c| c.is_ascii_digit()) => {
+                self.total = value;
+            }
+            Message::SplitMemoChanged(value) => {
+                self.memo = value;
+            }
+            Message::SplitParticipantsChanged(value) => {
+                self.participants = value;
+            }
+            Message::SplitMethodSelected(method) => {
+                self.method = method;
+            }
+            Message::SplitAddParticipant(id) if !self.has_participant(&id) => {
+                if !self.participants.trim().is_empty() {
+                    self.participants.push_str(", ");
+                }
+                self.participants.push_str(&id);
+            }
+            Message::SplitLoaded(result) => {
+                self.data = Loadable::from_result(result);
+            }
+            Message::SplitCreated(result) => {
+                self.status = Some(match result {
+                    Ok(id) => {
+                        self.total.clear();
+                        self.memo.clear();
+                        self.participants.clear();
+                        Loadable::Loaded(format!("Split #{} created and requests sent", id))
+                    }
+                    Err(e) => Loadable::Failed(e),
+                });
+                // The new split shows up in the list
+                return SplitPage::load();
+            }
+            _ => {}
+        }
+        Command::none()
+    }
+
+    fn has_participant(&self, id: &str) -> bool {
+        self.participants
+            .split(',')
+            .any(|entry| entry.split(':').next().unwrap_or("").trim() == id)
+    }
+
+    pub fn submit(&mut self) -> Command<Message> {
+        let total = match parse_amount(&self.total) {
+            Ok(total) => total,
+            Err(e) => {
+                self.status = Some(Loadable::Failed(e));
+                return Command::none();
+            }
+        };
+        let participants = match bill_split::parse_participants(&self.participants) {
+            Ok(participants) => participants,
+            Err(e) => {
+                self.status = Some(Loadable::Failed(e.to_string()));
+                return Command::none();
+            }
+        };
+        let (memo, method) = (self.memo.clone(), self.method);
+
+        self.status = Some(Loadable::Loading);
+        Command::perform(
+            with_user_storage(move |storage, user_id| storage.split_bill(user_id, total, &memo, method, &participants)),
+            Message::SplitCreated,
+        )
+    }
+
+    pub fn view(&mut self) -> Element<'_, super::Message> {
+        let (payees, splits) = match self.data.loaded() {
+            Some(data) => (data.payees.clone(), data.splits.clone()),
+            None => (Vec::new(), Vec::new()),
+        };
+
+        let title = Text::new("Split a Bill").size(35);
+
+        let total_input = TextInput::new(&mut self.total_input, "Total: ", &self.total, Message::SplitTotalChanged)
+            .padding(10)
+            .size(20);
+        let memo_input = TextInput::new(&mut self.memo_input, "What is it for? ", &self.memo, Message::SplitMemoChanged)
+            .padding(10)
+            .size(20);
+        let participants_input = TextInput::new(
+            &mut self.participants_input,
+            "IDs, comma separated (id:value for % or custom)",
+            &self.participants,
+            Message::SplitParticipantsChanged,
+        )
+        .padding(10)
+        .size(16);
+
+        let [equal_state, percentage_state, custom_state] = &mut self.method_buttons;
+        let method_row = Row::new()
+            .spacing(10)
+            .push(method_button(equal_state, "Equal", SplitMethod::Equal, self.method))
+            .push(method_button(percentage_state, "Percent", SplitMethod::Percentage, self.method))
+            .push(method_button(custom_state, "Custom", SplitMethod::Custom, self.method));
+
+        self.payee_buttons.resize_with(payees.len(), Default::default);
+        let mut payee_row = Row::new().spacing(8).push(Text::new("Recent:").size(14));
+        for (payee, state) in payees.into_iter().zip(self.payee_buttons.iter_mut()) {
+            payee_row = payee_row.push(
+                Button::new(state, Text::new(payee.name).size(14))
+                    .padding(5)
+                    .style(OptionButtonStyle { selected: false })
+                    .on_press(Message::SplitAddParticipant(payee.id.to_string())),
+            );
+        }
+
+        let status_text = status_text(&self.status, 16);
+
+        let create_button = Button::new(&mut self.create_button, Text::new("SEND REQUESTS"))
+            .padding(12)
+            .style(OptionButtonStyle { selected: true })
+            .on_press(Message::SubmitSplit);
+
+        let mut splits_column = Column::new().spacing(10);
+        for split in &splits {
+            splits_column = splits_column.push(split_summary(split));
+        }
+        let splits_list = Scrollable::new(&mut self.scrollable_state)
+            .padding(8)
+            .width(Length::Fill)
+            .height(Length::Units(170))
+            .push(splits_column);
+
+        let back_button = Button::new(&mut self.back_button, Text::new("Back"))
+            .padding(3)
+            .style(BackButtonStyle)
+            .on_press(Message::GoToFunction);
+
+        let content = Column::new()
+            .spacing(10)
+            .padding(20)
+            .align_items(Alignment::Center)
+            .push(title)
+            .push(total_input)
+            .push(memo_input)
+            .push(participants_input)
+            .push(payee_row)
+            .push(method_row)
+            .push(status_text)
+            .push(create_button)
+            .push(Text::new("Your splits").size(20))
+            .push(loading_text(&self.data, "your splits"))
+            .push(splits_list)
+            .push(back_button);
+
+        Container::new(content)
+            .width(Length::Fill)
+            .height(Length::Fill)
+            .center_x()
+            .center_y()
+            .into()
+    }
+}
+
+fn method_button<'a>(state: &'a mut button::State, label: &str, method: SplitMethod, selected: SplitMethod) -> Button<'a, Message> {
+    Button::new(state, Text::new(label).size(16))
+        .padding(8)
+        .style(OptionButtonStyle { selected: method == selected })
+        .on_press(Message::SplitMethodSelected(method))
+}
+
+fn split_summary<'a>(split: &SplitSummary) -> Column<'a, Message> {
+    let heading = if split.memo.is_empty() {
+        format!("#{}  ${}", split.id, split.total)
+    } else {
+        format!("#{} {}  ${}", split.id, split.memo, split.total)
+    };
+    let progress = if split.settled {
+        "Settled".to_string()
+    } else {
+        format!("${} of ${} collected", split.collected(), split.total)
+    };
+
+    let mut column = Column::new()
+        .spacing(2)
+        .push(Text::new(heading).size(16).color([0.1, 0.1, 0.1]))
+        .push(
+            Text::new(progress)
+                .size(14)
+                .color(if split.settled { [0.2, 0.6, 0.2] } else { [0.5, 0.5, 0.5] }),
+        );
+    for share in &split.shares {
+        column = column.push(
+            Text::new(format!("   {}  ${}  {}", share.participant_name, share.amount, share.status))
+                .size(13)
+                .color([0.4, 0.4, 0.4]),
+        );
+    }
+    column
+}
+
+struct OptionButtonStyle {
+    selected: bool,
+}
+impl iced::button::StyleSheet for OptionButtonStyle {
+    fn active(&self) -> iced::button::Style {
+        if self.selected {
+            iced::button::Style {
+                background: Some(Background::Color(Color::from_rgb(0.1, 0.3, 0.6))),
+                border_radius: 10.0,
+                text_color: Color::WHITE,
+                ..iced::button::Style::default()
+            }
+        } else {
+            iced::button::Style {
+                background: Some(Background::Color(Color::WHITE)),
+                border_radius: 10.0,
+                border_color: Color::from_rgb(0.1, 0.3, 0.6),
+                border_width: 1.0,
+                text_color: Color::BLACK,
+                ..iced::button::Style::default()
+            }
+        }
+    }
+}
+
+struct BackButtonStyle;
+impl iced::button::StyleSheet for BackButtonStyle {
+    fn active(&self) -> iced::button::Style {
+        iced::button::Style {
+            background: Some(Background::Color(Color::WHITE)),
+            border_radius: 10.0,
+            text_color: Color::BLACK,
+            ..iced::button::Style::default()
+        }
+    }
+}
@@ -0,0 +1,377 @@
+use once_cell::sync::OnceCell;
+use std::fmt;
+use crate::auth::{AuthError, NewAccount};
+use crate::bill_split::{Participant, SplitError, SplitMethod, SplitSummary};
+use crate::db;
+use crate::fees::FeeQuote;
+use crate::idempotency::StoredResponse;
+use crate::ledger::{Receipt, TransferError};
+use crate::limits::Allowance;
+use crate::model::{AccountId, Email, Transaction, User};
+use crate::notifications::Notification;
+use crate::payment_requests::{PaymentRequest, RequestError};
+
+mod memory;
+mod sqlite;
+
+pub use memory::MemoryStorage;
+pub use sqlite::SqliteStorage;
+
+// Users, their accounts, the transactions between them, payment requests and
+// bill splits, notifications and login sessions. The app's pages, the CLI and the API go
+// through this rather than SQL, so they work the same on any implementation:
+//   SqliteStorage - the real thing, on a pool, with every check in ledger.rs
+//   MemoryStorage - plain maps, for tests that should not touch bank.db
+pub trait Storage: Send + Sync {
+    // users
+
+    // Creates the account with its welcome bonus and returns the new user ID
+    fn register(&self, account: &NewAccount) -> Result<AccountId, StorageError>;
+    // Checks credentials, locking the account after too many failures
+    fn login(&self, user_id: &AccountId, email: &Email, password: &str) -> Result<(), StorageError>;
+    // None if there is no such user
+    fn profile(&self, user_id: &AccountId) -> Result<Option<User>, StorageError>;
+
+    // accounts
+
+    // 0 for an unknown user
+    fn balance(&self, user_id: &AccountId) -> Result<i64, StorageError>;
+    // What the user may still send under their transfer limits, if limits
+    // apply at all
+    fn allowance(&self, user_id: &AccountId) -> Result<Option<Allowance>, StorageError>;
+
+    // transactions
+
+    // The fee `amount` would be charged at, whether or not it may be sent
+    fn fee_quote(&self, sender_id: &AccountId, receiver_id: &AccountId, amount: i64) -> Result<FeeQuote, StorageError>;
+    // Every check a transfer has to pass, without moving any money
+    fn check_transfer(&self, sender_id: &AccountId, receiver_id: &AccountId, amount: i64) -> Result<FeeQuote, StorageError>;
+    // Checks and posts the transfer in one transaction
+    fn transfer(&self, sender_id: &AccountId, receiver_id: &AccountId, amount: i64) -> Result<Receipt, StorageError>;
+    // Checks and posts the transfer unless the sender already used `key`, in
+    // which case the response stored for it comes back instead. `respond`
+    // makes the response to store for a new transfer, which is saved in the
+    // same transaction as the transfer.
+    fn transfer_once(
+        &self,
+        sender_id: &AccountId,
+        receiver_id: &AccountId,
+        amount: i64,
+        key: &str,
+        request_hash: &str,
+        respond: &dyn Fn(&Receipt) -> (u16, String),
+    ) -> Result<Idempotent, StorageError>;
+    // Oldest first, as in ledger::history
+    fn history(&self, user_id: &AccountId) -> Result<Vec<Transaction>, StorageError>;
+
+    // payment requests
+
+    // Asks `payer_id` for money and notifies them; returns the request's ID
+    fn request_payment(&self, requester_id: &AccountId, payer_id: &AccountId, amount: i64, memo: &str) -> Result<i64, StorageError>;
+    // Requests waiting for the payer, oldest first, expired ones left out
+    fn pending_requests(&self, payer_id: &AccountId) -> Result<Vec<PaymentRequest>, StorageError>;
+    // Pays a pending request with a transfer that commits with the change
+    // of the request's status
+    fn pay_request(&self, request_id: i64, payer_id: &AccountId) -> Result<Receipt, StorageError>;
+    fn decline_request(&self, request_id: i64, payer_id: &AccountId) -> Result<(), StorageError>;
+    // Records the split and requests every share but the creator's, all or
+    // nothing; returns the split's ID
+    fn split_bill(
+        &self,
+        creator_id: &AccountId,
+        total: i64,
+        memo: &str,
+        method: SplitMethod,
+        participants: &[Participant],
+    ) -> Result<i64, StorageError>;
+    // The creator's splits, newest first
+    fn bill_splits(&self, creator_id: &AccountId) -> Result<Vec<SplitSummary>, StorageError>;
+
+    // notifications
+
+    // The user's most recent `limit`, newest first
+    fn notifications(&self, user_id: &AccountId, limit: usize) -> Result<Vec<Notification>, StorageError>;
+    fn unread_notifications(&self, user_id: &AccountId) -> Result<i64, StorageError>;
+    fn mark_read(&self, user_id: &AccountId, id: i64) -> Result<(), StorageError>;
+    fn mark_all_read(&self, user_id: &AccountId) -> Result<(), StorageError>;
+
+    // sessions
+
+    // A bearer token for a user who has just logged in
+    fn create_session(&self, user_id: &AccountId) -> Result<String, StorageError>;
+    // None if the token is unknown or has expired
+    fn session_user(&self, token: &str) -> Result<Option<AccountId>, StorageError>;
+    fn end_session(&self, token: &str) -> Result<(), StorageError>;
+}
+
+// What `Storage::transfer_once` did
+#[derive(Debug, Clone)]
+pub enum Idempotent {
+    Posted(StoredResponse),
+    // The key was used before, perhaps for a different request: compare
+    // `request_hash` before sending this back
+    Replayed(StoredResponse),
+}
+
+#[derive(Debug)]
+pub enum StorageError {
+    Auth(AuthError),
+    Transfer(TransferError),
+    Request(RequestError),
+    Split(SplitError),
+    // anything the backend itself reports
+    Database(String),
+}
+
+impl fmt::Display for StorageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StorageError::Auth(e) => write!(f, "{}", e),
+            StorageError::Transfer(e) => write!(f, "{}", e),
+            StorageError::Request(e) => write!(f, "{}", e),
+            StorageError::Split(e) => write!(f, "{}", e),
+            StorageError::Database(e) => write!(f, "Database error: {}", e),
+        }
+    }
+}
+
+impl From<AuthError> for StorageError {
+    fn from(e: AuthError) -> Self {
+        StorageError::Auth(e)
+    }
+}
+
+impl From<TransferError> for StorageError {
+    fn from(e: TransferError) -> Self {
+        StorageError::Transfer(e)
+    }
+}
+
+// A refused transfer is reported as one, whether or not it paid a request
+impl From<RequestError> for StorageError {
+    fn from(e: RequestError) -> Self {
+        match e {
+            RequestError::Transfer(e) => StorageError::Transfer(e),
+            RequestError::Database(e) => e.into(),
+            e => StorageError::Request(e),
+        }
+    }
+}
+
+impl From<SplitError> for StorageError {
+    fn from(e: SplitError) -> Self {
+        match e {
+            SplitError::Database(e) | SplitError::Request(_, RequestError::Database(e)) => e.into(),
+            e => StorageError::Split(e),
+        }
+    }
+}
+
+impl From<rusqlite::Error> for StorageError {
+    fn from(e: rusqlite::Error) -> Self {
+        StorageError::Database(e.to_string())
+    }
+}
+
+static STORAGE: OnceCell<Box<dyn Storage>> = OnceCell::new();
+
+// Opens the configured database the first time it is called
+pub fn init() -> Result<&'static dyn Storage, StorageError> {
+    let storage = STORAGE.get_or_try_init(|| -> Result<Box<dyn Storage>, StorageError> {
+        Ok(Box::new(SqliteStorage::new(db::init()?)))
+    })?;
+    Ok(&**storage)
+}
+
+// Like `init`, but prints the problem and exits, for use at the top of main
+pub fn init_or_exit() -> &'static dyn Storage {
+    init().unwrap_or_else(|e| {
+        eprintln!("Cannot open the database: {}", e);
+        std::process::exit(1);
+    })
+}
+
+// What the app and the tools store their data in: the configured database
+pub fn storage() -> &'static dyn Storage {
+    init_or_exit()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bill_split::parse_participants;
+    use crate::db;
+    use crate::ledger::TransferStatus;
+    use crate::model::{BirthDate, PassportNumber, TransactionKind};
+    use crate::notifications::NotificationKind;
+    use crate::pool::Pool;
+
+    fn register(storage: &dyn Storage, name: &str, email: &str, passport: &str, birth_date: &str, password: &str) -> AccountId {
+        storage
+            .register(&NewAccount {
+                email: &Email::parse(email).unwrap(),
+                name,
+                passport: &PassportNumber::parse(passport).unwrap(),
+                birth_date: &BirthDate::parse(birth_date).unwrap(),
+                password,
+            })
+            .unwrap()
+    }
+
+    // The same behaviour is expected of every implementation
+    fn check_storage(storage: &dyn Storage) {
+        let alice = register(storage, "Alice", "alice@example.com", "AB123456", "01/02/1990", "111111");
+        let bob = register(storage, "Bob", "bob@example.com", "CD654321", "03/04/1985", "222222");
+        let nobody = AccountId::parse("000000000000").unwrap();
+        let (alice_email, bob_email) = (Email::parse("alice@example.com").unwrap(), Email::parse("bob@example.com").unwrap());
+
+        let user = storage.profile(&alice).unwrap().unwrap();
+        assert_eq!(user.name.as_deref(), Some("Alice"));
+        assert_eq!(user.email, Some(alice_email.clone()));
+        assert_eq!(user.passport.as_ref().map(PassportNumber::as_str), Some("AB123456"));
+        assert_eq!(user.birth_date.as_ref().map(BirthDate::as_str), Some("01/02/1990"));
+        assert_eq!(storage.profile(&nobody).unwrap(), None);
+        let bonus = storage.balance(&alice).unwrap();
+        assert!(bonus > 0);
+        assert_eq!(storage.history(&alice).unwrap().len(), 1);
+
+        storage.login(&alice, &alice_email, "111111").unwrap();
+        assert!(matches!(
+            storage.login(&alice, &alice_email, "000000"),
+            Err(StorageError::Auth(AuthError::InvalidCredentials))
+        ));
+        assert!(matches!(
+            storage.login(&bob, &alice_email, "222222"),
+            Err(StorageError::Auth(AuthError::InvalidCredentials))
+        ));
+
+        for (receiver, amount) in [(&bob, 0), (&alice, 10), (&nobody, 10), (&bob, bonus * 2)] {
+            let error = storage.check_transfer(&alice, receiver, amount).unwrap_err();
+            assert!(matches!(error, StorageError::Transfer(_)), "{}", error);
+            assert!(storage.transfer(&alice, receiver, amount).is_err());
+        }
+        let quote = storage.check_transfer(&alice, &bob, 50).unwrap();
+        let receipt = storage.transfer(&alice, &bob, 50).unwrap();
+        assert_eq!(receipt.quote, quote);
+        assert_eq!(receipt.receiver.name, "Bob");
+        assert_eq!(storage.balance(&alice).unwrap(), bonus - quote.total());
+        assert_eq!(storage.balance(&bob).unwrap(), bonus + 50);
+        let sent = storage.history(&alice).unwrap();
+        let sent = sent.iter().find(|entry| entry.id == receipt.transaction_id).unwrap();
+        assert!(sent.outgoing);
+        assert_eq!((sent.kind, sent.status), (TransactionKind::Transfer, TransferStatus::Completed));
+        assert_eq!((&sent.counterparty.id, sent.amount), (&bob, 50));
+
+        let token = storage.create_session(&alice).unwrap();
+        assert_eq!(storage.session_user(&token).unwrap(), Some(alice.clone()));
+        assert_eq!(storage.session_user("not a token").unwrap(), None);
+        storage.end_session(&token).unwrap();
+        assert_eq!(storage.session_user(&token).unwrap(), None);
+
+        // the fifth failure in a row locks the account, even for the right
+        // password
+        for _ in 0..4 {
+            storage.login(&bob, &bob_email, "000000").unwrap_err();
+        }
+        assert!(matches!(
+            storage.login(&bob, &bob_email, "222222"),
+            Err(StorageError::Auth(AuthError::LockedOut { .. }))
+        ));
+    }
+
+    // Payment requests, bill splits, notifications and idempotent transfers
+    fn check_requests(storage: &dyn Storage) {
+        let carol = register(storage, "Carol", "carol@example.com", "EF111222", "05/06/1992", "333333");
+        let dave = register(storage, "Dave", "dave@example.com", "GH333444", "07/08/1988", "444444");
+        let nobody = AccountId::parse("000000000000").unwrap();
+        let bonus = storage.balance(&dave).unwrap();
+        let pending = |payer| storage.pending_requests(payer).unwrap();
+
+        for (payer, amount) in [(&dave, 0), (&carol, 20), (&nobody, 20)] {
+            let error = storage.request_payment(&carol, payer, amount, "").unwrap_err();
+            assert!(matches!(error, StorageError::Request(_)), "{}", error);
+        }
+        let lunch = storage.request_payment(&carol, &dave, 20, " lunch ").unwrap();
+        let requests = pending(&dave);
+        assert_eq!(requests.len(), 1);
+        assert_eq!((requests[0].id, requests[0].amount, requests[0].memo.as_str()), (lunch, 20, "lunch"));
+        assert_eq!(requests[0].requester_name, "Carol");
+        let inbox = storage.notifications(&dave, 10).unwrap();
+        assert_eq!(inbox[0].kind, NotificationKind::RequestReceived);
+        assert_eq!(inbox[0].message, "Carol requested $20 for lunch");
+
+        // only the payer can act on a request, and only once
+        assert!(matches!(storage.pay_request(lunch, &carol), Err(StorageError::Request(RequestError::NotFound))));
+        let receipt = storage.pay_request(lunch, &dave).unwrap();
+        assert_eq!(receipt.receiver.name, "Carol");
+        assert_eq!(storage.balance(&dave).unwrap(), bonus - receipt.quote.total());
+        assert!(pending(&dave).is_empty());
+        assert!(matches!(storage.pay_request(lunch, &dave), Err(StorageError::Request(RequestError::NotPending(_)))));
+        assert_eq!(storage.notifications(&carol, 1).unwrap()[0].kind, NotificationKind::MoneyReceived);
+
+        // a payment that cannot be made leaves the request pending
+        let car = storage.request_payment(&carol, &dave, bonus * 10, "car").unwrap();
+        assert!(matches!(storage.pay_request(car, &dave), Err(StorageError::Transfer(_))));
+        assert_eq!(pending(&dave).len(), 1);
+        storage.decline_request(car, &dave).unwrap();
+        assert!(pending(&dave).is_empty());
+        assert!(matches!(storage.decline_request(car, &dave), Err(StorageError::Request(RequestError::NotPending(_)))));
+
+        let unread = storage.unread_notifications(&dave).unwrap();
+        assert!(unread >= 2);
+        let newest = storage.notifications(&dave, 1).unwrap()[0].id;
+        storage.mark_read(&carol, newest).unwrap();
+        assert_eq!(storage.unread_notifications(&dave).unwrap(), unread);
+        storage.mark_read(&dave, newest).unwrap();
+        assert_eq!(storage.unread_notifications(&dave).unwrap(), unread - 1);
+        assert!(storage.notifications(&dave, 1).unwrap()[0].read);
+        storage.mark_all_read(&dave).unwrap();
+        assert_eq!(storage.unread_notifications(&dave).unwrap(), 0);
+
+        // a split requests every share but the creator's, or nothing at all
+        let everyone = parse_participants(&format!("{}, {}", carol, dave)).unwrap();
+        let split = storage.split_bill(&carol, 30, "taxi", SplitMethod::Equal, &everyone).unwrap();
+        let bad = parse_participants(&format!("{}, {}", dave, nobody)).unwrap();
+        let error = storage.split_bill(&carol, 30, "", SplitMethod::Equal, &bad).unwrap_err();
+        assert!(matches!(error, StorageError::Split(SplitError::Request(..))), "{}", error);
+        let requests = pending(&dave);
+        assert_eq!(requests.len(), 1);
+        assert_eq!((requests[0].amount, requests[0].memo.as_str()), (15, format!("taxi (split #{})", split).as_str()));
+        let splits = storage.bill_splits(&carol).unwrap();
+        assert_eq!(splits.len(), 1);
+        let statuses: Vec<&str> = splits[0].shares.iter().map(|share| share.status.as_str()).collect();
+        assert_eq!((splits[0].id, statuses, splits[0].settled), (split, vec!["yours", "pending"], false));
+        storage.pay_request(requests[0].id, &dave).unwrap();
+        let splits = storage.bill_splits(&carol).unwrap();
+        assert!(splits[0].settled);
+        assert_eq!(splits[0].collected(), 15);
+
+        // a key posts once per sender; a refused transfer does not use it up
+        let respond = |receipt: &Receipt| (201, receipt.transaction_id.to_string());
+        let balance = storage.balance(&dave).unwrap();
+        let Idempotent::Posted(first) = storage.transfer_once(&dave, &carol, 10, "k1", "h1", &respond).unwrap() else {
+            panic!("a new key was replayed");
+        };
+        let Idempotent::Replayed(again) = storage.transfer_once(&dave, &carol, 10, "k1", "h1", &respond).unwrap() else {
+            panic!("a used key was posted again");
+        };
+        assert_eq!((again.status, again.body.as_str(), again.request_hash.as_str()), (201, first.body.as_str(), "h1"));
+        assert!(storage.balance(&dave).unwrap() < balance && storage.balance(&dave).unwrap() > balance - 20);
+        assert!(matches!(storage.transfer_once(&carol, &dave, 10, "k1", "h1", &respond), Ok(Idempotent::Posted(_))));
+        assert!(storage.transfer_once(&dave, &carol, bonus * 10, "k2", "h2", &respond).is_err());
+        assert!(matches!(storage.transfer_once(&dave, &carol, 10, "k2", "h3", &respond), Ok(Idempotent::Posted(_))));
+    }
+
+    #[test]
+    fn memory_storage() {
+        check_storage(&MemoryStorage::new());
+        check_requests(&MemoryStorage::new());
+    }
+
+    #[test]
+    fn sqlite_storage() {
+        let pool = Pool::single(db::open(":memory:").unwrap());
+        check_storage(&SqliteStorage::new(&pool));
+        check_requests(&SqliteStorage::new(&pool));
+    }
+}
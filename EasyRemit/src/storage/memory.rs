@@ -0,0 +1,517 @@
+use std::collections::HashMap;
+use std::sync::{Mutex, MutexGuard, PoisonError};
+use std::time::{SystemTime, UNIX_EPOCH};
+use crate::auth::{self, AuthError, NewAccount};
+use crate::bill_split::{self, Participant, ShareStatus, SplitError, SplitMethod, SplitSummary};
+use crate::config;
+use crate::fees::FeeQuote;
+use crate::idempotency::{StoredResponse, KEY_TTL_SECS};
+use crate::ledger::{Receipt, TransferError, TransferStatus};
+use crate::limits::Allowance;
+use crate::model::{Account, AccountId, BirthDate, Email, PassportNumber, Transaction, TransactionId, TransactionKind, User};
+use crate::notifications::{Notification, NotificationKind};
+use crate::payment_requests::{self, PaymentRequest, RequestError, REQUEST_TTL_SECS};
+use crate::sessions::{self, SESSION_TTL_SECS};
+use super::{Idempotent, Storage, StorageError};
+
+// Storage in plain maps that lives as long as the value does. Credentials,
+// lockouts, the welcome bonus, balance checks, payment requests, bill splits,
+// notifications and idempotency keys behave as in SQLite, but there are no
+// fees, limits, screening or audit log.
+#[derive(Default)]
+pub struct MemoryStorage {
+    state: Mutex<State>,
+}
+
+#[derive(Default)]
+struct State {
+    users: HashMap<AccountId, UserRecord>,
+    // in the order they were posted; the index is the transaction ID - 1
+    transactions: Vec<Posted>,
+    // token -> (user ID, expiry as Unix time)
+    sessions: HashMap<String, (AccountId, i64)>,
+    // the index is the request ID - 1
+    requests: Vec<Request>,
+    // the index is the split ID - 1
+    splits: Vec<Split>,
+    // the index is the notification ID - 1
+    notifications: Vec<Notice>,
+    // (sender, key) -> (response, when the key was used)
+    idempotency_keys: HashMap<(AccountId, String), (StoredResponse, i64)>,
+}
+
+struct UserRecord {
+    email: Email,
+    name: String,
+    encrypted_passport: String,
+    encrypted_birth_date: String,
+    hashed_password: String,
+    balance: i64,
+    failed_logins: i64,
+    locked_until: Option<i64>,
+}
+
+struct Request {
+    requester_id: AccountId,
+    payer_id: AccountId,
+    amount: i64,
+    memo: String,
+    status: &'static str,
+    expires_at: i64,
+}
+
+struct Split {
+    creator_id: AccountId,
+    total: i64,
+    memo: String,
+    // (participant ID, share, the request for it)
+    shares: Vec<(String, i64, Option<i64>)>,
+}
+
+struct Notice {
+    user_id: AccountId,
+    kind: NotificationKind,
+    message: String,
+    created_at: i64,
+    read: bool,
+}
+
+struct Posted {
+    sender_id: String,
+    receiver_id: String,
+    amount: i64,
+    kind: TransactionKind,
+    created_at: i64,
+}
+
+impl MemoryStorage {
+    pub fn new() -> Self {
+        MemoryStorage::default()
+    }
+
+    fn state(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl State {
+    fn name_of(&self, id: &str) -> String {
+        self.users.get(id).map_or_else(|| id.to_string(), |user| user.name.clone())
+    }
+
+    fn account(&self, id: &str) -> Account {
+        Account { id: AccountId::stored(id.to_string()), name: self.name_of(id) }
+    }
+
+    fn validate(&self, sender_id: &AccountId, receiver_id: &AccountId, amount: i64) -> Result<FeeQuote, TransferError> {
+        if amount <= 0 {
+            return Err(TransferError::InvalidAmount);
+        }
+        if sender_id == receiver_id {
+            return Err(TransferError::SameAccount);
+        }
+        if !self.users.contains_key(receiver_id) {
+            return Err(TransferError::UnknownRecipient);
+        }
+        let quote = free_quote(amount);
+        let balance = self.users.get(sender_id).map_or(0, |user| user.balance);
+        if balance < quote.total() {
+            return Err(TransferError::InsufficientFunds { balance, required: quote.total() });
+        }
+        Ok(quote)
+    }
+
+    fn post(&mut self, sender_id: &str, receiver_id: &str, amount: i64, kind: TransactionKind) -> TransactionId {
+        self.transactions.push(Posted {
+            sender_id: sender_id.to_string(),
+            receiver_id: receiver_id.to_string(),
+            amount,
+            kind,
+            created_at: now(),
+        });
+        TransactionId::assigned(self.transactions.len() as i64)
+    }
+
+    fn transfer(&mut self, sender_id: &AccountId, receiver_id: &AccountId, amount: i64) -> Result<Receipt, TransferError> {
+        let quote = self.validate(sender_id, receiver_id, amount)?;
+        let transaction_id = self.post(sender_id.as_str(), receiver_id.as_str(), amount, TransactionKind::Transfer);
+        for (id, delta) in [(sender_id, -quote.total()), (receiver_id, amount)] {
+            if let Some(user) = self.users.get_mut(id) {
+                user.balance += delta;
+            }
+        }
+        let receipt = Receipt {
+            transaction_id,
+            status: TransferStatus::Completed,
+            sender: self.account(sender_id.as_str()),
+            receiver: self.account(receiver_id.as_str()),
+            quote,
+        };
+        self.notify(receiver_id, NotificationKind::MoneyReceived, format!("{} sent you ${}", receipt.sender.name, amount));
+        Ok(receipt)
+    }
+
+    // payment_requests::create
+    fn request(&mut self, requester_id: &AccountId, payer_id: &AccountId, amount: i64, memo: &str) -> Result<i64, RequestError> {
+        self.check_request(requester_id, payer_id, amount)?;
+        self.requests.push(Request {
+            requester_id: requester_id.clone(),
+            payer_id: payer_id.clone(),
+            amount,
+            memo: memo.trim().to_string(),
+            status: "pending",
+            expires_at: now() + REQUEST_TTL_SECS,
+        });
+        let message = payment_requests::request_message(&self.name_of(requester_id.as_str()), amount, memo);
+        self.notify(payer_id, NotificationKind::RequestReceived, message);
+        Ok(self.requests.len() as i64)
+    }
+
+    fn check_request(&self, requester_id: &AccountId, payer_id: &AccountId, amount: i64) -> Result<(), RequestError> {
+        if amount <= 0 {
+            return Err(RequestError::InvalidAmount);
+        }
+        if requester_id == payer_id {
+            return Err(RequestError::SameAccount);
+        }
+        if !self.users.contains_key(payer_id) {
+            return Err(RequestError::UnknownPayer);
+        }
+        Ok(())
+    }
+
+    // A pending request past its expiry reads as expired, as after
+    // payment_requests::expire_stale
+    fn request_status(&self, id: i64) -> &'static str {
+        match &self.requests[id as usize - 1] {
+            request if request.status == "pending" && request.expires_at < now() => "expired",
+            request => request.status,
+        }
+    }
+
+    fn notify(&mut self, user_id: &AccountId, kind: NotificationKind, message: String) {
+        self.notifications.push(Notice { user_id: user_id.clone(), kind, message, created_at: now(), read: false });
+    }
+
+    // payment_requests::pending_request
+    fn pending_request(&mut self, id: i64, payer_id: &AccountId) -> Result<&mut Request, RequestError> {
+        let now = now();
+        let request = usize::try_from(id - 1)
+            .ok()
+            .and_then(|index| self.requests.get_mut(index))
+            .filter(|request| request.payer_id == *payer_id)
+            .ok_or(RequestError::NotFound)?;
+        if request.status != "pending" {
+            return Err(RequestError::NotPending(request.status.to_string()));
+        }
+        if request.expires_at < now {
+            return Err(RequestError::Expired);
+        }
+        Ok(request)
+    }
+}
+
+impl Storage for MemoryStorage {
+    fn register(&self, account: &NewAccount) -> Result<AccountId, StorageError> {
+        let hashed_password = auth::hash_password(account.password)?;
+        let encrypted_passport = auth::encrypt(account.passport.as_str())?;
+        let encrypted_birth_date = auth::encrypt(account.birth_date.as_str())?;
+        let bonus = config::get().accounts.signup_bonus;
+
+        let mut state = self.state();
+        let user_id = loop {
+            let id = AccountId::random(account.birth_date);
+            if !state.users.contains_key(&id) {
+                break id;
+            }
+        };
+        state.users.insert(
+            user_id.clone(),
+            UserRecord {
+                email: account.email.clone(),
+                name: account.name.to_string(),
+                encrypted_passport,
+                encrypted_birth_date,
+                hashed_password,
+                balance: bonus,
+                failed_logins: 0,
+                locked_until: None,
+            },
+        );
+        state.post(auth::BONUS_ACCOUNT, user_id.as_str(), bonus, TransactionKind::Transfer);
+        Ok(user_id)
+    }
+
+    fn login(&self, user_id: &AccountId, email: &Email, password: &str) -> Result<(), StorageError> {
+        let mut state = self.state();
+        let Some(user) = state.users.get_mut(user_id) else {
+            return Err(AuthError::InvalidCredentials.into());
+        };
+        let now = now();
+        if let Some(remaining) = user.locked_until.map(|until| until - now).filter(|secs| *secs > 0) {
+            return Err(AuthError::LockedOut { minutes: (remaining + 59) / 60 }.into());
+        }
+        if user.email != *email || !auth::verify_password(password, &user.hashed_password) {
+            user.failed_logins += 1;
+            if user.failed_logins >= auth::MAX_FAILED_LOGINS {
+                user.locked_until = Some(now + auth::LOCKOUT_SECS);
+            }
+            return Err(AuthError::InvalidCredentials.into());
+        }
+        user.failed_logins = 0;
+        user.locked_until = None;
+        state.notify(user_id, NotificationKind::NewLogin, auth::NEW_LOGIN_MESSAGE.to_string());
+        Ok(())
+    }
+
+    fn profile(&self, user_id: &AccountId) -> Result<Option<User>, StorageError> {
+        Ok(self.state().users.get(user_id).map(|user| User {
+            id: user_id.clone(),
+            name: Some(user.name.clone()),
+            email: Some(user.email.clone()),
+            balance: user.balance,
+            currency: "USD".to_string(),
+            passport: auth::decrypt(&user.encrypted_passport).and_then(|data| PassportNumber::parse(&data).ok()),
+            birth_date: auth::decrypt(&user.encrypted_birth_date).and_then(|data| BirthDate::parse(&data).ok()),
+        }))
+    }
+
+    fn balance(&self, user_id: &AccountId) -> Result<i64, StorageError> {
+        Ok(self.state().users.get(user_id).map_or(0, |user| user.balance))
+    }
+
+    fn allowance(&self, _user_id: &AccountId) -> Result<Option<Allowance>, StorageError> {
+        Ok(None)
+    }
+
+    fn fee_quote(&self, _sender_id: &AccountId, _receiver_id: &AccountId, amount: i64) -> Result<FeeQuote, StorageError> {
+        Ok(free_quote(amount))
+    }
+
+    fn check_transfer(&self, sender_id: &AccountId, receiver_id: &AccountId, amount: i64) -> Result<FeeQuote, StorageError> {
+        Ok(self.state().validate(sender_id, receiver_id, amount)?)
+    }
+
+    fn transfer(&self, sender_id: &AccountId, receiver_id: &AccountId, amount: i64) -> Result<Receipt, StorageError> {
+        Ok(self.state().transfer(sender_id, receiver_id, amount)?)
+    }
+
+    fn transfer_once(
+        &self,
+        sender_id: &AccountId,
+        receiver_id: &AccountId,
+        amount: i64,
+        key: &str,
+        request_hash: &str,
+        respond: &dyn Fn(&Receipt) -> (u16, String),
+    ) -> Result<Idempotent, StorageError> {
+        let mut state = self.state();
+        let now = now();
+        let entry = (sender_id.clone(), key.to_string());
+        if let Some((stored, _)) = state.idempotency_keys.get(&entry).filter(|(_, used_at)| *used_at > now - KEY_TTL_SECS) {
+            return Ok(Idempotent::Replayed(stored.clone()));
+        }
+        let receipt = state.transfer(sender_id, receiver_id, amount)?;
+        let (status, body) = respond(&receipt);
+        let response = StoredResponse { request_hash: request_hash.to_string(), status, body };
+        state.idempotency_keys.insert(entry, (response.clone(), now));
+        Ok(Idempotent::Posted(response))
+    }
+
+    fn history(&self, user_id: &AccountId) -> Result<Vec<Transaction>, StorageError> {
+        let state = self.state();
+        let entries = state
+            .transactions
+            .iter()
+            .enumerate()
+            .filter(|(_, posted)| *user_id == *posted.sender_id || *user_id == *posted.receiver_id)
+            .map(|(index, posted)| {
+                let outgoing = *user_id == *posted.sender_id;
+                let counterparty_id = if outgoing { &posted.receiver_id } else { &posted.sender_id };
+                Transaction {
+                    id: TransactionId::assigned(index as i64 + 1),
+                    created_at: Some(posted.created_at),
+                    kind: posted.kind,
+                    outgoing,
+                    counterparty: state.account(counterparty_id),
+                    amount: posted.amount,
+                    status: TransferStatus::Completed,
+                    memo: None,
+                }
+            })
+            .collect();
+        Ok(entries)
+    }
+
+    fn request_payment(&self, requester_id: &AccountId, payer_id: &AccountId, amount: i64, memo: &str) -> Result<i64, StorageError> {
+        Ok(self.state().request(requester_id, payer_id, amount, memo)?)
+    }
+
+    fn pending_requests(&self, payer_id: &AccountId) -> Result<Vec<PaymentRequest>, StorageError> {
+        let state = self.state();
+        let now = now();
+        Ok(state
+            .requests
+            .iter()
+            .enumerate()
+            .filter(|(_, request)| request.payer_id == *payer_id && request.status == "pending" && request.expires_at >= now)
+            .map(|(index, request)| PaymentRequest {
+                id: index as i64 + 1,
+                requester_id: request.requester_id.to_string(),
+                requester_name: state.name_of(request.requester_id.as_str()),
+                payer_id: request.payer_id.to_string(),
+                amount: request.amount,
+                memo: request.memo.clone(),
+                expires_at: request.expires_at,
+            })
+            .collect())
+    }
+
+    fn pay_request(&self, request_id: i64, payer_id: &AccountId) -> Result<Receipt, StorageError> {
+        let mut state = self.state();
+        let request = state.pending_request(request_id, payer_id)?;
+        let (requester_id, amount) = (request.requester_id.clone(), request.amount);
+        let receipt = state.transfer(payer_id, &requester_id, amount)?;
+        state.pending_request(request_id, payer_id)?.status = "paid";
+        Ok(receipt)
+    }
+
+    fn decline_request(&self, request_id: i64, payer_id: &AccountId) -> Result<(), StorageError> {
+        self.state().pending_request(request_id, payer_id)?.status = "declined";
+        Ok(())
+    }
+
+    // bill_split::create_split. Every share is checked before any request is
+    // sent, so a bad participant leaves nothing behind.
+    fn split_bill(
+        &self,
+        creator_id: &AccountId,
+        total: i64,
+        memo: &str,
+        method: SplitMethod,
+        participants: &[Participant],
+    ) -> Result<i64, StorageError> {
+        let amounts = bill_split::shares(total, method, participants)?;
+        let mut state = self.state();
+        let mut requested = Vec::new();
+        for (participant, &amount) in participants.iter().zip(&amounts) {
+            if participant.id == creator_id.as_str() || amount == 0 {
+                continue;
+            }
+            let payer_id = AccountId::parse(&participant.id)
+                .map_err(|_| RequestError::UnknownPayer)
+                .and_then(|payer_id| state.check_request(creator_id, &payer_id, amount).map(|()| payer_id))
+                .map_err(|e| SplitError::Request(participant.id.clone(), e))?;
+            requested.push((payer_id, amount));
+        }
+
+        let split_id = state.splits.len() as i64 + 1;
+        let request_memo = bill_split::request_memo(split_id, memo);
+        let mut shares = Vec::new();
+        for (participant, amount) in participants.iter().zip(amounts) {
+            let request_id = match requested.iter().position(|(payer_id, _)| payer_id.as_str() == participant.id) {
+                Some(index) => Some(state.request(creator_id, &requested[index].0, amount, &request_memo)?),
+                None => None,
+            };
+            shares.push((participant.id.clone(), amount, request_id));
+        }
+        state.splits.push(Split { creator_id: creator_id.clone(), total, memo: memo.trim().to_string(), shares });
+        Ok(split_id)
+    }
+
+    fn bill_splits(&self, creator_id: &AccountId) -> Result<Vec<SplitSummary>, StorageError> {
+        let state = self.state();
+        Ok(state
+            .splits
+            .iter()
+            .enumerate()
+            .rev()
+            .filter(|(_, split)| split.creator_id == *creator_id)
+            .map(|(index, split)| {
+                let shares: Vec<ShareStatus> = split
+                    .shares
+                    .iter()
+                    .map(|(participant_id, amount, request_id)| ShareStatus {
+                        participant_name: state.name_of(participant_id),
+                        amount: *amount,
+                        status: request_id.map_or("yours", |id| state.request_status(id)).to_string(),
+                    })
+                    .collect();
+                // bill_split::settle_if_complete, which runs when a request is paid
+                let requested = || split.shares.iter().filter_map(|(_, _, request_id)| *request_id);
+                let settled = requested().next().is_some() && requested().all(|id| state.request_status(id) == "paid");
+                SplitSummary { id: index as i64 + 1, total: split.total, memo: split.memo.clone(), settled, shares }
+            })
+            .collect())
+    }
+
+    fn notifications(&self, user_id: &AccountId, limit: usize) -> Result<Vec<Notification>, StorageError> {
+        Ok(self
+            .state()
+            .notifications
+            .iter()
+            .enumerate()
+            .rev()
+            .filter(|(_, notice)| notice.user_id == *user_id)
+            .take(limit)
+            .map(|(index, notice)| Notification {
+                id: index as i64 + 1,
+                kind: notice.kind,
+                message: notice.message.clone(),
+                created_at: notice.created_at,
+                read: notice.read,
+            })
+            .collect())
+    }
+
+    fn unread_notifications(&self, user_id: &AccountId) -> Result<i64, StorageError> {
+        let state = self.state();
+        Ok(state.notifications.iter().filter(|notice| notice.user_id == *user_id && !notice.read).count() as i64)
+    }
+
+    fn mark_read(&self, user_id: &AccountId, id: i64) -> Result<(), StorageError> {
+        let mut state = self.state();
+        let notice = usize::try_from(id - 1).ok().and_then(|index| state.notifications.get_mut(index));
+        if let Some(notice) = notice.filter(|notice| notice.user_id == *user_id) {
+            notice.read = true;
+        }
+        Ok(())
+    }
+
+    fn mark_all_read(&self, user_id: &AccountId) -> Result<(), StorageError> {
+        for notice in self.state().notifications.iter_mut().filter(|notice| notice.user_id == *user_id) {
+            notice.read = true;
+        }
+        Ok(())
+    }
+
+    fn create_session(&self, user_id: &AccountId) -> Result<String, StorageError> {
+        let token = sessions::new_token();
+        self.state()
+            .sessions
+            .insert(token.clone(), (user_id.clone(), now() + SESSION_TTL_SECS));
+        Ok(token)
+    }
+
+    fn session_user(&self, token: &str) -> Result<Option<AccountId>, StorageError> {
+        Ok(self
+            .state()
+            .sessions
+            .get(token)
+            .filter(|(_, expires_at)| *expires_at > now())
+            .map(|(user_id, _)| user_id.clone()))
+    }
+
+    fn end_session(&self, token: &str) -> Result<(), StorageError> {
+        self.state().sessions.remove(token);
+        Ok(())
+    }
+}
+
+fn free_quote(amount: i64) -> FeeQuote {
+    FeeQuote { amount, flat_fee: 0, percentage_fee: 0, percent_bp: 0, fee: 0 }
+}
+
+fn now() -> i64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |elapsed| elapsed.as_secs() as i64)
+}
@@ -0,0 +1,179 @@
+use rusqlite::{params, OptionalExtension, Transaction, TransactionBehavior};
+use crate::auth::{self, NewAccount};
+use crate::bill_split::{self, Participant, SplitMethod, SplitSummary};
+use crate::fees::{self, FeeQuote};
+use crate::idempotency::{self, StoredResponse};
+use crate::ledger::{self, Receipt};
+use crate::limits::{self, Allowance};
+use crate::model::{self, AccountId, BirthDate, Email, PassportNumber, User};
+use crate::notifications::{self, Notification};
+use crate::payment_requests::{self, PaymentRequest};
+use crate::pool::Pool;
+use crate::sessions;
+use super::{Idempotent, Storage, StorageError};
+
+// Storage on a SQLite pool. Writes go through the pool's writer and reads
+// through its readers; the rules themselves live in auth.rs, ledger.rs,
+// payment_requests.rs, bill_split.rs, notifications.rs and sessions.rs, which
+// the rest of the app shares.
+pub struct SqliteStorage<'a> {
+    pool: &'a Pool,
+}
+
+impl<'a> SqliteStorage<'a> {
+    pub fn new(pool: &'a Pool) -> Self {
+        SqliteStorage { pool }
+    }
+}
+
+impl Storage for SqliteStorage<'_> {
+    fn register(&self, account: &NewAccount) -> Result<AccountId, StorageError> {
+        Ok(auth::register(&self.pool.writer(), account)?)
+    }
+
+    fn login(&self, user_id: &AccountId, email: &Email, password: &str) -> Result<(), StorageError> {
+        Ok(auth::login(&self.pool.writer(), user_id.as_str(), email.as_str(), password)?)
+    }
+
+    fn profile(&self, user_id: &AccountId) -> Result<Option<User>, StorageError> {
+        let row = self.pool.read(|conn| {
+            conn.query_row(
+                "SELECT name, email, balance, currency, encrypted_passport, encrypted_birthdate
+                 FROM user_information WHERE id = ?1",
+                params![user_id],
+                |row| {
+                    Ok((
+                        row.get::<_, Option<String>>(0)?,
+                        row.get::<_, Option<String>>(1)?,
+                        row.get::<_, Option<i64>>(2)?,
+                        row.get::<_, String>(3)?,
+                        row.get::<_, Option<String>>(4)?,
+                        row.get::<_, Option<String>>(5)?,
+                    ))
+                },
+            )
+            .optional()
+        })?;
+        Ok(row.map(|(name, email, balance, currency, passport, birth_date)| User {
+            id: user_id.clone(),
+            name,
+            email: email.and_then(|email| Email::parse(&email).ok()),
+            balance: balance.unwrap_or(0),
+            currency,
+            passport: passport.and_then(|data| PassportNumber::parse(&auth::decrypt(&data)?).ok()),
+            birth_date: birth_date.and_then(|data| BirthDate::parse(&auth::decrypt(&data)?).ok()),
+        }))
+    }
+
+    fn balance(&self, user_id: &AccountId) -> Result<i64, StorageError> {
+        Ok(self.pool.read(|conn| ledger::balance_of(conn, user_id.as_str()))?)
+    }
+
+    fn allowance(&self, user_id: &AccountId) -> Result<Option<Allowance>, StorageError> {
+        Ok(Some(self.pool.read(|conn| limits::allowance(conn, user_id.as_str()))?))
+    }
+
+    fn fee_quote(&self, sender_id: &AccountId, receiver_id: &AccountId, amount: i64) -> Result<FeeQuote, StorageError> {
+        Ok(self.pool.read(|conn| fees::quote(conn, sender_id.as_str(), receiver_id.as_str(), amount))?)
+    }
+
+    fn check_transfer(&self, sender_id: &AccountId, receiver_id: &AccountId, amount: i64) -> Result<FeeQuote, StorageError> {
+        let conn = self.pool.reader()?;
+        Ok(ledger::validate_transfer(&conn, sender_id.as_str(), receiver_id.as_str(), amount)?)
+    }
+
+    fn transfer(&self, sender_id: &AccountId, receiver_id: &AccountId, amount: i64) -> Result<Receipt, StorageError> {
+        Ok(ledger::execute_transfer(&self.pool.writer(), sender_id.as_str(), receiver_id.as_str(), amount)?)
+    }
+
+    // The key is looked up and saved in a transaction taken for writing up
+    // front, so two requests with one key cannot both get past the lookup,
+    // even from another process.
+    fn transfer_once(
+        &self,
+        sender_id: &AccountId,
+        receiver_id: &AccountId,
+        amount: i64,
+        key: &str,
+        request_hash: &str,
+        respond: &dyn Fn(&Receipt) -> (u16, String),
+    ) -> Result<Idempotent, StorageError> {
+        let conn = self.pool.writer();
+        let tx = Transaction::new_unchecked(&conn, TransactionBehavior::Immediate)?;
+        if let Some(stored) = idempotency::lookup(&tx, sender_id.as_str(), key)? {
+            return Ok(Idempotent::Replayed(stored));
+        }
+        let receipt = ledger::post_transfer(&tx, sender_id.as_str(), receiver_id.as_str(), amount)?;
+        let (status, body) = respond(&receipt);
+        let response = StoredResponse { request_hash: request_hash.to_string(), status, body };
+        idempotency::store(&tx, sender_id.as_str(), key, &response)?;
+        tx.commit()?;
+        Ok(Idempotent::Posted(response))
+    }
+
+    fn history(&self, user_id: &AccountId) -> Result<Vec<model::Transaction>, StorageError> {
+        Ok(self.pool.read(|conn| ledger::history(conn, user_id.as_str()))?)
+    }
+
+    fn request_payment(&self, requester_id: &AccountId, payer_id: &AccountId, amount: i64, memo: &str) -> Result<i64, StorageError> {
+        Ok(payment_requests::create(&self.pool.writer(), requester_id.as_str(), payer_id.as_str(), amount, memo)?)
+    }
+
+    fn pending_requests(&self, payer_id: &AccountId) -> Result<Vec<PaymentRequest>, StorageError> {
+        // pending_for marks expired requests, so it needs the writer
+        Ok(self.pool.write(|conn| payment_requests::pending_for(conn, payer_id.as_str()))?)
+    }
+
+    fn pay_request(&self, request_id: i64, payer_id: &AccountId) -> Result<Receipt, StorageError> {
+        Ok(payment_requests::approve(&self.pool.writer(), request_id, payer_id.as_str())?)
+    }
+
+    fn decline_request(&self, request_id: i64, payer_id: &AccountId) -> Result<(), StorageError> {
+        Ok(payment_requests::decline(&self.pool.writer(), request_id, payer_id.as_str())?)
+    }
+
+    fn split_bill(
+        &self,
+        creator_id: &AccountId,
+        total: i64,
+        memo: &str,
+        method: SplitMethod,
+        participants: &[Participant],
+    ) -> Result<i64, StorageError> {
+        Ok(bill_split::create_split(&self.pool.writer(), creator_id.as_str(), total, memo, method, participants)?)
+    }
+
+    fn bill_splits(&self, creator_id: &AccountId) -> Result<Vec<SplitSummary>, StorageError> {
+        // splits_for marks expired requests, so it needs the writer
+        Ok(self.pool.write(|conn| bill_split::splits_for(conn, creator_id.as_str()))?)
+    }
+
+    fn notifications(&self, user_id: &AccountId, limit: usize) -> Result<Vec<Notification>, StorageError> {
+        Ok(self.pool.read(|conn| notifications::inbox(conn, user_id.as_str(), limit))?)
+    }
+
+    fn unread_notifications(&self, user_id: &AccountId) -> Result<i64, StorageError> {
+        Ok(self.pool.read(|conn| notifications::unread_count(conn, user_id.as_str()))?)
+    }
+
+    fn mark_read(&self, user_id: &AccountId, id: i64) -> Result<(), StorageError> {
+        Ok(self.pool.write(|conn| notifications::mark_read(conn, user_id.as_str(), id))?)
+    }
+
+    fn mark_all_read(&self, user_id: &AccountId) -> Result<(), StorageError> {
+        Ok(self.pool.write(|conn| notifications::mark_all_read(conn, user_id.as_str()))?)
+    }
+
+    fn create_session(&self, user_id: &AccountId) -> Result<String, StorageError> {
+        Ok(self.pool.write(|conn| sessions::create(conn, user_id.as_str()))?)
+    }
+
+    fn session_user(&self, token: &str) -> Result<Option<AccountId>, StorageError> {
+        let user_id = self.pool.read(|conn| sessions::user_for(conn, token))?;
+        Ok(user_id.and_then(|id| AccountId::parse(&id).ok()))
+    }
+
+    fn end_session(&self, token: &str) -> Result<(), StorageError> {
+        Ok(self.pool.write(|conn| sessions::revoke(conn, token))?)
+    }
+}
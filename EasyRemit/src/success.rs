@@ -3,120 +3,88 @@ use iced::{
     container, Alignment, Length, Row, Space, Background, Color,
 };
 use crate::Message;
-use crate::{get_user_id, get_t_amount, get_receiver_id};
-use rusqlite::{params, Connection, Result};
-use std::sync::{Arc, Mutex};
-use crate::db::execute_with_retry;
+use crate::config;
+use crate::ledger::{Receipt, TransferStatus};
+use crate::tasks::Loadable;
 use rodio::{Decoder, OutputStream, Source};
 use std::fs::File;
+use tracing::{debug, info, warn};
 
 // Define colors for the theme
 const BACKGROUND_COLOR: Color = Color::from_rgb(0.96, 0.96, 0.96); // Light Gray (#F5F5F5)
 const TEXT_COLOR: Color = Color::from_rgb(0.2, 0.2, 0.2); // Dark Gray (#333333)
 const PRIMARY_COLOR: Color = Color::from_rgb(0.1, 0.3, 0.6); // Blue for title (#1A4D9);
 
-// Add your sound file path here
-const SUCCESS_SOUND: &str = "successsound.wav";
-
 
 
 #[derive(Debug, Clone)]
 pub struct SuccessPage {
     confirm_button: button::State,
+    // Loading while the transfer (or payment request approval) is posted
+    outcome: Loadable<Receipt>,
 }
 
 impl SuccessPage {
    
-        pub fn new() -> Self {
-            // Initialize audio playback
-            if let Ok((_stream, stream_handle)) = OutputStream::try_default() {
-                if let Ok(file) = File::open(SUCCESS_SOUND) {
-                    // Create a decoder for the audio file
-                    if let Ok(source) = Decoder::new(file) {
-                        println!("Playing success sound..."); // Debugging message
-                        // Play the audio
-                        match stream_handle.play_raw(source.convert_samples()) {
-                            Ok(_) => println!("Sound is playing."),
-                            Err(e) => eprintln!("Error playing sound: {:?}", e),
-                        }
-                        // Optional delay to allow the sound to finish
-                        std::thread::sleep(std::time::Duration::from_millis(600)); 
-                    } else {
-                        eprintln!("Failed to create decoder for audio file");
-                    }
-                } else {
-                    eprintln!("Failed to open audio file");
-                }
-            } else {
-                eprintln!("Failed to initialize audio stream");
-            }
-    
+        // Shown while the transfer is being posted in the background; the
+        // result arrives through `finished`.
+        pub fn sending() -> Self {
             SuccessPage {
                 confirm_button: button::State::new(),
+                outcome: Loadable::Loading,
             }
         }
 
-    pub fn view(&mut self, db_conn: &Arc<Mutex<Connection>>) -> Element<super::Message> {
-      
-
-        let conn = db_conn.lock().expect("Failed to acquire lock");
-        conn.busy_timeout(std::time::Duration::from_secs(5)).unwrap();
-
-        let sender_id = match get_user_id() { Some(id) => id, None => "NULL".to_owned(), };
-        let receiver_id = match get_receiver_id() { Some(id) => id, None => "NULL".to_owned(), };
-        let transferred_amount = match get_t_amount() { Some(amount) => amount, None => 0, };
+        pub fn finished(&mut self, outcome: Result<Receipt, String>) {
+            match &outcome {
+                Ok(receipt) if receipt.status == TransferStatus::Completed => {
+                    info!(transaction_id = %receipt.transaction_id, "transfer completed");
+                    // Playing blocks until the sound is done, so it gets its
+                    // own thread
+                    std::thread::spawn(play_success_sound);
+                }
+                Ok(receipt) => info!(transaction_id = %receipt.transaction_id, status = receipt.status.as_str(), "transfer not completed"),
+                // shown on the page, and logged already if it was on our side
+                Err(_) => {}
+            }
+            self.outcome = Loadable::from_result(outcome);
+        }
 
-        match conn.execute("INSERT INTO transaction_history (sender_id, amount, receiver_id) VALUES (?1, ?2, ?3)", params![sender_id, transferred_amount, receiver_id]) {
-            Ok(_) => println!("Transaction added successfully"),
-            Err(e) => eprintln!("Failed to add transaction: {:?}", e),
+    pub fn view(&mut self) -> Element<'_, super::Message> {
+        let receipt = match &self.outcome {
+            Loadable::Loaded(receipt) => receipt.clone(),
+            Loadable::Loading => return self.sending_view(),
+            Loadable::Failed(reason) => return self.failure_view(reason.clone()),
         };
-
-        let s_balance = execute_with_retry(|| {
-            conn.query_row(
-                "SELECT balance FROM user_information WHERE id = ?1",
-                params![sender_id.clone()],
-                |row| row.get::<_, i64>(0),
-            )
-        }, 3).unwrap_or(0);
-
-        let r_balance = execute_with_retry(|| {
-            conn.query_row(
-                "SELECT balance FROM user_information WHERE id = ?1",
-                params![receiver_id.clone()],
-                |row| row.get::<_, i64>(0),
-            )
-        }, 3).unwrap_or(0);
-
-        let sql = "UPDATE user_information SET balance=?1 WHERE id=?2";
-        conn.execute(sql, params![s_balance - transferred_amount, sender_id.clone()]).expect("Failed to update sender balance");
-        conn.execute(sql, params![r_balance + transferred_amount, receiver_id.clone()]).expect("Failed to update receiver balance");
-
-        let sender_name = execute_with_retry(|| {
-            conn.query_row(
-                "SELECT name FROM user_information WHERE id = ?1",
-                params![sender_id.clone()],
-                |row| row.get::<_, String>(0),
-            )
-        }, 3).unwrap_or_else(|_| "Null".to_string());
-
-        let receiver_name = execute_with_retry(|| {
-            conn.query_row(
-                "SELECT name FROM user_information WHERE id = ?1",
-                params![receiver_id.clone()],
-                |row| row.get::<_, String>(0),
-            )
-        }, 3).unwrap_or_else(|_| "Null".to_string());
+        // Screening reasons are for compliance only and are not shown here.
+        if receipt.status == TransferStatus::Blocked {
+            return self.failure_view("This transfer cannot be completed. No money has left your account.".to_string());
+        }
+        let headline = if receipt.status == TransferStatus::Held {
+            "Transfer under review"
+        } else {
+            "Transaction successful"
+        };
+        let transferred_amount = receipt.quote.amount;
+        let sender_name = receipt.sender.name;
+        let receiver_name = receipt.receiver.name;
 
         // Title with primary color
         let title = Text::new("EasyRemit").size(30).color(PRIMARY_COLOR);
-        let transaction_successful = Text::new("Transaction successful").size(25).color(TEXT_COLOR);
+        let transaction_successful = Text::new(headline).size(25).color(TEXT_COLOR);
         let amount = Text::new("Amount").size(20).color(TEXT_COLOR);
         let value = Text::new(transferred_amount.to_string()).size(40).color(TEXT_COLOR);
+        let fee = Text::new(receipt.quote.to_string()).size(16).color(TEXT_COLOR);
+        let total = Text::new(format!("Total charged: ${}", receipt.quote.total())).size(16).color(TEXT_COLOR);
         let from_label = Text::new("From").size(20).color(Color::WHITE);
         let from_name = Text::new(sender_name.clone()).size(20).color(Color::WHITE);
         let to_label = Text::new("To").size(20).color(Color::WHITE);
         let to_name = Text::new(receiver_name.clone()).size(20).color(Color::WHITE);
-        let tagline = Text::new("\"Instant Transfer, Anytime, Anywhere\"").size(18).color(TEXT_COLOR);
+        let tagline = if receipt.status == TransferStatus::Held {
+            Text::new("We're checking this transfer. No money has left your account yet.").size(16).color(TEXT_COLOR)
+        } else {
+            Text::new("\"Instant Transfer, Anytime, Anywhere\"").size(18).color(TEXT_COLOR)
+        };
 
         // Layout for "From" and "To"
         let from_row = Row::new()
@@ -167,13 +135,15 @@ impl SuccessPage {
             .push(amount)
             .push(Space::with_height(Length::Units(10)))
             .push(value)
+            .push(fee)
+            .push(total)
             .push(Space::with_height(Length::Units(20)))
             .push(filled_space_above_from_row)
             .push(styled_from_row)
             .push(filled_space_between_rows)
             .push(styled_to_row)
             .push(filled_space_below_to_row)
-            .push(Space::with_height(Length::Units(60)))
+            .push(Space::with_height(Length::Units(30)))
             .push(tagline)
             .push(Space::with_height(Length::Units(20)))
             .push(ok_button);
@@ -187,6 +157,79 @@ impl SuccessPage {
             .into()
     
 }
+
+    fn sending_view(&mut self) -> Element<'_, super::Message> {
+        let title = Text::new("EasyRemit").size(30).color(PRIMARY_COLOR);
+        let sending = Text::new("Sending...").size(25).color(TEXT_COLOR);
+
+        let main_content = Column::new()
+            .align_items(Alignment::Center)
+            .push(title)
+            .push(Space::with_height(Length::Units(80)))
+            .push(sending);
+
+        Container::new(main_content)
+            .width(Length::Fill)
+            .height(Length::Fill)
+            .center_x()
+            .center_y()
+            .style(CustomContainerStyle)
+            .into()
+    }
+
+    fn failure_view(&mut self, reason: String) -> Element<'_, super::Message> {
+        let title = Text::new("EasyRemit").size(30).color(PRIMARY_COLOR);
+        let transaction_failed = Text::new("Transaction failed").size(25).color(TEXT_COLOR);
+        let reason = Text::new(reason).size(18).color(Color::from_rgb(0.8, 0.1, 0.1));
+
+        let ok_button = Button::new(&mut self.confirm_button, Text::new(" Ok "))
+            .on_press(Message::GoToFunction)
+            .padding(10)
+            .style(CustomButtonStyle);
+
+        let main_content = Column::new()
+            .align_items(Alignment::Center)
+            .push(title)
+            .push(Space::with_height(Length::Units(80)))
+            .push(transaction_failed)
+            .push(Space::with_height(Length::Units(30)))
+            .push(reason)
+            .push(Space::with_height(Length::Units(60)))
+            .push(ok_button);
+
+        Container::new(main_content)
+            .width(Length::Fill)
+            .height(Length::Fill)
+            .center_x()
+            .center_y()
+            .style(CustomContainerStyle)
+            .into()
+    }
+}
+
+fn play_success_sound() {
+    // Initialize audio playback
+    if let Ok((_stream, stream_handle)) = OutputStream::try_default() {
+        if let Ok(file) = File::open(&config::get().app.success_sound) {
+            // Create a decoder for the audio file
+            if let Ok(source) = Decoder::new(file) {
+                // Play the audio
+                match stream_handle.play_raw(source.convert_samples()) {
+                    Ok(_) => debug!("playing the success sound"),
+                    Err(e) => warn!(error = %e, "could not play the success sound"),
+                }
+                // The sound stops when the stream is dropped, so wait for it
+                // to finish (this runs on its own thread)
+                std::thread::sleep(std::time::Duration::from_millis(600));
+            } else {
+                warn!("could not decode the success sound");
+            }
+        } else {
+            warn!(path = %config::get().app.success_sound.display(), "could not open the success sound");
+        }
+    } else {
+        warn!("no audio output for the success sound");
+    }
 }
 
 
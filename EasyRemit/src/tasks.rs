@@ -0,0 +1,121 @@
+use iced::{Color, Text};
+use rusqlite::Connection;
+use crate::db::DB_POOL;
+use crate::error::AppError;
+use crate::get_account_id;
+use crate::model::AccountId;
+use crate::storage::{self, Storage};
+
+// Database work for the pages runs on iced's executor, started with
+// `Command::perform`, and comes back to the page as a message. Views only
+// draw what the page has cached, so redrawing never touches the database.
+
+// A page's data from the time it is requested until it arrives
+#[derive(Debug, Clone)]
+pub enum Loadable<T> {
+    Loading,
+    Loaded(T),
+    Failed(String),
+}
+
+impl<T> Loadable<T> {
+    pub fn from_result(result: Result<T, String>) -> Self {
+        match result {
+            Ok(value) => Loadable::Loaded(value),
+            Err(e) => Loadable::Failed(e),
+        }
+    }
+
+    pub fn loaded(&self) -> Option<&T> {
+        match self {
+            Loadable::Loaded(value) => Some(value),
+            _ => None,
+        }
+    }
+}
+
+// The message a page shows for `e`. What the user cannot act on is logged in
+// full for whoever looks after the app.
+pub fn report(e: impl Into<AppError>) -> String {
+    let e = e.into();
+    if e.is_internal() {
+        tracing::error!(error = e.detail(), "{}", e);
+    }
+    e.to_string()
+}
+
+// Runs `f` with the database writer. Meant to be awaited by
+// `Command::perform`, which polls it on the executor's thread pool.
+pub async fn with_db<T, E: Into<AppError>>(f: impl FnOnce(&Connection) -> Result<T, E>) -> Result<T, String> {
+    let conn = DB_POOL.writer();
+    f(&conn).map_err(report)
+}
+
+// The same on behalf of the logged-in user
+pub async fn with_user_db<T, E: Into<AppError>>(
+    f: impl FnOnce(&Connection, &AccountId) -> Result<T, E>,
+) -> Result<T, String> {
+    let user_id = get_account_id().ok_or_else(|| report(AppError::not_logged_in()))?;
+    with_db(|conn| f(conn, &user_id)).await
+}
+
+// Runs `f` with the app's storage. Users, accounts, transactions and sessions
+// go through here rather than `with_db`.
+pub async fn with_storage<T, E: Into<AppError>>(f: impl FnOnce(&dyn Storage) -> Result<T, E>) -> Result<T, String> {
+    f(storage::storage()).map_err(report)
+}
+
+// The same on behalf of the logged-in user
+pub async fn with_user_storage<T, E: Into<AppError>>(
+    f: impl FnOnce(&dyn Storage, &AccountId) -> Result<T, E>,
+) -> Result<T, String> {
+    let user_id = get_account_id().ok_or_else(|| report(AppError::not_logged_in()))?;
+    f(storage::storage(), &user_id).map_err(report)
+}
+
+// For work that only reads: runs `f` on one of the pool's readers, alongside
+// other reads and any write. While the database is busy it tries again,
+// giving the reader back and leaving the executor's thread free in between.
+pub async fn read_db<T>(f: impl Fn(&Connection) -> rusqlite::Result<T>) -> Result<T, String> {
+    let attempt = || {
+        let result = DB_POOL.reader().and_then(|conn| f(&conn));
+        async move { result }
+    };
+    DB_POOL.retry_policy().run_async(attempt).await.map_err(report)
+}
+
+// The same on behalf of the logged-in user
+pub async fn read_user_db<T>(f: impl Fn(&Connection, &AccountId) -> rusqlite::Result<T>) -> Result<T, String> {
+    let user_id = get_account_id().ok_or_else(|| report(AppError::not_logged_in()))?;
+    read_db(|conn| f(conn, &user_id)).await
+}
+
+// An amount typed into a page, in whole dollars
+pub fn parse_amount(text: &str) -> Result<i64, String> {
+    text.trim()
+        .parse()
+        .map_err(|_| report(AppError::Validation("Enter the amount in whole dollars, e.g. 25".to_string())))
+}
+
+// "Loading..." while waiting, the error in red if loading failed, nothing
+// once the data is there
+pub fn loading_text<T>(state: &Loadable<T>, what: &str) -> Text {
+    match state {
+        Loadable::Loading => Text::new(format!("Loading {}...", what)).size(16).color([0.5, 0.5, 0.5]),
+        Loadable::Failed(error) => Text::new(format!("Could not load {}: {}", what, error))
+            .size(16)
+            .color(Color::from_rgb(1.0, 0.0, 0.0)),
+        Loadable::Loaded(_) => Text::new("").size(4),
+    }
+}
+
+// The outcome of the last action on a page: grey while it runs, then green
+// or red
+pub fn status_text(status: &Option<Loadable<String>>, size: u16) -> Text {
+    match status {
+        Some(Loadable::Loading) => Text::new("Working...").size(size).color([0.5, 0.5, 0.5]),
+        Some(Loadable::Loaded(message)) => Text::new(message.clone()).size(size).color(Color::from_rgb(0.2, 0.6, 0.2)),
+        Some(Loadable::Failed(message)) => Text::new(message.clone()).size(size).color(Color::from_rgb(1.0, 0.0, 0.0)),
+        None => Text::new("").size(4),
+    }
+}
@@ -1,21 +1,30 @@
-use iced::{button, Button, Column, Container, Element, Text};
+use iced::{button, Button, Column, Command, Container, Element, Text};
 use iced::{ text_input, Alignment,Length,Row,  TextInput, Background, Color};
 use crate::Message;
-use rusqlite::{params, Connection};
-use std::sync::{Arc, Mutex};
-use once_cell::sync::Lazy;
-use crate::{set_receiver_id, set_t_amount,get_user_id};
-
-static USER_ID: Lazy<Mutex<String>> = Lazy::new(|| {
-    Mutex::new(match get_user_id() {
-        Some(id) => id,              // If there is a user ID, use it
-        None => "None".to_string(),  // Default to "None" if no user ID
-    })
-});
-
-
-static mut ERROR:bool=false;
-
+use crate::get_user_id;
+use crate::qr;
+use crate::fees::FeeQuote;
+use crate::ledger::TransferError;
+use crate::limits::Allowance;
+use crate::model::AccountId;
+use crate::qr::PaymentPayload;
+use crate::storage::{Storage, StorageError};
+use crate::tasks::{loading_text, with_user_storage, Loadable};
+use std::path::PathBuf;
+
+// The result of running every transfer check for one recipient and amount,
+// along with what the page shows around it
+#[derive(Debug, Clone)]
+pub struct TransferCheck {
+    recipient: String,
+    amount: i64,
+    balance: i64,
+    allowance: Option<Allowance>,
+    // the quote if the transfer may go ahead, otherwise why not
+    result: Result<FeeQuote, String>,
+    // what it would cost, shown even when some other check fails
+    quote: Option<FeeQuote>,
+}
 
 #[derive(Debug, Clone)]
 pub struct TransferPage {
@@ -25,25 +34,40 @@ pub struct TransferPage {
     amount: String,
     keypad_buttons: [button::State; 12],
     back_button:button::State,
+    qr_path: String,
+    qr_path_input: text_input::State,
+    import_qr_button: button::State,
+    // memo carried by an imported QR code, or why it could not be read
+    qr_status: Option<Loadable<String>>,
+    // checks for the latest recipient and amount that have come back
+    check: Loadable<TransferCheck>,
 }
 
 impl TransferPage {
-    pub fn new() -> Self {
-        TransferPage {
+    pub fn new() -> (Self, Command<Message>) {
+        let page = TransferPage {
                 recipient: String::new(),
                 recipient_input: text_input::State::new(),
                 amount: String::from("0"),
                 keypad_buttons: Default::default(), 
                 transfer_button: button::State::new(),
                 back_button:button::State::new(),
-        }
+                qr_path: String::new(),
+                qr_path_input: text_input::State::new(),
+                import_qr_button: button::State::new(),
+                qr_status: None,
+                check: Loadable::Loading,
+        };
+        let check = page.run_checks();
+        (page, check)
     }
 
-    pub fn update(&mut self, message: Message) {
+    pub fn update(&mut self, message: Message) -> Command<Message> {
 
         match message {
             Message::InputChanged(input) => {
                 self.recipient = input;
+                return self.run_checks();
             }
             Message::KeypadPressed(digit) => {
                 if digit == 'X' {
@@ -59,41 +83,97 @@ impl TransferPage {
                         self.amount.push(digit);
                     }
                 }
+                return self.run_checks();
+            }
+
+            Message::QrPathChanged(path) => {
+                self.qr_path = path;
+            }
+            Message::ImportQr => {
+                return self.import_qr();
+            }
+            Message::QrImported(result) => {
+                return self.qr_imported(result);
+            }
+            Message::TransferChecked(result) => {
+                // Answers for inputs that have changed since are dropped
+                match result {
+                    Ok(check) if !self.is_current(&check) => {}
+                    result => self.check = Loadable::from_result(result),
+                }
             }
 
-            
             _=>{}
             
         }
+        Command::none()
     }
-    pub fn view(&mut self,db_conn:&Arc<Mutex<Connection>>) -> Element<super::Message> {
-        
-        let conn = db_conn.lock().expect("Failed to acquire lock");
-        let user_id = match get_user_id(){
-            Some(id)=>id,
-            None=>"NULL".to_string(),
-        };
-        let balance1= conn.query_row(
-            "SELECT balance FROM user_information WHERE id = ?1",
-            params![user_id.clone()], |row| row.get::<_, i64>(0),);
-        let balance:i64=match balance1{
-            Ok(t)=> t,
-            Err(_)=>0,
 
-        };
+    fn amount(&self) -> i64 {
+        self.amount.parse().unwrap_or(0)
+    }
 
-        let mut stmt = conn.prepare("SELECT EXISTS(SELECT 1 FROM user_information WHERE id = ?)").expect("Failed to prepare statement");
-        let exists: bool = stmt.query_row(params![self.recipient.clone()], |row| row.get(0)).unwrap_or(false);
-        let amountt:i64=self.amount.parse().expect("error");   
-        let invalid=self.recipient == user_id.clone() || balance-amountt<0 || !exists || self.amount=="0".to_string();
-        unsafe{
-        
-        if invalid{
-            ERROR=true;
-        }else{
-            ERROR=false;
+    fn is_current(&self, check: &TransferCheck) -> bool {
+        check.recipient == self.recipient && check.amount == self.amount()
+    }
+
+    // Runs the same checks as sending would, in the background, so the page
+    // can explain why SEND is disabled.
+    fn run_checks(&self) -> Command<Message> {
+        let (recipient, amount) = (self.recipient.clone(), self.amount());
+        Command::perform(
+            with_user_storage(move |storage, user_id| check_transfer(storage, user_id, &recipient, amount)),
+            Message::TransferChecked,
+        )
+    }
+
+    // The recipient and amount to send, if the latest checks allow it
+    pub fn ready_to_send(&self) -> Option<(AccountId, i64)> {
+        match self.check.loaded() {
+            Some(check) if self.is_current(check) && check.result.is_ok() => {
+                Some((AccountId::parse(&check.recipient).ok()?, check.amount))
+            }
+            _ => None,
+        }
+    }
+
+    // Fills in the recipient, and the amount for a payment request, from a
+    // saved or photographed QR code. Decoding the image runs in the
+    // background.
+    fn import_qr(&mut self) -> Command<Message> {
+        let path = PathBuf::from(self.qr_path.trim().trim_matches('"'));
+        self.qr_status = Some(Loadable::Loading);
+        Command::perform(
+            async move { qr::scan_file(&path).map_err(|e| e.to_string()) },
+            Message::QrImported,
+        )
+    }
+
+    fn qr_imported(&mut self, result: Result<PaymentPayload, String>) -> Command<Message> {
+        let payload = match result {
+            Ok(payload) => payload,
+            Err(e) => {
+                self.qr_status = Some(Loadable::Failed(e));
+                return Command::none();
+            }
+        };
+        self.recipient = payload.account_id.clone();
+        if let Some(amount) = payload.amount {
+            self.amount = amount.to_string();
         }
-        let display_string=format!("VISA CARD\n{}",user_id.clone());
+        let payee = payload.name.unwrap_or(payload.account_id);
+        self.qr_status = Some(Loadable::Loaded(match payload.memo {
+            Some(memo) => format!("Paying {} for {}", payee, memo),
+            None => format!("Paying {}", payee),
+        }));
+        self.run_checks()
+    }
+
+    pub fn view(&mut self) -> Element<'_, super::Message> {
+        let user_id = get_user_id().unwrap_or_default();
+        let check = self.check.loaded().filter(|check| self.is_current(check));
+
+        let display_string=format!("VISA CARD\n{}",user_id);
         let profile_circle = Container::new(Text::new(""))
             .width(Length::Units(50)) // Set the width for the circle
             .height(Length::Units(50)) // Set the height for the circle
@@ -129,7 +209,11 @@ impl TransferPage {
         );
 
 
-        let balance_display = Text::new(format!("Your balance: {}",balance.clone())).size(16);
+        // The balance from the last checks stays up while new ones run
+        let balance_display = match self.check.loaded() {
+            Some(last) => Text::new(format!("Your balance: {}", last.balance)).size(16),
+            None => loading_text(&self.check, "your balance"),
+        };
 
         let input = TextInput::new(
             &mut self.recipient_input,
@@ -140,20 +224,70 @@ impl TransferPage {
         .padding(10)
         .size(20);
 
+        let qr_row = Row::new()
+            .spacing(10)
+            .align_items(Alignment::Center)
+            .push(
+                TextInput::new(
+                    &mut self.qr_path_input,
+                    "QR image file: ",
+                    &self.qr_path,
+                    Message::QrPathChanged,
+                )
+                .padding(6)
+                .size(14),
+            )
+            .push(
+                Button::new(&mut self.import_qr_button, Text::new("Import QR").size(14))
+                    .padding(6)
+                    .style(SendButtonStyle)
+                    .on_press(Message::ImportQr),
+            );
+
+        let qr_status = match &self.qr_status {
+            Some(Loadable::Loading) => Text::new("Reading QR code...")
+                .color(Color::from_rgb(0.5, 0.5, 0.5))
+                .size(14),
+            Some(Loadable::Loaded(message)) => Text::new(message.clone())
+                .color(Color::from_rgb(0.2, 0.6, 0.2))
+                .size(14),
+            Some(Loadable::Failed(message)) => Text::new(message.clone())
+                .color(Color::from_rgb(1.0, 0.0, 0.0))
+                .size(14),
+            None => Text::new("").size(4),
+        };
+
         let amount_display = Text::new(format!("${}", self.amount)).size(50);
         
-        let error_text = if ERROR{
-
-            Text::new("Invalid Amount or Invalid Receiver")
+        let error_text = match check.map(|check| &check.result) {
+            Some(Err(reason)) => Text::new(reason.clone())
                 .color(Color::from_rgb(1.0, 0.0, 0.0))
+                .size(16),
+            _ => Text::new("")
+                .size(4),
+        };
+
+        let fee_display = match check.and_then(|check| check.quote) {
+            Some(quote) => Text::new(format!("{}\nTotal charged: ${}", quote, quote.total()))
                 .size(16)
-                  
-        } else {
-            Text::new("")
-            .size(4)    
-            
+                .color(Color::from_rgb(0.2, 0.2, 0.2)),
+            None => Text::new("").size(4),
         };
 
+        let allowance_display = match self.check.loaded().and_then(|last| last.allowance) {
+            Some(allowance) => Text::new(format!(
+                "You can send up to ${} now\nToday: ${} left  This month: ${} left",
+                allowance.max_transfer(),
+                allowance.remaining_today(),
+                allowance.remaining_this_month(),
+            ))
+            .size(14)
+            .color(Color::from_rgb(0.4, 0.4, 0.4)),
+            None => Text::new("").size(4),
+        };
+
+        let ready = matches!(check, Some(check) if check.result.is_ok());
+
         // Split the keypad_buttons into non-overlapping slices
         let (first_row, rest) = self.keypad_buttons.split_at_mut(3);
         let (second_row, rest) = rest.split_at_mut(3);
@@ -169,26 +303,14 @@ impl TransferPage {
             .push(create_keypad_row(third_row, ['7', '8', '9']))
             .push(create_keypad_row(fourth_row, ['.', '0', 'X']));
 
-       
-        let send_button = if invalid {
-            ERROR=true;
-
-            Button::new(&mut self.transfer_button, Text::new("SEND"))
-            .padding(15)
-            .style(SendButtonStyle) // Custom style for the send button
-            .on_press(Message::GoToTransfer)
-
-        }else{
-            ERROR=false;
-            let t_amount:i64=self.amount.trim().parse().unwrap();
-            set_receiver_id(self.recipient.clone());
-            set_t_amount(t_amount);
-            Button::new(&mut self.transfer_button, Text::new("SEND"))
+        // SEND only does something once the checks for exactly what is on
+        // screen have passed
+        let mut send_button = Button::new(&mut self.transfer_button, Text::new("SEND"))
             .padding(15)
-            .style(SendButtonStyle) // Custom style for the send button
-            .on_press(Message::GoToSuccess)
-
-        };
+            .style(SendButtonStyle); // Custom style for the send button
+        if ready {
+            send_button = send_button.on_press(Message::SubmitTransfer);
+        }
 
         let back_button = Button::new(&mut self.back_button, Text::new("Back"))
         .padding(3)
@@ -202,9 +324,13 @@ impl TransferPage {
             .align_items(Alignment::Center)
             .push(card_display)
             .push(input)
+            .push(qr_row)
+            .push(qr_status)
             .push(amount_display)
+            .push(fee_display)
             .push(error_text)
             .push(balance_display)
+            .push(allowance_display)
             .push(keypad)
             .push(send_button)
             .push(back_button);
@@ -215,9 +341,30 @@ impl TransferPage {
             .center_x()
             .center_y()
             .into()
-    
-        
-    }}
+    }
+}
+
+fn check_transfer(storage: &dyn Storage, user_id: &AccountId, recipient: &str, amount: i64) -> Result<TransferCheck, StorageError> {
+    let recipient_id = AccountId::parse(recipient);
+    let result = match &recipient_id {
+        Ok(recipient_id) => storage.check_transfer(user_id, recipient_id, amount).map_err(|e| e.to_string()),
+        // the amount is checked first, as in ledger::validate_transfer
+        Err(_) if amount <= 0 => Err(TransferError::InvalidAmount.to_string()),
+        Err(e) => Err(e.to_string()),
+    };
+    let quote = match (&result, &recipient_id) {
+        (Ok(quote), _) => Some(*quote),
+        (Err(_), Ok(recipient_id)) if amount > 0 => storage.fee_quote(user_id, recipient_id, amount).ok(),
+        _ => None,
+    };
+    Ok(TransferCheck {
+        balance: storage.balance(user_id)?,
+        allowance: storage.allowance(user_id).ok().flatten(),
+        recipient: recipient.to_string(),
+        amount,
+        result,
+        quote,
+    })
 }
 
 
@@ -262,24 +409,16 @@ impl iced::button::StyleSheet for SendButtonStyle {
     }
 }
 
+// One button per label; a short slice of states leaves buttons out rather
+// than failing
 fn create_keypad_row<'a>(
     states: &'a mut [button::State],
     labels: [char; 3],
 ) -> Row<'a, Message> {
-    // Ensure that the slice has exactly 3 elements to avoid index out of bounds
-    if states.len() != 3 {
-        panic!("Expected exactly 3 button states, but got {}", states.len());
-    }
-
-    // Use split_at_mut to get mutable references without borrowing conflicts
-    let (first_state, rest) = states.split_at_mut(1);
-    let (second_state, third_state) = rest.split_at_mut(1);
-
-    Row::new()
-        .spacing(10)
-        .push(create_keypad_button(&mut first_state[0], labels[0])) // Use first state
-        .push(create_keypad_button(&mut second_state[0], labels[1])) // Use second state
-        .push(create_keypad_button(&mut third_state[0], labels[2])) // Use third state
+    states
+        .iter_mut()
+        .zip(labels)
+        .fold(Row::new().spacing(10), |row, (state, label)| row.push(create_keypad_button(state, label)))
 }
 
 fn create_keypad_button<'a>(
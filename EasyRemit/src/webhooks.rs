@@ -0,0 +1,481 @@
+use hmac::{Hmac, Mac};
+use rand::{thread_rng, RngCore};
+use rusqlite::{params, Connection};
+use serde_json::{json, Value};
+use sha2::Sha256;
+use std::fmt;
+use crate::pool::Pool;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+// Event types a subscription can ask for
+pub const TRANSFER_COMPLETED: &str = "transfer.completed";
+pub const TRANSFER_FAILED: &str = "transfer.failed";
+// Reserved: nothing sends it yet. A completed transfer is final in this
+// ledger, and a transfer that fails review was never debited, so no money
+// ever goes back. Subscriptions to it are accepted so back-office systems
+// can be set up before a reversal path exists, which should publish it.
+pub const TRANSFER_REVERSED: &str = "transfer.reversed";
+pub const EVENTS: [&str; 3] = [TRANSFER_COMPLETED, TRANSFER_FAILED, TRANSFER_REVERSED];
+
+// Headers sent with every delivery
+pub const SIGNATURE_HEADER: &str = "X-EasyRemit-Signature";
+pub const EVENT_HEADER: &str = "X-EasyRemit-Event";
+pub const DELIVERY_HEADER: &str = "X-EasyRemit-Delivery";
+
+// A delivery that still fails after this many attempts is marked failed and
+// left in the outbox for an operator to look at.
+pub const MAX_ATTEMPTS: i64 = 10;
+// Wait after the first failure; doubles with each further one up to the cap
+const BASE_DELAY_SECS: i64 = 30;
+const MAX_DELAY_SECS: i64 = 60 * 60;
+// How long a dispatcher owns a delivery it has picked up, so two processes
+// sharing bank.db do not send the same one at once
+const CLAIM_SECS: i64 = 60;
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+const BATCH_SIZE: i64 = 20;
+
+#[derive(Debug, Clone)]
+pub struct Subscription {
+    pub id: i64,
+    pub url: String,
+    pub events: Vec<String>,
+    pub active: bool,
+    pub created_at: i64,
+}
+
+#[derive(Debug)]
+pub enum WebhookError {
+    InvalidUrl,
+    UnknownEvent(String),
+    NoEvents,
+    UnknownSubscription,
+    Database(rusqlite::Error),
+}
+
+impl fmt::Display for WebhookError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WebhookError::InvalidUrl => write!(f, "Webhook URLs must start with http:// or https://"),
+            WebhookError::UnknownEvent(event) => {
+                write!(f, "Unknown event {}, expected one of: {}", event, EVENTS.join(", "))
+            }
+            WebhookError::NoEvents => write!(f, "Subscribe to at least one event"),
+            WebhookError::UnknownSubscription => write!(f, "No active webhook with that ID"),
+            WebhookError::Database(e) => write!(f, "Database error: {}", e),
+        }
+    }
+}
+
+impl From<rusqlite::Error> for WebhookError {
+    fn from(e: rusqlite::Error) -> Self {
+        WebhookError::Database(e)
+    }
+}
+
+// Adds a subscription and returns its ID and signing secret. A secret is
+// generated when none is given; it is only shown here.
+pub fn subscribe(conn: &Connection, url: &str, events: &[&str], secret: Option<&str>) -> Result<(i64, String), WebhookError> {
+    if !(url.starts_with("http://") || url.starts_with("https://")) {
+        return Err(WebhookError::InvalidUrl);
+    }
+    if events.is_empty() {
+        return Err(WebhookError::NoEvents);
+    }
+    if let Some(event) = events.iter().find(|event| !EVENTS.contains(event)) {
+        return Err(WebhookError::UnknownEvent(event.to_string()));
+    }
+    let secret = match secret {
+        Some(secret) => secret.to_string(),
+        None => {
+            let mut bytes = [0u8; 32];
+            thread_rng().fill_bytes(&mut bytes);
+            format!("whsec_{}", hex::encode(bytes))
+        }
+    };
+    conn.execute(
+        "INSERT INTO webhook_subscriptions (url, events, secret, created_at) VALUES (?1, ?2, ?3, strftime('%s', 'now'))",
+        params![url, events.join(","), secret],
+    )?;
+    Ok((conn.last_insert_rowid(), secret))
+}
+
+// Stops new events for a subscription. Deliveries still waiting in the
+// outbox are cancelled, so call this inside a transaction.
+pub fn unsubscribe(conn: &Connection, id: i64) -> Result<(), WebhookError> {
+    let changed = conn.execute(
+        "UPDATE webhook_subscriptions SET active = 0 WHERE id = ?1 AND active = 1",
+        params![id],
+    )?;
+    if changed == 0 {
+        return Err(WebhookError::UnknownSubscription);
+    }
+    conn.execute(
+        "UPDATE webhook_outbox SET status = 'cancelled' WHERE subscription_id = ?1 AND status = 'pending'",
+        params![id],
+    )?;
+    Ok(())
+}
+
+pub fn subscriptions(conn: &Connection) -> rusqlite::Result<Vec<Subscription>> {
+    let mut stmt = conn.prepare("SELECT id, url, events, active, created_at FROM webhook_subscriptions ORDER BY id")?;
+    let rows = stmt.query_map([], |row| {
+        let events: String = row.get(2)?;
+        Ok(Subscription {
+            id: row.get(0)?,
+            url: row.get(1)?,
+            events: events.split(',').map(str::to_string).collect(),
+            active: row.get(3)?,
+            created_at: row.get(4)?,
+        })
+    })?;
+    rows.collect()
+}
+
+// Counts of outbox rows by status: pending, delivered, failed, cancelled
+pub fn outbox_counts(conn: &Connection) -> rusqlite::Result<Vec<(String, i64)>> {
+    let mut stmt = conn.prepare("SELECT status, COUNT(*) FROM webhook_outbox GROUP BY status ORDER BY status")?;
+    let rows = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?;
+    rows.collect()
+}
+
+// Queues an event for every active subscription that wants it. Call with the
+// transaction that made the change, so the event exists if and only if the
+// change commits; the dispatcher sends it later.
+pub fn enqueue(conn: &Connection, event_type: &str, data: Value) -> rusqlite::Result<()> {
+    let mut stmt = conn.prepare(
+        "SELECT id FROM webhook_subscriptions
+         WHERE active = 1 AND ',' || events || ',' LIKE '%,' || ?1 || ',%'",
+    )?;
+    let subscribers = stmt
+        .query_map(params![event_type], |row| row.get::<_, i64>(0))?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    if subscribers.is_empty() {
+        return Ok(());
+    }
+
+    let mut bytes = [0u8; 16];
+    thread_rng().fill_bytes(&mut bytes);
+    let event_id = format!("evt_{}", hex::encode(bytes));
+    let payload = json!({
+        "id": event_id,
+        "type": event_type,
+        "created_at": unix_now(),
+        "data": data,
+    })
+    .to_string();
+    for subscription_id in subscribers {
+        conn.execute(
+            "INSERT INTO webhook_outbox (subscription_id, event_id, event_type, payload, next_attempt_at, created_at)
+             VALUES (?1, ?2, ?3, ?4, strftime('%s', 'now'), strftime('%s', 'now'))",
+            params![subscription_id, event_id, event_type, payload],
+        )?;
+    }
+    Ok(())
+}
+
+// HMAC-SHA256 over "<timestamp>.<body>", sent as "t=<timestamp>,v1=<hex>".
+// Including the timestamp lets receivers refuse old deliveries being replayed.
+pub fn signature(secret: &str, timestamp: i64, body: &str) -> String {
+    format!("t={},v1={}", timestamp, hex::encode(mac(secret, timestamp, body).finalize().into_bytes()))
+}
+
+// Checks a signature header the way a receiver should, in constant time.
+pub fn verify(secret: &str, header: &str, body: &str) -> bool {
+    let mut timestamp = None;
+    let mut signature = None;
+    for part in header.split(',') {
+        match part.split_once('=') {
+            Some(("t", value)) => timestamp = value.parse::<i64>().ok(),
+            Some(("v1", value)) => signature = hex::decode(value).ok(),
+            _ => {}
+        }
+    }
+    match (timestamp, signature) {
+        (Some(timestamp), Some(signature)) => mac(secret, timestamp, body).verify_slice(&signature).is_ok(),
+        _ => false,
+    }
+}
+
+fn mac(secret: &str, timestamp: i64, body: &str) -> Hmac<Sha256> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any length");
+    mac.update(timestamp.to_string().as_bytes());
+    mac.update(b".");
+    mac.update(body.as_bytes());
+    mac
+}
+
+// Seconds to wait before the next attempt after `attempts` failures
+pub fn backoff_secs(attempts: i64) -> i64 {
+    let exponent = (attempts - 1).clamp(0, 30) as u32;
+    BASE_DELAY_SECS.saturating_mul(1 << exponent).min(MAX_DELAY_SECS)
+}
+
+// One outbox row ready to send
+#[derive(Debug, Clone)]
+struct Delivery {
+    id: i64,
+    url: String,
+    secret: String,
+    event_type: String,
+    payload: String,
+    attempts: i64,
+}
+
+// Claims up to BATCH_SIZE due deliveries. Another dispatcher will not pick
+// them up again until the claim runs out.
+fn claim_due(conn: &Connection) -> rusqlite::Result<Vec<Delivery>> {
+    let tx = conn.unchecked_transaction()?;
+    let due = {
+        let mut stmt = tx.prepare(
+            "SELECT o.id, s.url, s.secret, o.event_type, o.payload, o.attempts
+             FROM webhook_outbox o JOIN webhook_subscriptions s ON s.id = o.subscription_id
+             WHERE o.status = 'pending' AND o.next_attempt_at <= strftime('%s', 'now')
+             ORDER BY o.id LIMIT ?1",
+        )?;
+        let rows = stmt.query_map(params![BATCH_SIZE], |row| {
+            Ok(Delivery {
+                id: row.get(0)?,
+                url: row.get(1)?,
+                secret: row.get(2)?,
+                event_type: row.get(3)?,
+                payload: row.get(4)?,
+                attempts: row.get(5)?,
+            })
+        })?;
+        rows.collect::<rusqlite::Result<Vec<_>>>()?
+    };
+    for delivery in &due {
+        tx.execute(
+            "UPDATE webhook_outbox SET next_attempt_at = strftime('%s', 'now') + ?2 WHERE id = ?1",
+            params![delivery.id, CLAIM_SECS],
+        )?;
+    }
+    tx.commit()?;
+    Ok(due)
+}
+
+fn send(agent: &ureq::Agent, delivery: &Delivery) -> Result<(), String> {
+    let result = agent
+        .post(&delivery.url)
+        .set("Content-Type", "application/json")
+        .set(EVENT_HEADER, &delivery.event_type)
+        .set(DELIVERY_HEADER, &delivery.id.to_string())
+        .set(SIGNATURE_HEADER, &signature(&delivery.secret, unix_now(), &delivery.payload))
+        .send_string(&delivery.payload);
+    match result {
+        Ok(_) => Ok(()),
+        Err(ureq::Error::Status(code, _)) => Err(format!("HTTP {}", code)),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+fn record_attempt(conn: &Connection, delivery: &Delivery, result: &Result<(), String>) -> rusqlite::Result<()> {
+    let attempts = delivery.attempts + 1;
+    match result {
+        Ok(()) => conn.execute(
+            "UPDATE webhook_outbox SET status = 'delivered', attempts = ?2, last_error = NULL,
+             delivered_at = strftime('%s', 'now') WHERE id = ?1",
+            params![delivery.id, attempts],
+        )?,
+        Err(error) if attempts >= MAX_ATTEMPTS => conn.execute(
+            "UPDATE webhook_outbox SET status = 'failed', attempts = ?2, last_error = ?3 WHERE id = ?1",
+            params![delivery.id, attempts, error],
+        )?,
+        Err(error) => conn.execute(
+            "UPDATE webhook_outbox SET attempts = ?2, last_error = ?3,
+             next_attempt_at = strftime('%s', 'now') + ?4 WHERE id = ?1",
+            params![delivery.id, attempts, error, backoff_secs(attempts)],
+        )?,
+    };
+    Ok(())
+}
+
+// Sends everything that is due and returns how many were delivered. The
+// database lock is only held around the reads and writes, never while
+// waiting on a receiver.
+pub fn deliver_due(db: &Pool) -> rusqlite::Result<usize> {
+    let agent = ureq::AgentBuilder::new().timeout(REQUEST_TIMEOUT).build();
+    let mut delivered = 0;
+    loop {
+        let due = db.write(claim_due)?;
+        if due.is_empty() {
+            return Ok(delivered);
+        }
+        for delivery in &due {
+            let result = send(&agent, delivery);
+            if result.is_ok() {
+                delivered += 1;
+            }
+            db.write(|conn| record_attempt(conn, delivery, &result))?;
+        }
+    }
+}
+
+// Polls the outbox in the background for as long as the process runs.
+// Events queued by a process that exited are picked up by the next one.
+pub fn spawn_dispatcher(db: &'static Pool) {
+    std::thread::spawn(move || loop {
+        if let Err(e) = deliver_due(db) {
+            tracing::warn!(error = %e, "webhook delivery failed");
+        }
+        std::thread::sleep(POLL_INTERVAL);
+    });
+}
+
+fn unix_now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs() as i64)
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::init_schema;
+    use tiny_http::{Response, Server};
+
+    fn database() -> Pool {
+        let conn = Connection::open_in_memory().unwrap();
+        init_schema(&conn).unwrap();
+        Pool::single(conn)
+    }
+
+    // (event header, signature header, body) of each request received
+    type Received = Vec<(String, String, String)>;
+
+    // A local receiver that answers every request with `status` and hands
+    // back what it got.
+    fn stand_in(status: u16, requests: usize) -> (String, std::thread::JoinHandle<Received>) {
+        let server = Server::http("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/hooks", server.server_addr().to_ip().unwrap());
+        let handle = std::thread::spawn(move || {
+            let mut received = Vec::new();
+            for _ in 0..requests {
+                let mut request = server.recv().unwrap();
+                let header = |name: &'static str| {
+                    request
+                        .headers()
+                        .iter()
+                        .find(|header| header.field.equiv(name))
+                        .map(|header| header.value.to_string())
+                        .unwrap_or_default()
+                };
+                let (event, signature) = (header(EVENT_HEADER), header(SIGNATURE_HEADER));
+                let mut body = String::new();
+                request.as_reader().read_to_string(&mut body).unwrap();
+                received.push((event, signature, body));
+                request.respond(Response::empty(status)).unwrap();
+            }
+            received
+        });
+        (url, handle)
+    }
+
+    fn outbox(db: &Pool) -> (String, i64, Option<String>) {
+        db.writer()
+            .query_row("SELECT status, attempts, last_error FROM webhook_outbox", [], |row| {
+                Ok((row.get(0)?, row.get(1)?, row.get(2)?))
+            })
+            .unwrap()
+    }
+
+    #[test]
+    fn delivers_signed_events_to_subscribers() {
+        let db = database();
+        let (url, receiver) = stand_in(200, 1);
+        let secret = {
+            let conn = db.writer();
+            let (_, secret) = subscribe(&conn, &url, &[TRANSFER_COMPLETED], None).unwrap();
+            subscribe(&conn, &url, &[TRANSFER_FAILED], Some("other")).unwrap();
+            enqueue(&conn, TRANSFER_COMPLETED, json!({ "transaction_id": 7 })).unwrap();
+            secret
+        };
+
+        assert_eq!(deliver_due(&db).unwrap(), 1);
+        let received = receiver.join().unwrap();
+        let (event, signature, body) = &received[0];
+        assert_eq!(event, TRANSFER_COMPLETED);
+        assert!(verify(&secret, signature, body));
+        assert!(!verify("other", signature, body));
+        assert!(!verify(&secret, signature, &body.replace('7', "8")));
+        let payload: Value = serde_json::from_str(body).unwrap();
+        assert_eq!(payload["type"], TRANSFER_COMPLETED);
+        assert_eq!(payload["data"]["transaction_id"], 7);
+        assert_eq!(outbox(&db), ("delivered".to_string(), 1, None));
+    }
+
+    #[test]
+    fn failed_deliveries_wait_and_retry() {
+        let db = database();
+        let (url, receiver) = stand_in(500, 1);
+        {
+            let conn = db.writer();
+            subscribe(&conn, &url, &EVENTS, None).unwrap();
+            enqueue(&conn, TRANSFER_FAILED, json!({ "transaction_id": 1 })).unwrap();
+        }
+
+        assert_eq!(deliver_due(&db).unwrap(), 0);
+        receiver.join().unwrap();
+        assert_eq!(outbox(&db), ("pending".to_string(), 1, Some("HTTP 500".to_string())));
+        // Not due again until the backoff has passed
+        assert_eq!(deliver_due(&db).unwrap(), 0);
+        assert_eq!(outbox(&db).1, 1);
+
+        let (url, receiver) = stand_in(204, 1);
+        {
+            let conn = db.writer();
+            conn.execute("UPDATE webhook_subscriptions SET url = ?1", params![url]).unwrap();
+            conn.execute("UPDATE webhook_outbox SET next_attempt_at = 0", []).unwrap();
+        }
+        assert_eq!(deliver_due(&db).unwrap(), 1);
+        receiver.join().unwrap();
+        assert_eq!(outbox(&db), ("delivered".to_string(), 2, None));
+    }
+
+    #[test]
+    fn gives_up_after_max_attempts() {
+        let db = database();
+        {
+            let conn = db.writer();
+            // Nothing listens on port 9 locally, so every attempt fails
+            subscribe(&conn, "http://127.0.0.1:9/", &[TRANSFER_COMPLETED], None).unwrap();
+            enqueue(&conn, TRANSFER_COMPLETED, json!({})).unwrap();
+            conn.execute("UPDATE webhook_outbox SET attempts = ?1", params![MAX_ATTEMPTS - 1]).unwrap();
+        }
+        deliver_due(&db).unwrap();
+        let (status, attempts, error) = outbox(&db);
+        assert_eq!((status.as_str(), attempts), ("failed", MAX_ATTEMPTS));
+        assert!(error.is_some());
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_the_cap() {
+        assert_eq!(backoff_secs(1), 30);
+        assert_eq!(backoff_secs(2), 60);
+        assert_eq!(backoff_secs(5), 480);
+        assert_eq!(backoff_secs(9), MAX_DELAY_SECS);
+        assert_eq!(backoff_secs(1000), MAX_DELAY_SECS);
+    }
+
+    #[test]
+    fn events_without_subscribers_are_not_queued() {
+        let db = database();
+        let conn = db.writer();
+        subscribe(&conn, "http://127.0.0.1:9/", &[TRANSFER_FAILED], None).unwrap();
+        enqueue(&conn, TRANSFER_COMPLETED, json!({})).unwrap();
+        let queued: i64 = conn.query_row("SELECT COUNT(*) FROM webhook_outbox", [], |row| row.get(0)).unwrap();
+        assert_eq!(queued, 0);
+        assert!(matches!(
+            subscribe(&conn, "ftp://example.com", &[TRANSFER_FAILED], None),
+            Err(WebhookError::InvalidUrl)
+        ));
+        assert!(matches!(
+            subscribe(&conn, "http://example.com", &["transfer.sent"], None),
+            Err(WebhookError::UnknownEvent(_))
+        ));
+        // reserved, so it can be subscribed to though nothing sends it yet
+        assert!(subscribe(&conn, "http://example.com", &[TRANSFER_REVERSED], None).is_ok());
+    }
+}